@@ -0,0 +1,523 @@
+use std::fmt;
+
+use euclid::default::Box2D;
+use g_code::emit::{
+    Field, Token, ABSOLUTE_DISTANCE_MODE_FIELD, LINEAR_INTERPOLATION_FIELD,
+    RAPID_POSITIONING_FIELD, RELATIVE_DISTANCE_MODE_FIELD,
+};
+use lyon_geom::{point, LineSegment, Point};
+
+type F64Point = Point<f64>;
+
+/// Distance under which a cut is considered to have returned to where it started
+const CLOSED_TOLERANCE: f64 = 1e-6;
+
+/// A straight line movement of the machine, interpreted from a program
+#[derive(Debug, Clone)]
+pub struct Motion {
+    pub segment: LineSegment<f64>,
+    /// Linear interpolation, as opposed to rapid positioning
+    pub is_cutting: bool,
+    /// The most recent full-line comment, which names the SVG element being drawn
+    pub source: Option<String>,
+}
+
+/// Reproduce the motions of a program.
+/// Rapid positioning is assumed to be travel and linear interpolation to be cutting.
+pub fn motions(tokens: &[Token<'_>]) -> Vec<Motion> {
+    let mut motions = vec![];
+    let mut is_relative = false;
+    let mut is_cutting = false;
+    let mut should_skip = false;
+    let mut source: Option<String> = None;
+    let mut current_position = point(0f64, 0f64);
+    let mut next_position = current_position;
+
+    let mut flush =
+        |from: &mut F64Point, to: F64Point, is_cutting: bool, source: &Option<String>| {
+            if *from != to {
+                motions.push(Motion {
+                    segment: LineSegment { from: *from, to },
+                    is_cutting,
+                    source: source.clone(),
+                });
+            }
+            *from = to;
+        };
+
+    for token in tokens {
+        match token {
+            Token::Field(Field { letters, .. }) if *letters == "G" || *letters == "M" => {
+                flush(&mut current_position, next_position, is_cutting, &source);
+                should_skip = *letters == "M";
+                match token {
+                    abs if *abs == Token::Field(ABSOLUTE_DISTANCE_MODE_FIELD) => {
+                        is_relative = false
+                    }
+                    rel if *rel == Token::Field(RELATIVE_DISTANCE_MODE_FIELD) => is_relative = true,
+                    rapid if *rapid == Token::Field(RAPID_POSITIONING_FIELD) => is_cutting = false,
+                    linear if *linear == Token::Field(LINEAR_INTERPOLATION_FIELD) => {
+                        is_cutting = true
+                    }
+                    _ => {}
+                }
+            }
+            Token::Field(Field { letters, value }) if *letters == "X" && !should_skip => {
+                if let Some(value) = value.as_f64() {
+                    next_position.x = if is_relative {
+                        current_position.x + value
+                    } else {
+                        value
+                    };
+                }
+            }
+            Token::Field(Field { letters, value }) if *letters == "Y" && !should_skip => {
+                if let Some(value) = value.as_f64() {
+                    next_position.y = if is_relative {
+                        current_position.y + value
+                    } else {
+                        value
+                    };
+                }
+            }
+            Token::Comment {
+                is_inline: false,
+                inner,
+            } => {
+                flush(&mut current_position, next_position, is_cutting, &source);
+                source = Some(inner.to_string());
+            }
+            _ => {}
+        }
+    }
+    flush(&mut current_position, next_position, is_cutting, &source);
+
+    motions
+}
+
+/// A travel move passing over a part that was already cut free
+#[derive(Debug)]
+pub struct Snag {
+    pub travel: LineSegment<f64>,
+    /// What was being drawn when the part was cut free
+    pub part: Option<String>,
+}
+
+impl fmt::Display for Snag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Travel from ({:.3}, {:.3}) to ({:.3}, {:.3}) passes over a part already cut free by {}, consider cutting it later or adding tabs",
+            self.travel.from.x,
+            self.travel.from.y,
+            self.travel.to.x,
+            self.travel.to.y,
+            self.part.as_deref().unwrap_or("an unnamed path").trim()
+        )
+    }
+}
+
+/// Find travel moves that pass over closed cuts made earlier in the program.
+///
+/// A part that has been cut free can tip up and catch on the tool as it travels over it.
+/// This is usually avoided by cutting inner contours before outer ones, or by adding tabs.
+pub fn find_snags(motions: &[Motion]) -> Vec<Snag> {
+    let mut snags = vec![];
+    let mut closed_cuts: Vec<(Vec<F64Point>, Option<String>)> = vec![];
+    let mut cut: Vec<&Motion> = vec![];
+
+    for motion in motions {
+        if motion.is_cutting {
+            cut.push(motion);
+            continue;
+        }
+
+        if let (Some(first), Some(last)) = (cut.first(), cut.last()) {
+            if (first.segment.from - last.segment.to).length() < CLOSED_TOLERANCE {
+                closed_cuts.push((
+                    cut.iter().map(|m| m.segment.from).collect(),
+                    last.source.clone(),
+                ));
+            }
+        }
+        cut.clear();
+
+        // Leave out the endpoints, since travel commonly starts or ends on a cut edge
+        let travel = motion.segment.split_range(0.001..0.999);
+        for (polygon, part) in &closed_cuts {
+            let crosses_edge =
+                polygon
+                    .iter()
+                    .zip(polygon.iter().cycle().skip(1))
+                    .any(|(from, to)| {
+                        LineSegment {
+                            from: *from,
+                            to: *to,
+                        }
+                        .intersects(&travel)
+                    });
+            if crosses_edge || contains(polygon, motion.segment.sample(0.5)) {
+                snags.push(Snag {
+                    travel: motion.segment,
+                    part: part.clone(),
+                });
+                break;
+            }
+        }
+    }
+
+    snags
+}
+
+/// Totals describing the work done by a program
+#[derive(Debug, Clone, Copy)]
+pub struct Statistics {
+    /// Millimeters cut
+    pub cut_length: f64,
+    /// Millimeters traveled with the tool off
+    pub travel_length: f64,
+    /// Bounding box of the cuts, if there are any
+    pub bounds: Option<Box2D<f64>>,
+}
+
+impl Statistics {
+    /// Seconds spent moving at the given speeds in millimeters / minute, ignoring acceleration
+    /// and dwells
+    pub fn duration(&self, feedrate: f64, travel_feedrate: f64) -> f64 {
+        (self.cut_length / feedrate + self.travel_length / travel_feedrate) * 60.
+    }
+
+    /// Area of the bounding box of the cuts in square millimeters, an estimate of the material
+    /// used
+    pub fn area(&self) -> f64 {
+        self.bounds.map_or(0., |bounds| bounds.area())
+    }
+}
+
+/// Add up the lengths and extent of motions
+pub fn statistics(motions: &[Motion]) -> Statistics {
+    let mut statistics = Statistics {
+        cut_length: 0.,
+        travel_length: 0.,
+        bounds: None,
+    };
+    for motion in motions {
+        if motion.is_cutting {
+            statistics.cut_length += motion.segment.length();
+            let LineSegment { from, to } = motion.segment;
+            // Box2D::union skips boxes without area, like those of straight lines along an axis
+            statistics.bounds = Some(match statistics.bounds {
+                Some(bounds) => {
+                    Box2D::new(bounds.min.min(from.min(to)), bounds.max.max(from.max(to)))
+                }
+                None => Box2D::new(from.min(to), from.max(to)),
+            });
+        } else {
+            statistics.travel_length += motion.segment.length();
+        }
+    }
+    statistics
+}
+
+/// How a machine speeds up and slows down, like Grbl's planner does
+#[derive(Debug, Clone, Copy)]
+pub struct Acceleration {
+    /// Acceleration in millimeters / second²
+    pub acceleration: f64,
+    /// Distance in millimeters that the path may deviate from a corner while keeping speed
+    /// through it (Grbl's `$11`), which limits the speed at each corner
+    pub junction_deviation: f64,
+}
+
+/// Seconds spent moving at the given speeds in millimeters / minute, speeding up and slowing
+/// down at the machine's acceleration. The machine stops when it changes between cutting and
+/// travel, and slows down for corners by their angle, so that many tiny segments around a curve
+/// hardly slow it down while every corner of a zigzag does.
+pub fn estimate_duration(
+    motions: &[Motion],
+    feedrate: f64,
+    travel_feedrate: f64,
+    acceleration: Acceleration,
+) -> f64 {
+    let Acceleration {
+        acceleration,
+        junction_deviation,
+    } = acceleration;
+    let speed = |motion: &Motion| {
+        if motion.is_cutting {
+            feedrate / 60.
+        } else {
+            travel_feedrate / 60.
+        }
+    };
+    // Highest speed at the start of each motion, and at the end of the last
+    let mut junctions = vec![0.; motions.len() + 1];
+    for i in 1..motions.len() {
+        let (previous, next) = (&motions[i - 1], &motions[i]);
+        if previous.is_cutting != next.is_cutting {
+            continue;
+        }
+        let (incoming, outgoing) = (
+            previous.segment.to_vector().normalize(),
+            next.segment.to_vector().normalize(),
+        );
+        // Half the angle that the path turns by, from 0 for straight on to 90° for reversing
+        let sin_half_turn = ((1. - incoming.dot(outgoing)) / 2.).max(0.).sqrt();
+        let cos_half_turn = (1. - sin_half_turn * sin_half_turn).max(0.).sqrt();
+        let junction = if sin_half_turn <= f64::EPSILON {
+            f64::INFINITY
+        } else {
+            (acceleration * junction_deviation * cos_half_turn / (1. - cos_half_turn)).sqrt()
+        };
+        junctions[i] = junction.min(speed(previous)).min(speed(next));
+    }
+    // Limit corners to what can be reached from the corners around them
+    for i in 0..motions.len() {
+        let reachable =
+            (junctions[i].powi(2) + 2. * acceleration * motions[i].segment.length()).sqrt();
+        junctions[i + 1] = junctions[i + 1].min(reachable);
+    }
+    for i in (0..motions.len()).rev() {
+        let reachable =
+            (junctions[i + 1].powi(2) + 2. * acceleration * motions[i].segment.length()).sqrt();
+        junctions[i] = junctions[i].min(reachable);
+    }
+
+    motions
+        .iter()
+        .enumerate()
+        .map(|(i, motion)| {
+            let (entry, exit, cruise) = (junctions[i], junctions[i + 1], speed(motion));
+            let length = motion.segment.length();
+            let speeding_up = (cruise.powi(2) - entry.powi(2)) / (2. * acceleration);
+            let slowing_down = (cruise.powi(2) - exit.powi(2)) / (2. * acceleration);
+            if speeding_up + slowing_down <= length {
+                (cruise - entry) / acceleration
+                    + (cruise - exit) / acceleration
+                    + (length - speeding_up - slowing_down) / cruise
+            } else {
+                // Never reaches the cruising speed
+                let peak =
+                    ((2. * acceleration * length + entry.powi(2) + exit.powi(2)) / 2.).sqrt();
+                (peak - entry) / acceleration + (peak - exit) / acceleration
+            }
+        })
+        .sum()
+}
+
+/// Add up the motions of each element separately, by the comment naming the element, in the
+/// order that they are first drawn. Travel counts towards the element it travels to.
+pub fn statistics_by_source(motions: &[Motion]) -> Vec<(String, Statistics)> {
+    let mut sources: Vec<(String, Vec<Motion>)> = vec![];
+    for motion in motions {
+        let source = motion.source.as_deref().unwrap_or_default().trim();
+        match sources.iter_mut().find(|(name, _)| name == source) {
+            Some((_, motions)) => motions.push(motion.clone()),
+            None => sources.push((source.to_string(), vec![motion.clone()])),
+        }
+    }
+    sources
+        .into_iter()
+        .map(|(source, motions)| (source, statistics(&motions)))
+        .collect()
+}
+
+/// A point along a program, as reached when moving at a constant speed
+#[derive(Debug, Clone, Copy)]
+pub struct Sample {
+    pub position: F64Point,
+    /// Seconds since the program started moving
+    pub time: f64,
+    /// Whether the tool is cutting on the way to this point
+    pub is_cutting: bool,
+}
+
+/// Resample motions at a fixed distance apart in millimeters, timing them as if the machine moved
+/// at constant speeds in millimeters / minute, one for cutting and one for travel. Useful for
+/// devices that are driven by positions at regular intervals rather than by GCode, like
+/// galvanometer scanners.
+///
+/// The first and last points of the motions are always included.
+pub fn resample(
+    motions: &[Motion],
+    interval: f64,
+    cut_speed: f64,
+    travel_speed: f64,
+) -> Vec<Sample> {
+    let mut samples = vec![];
+    let first = match motions.first() {
+        Some(first) => first,
+        None => return samples,
+    };
+    samples.push(Sample {
+        position: first.segment.from,
+        time: 0.,
+        is_cutting: first.is_cutting,
+    });
+
+    let mut distance = 0.;
+    let mut time = 0.;
+    let mut next_sample = interval;
+    for motion in motions {
+        let length = motion.segment.length();
+        let seconds_per_mm = 60.
+            / if motion.is_cutting {
+                cut_speed
+            } else {
+                travel_speed
+            };
+        while next_sample <= distance + length {
+            samples.push(Sample {
+                position: motion.segment.sample((next_sample - distance) / length),
+                time: time + (next_sample - distance) * seconds_per_mm,
+                is_cutting: motion.is_cutting,
+            });
+            next_sample += interval;
+        }
+        distance += length;
+        time += length * seconds_per_mm;
+    }
+
+    let last = motions.last().unwrap();
+    if samples.last().map(|sample| sample.position) != Some(last.segment.to) {
+        samples.push(Sample {
+            position: last.segment.to,
+            time,
+            is_cutting: last.is_cutting,
+        });
+    }
+    samples
+}
+
+/// Even-odd rule point in polygon test
+fn contains(polygon: &[F64Point], p: F64Point) -> bool {
+    let mut inside = false;
+    for (from, to) in polygon.iter().zip(polygon.iter().cycle().skip(1)) {
+        if (from.y > p.y) != (to.y > p.y)
+            && p.x < (to.x - from.x) * (p.y - from.y) / (to.y - from.y) + from.x
+        {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn travel_over_cut_out_parts_snags() {
+        let moves = |points: &[(f64, f64, bool)]| {
+            points
+                .windows(2)
+                .map(|pair| Motion {
+                    segment: lyon_geom::LineSegment {
+                        from: lyon_geom::point(pair[0].0, pair[0].1),
+                        to: lyon_geom::point(pair[1].0, pair[1].1),
+                    },
+                    is_cutting: pair[1].2,
+                    source: Some("path#part".to_string()),
+                })
+                .collect::<Vec<_>>()
+        };
+        let square = [
+            (0., 0., false),
+            (10., 0., true),
+            (10., 10., true),
+            (0., 10., true),
+            (0., 0., true),
+        ];
+        let snags = |travel: &[(f64, f64, bool)]| {
+            let mut points = square.to_vec();
+            points.extend_from_slice(travel);
+            find_snags(&moves(&points))
+        };
+
+        // Across the square, and from its corner to a point inside it
+        let across = snags(&[(20., 20., false)]);
+        assert_eq!(across.len(), 1);
+        assert_eq!(across[0].part.as_deref(), Some("path#part"));
+        assert_eq!(snags(&[(5., 5., false)]).len(), 1);
+        // Away from the square
+        assert!(snags(&[(-10., -10., false)]).is_empty());
+        assert!(snags(&[(-10., 5., false)]).is_empty());
+        // An open cut doesn't free a part
+        let open = moves(&[
+            (0., 0., false),
+            (10., 0., true),
+            (10., 10., true),
+            (20., 20., false),
+            (0., 0., false),
+        ]);
+        assert!(find_snags(&open).is_empty());
+        // Nor does travel before the part is cut
+        let mut before = moves(&[(20., 20., false), (0., 0., false)]);
+        before.extend(moves(&square));
+        assert!(find_snags(&before).is_empty());
+    }
+
+    #[test]
+    fn samples_are_timed_at_the_speed_of_each_move() {
+        let motions = [(true, 10.), (false, 20.)]
+            .iter()
+            .map(|(is_cutting, x)| Motion {
+                segment: lyon_geom::LineSegment {
+                    from: lyon_geom::point(x - 10., 0.),
+                    to: lyon_geom::point(*x, 0.),
+                },
+                is_cutting: *is_cutting,
+                source: None,
+            })
+            .collect::<Vec<_>>();
+        // Cutting at 10mm/s and traveling at 20mm/s
+        let samples = resample(&motions, 5., 600., 1200.);
+        let actual = samples
+            .iter()
+            .map(|sample| (sample.position.x, sample.time, sample.is_cutting))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            actual,
+            [
+                (0., 0., true),
+                (5., 0.5, true),
+                (10., 1., true),
+                (15., 1.25, false),
+                (20., 1.5, false)
+            ]
+        );
+    }
+
+    #[test]
+    fn acceleration_slows_corners_but_not_straight_lines() {
+        let cut = |points: &[(f64, f64)]| {
+            points
+                .windows(2)
+                .map(|pair| Motion {
+                    segment: lyon_geom::LineSegment {
+                        from: lyon_geom::point(pair[0].0, pair[0].1),
+                        to: lyon_geom::point(pair[1].0, pair[1].1),
+                    },
+                    is_cutting: true,
+                    source: None,
+                })
+                .collect::<Vec<_>>()
+        };
+        let acceleration = Acceleration {
+            acceleration: 100.,
+            junction_deviation: 0.01,
+        };
+        let duration = |motions: Vec<_>| estimate_duration(&motions, 600., 600., acceleration);
+
+        let straight = duration(cut(&[(0., 0.), (100., 0.)]));
+        let segmented = duration(cut(&(0..=100).map(|x| (x as f64, 0.)).collect::<Vec<_>>()));
+        let zigzag = duration(cut(&(0..=100)
+            .map(|x| (x as f64, (x % 2) as f64))
+            .collect::<Vec<_>>()));
+        // 100mm at 10mm/s, speeding up and slowing down for 0.1s each at the ends
+        assert!((straight - 10.1).abs() < 1e-9, "{}", straight);
+        assert!((segmented - straight).abs() < 1e-9);
+        assert!(zigzag > straight * 1.5);
+    }
+}
@@ -1,35 +1,482 @@
 use std::borrow::Cow;
+use std::fmt;
+use std::io;
 use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 use g_code::{command, emit::Token};
 use lyon_geom::{
-    euclid::{default::Transform2D, Angle, Transform3D},
-    vector,
+    euclid::{
+        default::{Box2D, Transform2D},
+        Angle, Transform3D,
+    },
+    point, vector, Point, Vector,
 };
 use roxmltree::{Document, Node};
 use svgtypes::{
-    LengthListParser, PathParser, PathSegment, TransformListParser, TransformListToken, ViewBox,
+    Align, AspectRatio, Color, Length, LengthListParser, LengthUnit, PathParser, PathSegment,
+    TransformListParser, TransformListToken, ViewBox,
 };
 
+use crate::analysis::{find_snags, motions, statistics, statistics_by_source, Statistics};
+use crate::machine::Machine;
+use crate::postprocess::round_coordinates;
+use crate::raster::{self, RasterOptions};
+use crate::stroke::{dash, outline_strokes, LineCap, LineJoin, Stroke};
+use crate::style::StyleSheet;
 use crate::turtle::*;
 
 /// High-level output options
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ProgramOptions {
     /// Curve interpolation tolerance in millimeters
     pub tolerance: f64,
+    /// Elliptical arc interpolation tolerance in millimeters, falls back to [ProgramOptions::tolerance] if not set
+    pub arc_tolerance: Option<f64>,
     /// Feedrate in millimeters / minute
     pub feedrate: f64,
     /// Dots per inch for pixels, picas, points, etc.
     pub dpi: f64,
+    /// Width and height to use for the root SVG element instead of its own.
+    /// If the SVG is sized in pixels, the DPI is inferred from these.
+    pub dimensions: Option<[Length; 2]>,
+    /// How an SVG without a physical size is sized, when [ProgramOptions::dimensions] aren't set
+    pub dimensionless: DimensionlessPolicy,
+    /// Emit `;PROGRESS n/m` comments between paths, for senders that show job progress
+    pub progress_comments: bool,
+    /// Emit machine-readable comments like `;LAYER: cut` before the paths of each top-level
+    /// element and `;PASS: 2/3` before each pass of an operation, for senders that show them
+    pub metadata_comments: bool,
+    /// Cut along the outline of stroked paths instead of their center, taking the stroke width,
+    /// line caps and line joins into account
+    pub outline_strokes: bool,
+    /// Tool power or spindle speed (S) set when turning the tool on
+    pub power: Option<f64>,
+    /// Power for paths stroked in a color, instead of [ProgramOptions::power]
+    pub color_power: Vec<(Color, f64)>,
+    /// Kinds of work done on the elements they select, in order
+    pub operations: Vec<Operation>,
+    /// Tool changed to for elements that aren't cut by an operation with its own tool
+    pub tool: Option<u32>,
+    /// Pause between the paths of different layers or colors
+    pub pause_between: Option<PauseGrouping>,
+    /// Round coordinates to this many decimal places as each path is generated, so that
+    /// differences in the last bits of floating point math between builds and platforms don't
+    /// reach the program or anything computed from it
+    pub precision: Option<u32>,
+    /// How much of the SVG is described in comments
+    pub comments: CommentLevel,
+    /// Engrave image elements line by line, else they are skipped
+    pub raster: Option<RasterOptions>,
+    /// What is done with masked elements, since masks can't be cut
+    pub masks: MaskHandling,
+    /// What is done with path segments that have no length, or arcs with a zero radius
+    pub degenerate: DegenerateHandling,
 }
 
 impl Default for ProgramOptions {
     fn default() -> Self {
         Self {
             tolerance: 0.002,
+            arc_tolerance: None,
             feedrate: 300.0,
             dpi: 96.0,
+            dimensions: None,
+            dimensionless: DimensionlessPolicy::Px,
+            progress_comments: false,
+            metadata_comments: false,
+            outline_strokes: false,
+            power: None,
+            color_power: vec![],
+            operations: vec![],
+            tool: None,
+            pause_between: None,
+            precision: None,
+            comments: CommentLevel::Paths,
+            raster: None,
+            masks: MaskHandling::Ignore,
+            degenerate: DegenerateHandling::Skip,
+        }
+    }
+}
+
+/// How the user units of an SVG without a physical size are interpreted. An SVG has no physical
+/// size if its root element has no width and height, or a width and height without units.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DimensionlessPolicy {
+    /// User units are pixels at [ProgramOptions::dpi], as browsers draw them
+    Px,
+    /// User units are millimeters
+    Mm,
+    /// Refuse to convert the SVG, since a wrong guess makes a program of the wrong size
+    Error,
+}
+
+impl FromStr for DimensionlessPolicy {
+    type Err = String;
+
+    /// Parses `px`, `mm` or `error`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "px" => Ok(Self::Px),
+            "mm" => Ok(Self::Mm),
+            "error" => Ok(Self::Error),
+            other => Err(format!(
+                "unknown policy for dimensionless SVGs {}, expected one of px, mm or error",
+                other
+            )),
+        }
+    }
+}
+
+/// What is done with elements that have a mask, which can only partly hide them in a way that a
+/// tool can't follow
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MaskHandling {
+    /// Draw masked elements whole, as if they had no mask
+    Ignore,
+    /// Leave masked elements out
+    Skip,
+}
+
+impl FromStr for MaskHandling {
+    type Err = String;
+
+    /// Parses `ignore` or `skip`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ignore" => Ok(Self::Ignore),
+            "skip" => Ok(Self::Skip),
+            other => Err(format!(
+                "unknown mask handling {}, expected ignore or skip",
+                other
+            )),
+        }
+    }
+}
+
+/// Which changes between paths the program pauses at, i.e. so that the operator can swap pens or
+/// material
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PauseGrouping {
+    /// Pause when the top-level element (layer) changes
+    Layers,
+    /// Pause when the stroke color changes
+    Colors,
+}
+
+impl FromStr for PauseGrouping {
+    type Err = String;
+
+    /// Parses `layers` or `colors`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "layers" => Ok(Self::Layers),
+            "colors" => Ok(Self::Colors),
+            other => Err(format!(
+                "unknown pause grouping {}, expected layers or colors",
+                other
+            )),
+        }
+    }
+}
+
+/// What is done with degenerate path segments: lines and curves with no length, arcs that end
+/// where they start, and arcs with a zero radius. Arcs with a zero radius that go somewhere are
+/// still drawn as the straight lines that SVG makes of them, unless they are an error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DegenerateHandling {
+    /// Leave them out silently
+    Skip,
+    /// Leave them out with a warning naming the element and segment
+    Warn,
+    /// Refuse to convert the SVG, for drawings that are expected to be clean
+    Error,
+}
+
+impl FromStr for DegenerateHandling {
+    type Err = String;
+
+    /// Parses `skip`, `warn` or `error`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "skip" => Ok(Self::Skip),
+            "warn" => Ok(Self::Warn),
+            "error" => Ok(Self::Error),
+            other => Err(format!(
+                "unknown degenerate geometry handling {}, expected one of skip, warn or error",
+                other
+            )),
+        }
+    }
+}
+
+/// How much of the SVG is described in comments of the program, since some controllers choke on
+/// comments and they can make up much of a large program
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum CommentLevel {
+    /// No comments at all, which are removed once the program is otherwise done
+    None,
+    /// A comment naming each element before its path, i.e. `svg > g#layer1 > path#star`
+    Paths,
+    /// Also a comment with each command of a path, i.e. `L 10,20`
+    Segments,
+    /// Also a comment with the transform, feedrate and power of each path
+    Debug,
+}
+
+impl FromStr for CommentLevel {
+    type Err = String;
+
+    /// Parses `none`, `paths`, `segments` or `debug`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Self::None),
+            "paths" => Ok(Self::Paths),
+            "segments" => Ok(Self::Segments),
+            "debug" => Ok(Self::Debug),
+            other => Err(format!(
+                "unknown comment level {}, expected one of none, paths, segments or debug",
+                other
+            )),
+        }
+    }
+}
+
+/// A kind of work, like engraving, scoring or cutting, done on the elements it selects with its
+/// own settings
+#[derive(Debug, Clone)]
+pub struct Operation {
+    pub name: String,
+    pub selector: ElementSelector,
+    /// Feedrate in millimeters / minute, else [ProgramOptions::feedrate]
+    pub feedrate: Option<f64>,
+    /// Tool power (S), else the power for the element's color or [ProgramOptions::power]
+    pub power: Option<f64>,
+    /// Number of times the elements are cut
+    pub passes: usize,
+    /// Tool changed to before the operation, else [ProgramOptions::tool]
+    pub tool: Option<u32>,
+}
+
+/// Which elements an [Operation] applies to
+#[derive(Debug, Clone)]
+pub enum ElementSelector {
+    /// Elements stroked in a color
+    Color(Color),
+    /// Elements in a top-level group with this id or Inkscape layer label
+    Layer(String),
+    /// Elements that have, or are in an element that has, an attribute, optionally with a value
+    Attribute { name: String, value: Option<String> },
+}
+
+impl ElementSelector {
+    fn matches(&self, node: &Node, style: &StyleSheet) -> bool {
+        match self {
+            Self::Color(color) => style
+                .property(node, "stroke")
+                .and_then(|stroke| stroke.parse::<Color>().ok())
+                .is_some_and(|stroke| stroke == *color),
+            Self::Layer(layer) => node
+                .ancestors()
+                .filter(|ancestor| ancestor.is_element())
+                .take_while(|ancestor| ancestor.parent_element().is_some())
+                .last()
+                .is_some_and(|top_level| {
+                    top_level.attribute("id") == Some(layer)
+                        || top_level.attribute((INKSCAPE_NAMESPACE, "label")) == Some(layer)
+                }),
+            Self::Attribute { name, value } => node.ancestors().any(|ancestor| {
+                ancestor
+                    .attribute(name.as_str())
+                    .is_some_and(|found| value.as_ref().is_none_or(|value| found == value.as_str()))
+            }),
+        }
+    }
+}
+
+impl FromStr for Operation {
+    type Err = String;
+
+    /// Parse an operation like `cut,color=red,feedrate=100,power=1000,passes=2,tool=2`, which has a
+    /// name, a selector that is one of `color=COLOR`, `layer=ID` or `attribute=NAME[=VALUE]`, and
+    /// optional settings
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(',');
+        let name = parts.next().unwrap_or_default().trim().to_string();
+        let (kind, argument) = parts
+            .next()
+            .and_then(|selector| selector.split_once('='))
+            .ok_or_else(|| format!("operation {} needs a selector like color=red", name))?;
+        let selector = match kind.trim() {
+            "color" => ElementSelector::Color(
+                argument
+                    .parse()
+                    .map_err(|err| format!("could not parse color {}: {}", argument, err))?,
+            ),
+            "layer" => ElementSelector::Layer(argument.to_string()),
+            "attribute" => match argument.split_once('=') {
+                Some((name, value)) => ElementSelector::Attribute {
+                    name: name.to_string(),
+                    value: Some(value.to_string()),
+                },
+                None => ElementSelector::Attribute {
+                    name: argument.to_string(),
+                    value: None,
+                },
+            },
+            other => {
+                return Err(format!(
+                    "unknown selector {}, expected color, layer or attribute",
+                    other
+                ))
+            }
+        };
+
+        let mut operation = Self {
+            name,
+            selector,
+            feedrate: None,
+            power: None,
+            passes: 1,
+            tool: None,
+        };
+        for setting in parts {
+            let (key, value) = setting
+                .split_once('=')
+                .ok_or_else(|| format!("setting {} needs a value", setting))?;
+            let number = || {
+                value
+                    .trim()
+                    .parse::<f64>()
+                    .map_err(|err| format!("could not parse {}: {}", key, err))
+            };
+            match key.trim() {
+                "feedrate" => operation.feedrate = Some(number()?),
+                "power" => operation.power = Some(number()?),
+                "passes" => {
+                    operation.passes = value
+                        .trim()
+                        .parse()
+                        .map_err(|err| format!("could not parse passes: {}", err))?
+                }
+                "tool" => {
+                    operation.tool = Some(
+                        value
+                            .trim()
+                            .parse()
+                            .map_err(|err| format!("could not parse tool: {}", err))?,
+                    )
+                }
+                other => {
+                    return Err(format!(
+                        "unknown setting {}, expected feedrate, power, passes or tool",
+                        other
+                    ))
+                }
+            }
+        }
+        Ok(operation)
+    }
+}
+
+const INKSCAPE_NAMESPACE: &str = "http://www.inkscape.org/namespaces/inkscape";
+
+/// A problem with an SVG that stops it from being converted
+#[derive(Debug)]
+pub enum ConversionError {
+    /// An attribute could not be parsed, i.e. a malformed viewBox, transform, or path data
+    InvalidAttribute {
+        element: String,
+        attribute: &'static str,
+        error: svgtypes::Error,
+    },
+    /// A transform collapses the element's contents, i.e. `scale(0)`, so they can't be drawn
+    SingularTransform { element: String },
+    /// The root element is sized relative to a viewport, which doesn't exist for a machine
+    RelativeDimensions {
+        attribute: &'static str,
+        length: Length,
+    },
+    /// The SVG has no physical size and [DimensionlessPolicy::Error] refuses to guess it
+    Dimensionless,
+    /// A path segment is degenerate and [DegenerateHandling::Error] refuses it
+    DegenerateGeometry {
+        element: String,
+        segment: String,
+        problem: &'static str,
+    },
+    /// A length is a percentage of a viewport whose size isn't known
+    RelativeLength {
+        element: String,
+        attribute: &'static str,
+        length: Length,
+    },
+    /// A length has no unit, or a relative one that can't be resolved, so it has no physical size
+    UnsupportedLength { length: Length },
+    /// The conversion was cancelled before it finished
+    Cancelled,
+    /// The program could not be written out while it was converted
+    Output(io::Error),
+}
+
+impl fmt::Display for ConversionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidAttribute {
+                element,
+                attribute,
+                error,
+            } => write!(f, "could not parse {} of {}: {}", attribute, element, error),
+            Self::SingularTransform { element } => {
+                write!(f, "the transform of {} cannot be inverted", element)
+            }
+            Self::RelativeDimensions { attribute, length } => write!(
+                f,
+                "the SVG has a {} of {}%, which has no physical size. Set its size with --dimensions (i.e. --dimensions 210mm,297mm) or give it an absolute width and height like 210mm.",
+                attribute, length.num
+            ),
+            Self::Dimensionless => write!(
+                f,
+                "the SVG has no physical size, since it is missing a width and height with units. Set its size with --dimensions (i.e. --dimensions 210mm,297mm) or choose how its user units are read with --dimensionless px or --dimensionless mm."
+            ),
+            Self::DegenerateGeometry {
+                element,
+                segment,
+                problem,
+            } => write!(
+                f,
+                "the segment {} of {} is {}. Leave out degenerate geometry with --degenerate skip or --degenerate warn.",
+                segment, element, problem
+            ),
+            Self::RelativeLength {
+                element,
+                attribute,
+                length,
+            } => write!(
+                f,
+                "the {} of {} is {}%, but it is not in a viewport with a known size to be a percentage of",
+                attribute, element, length.num
+            ),
+            Self::UnsupportedLength { length } => write!(
+                f,
+                "the length {} has no physical size. Give it an absolute unit like mm, cm, in, pt, pc or px.",
+                length
+            ),
+            Self::Cancelled => write!(f, "the conversion was cancelled"),
+            Self::Output(error) => write!(f, "could not write the program: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for ConversionError {}
+
+impl ConversionError {
+    fn invalid_attribute(node: &Node, attribute: &'static str, error: svgtypes::Error) -> Self {
+        Self::InvalidAttribute {
+            element: node_name(node),
+            attribute,
+            error,
         }
     }
 }
@@ -37,35 +484,550 @@ impl Default for ProgramOptions {
 pub fn svg2program<'input>(
     doc: &Document,
     options: ProgramOptions,
-    turtle: &'input mut Turtle<'input>,
-) -> Vec<Token<'input>> {
+    turtle: &mut Turtle<'input>,
+) -> Result<Vec<Token<'input>>, ConversionError> {
+    svg2program_with_progress(doc, options, turtle, None, None)
+}
+
+/// Like [svg2program], calling back with the number of paths converted so far and the total
+/// after each path, i.e. to show a progress bar for very large SVGs.
+///
+/// The conversion stops with [ConversionError::Cancelled] at the next element once `cancel` is
+/// set, so that another thread can abort it.
+pub fn svg2program_with_progress<'input>(
+    doc: &Document,
+    options: ProgramOptions,
+    turtle: &mut Turtle<'input>,
+    progress: Option<&mut dyn FnMut(usize, usize)>,
+    cancel: Option<&AtomicBool>,
+) -> Result<Vec<Token<'input>>, ConversionError> {
+    let mut program = vec![];
+    svg2program_streaming(doc, options, turtle, progress, cancel, &mut |chunk| {
+        program.extend(chunk);
+        Ok(())
+    })?;
+    Ok(program)
+}
+
+/// Like [svg2program_with_progress], passing the program to `output` in chunks as each path is
+/// converted instead of returning all of it, so that a very large program can be written out
+/// without holding it in memory.
+///
+/// The setup before the first path and the teardown after the last one are passed as chunks of
+/// their own, first and last.
+pub fn svg2program_streaming<'input>(
+    doc: &Document,
+    options: ProgramOptions,
+    turtle: &mut Turtle<'input>,
+    mut progress: Option<&mut dyn FnMut(usize, usize)>,
+    cancel: Option<&AtomicBool>,
+    output: &mut dyn FnMut(Vec<Token<'input>>) -> io::Result<()>,
+) -> Result<(), ConversionError> {
+    check_relative_dimensions(&options, &doc.root_element())?;
+    check_dimensionless(&options, &doc.root_element())?;
+    let options = match implied_dpi(&options, &doc.root_element())? {
+        Some(dpi) => {
+            info!("Dimensions imply a DPI of {}", dpi);
+            ProgramOptions { dpi, ..options }
+        }
+        None => options,
+    };
+
     let mut program = command!(UnitsMillimeters {})
         .into_token_vec()
         .drain(..)
         .collect::<Vec<_>>();
+    turtle
+        .machine
+        .set_variable("feedrate", options.feedrate.to_string());
+    turtle.machine.set_variable("layer_name", String::new());
+    program.extend(turtle.machine.absolute());
+    program.extend(turtle.machine.path_blending(options.tolerance));
+    program.extend(turtle.machine.program_begin());
+    program.extend(turtle.machine.absolute());
+    output(std::mem::take(&mut program)).map_err(ConversionError::Output)?;
+
+    let style = StyleSheet::new(doc);
+    let visits = drawn_elements(doc, &style, options.masks);
+    if !options.operations.is_empty() {
+        summarize_operations(&visits, &style, &options);
+    }
+    let total_paths = if options.progress_comments || progress.is_some() {
+        count_paths(&visits, &style, &options)
+    } else {
+        0
+    };
+    let mut paths_converted = 0;
+
+    // Operations are done in order, each for all of its passes, and elements that no operation
+    // selects are done last with the global settings
+    let mut passes = vec![];
+    for (i, operation) in options.operations.iter().enumerate() {
+        passes.extend((1..=operation.passes).map(|pass| Some((i, pass))));
+    }
+    passes.push(None);
+
+    // Layer or color of the last path, which pauses carry on across passes
+    let mut previous_group = None;
+    for current in passes {
+        let operation = current.map(|(i, _)| &options.operations[i]);
+        if let (Some(operation), Some((_, pass))) = (operation, current) {
+            program.push(Token::Comment {
+                is_inline: false,
+                inner: Cow::Owned(format!(
+                    "Operation {}, pass {} of {}",
+                    operation.name, pass, operation.passes
+                )),
+            });
+            if options.metadata_comments {
+                program.push(metadata_comment(format!(
+                    "PASS: {}/{}",
+                    pass, operation.passes
+                )));
+            }
+        }
+        let tool = operation
+            .and_then(|operation| operation.tool)
+            .or(options.tool);
+        if let Some(tool) = tool {
+            program.extend(turtle.machine.tool_change(tool));
+        }
+        let mut previous_layer = None;
+
+        // Whether each element being drawn pushed a transform that must be popped after its children
+        let mut transform_stack = vec![];
+        let mut name_stack: Vec<String> = vec![];
+
+        for visit in &visits {
+            if cancel.is_some_and(|cancel| cancel.load(Ordering::Relaxed)) {
+                return Err(ConversionError::Cancelled);
+            }
+            let (node, parent) = match *visit {
+                Visit::Enter { node, parent } => (node, parent),
+                Visit::Leave => {
+                    if transform_stack.pop() == Some(true) {
+                        turtle.pop_transform();
+                    }
+                    name_stack.pop();
+                    continue;
+                }
+            };
+
+            let is_used = parent.tag_name().name() == "use";
+            let transform = node_transform(
+                &options,
+                &style,
+                &node,
+                node == doc.root_element(),
+                Some(parent).filter(|_| is_used),
+            )?;
+            if let Some(transform) = transform {
+                turtle.push_transform(transform);
+            }
+
+            let is_in_pass = |node: &Node| {
+                options
+                    .operations
+                    .iter()
+                    .position(|operation| operation.selector.matches(node, &style))
+                    == current.map(|(i, _)| i)
+            };
+            // Pause before a section for the operator, i.e. to insert dowels before cutting a lid
+            if let Some(message) = node.attribute("data-pause") {
+                let has_paths_in_pass = node.descendants().any(|descendant| {
+                    descendant.tag_name().name() == "path" && is_in_pass(&descendant)
+                });
+                if has_paths_in_pass {
+                    program.extend(turtle.machine.tool_off());
+                    program.extend(turtle.machine.pause(message));
+                }
+            }
+            if node.tag_name().name() == "path" && !style.is_invisible(&node) && is_in_pass(&node) {
+                if let Some(pattern) = paint_server(&node, &style, "fill")
+                    .filter(|server| server.tag_name().name() == "pattern")
+                {
+                    warn!(
+                        "Only the outline of {} is drawn, its fill {} is not supported",
+                        node_name(&node),
+                        node_name(&pattern)
+                    );
+                }
+                if let Some(d) = node.attribute("d") {
+                    turtle.reset();
+                    let layer = node
+                        .ancestors()
+                        .filter(|ancestor| ancestor.is_element())
+                        .take_while(|ancestor| *ancestor != doc.root_element())
+                        .last()
+                        .map(|layer| {
+                            layer
+                                .attribute("id")
+                                .map(str::to_string)
+                                .unwrap_or_else(|| node_name(&layer))
+                        })
+                        .unwrap_or_default();
+                    if let Some(grouping) = options.pause_between {
+                        let group = match grouping {
+                            PauseGrouping::Layers => layer.clone(),
+                            PauseGrouping::Colors => stroke_color(&node, &style),
+                        };
+                        if previous_group
+                            .as_ref()
+                            .is_some_and(|previous| *previous != group)
+                        {
+                            let message = match grouping {
+                                PauseGrouping::Layers => format!("Next layer: {}", group),
+                                PauseGrouping::Colors => format!("Next color: {}", group),
+                            };
+                            program.extend(turtle.machine.tool_off());
+                            program.extend(turtle.machine.pause(&message));
+                        }
+                        previous_group = Some(group);
+                    }
+                    if options.metadata_comments
+                        && !layer.is_empty()
+                        && previous_layer.as_ref() != Some(&layer)
+                    {
+                        program.push(metadata_comment(format!("LAYER: {}", layer)));
+                        previous_layer = Some(layer.clone());
+                    }
+                    let mut comment = String::new();
+                    name_stack.iter().for_each(|name| {
+                        comment += name;
+                        comment += " > ";
+                    });
+                    comment += &node_name(&node);
+                    program.push(Token::Comment {
+                        is_inline: false,
+                        inner: Cow::Owned(comment),
+                    });
+                    if options.progress_comments {
+                        program.push(progress_comment(paths_converted, total_paths));
+                    }
+                    let path_options = ProgramOptions {
+                        feedrate: operation
+                            .and_then(|operation| operation.feedrate)
+                            .unwrap_or(options.feedrate)
+                            * feed_scale(&node)?,
+                        ..options.clone()
+                    };
+                    turtle
+                        .machine
+                        .set_variable("feedrate", path_options.feedrate.to_string());
+                    turtle.machine.set_power(
+                        operation
+                            .and_then(|operation| operation.power)
+                            .or_else(|| node_power(&node, &style, &options)),
+                    );
+                    turtle.machine.set_variable("layer_name", layer);
+                    if options.comments >= CommentLevel::Debug {
+                        program.push(debug_comment(turtle, &path_options));
+                    }
+                    let mut path = apply_path(turtle, &path_options, &node, d)?;
+                    if let Some((pattern, offset)) = node_dashes(&node, &style, turtle, &options)? {
+                        path = dash(
+                            path,
+                            &pattern,
+                            offset,
+                            &turtle.machine.tool_on_sequence(),
+                            &turtle.machine.tool_off_sequence(),
+                        );
+                    }
+                    if options.outline_strokes {
+                        if let Some(stroke) = node_stroke(&node, &style, turtle, &options)? {
+                            path = outline_strokes(
+                                path,
+                                &stroke,
+                                options.tolerance,
+                                &turtle.machine.tool_on_sequence(),
+                                &turtle.machine.tool_off_sequence(),
+                            );
+                        }
+                    }
+                    if let Some(precision) = options.precision {
+                        round_coordinates(&mut path, precision);
+                    }
+                    program.extend(path);
+                    output(std::mem::take(&mut program)).map_err(ConversionError::Output)?;
+                    paths_converted += 1;
+                    if let Some(progress) = &mut progress {
+                        progress(paths_converted, total_paths);
+                    }
+                } else {
+                    warn!("There is a path node containing no actual path: {:?}", node);
+                }
+            }
+
+            if node.tag_name().name() == "image" && !style.is_invisible(&node) && is_in_pass(&node)
+            {
+                match &options.raster {
+                    Some(raster_options) => {
+                        let href = node
+                            .attribute("href")
+                            .or_else(|| node.attribute(("http://www.w3.org/1999/xlink", "href")));
+                        let bitmap =
+                            href.map(|href| raster::load(href, raster_options.base_dir.as_deref()));
+                        match bitmap {
+                            Some(Ok(bitmap)) => {
+                                turtle.reset();
+                                let mut comment = String::new();
+                                name_stack.iter().for_each(|name| {
+                                    comment += name;
+                                    comment += " > ";
+                                });
+                                comment += &node_name(&node);
+                                program.push(Token::Comment {
+                                    is_inline: false,
+                                    inner: Cow::Owned(comment),
+                                });
+                                if options.progress_comments {
+                                    program.push(progress_comment(paths_converted, total_paths));
+                                }
+                                let [x, y, width, height] = [
+                                    ("x", Axis::X),
+                                    ("y", Axis::Y),
+                                    ("width", Axis::X),
+                                    ("height", Axis::Y),
+                                ]
+                                .map(|(name, axis)| {
+                                    user_units_attribute(&options, &style, &node, name, axis)
+                                });
+                                let min = point(x?.unwrap_or(0.), y?.unwrap_or(0.));
+                                let viewport = Box2D::new(
+                                    min,
+                                    min + vector(
+                                        width?.unwrap_or(bitmap.width as f64),
+                                        height?.unwrap_or(bitmap.height as f64),
+                                    ),
+                                );
+                                let aspect = node
+                                    .attribute("preserveAspectRatio")
+                                    .map(AspectRatio::from_str)
+                                    .transpose()
+                                    .map_err(|err| {
+                                        ConversionError::invalid_attribute(
+                                            &node,
+                                            "preserveAspectRatio",
+                                            err,
+                                        )
+                                    })?
+                                    .unwrap_or_default();
+                                let rect = aspect_ratio_rect(
+                                    aspect,
+                                    viewport,
+                                    [bitmap.width as f64, bitmap.height as f64],
+                                );
+                                let clip = rect.intersection(&viewport).unwrap_or(rect);
+                                let feedrate = operation
+                                    .and_then(|operation| operation.feedrate)
+                                    .unwrap_or(options.feedrate)
+                                    * feed_scale(&node)?;
+                                turtle.machine.set_power(
+                                    operation
+                                        .and_then(|operation| operation.power)
+                                        .or(options.power),
+                                );
+                                let mut path = raster::engrave(
+                                    &bitmap,
+                                    rect,
+                                    clip,
+                                    turtle,
+                                    raster_options,
+                                    feedrate,
+                                );
+                                if let Some(precision) = options.precision {
+                                    round_coordinates(&mut path, precision);
+                                }
+                                program.extend(path);
+                                output(std::mem::take(&mut program))
+                                    .map_err(ConversionError::Output)?;
+                            }
+                            Some(Err(err)) => {
+                                warn!("Skipping {}: {}", node_name(&node), err)
+                            }
+                            None => warn!("Skipping {}, it has no href", node_name(&node)),
+                        }
+                        // Counted whether or not it could be engraved, like the total
+                        paths_converted += 1;
+                        if let Some(progress) = &mut progress {
+                            progress(paths_converted, total_paths);
+                        }
+                    }
+                    None => warn!(
+                        "Skipping {}, images are only engraved with --raster",
+                        node_name(&node)
+                    ),
+                }
+            }
+
+            transform_stack.push(transform.is_some());
+            name_stack.push(node_name(&node));
+        }
+    }
+
+    // Critical step for actually moving the machine back to the origin, just in case SVG is malformed
+    turtle.pop_all_transforms();
+    output(std::mem::take(&mut program)).map_err(ConversionError::Output)?;
+    program.extend(turtle.machine.tool_off());
+    if options.progress_comments {
+        program.push(progress_comment(paths_converted, total_paths));
+    }
+    program.extend(turtle.machine.absolute());
+    program.extend(turtle.machine.program_end());
+    program.extend(turtle.machine.program_stop());
+
+    output(program).map_err(ConversionError::Output)
+}
+
+/// A program along with what a job report needs to know about it
+#[derive(Debug)]
+pub struct ConversionReport<'input> {
+    pub program: Vec<Token<'input>>,
+    /// Totals of the whole program, including the bounding box of its cuts
+    pub statistics: Statistics,
+    /// Totals of each element drawn, named like its comment in the program
+    pub elements: Vec<(String, Statistics)>,
+    /// Problems found with the machine settings and the program, like travel over parts that
+    /// were already cut free
+    pub diagnostics: Vec<String>,
+}
+
+/// Like [svg2program], also analyzing the program so that front-ends can show a job report
+/// without interpreting the program again
+pub fn convert_with_metadata<'input>(
+    doc: &Document,
+    options: ProgramOptions,
+    turtle: &mut Turtle<'input>,
+) -> Result<ConversionReport<'input>, ConversionError> {
+    let program = svg2program(doc, options, turtle)?;
+    let motions = motions(&program);
+    let diagnostics = turtle
+        .machine
+        .configuration_warnings()
+        .into_iter()
+        .map(str::to_string)
+        .chain(find_snags(&motions).iter().map(ToString::to_string))
+        .collect();
+    Ok(ConversionReport {
+        statistics: statistics(&motions),
+        elements: statistics_by_source(&motions),
+        diagnostics,
+        program,
+    })
+}
+
+/// Convert paths that don't come from an SVG, i.e. the outlines of a font engine, so that other
+/// programs with vector geometry can reuse the GCode backend. Coordinates are in millimeters with
+/// the Y axis pointing up, and each path is named in a comment like the elements of an SVG.
+pub fn paths2program<'input>(
+    paths: impl IntoIterator<Item = (String, Vec<PathSegment>)>,
+    options: &ProgramOptions,
+    turtle: &mut Turtle<'input>,
+) -> Result<Vec<Token<'input>>, ConversionError> {
+    let mut program = command!(UnitsMillimeters {}).into_token_vec();
+    turtle
+        .machine
+        .set_variable("feedrate", options.feedrate.to_string());
+    turtle.machine.set_variable("layer_name", String::new());
+    turtle.machine.set_power(options.power);
     program.extend(turtle.machine.absolute());
+    program.extend(turtle.machine.path_blending(options.tolerance));
     program.extend(turtle.machine.program_begin());
     program.extend(turtle.machine.absolute());
 
-    // Depth-first SVG DOM traversal
-    let mut node_stack = vec![(doc.root(), doc.root().children())];
-    let mut name_stack: Vec<String> = vec![];
+    for (name, segments) in paths {
+        turtle.reset();
+        program.push(Token::Comment {
+            is_inline: false,
+            inner: Cow::Owned(name.clone()),
+        });
+        let mut path = apply_segments(turtle, options, &name, segments)?;
+        if let Some(precision) = options.precision {
+            round_coordinates(&mut path, precision);
+        }
+        program.extend(path);
+    }
+
+    program.extend(turtle.machine.tool_off());
+    program.extend(turtle.machine.absolute());
+    program.extend(turtle.machine.program_end());
+    program.extend(turtle.machine.program_stop());
+    Ok(program)
+}
+
+/// Points of a path flattened into lines, split wherever the tool travels
+pub type Polylines = Vec<Vec<Point<f64>>>;
+
+/// The flattened polylines of each path in an SVG, in millimeters after all transforms, named
+/// like the path comments of a program.
+///
+/// This lets tests and tooling check geometry without depending on GCode formatting or machine
+/// sequences.
+pub fn svg2polylines(
+    doc: &Document,
+    options: ProgramOptions,
+) -> Result<Vec<(String, Polylines)>, ConversionError> {
+    let options = ProgramOptions {
+        progress_comments: false,
+        metadata_comments: false,
+        ..options
+    };
+    let mut turtle = Turtle::new(Machine::new(None, None, None, None));
+    let program = svg2program(doc, options, &mut turtle)?;
+
+    let mut paths: Vec<(String, Polylines)> = vec![];
+    for motion in motions(&program).iter().filter(|m| m.is_cutting) {
+        let name = match &motion.source {
+            Some(name) => name,
+            None => continue,
+        };
+        if paths.last().map(|(last, _)| last) != Some(name) {
+            paths.push((name.clone(), vec![]));
+        }
+        let polylines = &mut paths.last_mut().unwrap().1;
+        match polylines.last_mut() {
+            Some(polyline) if polyline.last() == Some(&motion.segment.from) => {
+                polyline.push(motion.segment.to)
+            }
+            _ => polylines.push(vec![motion.segment.from, motion.segment.to]),
+        }
+    }
+    Ok(paths)
+}
+
+/// A step of the depth-first traversal of the elements that are drawn
+enum Visit<'a, 'input> {
+    /// Draw an element, before its children. The parent of an element drawn by a use is the use.
+    Enter {
+        node: Node<'a, 'input>,
+        parent: Node<'a, 'input>,
+    },
+    /// Done drawing the element last entered and its children
+    Leave,
+}
 
+/// Traverse the elements that are drawn, once for every time they are drawn, skipping the same
+/// ones for converting and counting
+fn drawn_elements<'a, 'input>(
+    doc: &'a Document<'input>,
+    style: &StyleSheet,
+    masks: MaskHandling,
+) -> Vec<Visit<'a, 'input>> {
+    let mut visits = vec![];
+    let mut node_stack = vec![(
+        doc.root(),
+        doc.root().children().collect::<Vec<_>>().into_iter(),
+    )];
     while let Some((parent, mut children)) = node_stack.pop() {
-        let node: Node = match children.next() {
+        let node = match children.next() {
             Some(child) => {
                 node_stack.push((parent, children));
                 child
             }
             None => {
-                if parent.has_attribute("viewBox")
-                    || parent.has_attribute("transform")
-                    || parent.has_attribute("width")
-                    || parent.has_attribute("height")
-                {
-                    turtle.pop_transform();
+                if !node_stack.is_empty() {
+                    visits.push(Visit::Leave);
                 }
-                name_stack.pop();
                 continue;
             }
         };
@@ -80,253 +1042,1923 @@ pub fn svg2program<'input>(
             continue;
         }
 
-        let mut transforms = vec![];
-        if let Some(view_box) = node.attribute("viewBox") {
-            let view_box = ViewBox::from_str(view_box).expect("could not parse viewBox");
-            transforms.push(
-                Transform2D::translation(-view_box.x, -view_box.y)
-                    .then_scale(1. / view_box.w, 1. / view_box.h),
-            );
-        }
-
-        if let Some(transform) = width_and_height_into_transform(&options, &node) {
-            transforms.push(transform);
+        // Only drawn as the mask or fill of the elements referencing them
+        if matches!(node.tag_name().name(), "mask" | "pattern") {
+            continue;
         }
 
-        if let Some(transform) = node.attribute("transform") {
-            let parser = TransformListParser::from(transform);
-            transforms.extend(
-                parser
-                    .map(|token| {
-                        token.expect("could not parse a transform in a list of transforms")
-                    })
-                    .map(svg_transform_into_euclid_transform)
-                    .collect::<Vec<_>>()
-                    .iter()
-                    .rev(),
-            )
+        if is_origin_marker(&node) || style.is_hidden(&node) {
+            continue;
         }
 
-        if !transforms.is_empty() {
-            let transform = transforms
-                .iter()
-                .fold(Transform2D::identity(), |acc, t| acc.then(t));
-            turtle.push_transform(transform);
+        if let Some(mask) = style.property(&node, "mask").filter(|mask| *mask != "none") {
+            match masks {
+                MaskHandling::Ignore => warn!(
+                    "Drawing {} whole, its mask {} is not supported",
+                    node_name(&node),
+                    mask
+                ),
+                MaskHandling::Skip => {
+                    warn!("Skipping {}, which has the mask {}", node_name(&node), mask);
+                    continue;
+                }
+            }
         }
 
-        if node.tag_name().name() == "path" {
-            if let Some(d) = node.attribute("d") {
-                turtle.reset();
-                let mut comment = String::new();
-                name_stack.iter().for_each(|name| {
-                    comment += name;
-                    comment += " > ";
-                });
-                comment += &node_name(&node);
-                program.push(Token::Comment {
-                    is_inline: false,
-                    inner: Cow::Owned(comment),
-                });
-                program.extend(apply_path(turtle, &options, d));
-            } else {
-                warn!("There is a path node containing no actual path: {:?}", node);
-            }
+        // Only drawn where a use element references them
+        let is_used = parent.tag_name().name() == "use";
+        if matches!(node.tag_name().name(), "defs" | "symbol") && !is_used {
+            continue;
         }
 
-        if node.has_children() {
-            node_stack.push((node, node.children()));
-            name_stack.push(node_name(&node));
-        } else if !transforms.is_empty() {
-            // Pop transform early, since this is the only element that has it
-            turtle.pop_transform();
+        visits.push(Visit::Enter { node, parent });
+        let mut children = rendered_children(node);
+        if node.tag_name().name() == "use" {
+            children.retain(|referenced| {
+                let is_recursive = *referenced == node
+                    || node_stack
+                        .iter()
+                        .any(|(ancestor, _)| ancestor == referenced);
+                if is_recursive {
+                    warn!("Skipping a use element that references itself: {:?}", node);
+                }
+                !is_recursive
+            });
         }
+        node_stack.push((node, children.into_iter()));
     }
+    visits
+}
 
-    // Critical step for actually moving the machine back to the origin, just in case SVG is malformed
-    turtle.pop_all_transforms();
-    program.extend(turtle.machine.tool_off());
-    program.extend(turtle.machine.absolute());
-    program.extend(turtle.machine.program_end());
-    program.append(&mut command!(ProgramEnd {}).into_token_vec());
+/// Elements of a traversal that are converted and counted towards progress: paths, and images
+/// when they are engraved
+fn converted_elements<'v, 'a: 'v, 'input: 'v>(
+    visits: &'v [Visit<'a, 'input>],
+    style: &'v StyleSheet,
+    options: &'v ProgramOptions,
+) -> impl Iterator<Item = Node<'a, 'input>> + 'v {
+    visits
+        .iter()
+        .filter_map(|visit| match visit {
+            Visit::Enter { node, .. } => Some(*node),
+            Visit::Leave => None,
+        })
+        .filter(move |node| {
+            let is_converted = match node.tag_name().name() {
+                "path" => node.has_attribute("d"),
+                "image" => options.raster.is_some(),
+                _ => false,
+            };
+            is_converted && !style.is_invisible(node)
+        })
+}
 
-    program
+/// Count the elements that will be converted, counting every pass of their operation
+fn count_paths(visits: &[Visit], style: &StyleSheet, options: &ProgramOptions) -> usize {
+    converted_elements(visits, style, options)
+        .map(|node| {
+            options
+                .operations
+                .iter()
+                .find(|operation| operation.selector.matches(&node, style))
+                .map_or(1, |operation| operation.passes)
+        })
+        .sum()
 }
 
-fn node_name(node: &Node) -> String {
-    let mut name = node.tag_name().name().to_string();
-    if let Some(id) = node.attribute("id") {
-        name += "#";
-        name += id;
+/// Log the settings of each operation and how many paths it selects before converting, to catch
+/// mistakes like cutting at engraving power
+fn summarize_operations(visits: &[Visit], style: &StyleSheet, options: &ProgramOptions) {
+    let mut selected = vec![0; options.operations.len()];
+    let mut unselected = 0;
+    for node in converted_elements(visits, style, options) {
+        match options
+            .operations
+            .iter()
+            .position(|operation| operation.selector.matches(&node, style))
+        {
+            Some(i) => selected[i] += 1,
+            None => unselected += 1,
+        }
     }
-    name
-}
 
-fn width_and_height_into_transform(
-    options: &ProgramOptions,
-    node: &Node,
-) -> Option<Transform2D<f64>> {
-    if let (Some(mut width), Some(mut height)) = (
-        node.attribute("width").map(LengthListParser::from),
-        node.attribute("height").map(LengthListParser::from),
-    ) {
-        let width = width
-            .next()
-            .expect("no width in width property")
-            .expect("cannot parse width");
-        let height = height
-            .next()
-            .expect("no height in height property")
-            .expect("cannot parse height");
-        let width_in_mm = length_to_mm(width, options.dpi);
-        let height_in_mm = length_to_mm(height, options.dpi);
-
-        // SVGs have 0,0 in upper left
-        // g-code has 0,0 in lower left
-        Some(
-            Transform2D::scale(width_in_mm, -height_in_mm)
-                .then_translate(vector(0f64, height_in_mm)),
-        )
-    } else {
-        None
+    for (operation, count) in options.operations.iter().zip(selected) {
+        let describe =
+            |setting: Option<f64>| setting.map_or("default".to_string(), |s| s.to_string());
+        if count == 0 {
+            warn!(
+                "Operation {} does not select any paths, check its selector {:?}",
+                operation.name, operation.selector
+            );
+        } else {
+            info!(
+                "Operation {}: {} paths at feedrate {} and power {}, {} passes{}",
+                operation.name,
+                count,
+                describe(operation.feedrate),
+                describe(operation.power),
+                operation.passes,
+                operation
+                    .tool
+                    .map_or(String::new(), |tool| format!(" with tool T{}", tool))
+            );
+        }
+    }
+    if unselected > 0 {
+        info!(
+            "{} paths not selected by any operation use the default settings",
+            unselected
+        );
     }
 }
 
-fn apply_path<'a, 'input>(
-    turtle: &'a mut Turtle<'input>,
-    options: &ProgramOptions,
-    path: &str,
-) -> Vec<Token<'input>> {
-    use PathSegment::*;
-    PathParser::from(path)
-        .map(|segment| segment.expect("could not parse path segment"))
-        .flat_map(|segment| {
-            debug!("Drawing {:?}", &segment);
-            match segment {
-                MoveTo { abs, x, y } => turtle.move_to(abs, x, y),
-                ClosePath { abs: _ } => {
-                    // Ignore abs, should have identical effect: [9.3.4. The "closepath" command]("https://www.w3.org/TR/SVG/paths.html#PathDataClosePathCommand)
-                    turtle.close(None, options.feedrate)
+/// Children of an element that are drawn: the first child of a switch that passes conditional
+/// processing, the element referenced by a use, or else all of them
+fn rendered_children<'a, 'input>(node: Node<'a, 'input>) -> Vec<Node<'a, 'input>> {
+    match node.tag_name().name() {
+        "switch" => node
+            .children()
+            .find(|child| child.is_element() && passes_conditions(child))
+            .into_iter()
+            .collect(),
+        "use" => node
+            .attribute("href")
+            .or_else(|| node.attribute(("http://www.w3.org/1999/xlink", "href")))
+            .and_then(|href| href.strip_prefix('#'))
+            .and_then(|id| {
+                let root = node.ancestors().last().unwrap();
+                let referenced = root
+                    .descendants()
+                    .find(|node| node.attribute("id") == Some(id));
+                if referenced.is_none() {
+                    warn!("Skipping a use element referencing a missing #{}", id);
                 }
-                LineTo { abs, x, y } => turtle.line(abs, x, y, None, options.feedrate),
-                HorizontalLineTo { abs, x } => turtle.line(abs, x, None, None, options.feedrate),
-                VerticalLineTo { abs, y } => turtle.line(abs, None, y, None, options.feedrate),
-                CurveTo {
-                    abs,
-                    x1,
-                    y1,
-                    x2,
-                    y2,
-                    x,
-                    y,
-                } => turtle.cubic_bezier(
-                    abs,
-                    x1,
-                    y1,
-                    x2,
-                    y2,
-                    x,
-                    y,
-                    options.tolerance,
-                    None,
-                    options.feedrate,
-                ),
-                SmoothCurveTo { abs, x2, y2, x, y } => turtle.smooth_cubic_bezier(
-                    abs,
-                    x2,
-                    y2,
-                    x,
-                    y,
-                    options.tolerance,
-                    None,
-                    options.feedrate,
-                ),
-                Quadratic { abs, x1, y1, x, y } => turtle.quadratic_bezier(
-                    abs,
-                    x1,
-                    y1,
-                    x,
-                    y,
-                    options.tolerance,
-                    None,
-                    options.feedrate,
-                ),
-                SmoothQuadratic { abs, x, y } => turtle.smooth_quadratic_bezier(
-                    abs,
-                    x,
-                    y,
-                    options.tolerance,
-                    None,
-                    options.feedrate,
-                ),
-                EllipticalArc {
-                    abs,
-                    rx,
-                    ry,
-                    x_axis_rotation,
-                    large_arc,
-                    sweep,
-                    x,
-                    y,
-                } => turtle.elliptical(
-                    abs,
-                    rx,
-                    ry,
-                    x_axis_rotation,
-                    large_arc,
-                    sweep,
-                    x,
-                    y,
-                    None,
-                    options.feedrate,
-                    options.tolerance,
-                ),
-            }
-        })
-        .collect()
+                referenced
+            })
+            .into_iter()
+            .collect(),
+        _ => node.children().collect(),
+    }
 }
 
-fn svg_transform_into_euclid_transform(svg_transform: TransformListToken) -> Transform2D<f64> {
-    use TransformListToken::*;
-    match svg_transform {
-        Matrix { a, b, c, d, e, f } => Transform2D::new(a, b, c, d, e, f),
-        Translate { tx, ty } => Transform2D::translation(tx, ty),
-        Scale { sx, sy } => Transform2D::scale(sx, sy),
-        Rotate { angle } => Transform2D::rotation(Angle::degrees(angle)),
-        // https://drafts.csswg.org/css-transforms/#SkewXDefined
-        SkewX { angle } => Transform3D::skew(Angle::degrees(angle), Angle::zero()).to_2d(),
-        // https://drafts.csswg.org/css-transforms/#SkewYDefined
-        SkewY { angle } => Transform3D::skew(Angle::zero(), Angle::degrees(angle)).to_2d(),
-    }
+/// The element a paint property like `fill="url(#pattern)"` references, i.e. a gradient or pattern
+fn paint_server<'a, 'input>(
+    node: &Node<'a, 'input>,
+    style: &StyleSheet,
+    property: &str,
+) -> Option<Node<'a, 'input>> {
+    let id = style
+        .property(node, property)?
+        .trim()
+        .strip_prefix("url(")?
+        .split(')')
+        .next()?
+        .trim()
+        .trim_matches(|c| c == '"' || c == '\'')
+        .strip_prefix('#')?;
+    let root = node.ancestors().last()?;
+    root.descendants()
+        .find(|node| node.attribute("id") == Some(id))
 }
 
-/// Convenience function for converting absolute lengths to millimeters
+/// Conditional processing attributes: [SVG 2 §5.8.3](https://www.w3.org/TR/SVG/struct.html#ConditionalProcessing)
 ///
-/// Absolute lengths are listed in [CSS 4 §6.2](https://www.w3.org/TR/css-values/#absolute-lengths).
-/// Relative lengths in [CSS 4 §6.1](https://www.w3.org/TR/css-values/#relative-lengths) are not supported and will simply be interpreted as millimeters.
+/// No extensions are supported, and the language is taken to be English. Elements requiring
+/// features are skipped too, as exporters like draw.io use them to mark HTML foreignObjects that
+/// have a plain SVG fallback.
+fn passes_conditions(node: &Node) -> bool {
+    let requires_extensions = node
+        .attribute("requiredExtensions")
+        .is_some_and(|extensions| !extensions.trim().is_empty());
+    let is_english = node.attribute("systemLanguage").is_none_or(|languages| {
+        languages
+            .split(',')
+            .any(|language| language.trim().to_ascii_lowercase().starts_with("en"))
+    });
+    !requires_extensions && !node.has_attribute("requiredFeatures") && is_english
+}
+
+/// An element marking where the origin of the program should be, which is not drawn.
+/// It has the id `svg2gcode-origin` or a `data-svg2gcode-origin` attribute.
+fn is_origin_marker(node: &Node) -> bool {
+    node.attribute("id") == Some("svg2gcode-origin") || node.has_attribute("data-svg2gcode-origin")
+}
+
+/// Find the position of the origin marker in the program, if the SVG has one.
 ///
-/// A default DPI of 96 is used as per [CSS 4 §7.4](https://www.w3.org/TR/css-values/#resolution), which you can adjust with --dpi.
-/// Increasing DPI reduces the scale of an SVG.
-fn length_to_mm(l: svgtypes::Length, dpi: f64) -> f64 {
-    use svgtypes::LengthUnit::*;
-    use uom::si::f64::Length;
-    use uom::si::length::*;
+/// The position is the center of a circle, ellipse or rect, or the x and y of other elements.
+pub fn origin_marker(
+    doc: &Document,
+    options: &ProgramOptions,
+) -> Result<Option<Point<f64>>, ConversionError> {
+    let marker = match doc
+        .descendants()
+        .find(|node| node.is_element() && is_origin_marker(node))
+    {
+        Some(marker) => marker,
+        None => return Ok(None),
+    };
+    let options = match implied_dpi(options, &doc.root_element())? {
+        Some(dpi) => ProgramOptions {
+            dpi,
+            ..options.clone()
+        },
+        None => options.clone(),
+    };
 
-    let dpi_scaling = dpi / 96.0;
+    let style = StyleSheet::new(doc);
+    let coordinate = |name: &'static str, axis| -> Result<f64, ConversionError> {
+        Ok(user_units_attribute(&options, &style, &marker, name, axis)?.unwrap_or(0.))
+    };
+    let position = match marker.tag_name().name() {
+        "circle" | "ellipse" => point(coordinate("cx", Axis::X)?, coordinate("cy", Axis::Y)?),
+        "rect" => point(
+            coordinate("x", Axis::X)? + coordinate("width", Axis::X)? / 2.,
+            coordinate("y", Axis::Y)? + coordinate("height", Axis::Y)? / 2.,
+        ),
+        _ => point(coordinate("x", Axis::X)?, coordinate("y", Axis::Y)?),
+    };
+
+    // Transforms of the marker and its ancestors, innermost first like the turtle applies them
+    let mut transform = Transform2D::identity();
+    for node in marker.ancestors().filter(|node| node.is_element()) {
+        if let Some(node_transform) =
+            node_transform(&options, &style, &node, node == doc.root_element(), None)?
+        {
+            transform = transform.then(&node_transform);
+        }
+    }
+    Ok(Some(transform.transform_point(position)))
+}
+
+/// The transform an element applies to its contents, from its viewBox, viewport and transform
+/// attribute. `instance` is the use element a symbol is drawn for.
+fn node_transform(
+    options: &ProgramOptions,
+    style: &StyleSheet,
+    node: &Node,
+    is_root: bool,
+    instance: Option<Node>,
+) -> Result<Option<Transform2D<f64>>, ConversionError> {
+    let mut transforms = vec![];
+    if let Some(view_box) = node.attribute("viewBox") {
+        let view_box = ViewBox::from_str(view_box)
+            .map_err(|err| ConversionError::invalid_attribute(node, "viewBox", err))?;
+        transforms.push(
+            Transform2D::translation(-view_box.x, -view_box.y)
+                .then_scale(1. / view_box.w, 1. / view_box.h),
+        );
+    }
+
+    if let Some(transform) =
+        width_and_height_into_transform(options, style, node, is_root, instance)?
+    {
+        transforms.push(transform);
+    }
+
+    // A use element is offset by its x and y, after its transform attribute
+    if node.tag_name().name() == "use" {
+        let offset = nested_viewport_offset(options, style, node)?;
+        if offset != vector(0., 0.) {
+            transforms.push(Transform2D::translation(offset.x, offset.y));
+        }
+    }
+
+    if let Some(transform) = node.attribute("transform") {
+        let parser = TransformListParser::from(transform);
+        transforms.extend(
+            parser
+                .map(|token| {
+                    token
+                        .map(svg_transform_into_euclid_transform)
+                        .map_err(|err| ConversionError::invalid_attribute(node, "transform", err))
+                })
+                .collect::<Result<Vec<_>, _>>()?
+                .iter()
+                .rev(),
+        )
+    }
+
+    if !transforms.is_empty() {
+        let transform = transforms
+            .iter()
+            .fold(Transform2D::identity(), |acc, t| acc.then(t));
+        if transform.inverse().is_none() {
+            return Err(ConversionError::SingularTransform {
+                element: node_name(node),
+            });
+        }
+        Ok(Some(transform))
+    } else {
+        Ok(None)
+    }
+}
+
+fn metadata_comment(inner: String) -> Token<'static> {
+    Token::Comment {
+        is_inline: false,
+        inner: Cow::Owned(inner),
+    }
+}
+
+fn progress_comment(paths_converted: usize, total_paths: usize) -> Token<'static> {
+    Token::Comment {
+        is_inline: false,
+        inner: Cow::Owned(format!("PROGRESS {}/{}", paths_converted, total_paths)),
+    }
+}
+
+/// Product of the `data-feed-scale` attributes of an element and its ancestors, to slow down or
+/// speed up parts of a drawing
+fn feed_scale(node: &Node) -> Result<f64, ConversionError> {
+    node.ancestors()
+        .filter_map(|node| node.attribute("data-feed-scale").map(|scale| (node, scale)))
+        .try_fold(1., |product, (node, scale)| {
+            svgtypes::Stream::from(scale)
+                .parse_number()
+                .map(|scale| product * scale)
+                .map_err(|err| ConversionError::invalid_attribute(&node, "data-feed-scale", err))
+        })
+}
+
+/// The stroke color of a path like `#ff0000`, so that colors written differently compare equal
+fn stroke_color(node: &Node, style: &StyleSheet) -> String {
+    let stroke = style.property(node, "stroke").unwrap_or("none");
+    match stroke.parse::<Color>() {
+        Ok(color) => format!("#{:02x}{:02x}{:02x}", color.red, color.green, color.blue),
+        Err(_) => stroke.to_string(),
+    }
+}
+
+/// Power for a path, depending on its stroke color
+fn node_power(node: &Node, style: &StyleSheet, options: &ProgramOptions) -> Option<f64> {
+    let color = style
+        .property(node, "stroke")
+        .and_then(|stroke| stroke.parse().ok());
+    color
+        .and_then(|color: Color| {
+            options
+                .color_power
+                .iter()
+                .find(|(c, _)| *c == color)
+                .map(|(_, power)| *power)
+        })
+        .or(options.power)
+}
+
+/// The dash pattern and offset of a path's stroke in millimeters, if it is dashed
+fn node_dashes(
+    node: &Node,
+    style: &StyleSheet,
+    turtle: &Turtle,
+    options: &ProgramOptions,
+) -> Result<Option<(Vec<f64>, f64)>, ConversionError> {
+    let dash_array = match style.property(node, "stroke-dasharray") {
+        Some(dash_array) if dash_array != "none" => dash_array,
+        _ => return Ok(None),
+    };
+    let lengths = LengthListParser::from(dash_array)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| ConversionError::invalid_attribute(node, "stroke-dasharray", err))?;
+    let offset = style
+        .property(node, "stroke-dashoffset")
+        .map(Length::from_str)
+        .transpose()
+        .map_err(|err| ConversionError::invalid_attribute(node, "stroke-dashoffset", err))?
+        .unwrap_or(Length::zero());
+
+    let to_mm = |attribute, length| {
+        absolute_length(options, style, node, attribute, length, Axis::Diagonal)
+            .and_then(|length| length_to_user_units(length, options.dpi))
+            .map(|length| length * turtle.current_scale())
+    };
+    let pattern = lengths
+        .into_iter()
+        .map(|length| to_mm("stroke-dasharray", length))
+        .collect::<Result<Vec<_>, _>>()?;
+    // Invalid patterns are drawn as solid strokes
+    if pattern.iter().any(|length| *length < 0.) || pattern.iter().sum::<f64>() <= 0. {
+        return Ok(None);
+    }
+    Ok(Some((pattern, to_mm("stroke-dashoffset", offset)?)))
+}
+
+/// The stroke of a path in millimeters, if it has one
+fn node_stroke(
+    node: &Node,
+    style: &StyleSheet,
+    turtle: &Turtle,
+    options: &ProgramOptions,
+) -> Result<Option<Stroke>, ConversionError> {
+    if style.property(node, "stroke").unwrap_or("none") == "none" {
+        return Ok(None);
+    }
+
+    let width = style
+        .property(node, "stroke-width")
+        .map(Length::from_str)
+        .transpose()
+        .map_err(|err| ConversionError::invalid_attribute(node, "stroke-width", err))?
+        .map(|width| {
+            absolute_length(options, style, node, "stroke-width", width, Axis::Diagonal)
+                .and_then(|width| length_to_user_units(width, options.dpi))
+        })
+        .transpose()?
+        .unwrap_or(1.);
+    let line_cap = match style.property(node, "stroke-linecap") {
+        Some("round") => LineCap::Round,
+        Some("square") => LineCap::Square,
+        _ => LineCap::Butt,
+    };
+    let line_join = match style.property(node, "stroke-linejoin") {
+        Some("round") => LineJoin::Round,
+        Some("bevel") => LineJoin::Bevel,
+        _ => LineJoin::Miter,
+    };
+    let miter_limit = style
+        .property(node, "stroke-miterlimit")
+        .map(|limit| svgtypes::Stream::from(limit).parse_number())
+        .transpose()
+        .map_err(|err| ConversionError::invalid_attribute(node, "stroke-miterlimit", err))?
+        .unwrap_or(4.);
+
+    Ok(Some(Stroke {
+        width: width * turtle.current_scale(),
+        line_cap,
+        line_join,
+        miter_limit,
+    }))
+}
+
+fn node_name(node: &Node) -> String {
+    let mut name = node.tag_name().name().to_string();
+    if let Some(id) = node.attribute("id") {
+        name += "#";
+        name += id;
+    }
+    name
+}
+
+fn width_and_height_into_transform(
+    options: &ProgramOptions,
+    style: &StyleSheet,
+    node: &Node,
+    is_root: bool,
+    instance: Option<Node>,
+) -> Result<Option<Transform2D<f64>>, ConversionError> {
+    // Only the root, nested svg and symbol elements establish a viewport, width and height are
+    // just geometry on other elements
+    if !is_root && !matches!(node.tag_name().name(), "svg" | "symbol") {
+        return Ok(None);
+    }
+    // The use element drawing a symbol overrides its size
+    let dimension = |name: &'static str, axis| -> Result<Option<Length>, ConversionError> {
+        let element = match instance.map(|instance| length_attribute(&instance, name)) {
+            Some(Ok(Some(_))) => instance.unwrap(),
+            Some(Err(err)) => return Err(err),
+            _ => *node,
+        };
+        match (length_attribute(&element, name)?, options.dimensions) {
+            // The root is a percentage of the dimensions it is drawn at
+            (
+                Some(Length {
+                    num,
+                    unit: LengthUnit::Percent,
+                }),
+                Some(dimensions),
+            ) if is_root => {
+                let dimension = dimensions[if name == "width" { 0 } else { 1 }];
+                Ok(Some(Length {
+                    num: length_to_user_units(dimension, options.dpi)? * num / 100.,
+                    unit: LengthUnit::Px,
+                }))
+            }
+            (length, _) => length
+                .map(|length| absolute_length(options, style, &element, name, length, axis))
+                .transpose(),
+        }
+    };
+    let own_dimensions = (dimension("width", Axis::X)?, dimension("height", Axis::Y)?);
+    let (width, height) = match (is_root, options.dimensions) {
+        (true, Some([width, height])) => (width, height),
+        (true, None) => match (own_dimensions, node.attribute("viewBox")) {
+            ((Some(width), Some(height)), _) => {
+                let with_units = |length: Length| {
+                    if length.unit == LengthUnit::None {
+                        dimensionless_length(options, length.num)
+                    } else {
+                        length
+                    }
+                };
+                (with_units(width), with_units(height))
+            }
+            // The viewBox is taken to be the size of the SVG
+            (_, Some(view_box)) => {
+                let view_box = ViewBox::from_str(view_box)
+                    .map_err(|err| ConversionError::invalid_attribute(node, "viewBox", err))?;
+                (
+                    dimensionless_length(options, view_box.w),
+                    dimensionless_length(options, view_box.h),
+                )
+            }
+            // Nothing to flip the Y axis over, so user units are only scaled
+            _ => {
+                let scale = length_to_mm(dimensionless_length(options, 1.), options.dpi)?;
+                return Ok(Some(Transform2D::scale(scale, scale)).filter(|_| scale != 1.));
+            }
+        },
+        _ => match own_dimensions {
+            (Some(width), Some(height)) => (width, height),
+            // Without a size or viewBox, the contents are only offset
+            _ if !is_root && !node.has_attribute("viewBox") => {
+                let offset = nested_viewport_offset(options, style, node)?;
+                return Ok(Some(Transform2D::translation(offset.x, offset.y)));
+            }
+            // The size of the parent viewport isn't known, so the viewBox is kept at its own size
+            _ if !is_root => {
+                let view_box = ViewBox::from_str(node.attribute("viewBox").unwrap())
+                    .map_err(|err| ConversionError::invalid_attribute(node, "viewBox", err))?;
+                return Ok(Some(
+                    Transform2D::scale(view_box.w, view_box.h)
+                        .then_translate(nested_viewport_offset(options, style, node)?),
+                ));
+            }
+            _ => return Ok(None),
+        },
+    };
+
+    // The viewBox is in a unit square by now, which is fit into the viewport by its
+    // preserveAspectRatio. Without a viewBox, user units are taken to span the element's own
+    // width and height.
+    let normalization = if let Some(view_box) = node.attribute("viewBox") {
+        let view_box = ViewBox::from_str(view_box)
+            .map_err(|err| ConversionError::invalid_attribute(node, "viewBox", err))?;
+        let aspect = node
+            .attribute("preserveAspectRatio")
+            .map(AspectRatio::from_str)
+            .transpose()
+            .map_err(|err| ConversionError::invalid_attribute(node, "preserveAspectRatio", err))?
+            .unwrap_or_default();
+        let viewport = Box2D::new(
+            point(0., 0.),
+            point(
+                length_to_user_units(width, options.dpi)?,
+                length_to_user_units(height, options.dpi)?,
+            ),
+        );
+        let rect = aspect_ratio_rect(aspect, viewport, [view_box.w, view_box.h]);
+        if aspect.slice && rect != viewport {
+            debug!(
+                "Contents sliced by preserveAspectRatio are not clipped to the viewport: {:?}",
+                node
+            );
+        }
+        Transform2D::scale(
+            rect.width() / viewport.width(),
+            rect.height() / viewport.height(),
+        )
+        .then_translate(vector(
+            rect.min.x / viewport.width(),
+            rect.min.y / viewport.height(),
+        ))
+    } else if let (Some(width), Some(height)) = own_dimensions {
+        Transform2D::scale(
+            1. / length_to_user_units(width, options.dpi)?,
+            1. / length_to_user_units(height, options.dpi)?,
+        )
+    } else {
+        warn!(
+            "Dimensions cannot be applied to an element without a viewBox or its own width and height: {:?}",
+            node
+        );
+        return Ok(None);
+    };
+
+    if !is_root {
+        // A nested viewport is placed in the user units of its parent
+        return Ok(Some(
+            normalization
+                .then_scale(
+                    length_to_user_units(width, options.dpi)?,
+                    length_to_user_units(height, options.dpi)?,
+                )
+                .then_translate(nested_viewport_offset(options, style, node)?),
+        ));
+    }
+
+    let width_in_mm = length_to_mm(width, options.dpi)?;
+    let height_in_mm = length_to_mm(height, options.dpi)?;
+
+    // SVGs have 0,0 in upper left
+    // g-code has 0,0 in lower left
+    Ok(Some(
+        normalization
+            .then_scale(width_in_mm, -height_in_mm)
+            .then_translate(vector(0f64, height_in_mm)),
+    ))
+}
+
+/// Position of a nested svg element in the user units of its parent
+fn nested_viewport_offset(
+    options: &ProgramOptions,
+    style: &StyleSheet,
+    node: &Node,
+) -> Result<Vector<f64>, ConversionError> {
+    let x = user_units_attribute(options, style, node, "x", Axis::X)?.unwrap_or(0.);
+    let y = user_units_attribute(options, style, node, "y", Axis::Y)?.unwrap_or(0.);
+    Ok(vector(x, y))
+}
+
+/// Which size of the viewport a percentage is of
+#[derive(Debug, Clone, Copy)]
+enum Axis {
+    X,
+    Y,
+    /// The diagonal over the square root of 2, for lengths along no axis like stroke widths
+    Diagonal,
+}
+
+/// Default font size in user units, the `medium` of browsers, for em and ex lengths
+const DEFAULT_FONT_SIZE: f64 = 16.;
+
+/// A length attribute of an element in user units, resolving relative lengths
+fn user_units_attribute(
+    options: &ProgramOptions,
+    style: &StyleSheet,
+    node: &Node,
+    name: &'static str,
+    axis: Axis,
+) -> Result<Option<f64>, ConversionError> {
+    length_attribute(node, name)?
+        .map(|length| {
+            absolute_length(options, style, node, name, length, axis)
+                .and_then(|length| length_to_user_units(length, options.dpi))
+        })
+        .transpose()
+}
+
+/// Resolve a relative length of an element into user units (pixels): percentages of the viewport
+/// it is in, and em and ex of its font size. Other lengths are returned as they are.
+fn absolute_length(
+    options: &ProgramOptions,
+    style: &StyleSheet,
+    node: &Node,
+    attribute: &'static str,
+    length: Length,
+    axis: Axis,
+) -> Result<Length, ConversionError> {
+    let num = match length.unit {
+        LengthUnit::Percent => {
+            let [width, height] = viewport_size(options, style, node)?.ok_or_else(|| {
+                ConversionError::RelativeLength {
+                    element: node_name(node),
+                    attribute,
+                    length,
+                }
+            })?;
+            let size = match axis {
+                Axis::X => width,
+                Axis::Y => height,
+                Axis::Diagonal => width.hypot(height) / std::f64::consts::SQRT_2,
+            };
+            length.num / 100. * size
+        }
+        LengthUnit::Em => length.num * font_size(options, style, node)?,
+        // Fonts aren't known, so an x-height is half of the font size like browsers fall back to
+        LengthUnit::Ex => length.num * font_size(options, style, node)? / 2.,
+        _ => return Ok(length),
+    };
+    Ok(Length {
+        num,
+        unit: LengthUnit::Px,
+    })
+}
+
+/// The size of the viewport an element is in, in its user units, from the viewBox or size of the
+/// nearest svg element around it
+fn viewport_size(
+    options: &ProgramOptions,
+    style: &StyleSheet,
+    node: &Node,
+) -> Result<Option<[f64; 2]>, ConversionError> {
+    let viewport = match node
+        .ancestors()
+        .skip(1)
+        .find(|ancestor| ancestor.is_element() && ancestor.tag_name().name() == "svg")
+    {
+        Some(viewport) => viewport,
+        None => return Ok(None),
+    };
+    if let Some(view_box) = viewport.attribute("viewBox") {
+        let view_box = ViewBox::from_str(view_box)
+            .map_err(|err| ConversionError::invalid_attribute(&viewport, "viewBox", err))?;
+        return Ok(Some([view_box.w, view_box.h]));
+    }
+    let is_root = viewport.parent_element().is_none();
+    let (width, height) = match (is_root, options.dimensions) {
+        (true, Some([width, height])) => (Some(width), Some(height)),
+        _ => (
+            length_attribute(&viewport, "width")?,
+            length_attribute(&viewport, "height")?,
+        ),
+    };
+    match (width, height) {
+        (Some(width), Some(height)) => Ok(Some([
+            length_to_user_units(
+                absolute_length(options, style, &viewport, "width", width, Axis::X)?,
+                options.dpi,
+            )?,
+            length_to_user_units(
+                absolute_length(options, style, &viewport, "height", height, Axis::Y)?,
+                options.dpi,
+            )?,
+        ])),
+        _ => Ok(None),
+    }
+}
+
+/// The font size of an element in user units, which em and ex lengths are relative to
+fn font_size(
+    options: &ProgramOptions,
+    style: &StyleSheet,
+    node: &Node,
+) -> Result<f64, ConversionError> {
+    let parent_font_size = || match node.parent_element() {
+        Some(parent) => font_size(options, style, &parent),
+        None => Ok(DEFAULT_FONT_SIZE),
+    };
+    let font_size = match style.property(node, "font-size") {
+        Some(font_size) => Length::from_str(font_size).ok(),
+        None => None,
+    };
+    match font_size {
+        Some(Length {
+            num,
+            unit: LengthUnit::Em,
+        }) => Ok(num * parent_font_size()?),
+        Some(Length {
+            num,
+            unit: LengthUnit::Percent,
+        }) => Ok(num / 100. * parent_font_size()?),
+        Some(Length {
+            num,
+            unit: LengthUnit::Ex,
+        }) => Ok(num * parent_font_size()? / 2.),
+        Some(length) => length_to_user_units(length, options.dpi),
+        // Keywords like medium aren't supported and inherit instead
+        None => parent_font_size(),
+    }
+}
+
+fn length_attribute(node: &Node, name: &'static str) -> Result<Option<Length>, ConversionError> {
+    let attribute = node.attribute(name);
+    if let Some(expression) = attribute.filter(|attribute| {
+        attribute
+            .trim_start()
+            .get(..5)
+            .is_some_and(|function| function.eq_ignore_ascii_case("calc("))
+    }) {
+        let length = evaluate_calc(expression);
+        if length.is_none() {
+            warn!(
+                "Skipping the {} of {}, {:?} can only be evaluated if it adds or subtracts lengths in the same unit",
+                name,
+                node_name(node),
+                expression
+            );
+        }
+        return Ok(length);
+    }
+    attribute
+        .map(|attribute| {
+            LengthListParser::from(attribute)
+                .next()
+                .unwrap_or(Err(svgtypes::Error::UnexpectedEndOfStream))
+                .map_err(|err| ConversionError::invalid_attribute(node, name, err))
+        })
+        .transpose()
+}
+
+/// Evaluate a CSS `calc()` that adds and subtracts lengths in the same unit, like
+/// `calc(100mm - 2mm)`, which some editors emit for width and height.
+///
+/// [CSS 4 §10.1](https://www.w3.org/TR/css-values/#calc-func) requires whitespace around the
+/// operators, which tells them apart from the signs of numbers.
+fn evaluate_calc(expression: &str) -> Option<Length> {
+    let inner = expression
+        .trim()
+        .get(5..)?
+        .strip_suffix(')')
+        .filter(|inner| !inner.contains(['(', ')', '*', '/']))?;
+    let mut terms = inner.split_whitespace();
+    let mut total = Length::from_str(terms.next()?).ok()?;
+    while let Some(operator) = terms.next() {
+        let term = Length::from_str(terms.next()?).ok()?;
+        if term.unit != total.unit {
+            return None;
+        }
+        match operator {
+            "+" => total.num += term.num,
+            "-" => total.num -= term.num,
+            _ => return None,
+        }
+    }
+    Some(total)
+}
+
+/// Without a width and height in units, the physical size of an SVG is unknown, so it is sized
+/// by the [DimensionlessPolicy], which is pointed out since a wrong guess is far off
+fn check_dimensionless(options: &ProgramOptions, root: &Node) -> Result<(), ConversionError> {
+    if options.dimensions.is_some() || !is_dimensionless(root)? {
+        return Ok(());
+    }
+    let units = match options.dimensionless {
+        DimensionlessPolicy::Error => return Err(ConversionError::Dimensionless),
+        DimensionlessPolicy::Px => format!("pixels at {} DPI", options.dpi),
+        DimensionlessPolicy::Mm => "millimeters".to_string(),
+    };
+    let consequence = if root.has_attribute("viewBox")
+        || (root.has_attribute("width") && root.has_attribute("height"))
+    {
+        String::new()
+    } else {
+        ", with the Y axis upside down since there is no height".to_string()
+    };
+    warn!(
+        "The SVG has no physical size, so its user units are taken as {}{}. Set its size with --dimensions (i.e. --dimensions 210mm,297mm) or give it a width and height with units.",
+        units, consequence
+    );
+    Ok(())
+}
+
+/// Whether the root element is missing a width or height, or has one without units
+fn is_dimensionless(root: &Node) -> Result<bool, ConversionError> {
+    for attribute in ["width", "height"] {
+        match length_attribute(root, attribute)? {
+            None
+            | Some(Length {
+                unit: LengthUnit::None,
+                ..
+            }) => return Ok(true),
+            Some(_) => {}
+        }
+    }
+    Ok(false)
+}
+
+/// A length without units in the units of the [DimensionlessPolicy]
+fn dimensionless_length(options: &ProgramOptions, num: f64) -> Length {
+    let unit = match options.dimensionless {
+        DimensionlessPolicy::Mm => LengthUnit::Mm,
+        DimensionlessPolicy::Px | DimensionlessPolicy::Error => LengthUnit::Px,
+    };
+    Length { num, unit }
+}
+
+/// Percentages in the root width and height would be relative to the browser window, so they
+/// can only be used as a fallback when the dimensions are overridden
+fn check_relative_dimensions(options: &ProgramOptions, root: &Node) -> Result<(), ConversionError> {
+    if options.dimensions.is_some() {
+        return Ok(());
+    }
+    for attribute in ["width", "height"] {
+        if let Some(length) = length_attribute(root, attribute)? {
+            if length.unit == LengthUnit::Percent {
+                return Err(ConversionError::RelativeDimensions { attribute, length });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Infer the DPI from the overriding dimensions of the root element, if it is sized in pixels.
+/// An SVG sized in absolute units already has a physical size, so nothing is inferred.
+fn implied_dpi(options: &ProgramOptions, root: &Node) -> Result<Option<f64>, ConversionError> {
+    let width = match options.dimensions {
+        Some([width, _]) if !matches!(width.unit, LengthUnit::None | LengthUnit::Px) => width,
+        _ => return Ok(None),
+    };
+    let width_in_pixels = match length_attribute(root, "width")? {
+        Some(Length {
+            num,
+            unit: LengthUnit::None | LengthUnit::Px,
+        }) => num,
+        // A percentage of nothing, so the viewBox is all there is to go on
+        Some(Length {
+            unit: LengthUnit::Percent,
+            ..
+        })
+        | None => match root.attribute("viewBox") {
+            Some(view_box) => {
+                ViewBox::from_str(view_box)
+                    .map_err(|err| ConversionError::invalid_attribute(root, "viewBox", err))?
+                    .w
+            }
+            None => return Ok(None),
+        },
+        Some(_) => return Ok(None),
+    };
+    let width_in_inches = length_to_mm(width, options.dpi)? / 25.4;
+    Ok(Some(width_in_pixels / width_in_inches))
+}
+
+/// Where content of a size is placed in a viewport for its `preserveAspectRatio`, which may
+/// overflow the viewport when it is sliced
+fn aspect_ratio_rect(aspect: AspectRatio, viewport: Box2D<f64>, size: [f64; 2]) -> Box2D<f64> {
+    if aspect.align == Align::None || size[0] <= 0. || size[1] <= 0. {
+        return viewport;
+    }
+    let (scale_x, scale_y) = (viewport.width() / size[0], viewport.height() / size[1]);
+    let scale = if aspect.slice {
+        scale_x.max(scale_y)
+    } else {
+        scale_x.min(scale_y)
+    };
+    let (width, height) = (size[0] * scale, size[1] * scale);
+    let (x_fraction, y_fraction) = match aspect.align {
+        Align::None | Align::XMinYMin => (0., 0.),
+        Align::XMidYMin => (0.5, 0.),
+        Align::XMaxYMin => (1., 0.),
+        Align::XMinYMid => (0., 0.5),
+        Align::XMidYMid => (0.5, 0.5),
+        Align::XMaxYMid => (1., 0.5),
+        Align::XMinYMax => (0., 1.),
+        Align::XMidYMax => (0.5, 1.),
+        Align::XMaxYMax => (1., 1.),
+    };
+    let min = point(
+        viewport.min.x + (viewport.width() - width) * x_fraction,
+        viewport.min.y + (viewport.height() - height) * y_fraction,
+    );
+    Box2D::new(min, point(min.x + width, min.y + height))
+}
+
+/// Convert a length to user units, which are equivalent to pixels
+fn length_to_user_units(l: Length, dpi: f64) -> Result<f64, ConversionError> {
+    match l.unit {
+        LengthUnit::None | LengthUnit::Px => Ok(l.num),
+        _ => Ok(length_to_mm(l, dpi)? / 25.4 * dpi),
+    }
+}
+
+fn apply_path<'input>(
+    turtle: &mut Turtle<'input>,
+    options: &ProgramOptions,
+    node: &Node,
+    path: &str,
+) -> Result<Vec<Token<'input>>, ConversionError> {
+    let segments = PathParser::from(path)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| ConversionError::invalid_attribute(node, "d", err))?;
+    apply_segments(turtle, options, &node_name(node), segments)
+}
+
+/// Draw the segments of a path, named `name` in errors and warnings
+fn apply_segments<'input>(
+    turtle: &mut Turtle<'input>,
+    options: &ProgramOptions,
+    name: &str,
+    segments: impl IntoIterator<Item = PathSegment>,
+) -> Result<Vec<Token<'input>>, ConversionError> {
+    use PathSegment::*;
+    let mut program = vec![];
+    for segment in segments {
+        debug!("Drawing {:?}", &segment);
+        let mut segment = segment;
+        if let Some(problem) = degeneracy(&segment, turtle.local_position()) {
+            match options.degenerate {
+                DegenerateHandling::Skip => {}
+                DegenerateHandling::Warn => warn!(
+                    "{} the segment {} of {}, which is {}",
+                    if problem == ZERO_RADIUS {
+                        "Drawing a line for"
+                    } else {
+                        "Leaving out"
+                    },
+                    segment_comment(&segment),
+                    name,
+                    problem
+                ),
+                DegenerateHandling::Error => {
+                    return Err(ConversionError::DegenerateGeometry {
+                        element: name.to_string(),
+                        segment: segment_comment(&segment),
+                        problem,
+                    })
+                }
+            }
+            match segment {
+                EllipticalArc { abs, x, y, .. } if problem == ZERO_RADIUS => {
+                    segment = LineTo { abs, x, y };
+                }
+                _ => continue,
+            }
+        }
+        let comment = if options.comments >= CommentLevel::Segments {
+            Some(segment_comment(&segment))
+        } else {
+            None
+        };
+        let mut tokens = match segment {
+            MoveTo { abs, x, y } => turtle.move_to(abs, x, y),
+            ClosePath { abs: _ } => {
+                // Ignore abs, should have identical effect: [9.3.4. The "closepath" command]("https://www.w3.org/TR/SVG/paths.html#PathDataClosePathCommand)
+                turtle.close(None, options.feedrate)
+            }
+            LineTo { abs, x, y } => turtle.line(abs, x, y, None, options.feedrate),
+            HorizontalLineTo { abs, x } => turtle.line(abs, x, None, None, options.feedrate),
+            VerticalLineTo { abs, y } => turtle.line(abs, None, y, None, options.feedrate),
+            CurveTo {
+                abs,
+                x1,
+                y1,
+                x2,
+                y2,
+                x,
+                y,
+            } => turtle.cubic_bezier(
+                abs,
+                x1,
+                y1,
+                x2,
+                y2,
+                x,
+                y,
+                options.tolerance,
+                None,
+                options.feedrate,
+            ),
+            SmoothCurveTo { abs, x2, y2, x, y } => turtle.smooth_cubic_bezier(
+                abs,
+                x2,
+                y2,
+                x,
+                y,
+                options.tolerance,
+                None,
+                options.feedrate,
+            ),
+            Quadratic { abs, x1, y1, x, y } => turtle.quadratic_bezier(
+                abs,
+                x1,
+                y1,
+                x,
+                y,
+                options.tolerance,
+                None,
+                options.feedrate,
+            ),
+            SmoothQuadratic { abs, x, y } => {
+                turtle.smooth_quadratic_bezier(abs, x, y, options.tolerance, None, options.feedrate)
+            }
+            EllipticalArc {
+                abs,
+                rx,
+                ry,
+                x_axis_rotation,
+                large_arc,
+                sweep,
+                x,
+                y,
+            } => turtle.elliptical(
+                abs,
+                rx,
+                ry,
+                x_axis_rotation,
+                large_arc,
+                sweep,
+                x,
+                y,
+                None,
+                options.feedrate,
+                options.arc_tolerance.unwrap_or(options.tolerance),
+            ),
+        };
+        // On the line of the move that ends the segment
+        if let Some(comment) = comment.filter(|_| !tokens.is_empty()) {
+            tokens.push(Token::Comment {
+                is_inline: true,
+                inner: Cow::Owned(comment),
+            });
+        }
+        program.extend(tokens);
+    }
+    Ok(program)
+}
+
+/// What [degeneracy] calls an arc with a zero radius, which is drawn as a line
+const ZERO_RADIUS: &str = "an arc with a zero radius";
+
+/// What is degenerate about a segment drawn from a point in user units, if anything
+fn degeneracy(segment: &PathSegment, from: Point<f64>) -> Option<&'static str> {
+    use PathSegment::*;
+    let is_here = |abs: bool, x: f64, y: f64| {
+        let offset = if abs {
+            point(x, y) - from
+        } else {
+            vector(x, y)
+        };
+        offset.x.abs() <= f64::EPSILON && offset.y.abs() <= f64::EPSILON
+    };
+    match *segment {
+        MoveTo { .. } | ClosePath { .. } => None,
+        LineTo { abs, x, y } => is_here(abs, x, y).then_some("a line with no length"),
+        HorizontalLineTo { abs, x } => {
+            is_here(abs, x, if abs { from.y } else { 0. }).then_some("a line with no length")
+        }
+        VerticalLineTo { abs, y } => {
+            is_here(abs, if abs { from.x } else { 0. }, y).then_some("a line with no length")
+        }
+        CurveTo {
+            abs,
+            x1,
+            y1,
+            x2,
+            y2,
+            x,
+            y,
+        } => (is_here(abs, x1, y1) && is_here(abs, x2, y2) && is_here(abs, x, y))
+            .then_some("a curve with no length"),
+        SmoothCurveTo { abs, x2, y2, x, y }
+        | Quadratic {
+            abs,
+            x1: x2,
+            y1: y2,
+            x,
+            y,
+        } => (is_here(abs, x2, y2) && is_here(abs, x, y)).then_some("a curve with no length"),
+        SmoothQuadratic { abs, x, y } => is_here(abs, x, y).then_some("a curve with no length"),
+        EllipticalArc {
+            abs, rx, ry, x, y, ..
+        } => {
+            if is_here(abs, x, y) {
+                Some("an arc that ends where it starts")
+            } else if rx == 0. || ry == 0. {
+                Some(ZERO_RADIUS)
+            } else {
+                None
+            }
+        }
+    }
+}
+
+/// A path command written like it is in the SVG, i.e. `c 1,2 3,4 5,6`
+fn segment_comment(segment: &PathSegment) -> String {
+    use PathSegment::*;
+    let letter = |abs: bool, letter: char| {
+        if abs {
+            letter
+        } else {
+            letter.to_ascii_lowercase()
+        }
+    };
+    match *segment {
+        MoveTo { abs, x, y } => format!("{} {},{}", letter(abs, 'M'), x, y),
+        ClosePath { abs } => letter(abs, 'Z').to_string(),
+        LineTo { abs, x, y } => format!("{} {},{}", letter(abs, 'L'), x, y),
+        HorizontalLineTo { abs, x } => format!("{} {}", letter(abs, 'H'), x),
+        VerticalLineTo { abs, y } => format!("{} {}", letter(abs, 'V'), y),
+        CurveTo {
+            abs,
+            x1,
+            y1,
+            x2,
+            y2,
+            x,
+            y,
+        } => format!(
+            "{} {},{} {},{} {},{}",
+            letter(abs, 'C'),
+            x1,
+            y1,
+            x2,
+            y2,
+            x,
+            y
+        ),
+        SmoothCurveTo { abs, x2, y2, x, y } => {
+            format!("{} {},{} {},{}", letter(abs, 'S'), x2, y2, x, y)
+        }
+        Quadratic { abs, x1, y1, x, y } => {
+            format!("{} {},{} {},{}", letter(abs, 'Q'), x1, y1, x, y)
+        }
+        SmoothQuadratic { abs, x, y } => format!("{} {},{}", letter(abs, 'T'), x, y),
+        EllipticalArc {
+            abs,
+            rx,
+            ry,
+            x_axis_rotation,
+            large_arc,
+            sweep,
+            x,
+            y,
+        } => format!(
+            "{} {},{} {} {},{} {},{}",
+            letter(abs, 'A'),
+            rx,
+            ry,
+            x_axis_rotation,
+            large_arc as u8,
+            sweep as u8,
+            x,
+            y
+        ),
+    }
+}
+
+/// The settings a path is converted with, for finding out why it came out the way it did
+fn debug_comment(turtle: &Turtle, options: &ProgramOptions) -> Token<'static> {
+    let transform = turtle.current_transform();
+    let mut comment = format!(
+        "transform {} {} {} {} {} {}, feedrate {}",
+        transform.m11,
+        transform.m12,
+        transform.m21,
+        transform.m22,
+        transform.m31,
+        transform.m32,
+        options.feedrate
+    );
+    if let Some(power) = turtle.machine.power {
+        comment += &format!(", power {}", power);
+    }
+    Token::Comment {
+        is_inline: true,
+        inner: Cow::Owned(comment),
+    }
+}
+
+fn svg_transform_into_euclid_transform(svg_transform: TransformListToken) -> Transform2D<f64> {
+    use TransformListToken::*;
+    match svg_transform {
+        Matrix { a, b, c, d, e, f } => Transform2D::new(a, b, c, d, e, f),
+        Translate { tx, ty } => Transform2D::translation(tx, ty),
+        Scale { sx, sy } => Transform2D::scale(sx, sy),
+        Rotate { angle } => Transform2D::rotation(Angle::degrees(angle)),
+        // https://drafts.csswg.org/css-transforms/#SkewXDefined
+        SkewX { angle } => Transform3D::skew(Angle::degrees(angle), Angle::zero()).to_2d(),
+        // https://drafts.csswg.org/css-transforms/#SkewYDefined
+        SkewY { angle } => Transform3D::skew(Angle::zero(), Angle::degrees(angle)).to_2d(),
+    }
+}
+
+/// Convenience function for converting absolute lengths to millimeters
+///
+/// Absolute lengths are listed in [CSS 4 §6.2](https://www.w3.org/TR/css-values/#absolute-lengths).
+/// Relative lengths in [CSS 4 §6.1](https://www.w3.org/TR/css-values/#relative-lengths) are resolved by [absolute_length] beforehand, and any others are an error.
+///
+/// A default DPI of 96 is used as per [CSS 4 §7.4](https://www.w3.org/TR/css-values/#resolution), which you can adjust with --dpi.
+/// Increasing DPI reduces the scale of an SVG.
+fn length_to_mm(l: svgtypes::Length, dpi: f64) -> Result<f64, ConversionError> {
+    use svgtypes::LengthUnit::*;
+    use uom::si::f64::Length;
+    use uom::si::length::*;
+
+    let dpi_scaling = dpi / 96.0;
     let length = match l.unit {
         Cm => Length::new::<centimeter>(l.num),
         Mm => Length::new::<millimeter>(l.num),
         In => Length::new::<inch>(l.num),
         Pc => Length::new::<pica_computer>(l.num) / dpi_scaling,
         Pt => Length::new::<point_computer>(l.num) / dpi_scaling,
-        Px => Length::new::<inch>(l.num / dpi_scaling),
-        other => {
-            warn!(
-                "Converting from '{:?}' to millimeters is not supported, treating as millimeters",
-                other
+        Px => Length::new::<inch>(l.num / dpi),
+        _ => return Err(ConversionError::UnsupportedLength { length: l }),
+    };
+
+    Ok(length.get::<millimeter>())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::analysis;
+    use crate::machine::Machine;
+    use crate::postprocess::{self, OriginMode};
+    use crate::raster::{RasterMode, RasterOptions};
+    use crate::writer::tokens_into_gcode_bytes;
+    use pretty_assertions::assert_eq;
+
+    fn get_actual(input: &str) -> String {
+        get_actual_with(input, ProgramOptions::default())
+    }
+
+    fn get_actual_with(input: &str, options: ProgramOptions) -> String {
+        let machine = Machine::new(None, None, None, None);
+        let document = roxmltree::Document::parse(input).unwrap();
+
+        let mut turtle = Turtle::new(machine);
+        let mut program = svg2program(&document, options, &mut turtle).unwrap();
+        postprocess::set_origin(
+            &mut program,
+            lyon_geom::point(0., 0.),
+            &[OriginMode::BoundingBoxMin, OriginMode::BoundingBoxMin],
+        );
+
+        let mut actual = vec![];
+        assert!(tokens_into_gcode_bytes(&program, &mut actual).is_ok());
+        String::from_utf8(actual).unwrap()
+    }
+
+    #[test]
+    fn square_produces_expected_gcode() {
+        let square = include_str!("../tests/square.svg");
+        let actual = get_actual(square);
+
+        assert_eq!(actual, include_str!("../tests/square.gcode"))
+    }
+
+    #[test]
+    fn square_transformed_produces_expected_gcode() {
+        let square_transformed = include_str!("../tests/square_transformed.svg");
+        let actual = get_actual(square_transformed);
+
+        assert_eq!(actual, include_str!("../tests/square_transformed.gcode"))
+    }
+
+    #[test]
+    fn square_viewport_produces_expected_gcode() {
+        let square_transformed = include_str!("../tests/square_viewport.svg");
+        let actual = get_actual(square_transformed);
+
+        assert_eq!(actual, include_str!("../tests/square_viewport.gcode"))
+    }
+
+    #[test]
+    fn square_nested_produces_expected_gcode() {
+        let square_nested = include_str!("../tests/square_nested.svg");
+        let actual = get_actual(square_nested);
+
+        assert_eq!(actual, include_str!("../tests/square_nested.gcode"))
+    }
+
+    #[test]
+    fn symbol_switch_produces_expected_gcode() {
+        let symbol_switch = include_str!("../tests/symbol_switch.svg");
+        let actual = get_actual(symbol_switch);
+
+        assert_eq!(actual, include_str!("../tests/symbol_switch.gcode"));
+
+        // Paths are counted each time a use draws them, and once for the switch
+        let options = ProgramOptions {
+            progress_comments: true,
+            ..Default::default()
+        };
+        let actual = get_actual_with(symbol_switch, options);
+        let last_progress = actual
+            .lines()
+            .rev()
+            .find_map(|line| line.split_once(";PROGRESS "))
+            .map(|(_, progress)| progress);
+        assert_eq!(last_progress, Some("4/4"));
+    }
+
+    #[test]
+    fn style_sheet_produces_expected_gcode() {
+        let style_sheet = include_str!("../tests/style_sheet.svg");
+        let actual = get_actual(style_sheet);
+
+        assert_eq!(actual, include_str!("../tests/style_sheet.gcode"))
+    }
+
+    #[test]
+    fn stroke_outline_produces_expected_gcode() {
+        let stroke_outline = include_str!("../tests/stroke_outline.svg");
+        let options = ProgramOptions {
+            outline_strokes: true,
+            ..Default::default()
+        };
+        let actual = get_actual_with(stroke_outline, options);
+
+        assert_eq!(actual, include_str!("../tests/stroke_outline.gcode"))
+    }
+
+    #[test]
+    fn dashes_produce_expected_gcode() {
+        let dashes = include_str!("../tests/dashes.svg");
+        let actual = get_actual(dashes);
+
+        assert_eq!(actual, include_str!("../tests/dashes.gcode"))
+    }
+
+    #[test]
+    fn operations_produce_expected_gcode() {
+        let operations = include_str!("../tests/operations.svg");
+        let options = ProgramOptions {
+            operations: vec![
+                "score,color=red,feedrate=1000".parse().unwrap(),
+                "mark,attribute=data-mark=yes,feedrate=500".parse().unwrap(),
+                "cut,layer=cuts,feedrate=100,passes=2".parse().unwrap(),
+            ],
+            ..Default::default()
+        };
+        let actual = get_actual_with(operations, options);
+
+        assert_eq!(actual, include_str!("../tests/operations.gcode"))
+    }
+
+    #[test]
+    fn degenerate_geometry_can_be_an_error() {
+        let document =
+            roxmltree::Document::parse(include_str!("../tests/empty_paths.svg")).unwrap();
+        let options = ProgramOptions {
+            degenerate: DegenerateHandling::Error,
+            ..Default::default()
+        };
+        let mut turtle = Turtle::new(Machine::new(None, None, None, None));
+        match svg2program(&document, options, &mut turtle) {
+            Err(ConversionError::DegenerateGeometry {
+                element, segment, ..
+            }) => assert_eq!(
+                (element.as_str(), segment.as_str()),
+                ("path#point", "L 2,2")
+            ),
+            other => panic!("expected degenerate geometry, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn feed_scales_multiply_down_the_tree() {
+        let actual = get_actual(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="10mm" height="10mm" viewBox="0 0 10 10">
+                <g data-feed-scale="0.5">
+                    <path d="M0,0 L1,0"/>
+                    <g data-feed-scale="0.5"><path d="M2,0 L3,0"/></g>
+                </g>
+                <path d="M4,0 L5,0"/>
+            </svg>"#,
+        );
+        let feedrates = actual
+            .lines()
+            .filter_map(|line| line.split_once(" F"))
+            .map(|(_, feedrate)| feedrate.split([';', '(']).next().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(feedrates, ["150", "75", "300"]);
+
+        let document = roxmltree::Document::parse(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="10mm" height="10mm" viewBox="0 0 10 10"><path data-feed-scale="half" d="M0,0 L1,0"/></svg>"#,
+        )
+        .unwrap();
+        let mut turtle = Turtle::new(Machine::new(None, None, None, None));
+        assert!(matches!(
+            svg2program(&document, ProgramOptions::default(), &mut turtle),
+            Err(ConversionError::InvalidAttribute {
+                attribute: "data-feed-scale",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn pauses_between_colors_ignore_how_they_are_written() {
+        let document = roxmltree::Document::parse(
+            r##"<svg xmlns="http://www.w3.org/2000/svg" width="10mm" height="10mm" viewBox="0 0 10 10">
+                <path stroke="red" d="M1 1 L2 2"/>
+                <path stroke="#f00" d="M3 3 L4 4"/>
+                <path stroke="blue" d="M5 5 L6 6"/>
+            </svg>"##,
+        )
+        .unwrap();
+        let options = ProgramOptions {
+            pause_between: Some(PauseGrouping::Colors),
+            ..Default::default()
+        };
+        let mut turtle = Turtle::new(Machine::new(None, None, None, None));
+        let program = svg2program(&document, options, &mut turtle).unwrap();
+        let mut actual = vec![];
+        tokens_into_gcode_bytes(&program, &mut actual).unwrap();
+        let actual = String::from_utf8(actual).unwrap();
+
+        assert_eq!(actual.matches("M0").count(), 1);
+        assert!(actual.contains("M0;Next color: #0000ff"));
+    }
+
+    #[test]
+    fn square_produces_expected_polylines() {
+        let document = roxmltree::Document::parse(include_str!("../tests/square.svg")).unwrap();
+        let polylines = svg2polylines(&document, ProgramOptions::default()).unwrap();
+
+        assert_eq!(polylines.len(), 2);
+        assert_eq!(polylines[0].0, "svg#svg8 > g#layer1 > path#path838");
+        assert_eq!(
+            polylines[0].1,
+            vec![vec![
+                lyon_geom::point(1., 9.),
+                lyon_geom::point(9., 9.),
+                lyon_geom::point(9., 1.),
+                lyon_geom::point(1., 1.),
+                lyon_geom::point(1., 9.),
+            ]]
+        );
+    }
+
+    #[test]
+    fn paths_produce_expected_gcode() {
+        use svgtypes::PathSegment::*;
+        let mut turtle = Turtle::new(Machine::new(None, None, None, None));
+        let program = paths2program(
+            vec![(
+                "triangle".to_string(),
+                vec![
+                    MoveTo {
+                        abs: true,
+                        x: 0.,
+                        y: 0.,
+                    },
+                    LineTo {
+                        abs: true,
+                        x: 10.,
+                        y: 0.,
+                    },
+                    LineTo {
+                        abs: false,
+                        x: -5.,
+                        y: 5.,
+                    },
+                    ClosePath { abs: true },
+                ],
+            )],
+            &ProgramOptions::default(),
+            &mut turtle,
+        )
+        .unwrap();
+
+        let mut actual = vec![];
+        assert!(tokens_into_gcode_bytes(&program, &mut actual).is_ok());
+        assert_eq!(
+            String::from_utf8(actual).unwrap(),
+            "G21\nG90;triangle\nG0 X0 Y0\nG1 X10 Y0 F300\nG1 X5 Y5 F300\nG1 X0 Y0 F300\nM2\n"
+        );
+    }
+
+    #[test]
+    fn cancelled_conversion_stops() {
+        let document = roxmltree::Document::parse(include_str!("../tests/square.svg")).unwrap();
+        let mut turtle = Turtle::new(Machine::new(None, None, None, None));
+        let cancel = std::sync::atomic::AtomicBool::new(true);
+        let result = svg2program_with_progress(
+            &document,
+            ProgramOptions::default(),
+            &mut turtle,
+            None,
+            Some(&cancel),
+        );
+        assert!(matches!(result, Err(ConversionError::Cancelled)));
+    }
+
+    #[test]
+    fn conversion_can_be_cancelled_partway() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        let document = roxmltree::Document::parse(include_str!("../tests/square.svg")).unwrap();
+        let mut turtle = Turtle::new(Machine::new(None, None, None, None));
+        let cancel = AtomicBool::new(false);
+        let mut paths_converted = 0;
+        // Stands in for another thread that cancels once the first path is done
+        let mut progress = |converted: usize, _total: usize| {
+            paths_converted = converted;
+            cancel.store(true, Ordering::Relaxed);
+        };
+        let result = svg2program_with_progress(
+            &document,
+            ProgramOptions::default(),
+            &mut turtle,
+            Some(&mut progress),
+            Some(&cancel),
+        );
+
+        assert!(matches!(result, Err(ConversionError::Cancelled)));
+        assert_eq!(paths_converted, 1);
+    }
+
+    #[test]
+    fn progress_ends_with_every_drawn_element_converted() {
+        let document = roxmltree::Document::parse(
+            r##"<svg xmlns="http://www.w3.org/2000/svg" width="10mm" height="10mm" viewBox="0 0 10 10">
+                <defs><path id="used" d="M1 1 L2 2"/></defs>
+                <use href="#used"/>
+                <use href="#used" x="3"/>
+                <path d="M5 5 L6 6"/>
+                <switch><path d="M7 7 L8 8"/><path d="M8 8 L9 9"/></switch>
+                <image width="4" height="2" href="data:image/png;base64,iVBORw0KGgoAAAANSUhEUgAAAAQAAAAECAAAAACMmsGiAAAAEElEQVR42mNgCF31nwGVAABJIAf5jV7nxQAAAABJRU5ErkJggg=="/>
+            </svg>"##,
+        )
+        .unwrap();
+        let options = ProgramOptions {
+            progress_comments: true,
+            raster: Some(RasterOptions {
+                scan_gap: 1.,
+                mode: RasterMode::Power,
+                max_power: 1000.,
+                base_dir: None,
+            }),
+            ..Default::default()
+        };
+        let mut turtle = Turtle::new(Machine::new(None, None, None, None));
+        let mut last = None;
+        let mut progress = |converted: usize, total: usize| last = Some((converted, total));
+        let program =
+            svg2program_with_progress(&document, options, &mut turtle, Some(&mut progress), None)
+                .unwrap();
+        let mut actual = vec![];
+        tokens_into_gcode_bytes(&program, &mut actual).unwrap();
+        let actual = String::from_utf8(actual).unwrap();
+
+        assert_eq!(last, Some((5, 5)));
+        let comments = actual
+            .lines()
+            .filter_map(|line| line.split_once(";PROGRESS "))
+            .map(|(_, progress)| progress)
+            .collect::<Vec<_>>();
+        assert_eq!(comments, ["0/5", "1/5", "2/5", "3/5", "4/5", "5/5"]);
+    }
+
+    #[test]
+    fn conversion_report_totals_each_element() {
+        let document = roxmltree::Document::parse(include_str!("../tests/square.svg")).unwrap();
+        let mut turtle = Turtle::new(Machine::new(None, None, None, None));
+        let report =
+            convert_with_metadata(&document, ProgramOptions::default(), &mut turtle).unwrap();
+
+        let names = report
+            .elements
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            [
+                "svg#svg8 > g#layer1 > path#path838",
+                "svg#svg8 > g#layer1 > path#path832"
+            ]
+        );
+        assert!((report.elements[0].1.cut_length - 32.).abs() < 1e-9);
+        let bounds = report.statistics.bounds.unwrap();
+        assert_eq!((bounds.min.x, bounds.max.x), (1., 9.));
+        // The circle inside the square is cut after it
+        assert_eq!(report.diagnostics.len(), 1);
+        assert!(report.diagnostics[0].contains("path#path838"));
+    }
+
+    #[test]
+    fn raster_power_follows_image_darkness() {
+        let document =
+            roxmltree::Document::parse(include_str!("../tests/gradient_image.svg")).unwrap();
+        let options = ProgramOptions {
+            raster: Some(RasterOptions {
+                scan_gap: 1.,
+                mode: RasterMode::Power,
+                max_power: 1000.,
+                base_dir: None,
+            }),
+            ..Default::default()
+        };
+        let mut turtle = Turtle::new(Machine::new(None, None, None, None));
+        let program = svg2program(&document, options, &mut turtle).unwrap();
+        let mut actual = vec![];
+        tokens_into_gcode_bytes(&program, &mut actual).unwrap();
+        let powers = String::from_utf8(actual)
+            .unwrap()
+            .lines()
+            .filter_map(|line| line.split_once(" S").map(|(_, power)| power.to_string()))
+            .collect::<Vec<_>>();
+        // A gradient from black to white, where white is skipped, on two scan lines
+        assert_eq!(powers, vec!["1000", "670", "330", "330", "670", "1000"]);
+    }
+
+    #[test]
+    fn looser_arc_tolerance_emits_fewer_segments() {
+        let document = roxmltree::Document::parse(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="100mm" height="100mm" viewBox="0 0 100 100">
+                <path d="M10,50 A40,40 0 0,1 90,50"/>
+                <path d="M10,90 Q50,50 90,90"/>
+            </svg>"#,
+        )
+        .unwrap();
+        // Cut moves of the arc and of the curve after it
+        let segments = |arc_tolerance| {
+            let options = ProgramOptions {
+                arc_tolerance,
+                ..Default::default()
+            };
+            let mut turtle = Turtle::new(Machine::new(None, None, None, None));
+            let program = svg2program(&document, options, &mut turtle).unwrap();
+            let mut actual = vec![];
+            tokens_into_gcode_bytes(&program, &mut actual).unwrap();
+            let actual = String::from_utf8(actual).unwrap();
+            let (arc, curve) = actual.split_once("G0").unwrap().1.split_once("G0").unwrap();
+            let count = |moves: &str| moves.matches("G1").count();
+            (count(arc), count(curve))
+        };
+        let (tight_arc, tight_curve) = segments(None);
+        let (loose_arc, loose_curve) = segments(Some(1.));
+        assert!(loose_arc < tight_arc, "{} < {}", loose_arc, tight_arc);
+        assert_eq!(loose_curve, tight_curve);
+    }
+
+    #[test]
+    fn dimensionless_svgs_follow_the_policy() {
+        let document = roxmltree::Document::parse(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 96 96"><path d="M0,0 L96,0"/></svg>"#,
+        )
+        .unwrap();
+        let line_length = |dimensionless| {
+            let options = ProgramOptions {
+                dimensionless,
+                ..Default::default()
+            };
+            let mut turtle = Turtle::new(Machine::new(None, None, None, None));
+            svg2program(&document, options, &mut turtle)
+                .map(|program| analysis::statistics(&analysis::motions(&program)).cut_length)
+        };
+        assert!((line_length(DimensionlessPolicy::Px).unwrap() - 25.4).abs() < 1e-9);
+        assert!((line_length(DimensionlessPolicy::Mm).unwrap() - 96.).abs() < 1e-9);
+        assert!(matches!(
+            line_length(DimensionlessPolicy::Error),
+            Err(ConversionError::Dimensionless)
+        ));
+    }
+
+    #[test]
+    fn dimensions_without_a_physical_size_are_an_error() {
+        let document = roxmltree::Document::parse(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 96 96"><path d="M0,0 L96,0"/></svg>"#,
+        )
+        .unwrap();
+        let convert = |width: &str, height: &str| {
+            let options = ProgramOptions {
+                dimensions: Some([width.parse().unwrap(), height.parse().unwrap()]),
+                ..Default::default()
+            };
+            let mut turtle = Turtle::new(Machine::new(None, None, None, None));
+            svg2program(&document, options, &mut turtle)
+        };
+        assert!(convert("210mm", "297mm").is_ok());
+        for (width, height) in [("210", "297"), ("10em", "10em"), ("210mm", "2ex")] {
+            assert!(
+                matches!(
+                    convert(width, height),
+                    Err(ConversionError::UnsupportedLength { .. })
+                ),
+                "{} by {}",
+                width,
+                height
             );
-            Length::new::<millimeter>(l.num)
         }
-    };
+    }
+
+    #[test]
+    fn calc_lengths_size_the_document() {
+        let actual = get_actual(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="calc(10mm + 10mm)" height="calc(25mm - 5mm)" viewBox="0 0 10 10"><path d="M0,5 L10,5"/></svg>"#,
+        );
+        assert_eq!(
+            actual,
+            "G21\nG90;svg > path\nG0 X0 Y10\nG1 X20 Y10 F300\nM2\n"
+        );
+
+        assert_eq!(
+            evaluate_calc("calc(1in - 0.5in + 2in)"),
+            Some(Length::new(2.5, LengthUnit::In))
+        );
+        for unsupported in ["calc(1in - 2mm)", "calc(2 * 1mm)", "calc(1mm -2mm)"] {
+            assert_eq!(evaluate_calc(unsupported), None, "{}", unsupported);
+        }
+    }
+
+    #[test]
+    fn masked_elements_are_drawn_whole_or_skipped() {
+        let masked = r#"<svg xmlns="http://www.w3.org/2000/svg" width="10mm" height="10mm" viewBox="0 0 10 10">
+            <mask id="m"><path d="M0,0 L10,0"/></mask>
+            <pattern id="p"><path d="M0,2 L10,2"/></pattern>
+            <path d="M0,5 L10,5" mask="url(#m)"/>
+            <path d="M0,8 L10,8"/>
+        </svg>"#;
+        let cuts = |masks| {
+            get_actual_with(
+                masked,
+                ProgramOptions {
+                    masks,
+                    ..Default::default()
+                },
+            )
+            .lines()
+            .filter(|line| line.starts_with("G1"))
+            .filter_map(|line| line.split(';').next())
+            .map(str::to_string)
+            .collect::<Vec<_>>()
+        };
+
+        // The contents of the mask and pattern are never cut
+        assert_eq!(
+            cuts(MaskHandling::Ignore),
+            ["G1 X10 Y5 F300", "G1 X10 Y2 F300"]
+        );
+        assert_eq!(cuts(MaskHandling::Skip), ["G1 X10 Y2 F300"]);
+    }
+
+    #[test]
+    fn relative_lengths_follow_their_viewport_and_font_size() {
+        let document = roxmltree::Document::parse(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="100mm" height="50mm" viewBox="0 0 100 50" font-size="10">
+                <svg x="10%" y="2em" width="50%" height="100%" viewBox="0 0 10 10"><path d="M0,0 L10,0"/></svg>
+            </svg>"#,
+        )
+        .unwrap();
+        let mut turtle = Turtle::new(Machine::new(None, None, None, None));
+        let program = svg2program(&document, ProgramOptions::default(), &mut turtle).unwrap();
+        let mut actual = vec![];
+        tokens_into_gcode_bytes(&program, &mut actual).unwrap();
+        let actual = String::from_utf8(actual).unwrap();
+        assert!(actual.contains("G0 X10 Y30"), "{}", actual);
+        assert!(actual.contains("G1 X60 Y30"), "{}", actual);
+    }
 
-    length.get::<millimeter>()
+    /// Check where a 10 by 10 square view box lands in a viewport for each preserveAspectRatio
+    fn assert_view_box_placement(width: &str, height: &str, cases: &[(&str, [f64; 4])]) {
+        for (aspect, expected) in cases {
+            let svg = format!(
+                r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}" viewBox="0 0 10 10" preserveAspectRatio="{}"><path d="M0,0 H10 V10 H0 Z"/></svg>"#,
+                width, height, aspect
+            );
+            let document = roxmltree::Document::parse(&svg).unwrap();
+            let mut turtle = Turtle::new(Machine::new(None, None, None, None));
+            let program = svg2program(&document, ProgramOptions::default(), &mut turtle).unwrap();
+            let bounds = analysis::statistics(&analysis::motions(&program))
+                .bounds
+                .unwrap();
+            let actual = [bounds.min.x, bounds.min.y, bounds.max.x, bounds.max.y];
+            assert!(
+                actual
+                    .iter()
+                    .zip(expected)
+                    .all(|(actual, expected)| (actual - expected).abs() < 1e-9),
+                "{} in {} by {}: {:?}",
+                aspect,
+                width,
+                height,
+                actual
+            );
+        }
+    }
+
+    #[test]
+    fn preserve_aspect_ratio_places_the_view_box_in_a_wide_viewport() {
+        // Meet fits the square in 10mm and slides it along X, slice covers the viewport with
+        // 20mm and slides it along Y, and none stretches it
+        assert_view_box_placement(
+            "20mm",
+            "10mm",
+            &[
+                ("xMinYMin meet", [0., 0., 10., 10.]),
+                ("xMidYMin meet", [5., 0., 15., 10.]),
+                ("xMaxYMin meet", [10., 0., 20., 10.]),
+                ("xMinYMid meet", [0., 0., 10., 10.]),
+                ("xMidYMid meet", [5., 0., 15., 10.]),
+                ("xMaxYMid meet", [10., 0., 20., 10.]),
+                ("xMinYMax meet", [0., 0., 10., 10.]),
+                ("xMidYMax meet", [5., 0., 15., 10.]),
+                ("xMaxYMax meet", [10., 0., 20., 10.]),
+                ("xMinYMin slice", [0., -10., 20., 10.]),
+                ("xMidYMin slice", [0., -10., 20., 10.]),
+                ("xMaxYMin slice", [0., -10., 20., 10.]),
+                ("xMinYMid slice", [0., -5., 20., 15.]),
+                ("xMidYMid slice", [0., -5., 20., 15.]),
+                ("xMaxYMid slice", [0., -5., 20., 15.]),
+                ("xMinYMax slice", [0., 0., 20., 20.]),
+                ("xMidYMax slice", [0., 0., 20., 20.]),
+                ("xMaxYMax slice", [0., 0., 20., 20.]),
+                ("none", [0., 0., 20., 10.]),
+            ],
+        );
+    }
+
+    #[test]
+    fn preserve_aspect_ratio_places_the_view_box_in_a_tall_viewport() {
+        // Meet slides the square along Y, where the top of the viewport is the highest Y, and
+        // slice slides it along X
+        assert_view_box_placement(
+            "10mm",
+            "20mm",
+            &[
+                ("xMinYMin meet", [0., 10., 10., 20.]),
+                ("xMidYMin meet", [0., 10., 10., 20.]),
+                ("xMaxYMin meet", [0., 10., 10., 20.]),
+                ("xMinYMid meet", [0., 5., 10., 15.]),
+                ("xMidYMid meet", [0., 5., 10., 15.]),
+                ("xMaxYMid meet", [0., 5., 10., 15.]),
+                ("xMinYMax meet", [0., 0., 10., 10.]),
+                ("xMidYMax meet", [0., 0., 10., 10.]),
+                ("xMaxYMax meet", [0., 0., 10., 10.]),
+                ("xMinYMin slice", [0., 0., 20., 20.]),
+                ("xMidYMin slice", [-5., 0., 15., 20.]),
+                ("xMaxYMin slice", [-10., 0., 10., 20.]),
+                ("xMinYMid slice", [0., 0., 20., 20.]),
+                ("xMidYMid slice", [-5., 0., 15., 20.]),
+                ("xMaxYMid slice", [-10., 0., 10., 20.]),
+                ("xMinYMax slice", [0., 0., 20., 20.]),
+                ("xMidYMax slice", [-5., 0., 15., 20.]),
+                ("xMaxYMax slice", [-10., 0., 10., 20.]),
+                ("none", [0., 0., 10., 20.]),
+            ],
+        );
+    }
 }
@@ -0,0 +1,134 @@
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::Instant;
+
+use lyon_geom::point;
+
+use crate::analysis::{motions, statistics};
+use crate::converter::{svg2program, ProgramOptions};
+use crate::machine::Machine;
+use crate::postprocess::{set_origin, OriginMode};
+use crate::preview::preview_svg;
+use crate::turtle::Turtle;
+
+/// Settings that each example is converted with, which exercise curve and arc flattening
+fn permutations() -> Vec<(&'static str, ProgramOptions)> {
+    vec![
+        ("default", ProgramOptions::default()),
+        (
+            "coarse curves",
+            ProgramOptions {
+                tolerance: 0.1,
+                ..Default::default()
+            },
+        ),
+        (
+            "coarse arcs",
+            ProgramOptions {
+                arc_tolerance: Some(0.5),
+                ..Default::default()
+            },
+        ),
+        (
+            "outlined strokes",
+            ProgramOptions {
+                outline_strokes: true,
+                ..Default::default()
+            },
+        ),
+    ]
+}
+
+/// Convert every SVG in a directory with several settings, and write a preview of each program
+/// along with its statistics to an HTML page in `out`, to look over the effects of changes to
+/// the conversion before releasing them
+pub fn write_gallery(examples: &Path, out: &Path) -> io::Result<()> {
+    let mut examples = fs::read_dir(examples)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    examples.retain(|path| path.extension().is_some_and(|extension| extension == "svg"));
+    examples.sort();
+    fs::create_dir_all(out)?;
+
+    let permutations = permutations();
+    let mut html = String::from(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>svg2gcode gallery</title>\n\
+         <style>td { vertical-align: top; } img { width: 240px; height: 240px; object-fit: contain; }</style>\n\
+         </head>\n<body>\n<table>\n<tr><th>Example</th>",
+    );
+    for (name, _) in &permutations {
+        let _ = write!(html, "<th>{}</th>", name);
+    }
+    html.push_str("</tr>\n");
+
+    for example in &examples {
+        let stem = example
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        info!("Converting {}", example.display());
+        let input = fs::read_to_string(example)?;
+        let _ = write!(html, "<tr><td>{}</td>", escape(&stem));
+        let document = match roxmltree::Document::parse(&input) {
+            Ok(document) => document,
+            Err(err) => {
+                let _ = writeln!(
+                    html,
+                    "<td colspan=\"{}\">could not parse: {}</td></tr>",
+                    permutations.len(),
+                    escape(&err.to_string())
+                );
+                continue;
+            }
+        };
+
+        for (i, (_, options)) in permutations.iter().enumerate() {
+            let mut turtle = Turtle::new(Machine::new(None, None, None, None));
+            let start = Instant::now();
+            let mut program = match svg2program(&document, options.clone(), &mut turtle) {
+                Ok(program) => program,
+                Err(err) => {
+                    let _ = write!(html, "<td>{}</td>", escape(&err.to_string()));
+                    continue;
+                }
+            };
+            let elapsed = start.elapsed();
+            set_origin(
+                &mut program,
+                point(0., 0.),
+                &[OriginMode::BoundingBoxMin, OriginMode::BoundingBoxMin],
+            );
+
+            let preview = format!("{}-{}.svg", stem, i);
+            fs::write(out.join(&preview), preview_svg(&program, None))?;
+            let statistics = statistics(&motions(&program));
+            let _ = write!(
+                html,
+                "<td><img src=\"{}\"><br>{} tokens in {:.1}ms<br>{:.1}mm cut, {:.1}mm travel</td>",
+                escape(&preview),
+                program.len(),
+                elapsed.as_secs_f64() * 1000.,
+                statistics.cut_length,
+                statistics.travel_length
+            );
+        }
+        html.push_str("</tr>\n");
+    }
+    html.push_str("</table>\n</body>\n</html>\n");
+    fs::write(out.join("index.html"), html)?;
+    info!(
+        "Wrote a gallery of {} examples to {}",
+        examples.len(),
+        out.join("index.html").display()
+    );
+    Ok(())
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -0,0 +1,42 @@
+#[macro_use]
+extern crate log;
+
+/// Interprets generated GCode to check it for problems
+pub mod analysis;
+/// Converts an SVG to GCode in an internal representation
+pub mod converter;
+/// Converts a directory of examples into a page of previews for checking changes
+pub mod gallery;
+/// Emulates the state of an arbitrary machine that can run GCode
+pub mod machine;
+/// Operations that are easier to implement after GCode is generated, or would
+/// over-complicate SVG conversion
+pub mod postprocess;
+/// Draws generated GCode as an SVG for checking it before it is run
+pub mod preview;
+/// Reads named machine profiles of command line options
+pub mod profile;
+/// Engraves bitmap images line by line
+pub mod raster;
+/// Streams programs to controllers over serial ports
+pub mod sender;
+/// Builds the paths of basic shapes, for programs that generate them without an SVG
+pub mod shape;
+/// Reproduces the state of a machine running a program, to check it
+pub mod simulator;
+/// Traces the outlines of strokes
+pub mod stroke;
+/// Resolves CSS properties from style attributes and style sheets
+pub mod style;
+/// Generates a pattern for checking the axis directions and origin of a machine
+pub mod test_pattern;
+/// Renders a toolpath preview image that is embedded in the program as comments
+pub mod thumbnail;
+/// Traces the outlines of the dark areas of bitmaps into paths
+#[cfg(feature = "tracing")]
+pub mod trace;
+/// Provides an interface for drawing lines in GCode
+/// This concept is referred to as [Turtle graphics](https://en.wikipedia.org/wiki/Turtle_graphics).
+pub mod turtle;
+/// Formats GCode tokens as text
+pub mod writer;
@@ -1,4 +1,10 @@
-use g_code::{command, emit::Token, parse::ast::Snippet};
+use g_code::{
+    command,
+    emit::{Field, Token, Value},
+    parse::{ast::Snippet, snippet_parser, ParseError},
+};
+use std::borrow::Cow;
+use std::fmt;
 
 /// Whether the tool is active (i.e. cutting)
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
@@ -34,28 +40,542 @@ impl std::ops::Not for Distance {
     }
 }
 
+/// A family of controllers that interpret GCode in the same way
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Dialect {
+    Grbl,
+    /// Lasers are commonly wired to the fan output, dwells are in milliseconds, and there is no
+    /// program end
+    Marlin,
+    /// Dwells are in milliseconds
+    Smoothie,
+    /// Path blending tolerance is set with G64 P
+    LinuxCnc,
+}
+
+impl std::str::FromStr for Dialect {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "grbl" => Ok(Self::Grbl),
+            "marlin" => Ok(Self::Marlin),
+            "smoothie" | "smoothieware" => Ok(Self::Smoothie),
+            "linuxcnc" => Ok(Self::LinuxCnc),
+            other => Err(format!(
+                "unknown dialect {}, expected one of grbl, marlin, smoothie or linuxcnc",
+                other
+            )),
+        }
+    }
+}
+
+impl Dialect {
+    /// Sequences used to turn the tool on and off when none are given
+    fn default_tool_sequences(self) -> (&'static str, &'static str) {
+        match self {
+            Self::Marlin => ("M106 S255", "M107"),
+            Self::Grbl | Self::Smoothie | Self::LinuxCnc => ("M3", "M5"),
+        }
+    }
+
+    /// S value of full tool power, by default
+    pub fn full_power(self) -> f64 {
+        match self {
+            // Grbl's $30 defaults to 1000
+            Self::Grbl | Self::LinuxCnc => 1000.,
+            // PWM duty cycle of the fan output
+            Self::Marlin => 255.,
+            Self::Smoothie => 1.,
+        }
+    }
+}
+
+/// One of the work coordinate systems selected with G54 to G59
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct CoordinateSystem(u8);
+
+impl std::str::FromStr for CoordinateSystem {
+    type Err = String;
+
+    /// Parses `G54` to `G59`, or just the number
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let number = s.trim_start_matches(['G', 'g']);
+        match number.parse() {
+            Ok(number @ 54..=59) => Ok(Self(number)),
+            _ => Err(format!(
+                "unknown coordinate system {}, expected one of G54 to G59",
+                s
+            )),
+        }
+    }
+}
+
+/// Heights of the tool for machines that cut by moving it down on the Z axis, like routers and
+/// drag knives
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Depth {
+    /// Height that the tool travels at between cuts
+    pub travel_z: f64,
+    /// Height that the tool cuts at
+    pub cut_z: f64,
+    /// Feedrate to plunge down to the cut height at
+    pub plunge_feedrate: f64,
+    /// Feedrate to retract up to the travel height at
+    pub retract_feedrate: f64,
+}
+
+impl Depth {
+    fn move_to(z: f64, feedrate: Option<f64>) -> Vec<Token<'static>> {
+        let mut fields = vec![
+            ("G", Value::Integer(if feedrate.is_some() { 1 } else { 0 })),
+            ("Z", Value::Float(z)),
+        ];
+        fields.extend(feedrate.map(|feedrate| ("F", Value::Float(feedrate))));
+        fields
+            .into_iter()
+            .map(|(letters, value)| {
+                Token::Field(Field {
+                    letters: Cow::Borrowed(letters),
+                    value,
+                })
+            })
+            .collect()
+    }
+
+    fn plunge(&self) -> Vec<Token<'static>> {
+        Self::move_to(self.cut_z, Some(self.plunge_feedrate))
+    }
+
+    fn retract(&self) -> Vec<Token<'static>> {
+        Self::move_to(self.travel_z, Some(self.retract_feedrate))
+    }
+}
+
+/// Maps the tool power of cuts to the S value sent to the controller, for lasers that don't
+/// respond linearly to power or that only mark within a range of it
+#[derive(Clone, PartialEq, Debug)]
+pub struct PowerCurve {
+    /// S value of full power, which the curve is relative to
+    pub full_power: f64,
+    /// Exponent that power is raised to as a fraction of full power
+    pub gamma: f64,
+    /// Points of a curve through which power is interpolated linearly, as fractions of full power
+    /// from and to, sorted by the fraction of full power that they map from. Used instead of
+    /// [PowerCurve::gamma] unless empty.
+    pub table: Vec<(f64, f64)>,
+    /// Lowest S value of a tool that is on, since zero stays zero
+    pub min_power: Option<f64>,
+    /// Highest S value
+    pub max_power: Option<f64>,
+}
+
+impl PowerCurve {
+    /// The S value to send for a power
+    pub fn map(&self, power: f64) -> f64 {
+        if power <= 0. {
+            return power;
+        }
+        let fraction = power / self.full_power;
+        let mapped = match self.table.as_slice() {
+            [] => fraction.powf(self.gamma),
+            [(from, to), ..] if fraction <= *from => *to,
+            table => table
+                .windows(2)
+                .find(|pair| fraction <= pair[1].0)
+                .map(|pair| {
+                    let ((from_start, to_start), (from_end, to_end)) = (pair[0], pair[1]);
+                    to_start
+                        + (to_end - to_start) * (fraction - from_start) / (from_end - from_start)
+                })
+                .unwrap_or_else(|| table[table.len() - 1].1),
+        } * self.full_power;
+        let mapped = self.min_power.map_or(mapped, |min| mapped.max(min));
+        self.max_power.map_or(mapped, |max| mapped.min(max))
+    }
+
+    /// Why the curve can't be used, if it can't
+    fn problem(&self) -> Option<&'static str> {
+        if self.full_power.is_nan() || self.full_power <= 0. {
+            Some("full power must be more than zero")
+        } else if !(self.gamma > 0. && self.gamma.is_finite()) {
+            Some("gamma must be a positive number")
+        } else if self
+            .table
+            .windows(2)
+            .any(|pair| pair[0].0.partial_cmp(&pair[1].0) != Some(std::cmp::Ordering::Less))
+        {
+            Some("table must be sorted by the power it maps from, without repeating any")
+        } else if self
+            .min_power
+            .zip(self.max_power)
+            .is_some_and(|(min, max)| min.is_nan() || max.is_nan() || min > max)
+        {
+            Some("minimum power must not be more than maximum power")
+        } else {
+            None
+        }
+    }
+}
+
+/// Where the machine goes at the end of the program
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Parking {
+    /// A position in machine coordinates, reached with G53 G0
+    Position([f64; 2]),
+    /// The position stored with G28.1
+    G28,
+    /// The position stored with G30.1
+    G30,
+}
+
+impl std::str::FromStr for Parking {
+    type Err = String;
+
+    /// Parses `g28`, `g30`, or machine coordinates like `0,300`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "g28" => Ok(Self::G28),
+            "g30" => Ok(Self::G30),
+            position => {
+                let coordinates = position
+                    .split(',')
+                    .map(|coordinate| coordinate.trim().parse::<f64>())
+                    .collect::<Result<Vec<_>, _>>();
+                match coordinates.as_deref() {
+                    Ok([x, y]) => Ok(Self::Position([*x, *y])),
+                    _ => Err(format!(
+                        "unknown parking {}, expected g28, g30 or machine coordinates like 0,300",
+                        s
+                    )),
+                }
+            }
+        }
+    }
+}
+
+/// How the machine finds its home position at the start of the program
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Homing {
+    /// Grbl's homing cycle, `$H`
+    Grbl,
+    /// `G28`, which homes Marlin and moves other controllers to the position stored with G28.1
+    G28,
+}
+
+impl std::str::FromStr for Homing {
+    type Err = String;
+
+    /// Parses `grbl` (or `$h`) or `g28`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "grbl" | "$h" => Ok(Self::Grbl),
+            "g28" => Ok(Self::G28),
+            other => Err(format!("unknown homing {}, expected grbl or g28", other)),
+        }
+    }
+}
+
+impl Homing {
+    fn command(self) -> Token<'static> {
+        match self {
+            // Not GCode, so it is written as its letters alone
+            Self::Grbl => Token::Field(Field {
+                letters: Cow::Borrowed("$H"),
+                value: Value::String(Cow::Borrowed("")),
+            }),
+            Self::G28 => Token::Field(Field {
+                letters: Cow::Borrowed("G"),
+                value: Value::Integer(28),
+            }),
+        }
+    }
+}
+
+/// Finding the top of the work with a Z probe and a touch plate at the start of the program, so
+/// that the cut height is measured from it
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Probe {
+    /// How far down to probe before giving up
+    pub distance: f64,
+    /// Thickness of the touch plate, which the tool is on top of when the probe triggers
+    pub plate_thickness: f64,
+    pub feedrate: f64,
+}
+
+impl Probe {
+    /// Probe down and set Z to the plate thickness there, in the work coordinate system that the
+    /// program selects after probing. Probing is relative, since Z isn't known to be anywhere in
+    /// particular before it.
+    fn sequence(
+        &self,
+        dialect: Option<Dialect>,
+        coordinate_system: Option<CoordinateSystem>,
+    ) -> Vec<Token<'static>> {
+        let mut fields = vec![
+            ("G", Value::Integer(91)),
+            ("G", Value::Float(38.2)),
+            ("Z", Value::Float(-self.distance)),
+            ("F", Value::Float(self.feedrate)),
+            ("G", Value::Integer(90)),
+        ];
+        // P1 to P6 are G54 to G59, and P0 is the current one
+        let system = coordinate_system.map_or(0, |CoordinateSystem(number)| number as usize - 53);
+        match dialect {
+            // Marlin doesn't set work coordinate systems with G10 L20
+            Some(Dialect::Marlin) => fields.extend([("G", Value::Integer(92))]),
+            // A G92 offset would stay around for the programs run after this one, so the work
+            // coordinate system is set instead
+            _ => fields.extend([
+                ("G", Value::Integer(10)),
+                ("L", Value::Integer(20)),
+                ("P", Value::Integer(system)),
+            ]),
+        }
+        fields.push(("Z", Value::Float(self.plate_thickness)));
+        fields
+            .into_iter()
+            .map(|(letters, value)| {
+                Token::Field(Field {
+                    letters: Cow::Borrowed(letters),
+                    value,
+                })
+            })
+            .collect()
+    }
+
+    fn problem(&self, depth: Option<Depth>) -> Option<&'static str> {
+        if self.distance.is_nan() || self.distance <= 0. {
+            Some("the probe distance must be greater than 0")
+        } else if self.feedrate.is_nan() || self.feedrate <= 0. {
+            Some("the probe feedrate must be greater than 0")
+        } else if depth.is_none() {
+            Some("probing needs travel and cut heights to lift the tool off the plate")
+        } else {
+            None
+        }
+    }
+}
+
+/// How far moves are shifted while a tool is selected, to make up for where it sits relative to
+/// the tool that the machine is set up with, i.e. the other pens of a multi-pen plotter or the
+/// length of a router bit
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct ToolOffset {
+    /// Tool number, as selected with T
+    pub tool: u32,
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+impl std::str::FromStr for ToolOffset {
+    type Err = String;
+
+    /// Parses a tool number and its offset like `2=10,-5` or `2=10,-5,1.5` with Z
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let expected = || format!("unknown tool offset {}, expected one like 2=10,-5", s);
+        let (tool, offset) = s.split_once('=').ok_or_else(expected)?;
+        let tool = tool.trim().parse().map_err(|_| expected())?;
+        let offset = offset
+            .split(',')
+            .map(|coordinate| coordinate.trim().parse::<f64>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| expected())?;
+        match offset.as_slice() {
+            [x, y] => Ok(Self {
+                tool,
+                x: *x,
+                y: *y,
+                z: 0.,
+            }),
+            [x, y, z] => Ok(Self {
+                tool,
+                x: *x,
+                y: *y,
+                z: *z,
+            }),
+            _ => Err(expected()),
+        }
+    }
+}
+
+/// Variables that user-defined sequences can reference in braces, like `S{power}`
+pub const VARIABLES: [&str; 4] = ["feedrate", "power", "layer_name", "tool"];
+
+/// User-defined GCode
+#[derive(Debug)]
+pub(crate) enum Sequence<'input> {
+    Parsed(Snippet<'input>),
+    /// GCode that references [VARIABLES], parsed each time it is output
+    Template(&'input str),
+}
+
+/// Replace each `{name}` in a template with its value, or return the first name without one
+fn substitute(template: &str, value: impl Fn(&str) -> Option<String>) -> Result<String, String> {
+    let mut substituted = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        substituted += &rest[..start];
+        let end = rest[start..]
+            .find('}')
+            .map(|end| start + end)
+            .ok_or_else(|| rest[start + 1..].to_string())?;
+        let name = &rest[start + 1..end];
+        substituted += &value(name).ok_or_else(|| name.to_string())?;
+        rest = &rest[end + 1..];
+    }
+    substituted += rest;
+    Ok(substituted)
+}
+
 /// Generic machine state simulation, assuming nothing is known about the machine when initialized.
 /// This is used to reduce output GCode verbosity and run repetitive actions.
 #[derive(Debug)]
 pub struct Machine<'input> {
     pub(crate) tool_state: Option<Tool>,
     pub(crate) distance_mode: Option<Distance>,
-    pub(crate) tool_on_action: Option<Snippet<'input>>,
-    pub(crate) tool_off_action: Option<Snippet<'input>>,
-    pub(crate) program_begin_sequence: Option<Snippet<'input>>,
-    pub(crate) program_end_sequence: Option<Snippet<'input>>,
+    pub(crate) tool_on_action: Option<Sequence<'input>>,
+    pub(crate) tool_off_action: Option<Sequence<'input>>,
+    pub(crate) program_begin_sequence: Option<Sequence<'input>>,
+    pub(crate) program_end_sequence: Option<Sequence<'input>>,
+    /// Seconds to wait after turning the tool on, i.e. for a pen to settle
+    pub(crate) tool_on_dwell: Option<f64>,
+    /// Seconds to wait before turning the tool off
+    pub(crate) tool_off_dwell: Option<f64>,
+    /// Controller family to adjust commands for, else commands are emitted as for Grbl
+    pub(crate) dialect: Option<Dialect>,
+    /// Tool power is set inline with each move, so the tool is only turned on at the start of the
+    /// program and off at its end
+    pub(crate) inline_power: bool,
+    /// Values of [VARIABLES] substituted into sequences
+    pub(crate) variables: Vec<(&'static str, String)>,
+    /// Tool power or spindle speed set with an S word when the tool is turned on
+    pub(crate) power: Option<f64>,
+    /// Work coordinate system selected at the start of the program
+    pub(crate) coordinate_system: Option<CoordinateSystem>,
+    /// Position that the machine is declared to be at when the program starts, with G92
+    pub(crate) work_position: Option<[f64; 2]>,
+    /// Where the machine goes at the end of the program
+    pub(crate) parking: Option<Parking>,
+    /// Heights to travel and cut at, for machines with a Z axis
+    pub(crate) depth: Option<Depth>,
+    /// Tool on sequences are replaced with a comment, so that the program only moves the machine
+    pub(crate) dry_run: bool,
+    /// Applied to every S value once the program is generated
+    pub(crate) power_curve: Option<PowerCurve>,
+    /// Swaps in the tool numbered {tool}, else `T{tool} M6`
+    pub(crate) tool_change_action: Option<Sequence<'input>>,
+    pub(crate) tool_offsets: Vec<ToolOffset>,
+    /// Tool selected by the last tool change
+    pub(crate) tool_number: Option<u32>,
+    /// Pauses the program for the operator, else `M0`
+    pub(crate) pause_action: Option<Sequence<'input>>,
+    /// Homing at the start of the program, before the begin sequence
+    pub(crate) homing: Option<Homing>,
+    /// Probing at the start of the program, after the begin sequence
+    pub(crate) probe: Option<Probe>,
 }
 
 impl<'input> Machine<'input> {
+    pub fn new(
+        tool_on_action: Option<Snippet<'input>>,
+        tool_off_action: Option<Snippet<'input>>,
+        program_begin_sequence: Option<Snippet<'input>>,
+        program_end_sequence: Option<Snippet<'input>>,
+    ) -> Self {
+        Self {
+            tool_state: None,
+            distance_mode: None,
+            tool_on_action: tool_on_action.map(Sequence::Parsed),
+            tool_off_action: tool_off_action.map(Sequence::Parsed),
+            program_begin_sequence: program_begin_sequence.map(Sequence::Parsed),
+            program_end_sequence: program_end_sequence.map(Sequence::Parsed),
+            tool_on_dwell: None,
+            tool_off_dwell: None,
+            dialect: None,
+            inline_power: false,
+            variables: vec![],
+            power: None,
+            coordinate_system: None,
+            work_position: None,
+            parking: None,
+            depth: None,
+            dry_run: false,
+            power_curve: None,
+            tool_change_action: None,
+            tool_offsets: vec![],
+            tool_number: None,
+            pause_action: None,
+            homing: None,
+            probe: None,
+        }
+    }
+
+    /// Set the tool power (S) for the next time the tool is turned on, which is also the value of
+    /// `{power}`
+    pub fn set_power(&mut self, power: Option<f64>) {
+        self.power = power;
+        if let Some(power) = power {
+            self.set_variable("power", power.to_string());
+        }
+    }
+
+    /// Set the value of one of the [VARIABLES] for the sequences output after this
+    pub fn set_variable(&mut self, name: &'static str, value: String) {
+        match self.variables.iter_mut().find(|(n, _)| *n == name) {
+            Some((_, old)) => *old = value,
+            None => self.variables.push((name, value)),
+        }
+    }
+
+    /// Output a user-defined sequence, substituting variables if it has any
+    fn sequence(&self, sequence: &Option<Sequence<'input>>) -> Vec<Token<'input>> {
+        let template = match sequence {
+            Some(Sequence::Parsed(snippet)) => {
+                return snippet.iter_fields().map(Token::from).collect();
+            }
+            Some(Sequence::Template(template)) => template,
+            None => return vec![],
+        };
+        let gcode = substitute(template, |name| {
+            self.variables
+                .iter()
+                .find(|(n, _)| *n == name)
+                .map(|(_, value)| value.clone())
+        })
+        .unwrap_or_default();
+        match snippet_parser(&gcode) {
+            Ok(snippet) => snippet
+                .iter_fields()
+                .map(|field| {
+                    let field = Field::from(field);
+                    Token::Field(Field {
+                        letters: Cow::Owned(field.letters.into_owned()),
+                        value: match field.value {
+                            Value::String(s) => Value::String(Cow::Owned(s.into_owned())),
+                            Value::Rational(r) => Value::Rational(r),
+                            Value::Float(f) => Value::Float(f),
+                            Value::Integer(i) => Value::Integer(i),
+                        },
+                    })
+                })
+                .collect(),
+            Err(error) => {
+                warn!(
+                    "Skipping {:?}, which is not valid GCode after substituting variables: {}",
+                    gcode, error
+                );
+                vec![]
+            }
+        }
+    }
+
     /// Output gcode to turn the tool on.
     pub fn tool_on(&mut self) -> Vec<Token<'input>> {
-        if self.tool_state == Some(Tool::Off) || self.tool_state == None {
+        if self.tool_state == Some(Tool::Off) || self.tool_state.is_none() {
             self.tool_state = Some(Tool::On);
-            self.tool_on_action
-                .iter()
-                .flat_map(|s| s.iter_fields())
-                .map(Token::from)
-                .collect()
+            self.tool_on_sequence()
         } else {
             vec![]
         }
@@ -63,39 +583,363 @@ impl<'input> Machine<'input> {
 
     /// Output gcode to turn the tool off.
     pub fn tool_off(&mut self) -> Vec<Token<'input>> {
-        if self.tool_state == Some(Tool::On) || self.tool_state == None {
-            self.tool_state = Some(Tool::Off);
-            self.tool_off_action
-                .iter()
-                .flat_map(|s| s.iter_fields())
-                .map(Token::from)
-                .collect()
+        match self.tool_state {
+            Some(Tool::On) => {
+                self.tool_state = Some(Tool::Off);
+                self.tool_off_sequence()
+            }
+            // No need to wait if the tool wasn't known to be on
+            None if !self.inline_power => {
+                self.tool_state = Some(Tool::Off);
+                self.sequence(&self.tool_off_action)
+            }
+            None | Some(Tool::Off) => {
+                self.tool_state = Some(Tool::Off);
+                vec![]
+            }
+        }
+    }
+
+    /// Output gcode that turns the tool off and swaps in another tool, unless it is already the
+    /// selected one. Moves that follow are shifted by the XY offset of the new tool with
+    /// [crate::postprocess::offset_tools].
+    pub fn tool_change(&mut self, tool: u32) -> Vec<Token<'input>> {
+        if self.tool_number == Some(tool) {
+            return vec![];
+        }
+        let mut tokens = self.tool_off();
+        self.tool_number = Some(tool);
+        self.set_variable("tool", tool.to_string());
+        tokens.push(Token::Comment {
+            is_inline: false,
+            inner: Cow::Owned(format!("Tool change to T{}", tool)),
+        });
+        if self.tool_change_action.is_some() {
+            tokens.extend(self.sequence(&self.tool_change_action));
         } else {
-            vec![]
+            tokens.extend([
+                Token::Field(Field {
+                    letters: Cow::Borrowed("T"),
+                    value: Value::Integer(tool as usize),
+                }),
+                Token::Field(Field {
+                    letters: Cow::Borrowed("M"),
+                    value: Value::Integer(6),
+                }),
+            ]);
         }
+        // The tool changer may have moved the tool anywhere
+        tokens.extend(
+            self.offset_depth()
+                .map(|depth| Depth::move_to(depth.travel_z, None))
+                .unwrap_or_default(),
+        );
+        tokens
     }
 
-    /// Output user-defined setup gcode
-    pub fn program_begin(&self) -> Vec<Token<'input>> {
-        self.program_begin_sequence
-            .iter()
-            .flat_map(|s| s.iter_fields())
-            .map(Token::from)
+    /// Curve applied to every S value once the program is generated
+    pub fn power_curve(&self) -> Option<&PowerCurve> {
+        self.power_curve.as_ref()
+    }
+
+    fn selected_offset(&self) -> Option<&ToolOffset> {
+        let tool = self.tool_number?;
+        self.tool_offsets.iter().find(|offset| offset.tool == tool)
+    }
+
+    /// Heights to travel and cut at, shifted by the Z offset of the selected tool
+    fn offset_depth(&self) -> Option<Depth> {
+        let z = self.selected_offset().map_or(0., |offset| offset.z);
+        self.depth.map(|depth| Depth {
+            travel_z: depth.travel_z + z,
+            cut_z: depth.cut_z + z,
+            ..depth
+        })
+    }
+
+    /// Output the gcode that turns the tool on, regardless of the tool state.
+    /// Used when rewriting the tool state of a program after generation.
+    pub fn tool_on_sequence(&self) -> Vec<Token<'input>> {
+        if self.inline_power {
+            return vec![];
+        }
+        if self.dry_run {
+            return vec![Self::dry_run_comment()];
+        }
+        // A sequence that sets the power itself with {power} isn't given another S
+        let sets_power = matches!(
+            &self.tool_on_action,
+            Some(Sequence::Template(template)) if template.contains("{power}")
+        );
+        self.sequence(&self.tool_on_action)
+            .into_iter()
+            .chain(self.power.filter(|_| !sets_power).map(|power| {
+                Token::Field(Field {
+                    letters: Cow::Borrowed("S"),
+                    value: Value::Float(power),
+                })
+            }))
+            .chain(self.dwell(self.tool_on_dwell))
+            .chain(
+                self.offset_depth()
+                    .map(|depth| depth.plunge())
+                    .unwrap_or_default(),
+            )
+            .collect()
+    }
+
+    /// Comment left in place of turning the tool on in a dry run
+    fn dry_run_comment() -> Token<'static> {
+        Token::Comment {
+            is_inline: false,
+            inner: Cow::Borrowed("Dry run, tool stays off"),
+        }
+    }
+
+    /// Output the gcode that turns the tool off, regardless of the tool state.
+    pub fn tool_off_sequence(&self) -> Vec<Token<'input>> {
+        if self.inline_power {
+            return vec![];
+        }
+        self.offset_depth()
+            .map(|depth| depth.retract())
+            .unwrap_or_default()
+            .into_iter()
+            .chain(self.dwell(self.tool_off_dwell))
+            .chain(self.sequence(&self.tool_off_action))
             .collect()
     }
 
-    /// Output user-defined teardown gcode
+    fn dwell(&self, seconds: Option<f64>) -> Vec<Token<'input>> {
+        let scale = match self.dialect {
+            Some(Dialect::Marlin | Dialect::Smoothie) => 1000.,
+            _ => 1.,
+        };
+        seconds
+            .map(|seconds| command! {Dwell { P: seconds * scale, }}.into_token_vec())
+            .unwrap_or_default()
+    }
+
+    /// Output gcode that sets the path blending tolerance, if the dialect supports it
+    pub fn path_blending(&self, tolerance: f64) -> Vec<Token<'input>> {
+        match self.dialect {
+            Some(Dialect::LinuxCnc) => vec![
+                Token::Field(Field {
+                    letters: Cow::Borrowed("G"),
+                    value: Value::Integer(64),
+                }),
+                Token::Field(Field {
+                    letters: Cow::Borrowed("P"),
+                    value: Value::Float(tolerance),
+                }),
+            ],
+            _ => vec![],
+        }
+    }
+
+    /// Gcode that selects the work coordinate system and declares the position of the machine in
+    /// it
+    fn work_coordinates(&self) -> Vec<Token<'input>> {
+        let mut tokens = vec![];
+        if let Some(CoordinateSystem(number)) = self.coordinate_system {
+            tokens.push(Token::Field(Field {
+                letters: Cow::Borrowed("G"),
+                value: Value::Integer(number as usize),
+            }));
+        }
+        if let Some([x, y]) = self.work_position {
+            for (letters, value) in [
+                ("G", Value::Integer(92)),
+                ("X", Value::Float(x)),
+                ("Y", Value::Float(y)),
+            ] {
+                tokens.push(Token::Field(Field {
+                    letters: Cow::Borrowed(letters),
+                    value,
+                }));
+            }
+        }
+        tokens
+    }
+
+    /// Gcode that parks the machine and clears the offset set by [Machine::work_coordinates], so
+    /// that it doesn't affect the programs run after this one
+    fn park(&self) -> Vec<Token<'input>> {
+        let field = |letters: &'static str, value: Value<'static>| {
+            Token::Field(Field {
+                letters: Cow::Borrowed(letters),
+                value,
+            })
+        };
+        let mut tokens = match self.parking {
+            Some(Parking::Position([x, y])) => vec![
+                field("G", Value::Integer(53)),
+                field("G", Value::Integer(0)),
+                field("X", Value::Float(x)),
+                field("Y", Value::Float(y)),
+            ],
+            Some(Parking::G28) => vec![field("G", Value::Integer(28))],
+            Some(Parking::G30) => vec![field("G", Value::Integer(30))],
+            None => vec![],
+        };
+        if self.work_position.is_some() {
+            tokens.push(field("G", Value::Float(92.1)));
+        }
+        tokens
+    }
+
+    /// Output gcode that pauses the program until the operator resumes it, with a message for them
+    pub fn pause(&self, message: &str) -> Vec<Token<'input>> {
+        let mut tokens = if self.pause_action.is_some() {
+            self.sequence(&self.pause_action)
+        } else {
+            vec![Token::Field(Field {
+                letters: Cow::Borrowed("M"),
+                value: Value::Integer(0),
+            })]
+        };
+        tokens.push(Token::Comment {
+            is_inline: false,
+            // A comment ends at the end of its line
+            inner: Cow::Owned(message.replace(['\n', '\r'], " ")),
+        });
+        tokens
+    }
+
+    /// Output gcode that ends the program
+    pub fn program_stop(&self) -> Vec<Token<'input>> {
+        match self.dialect {
+            // Marlin doesn't know M2 and lets the program just run out
+            Some(Dialect::Marlin) => vec![],
+            _ => command!(ProgramEnd {}).into_token_vec(),
+        }
+    }
+
+    /// Output homing, user-defined setup gcode, probing and the work coordinates, followed by
+    /// moving up to the travel height and turning the tool on if power is set inline
+    pub fn program_begin(&self) -> Vec<Token<'input>> {
+        let mut tokens: Vec<_> = self.homing.map(Homing::command).into_iter().collect();
+        tokens.extend(self.sequence(&self.program_begin_sequence));
+        tokens.extend(
+            self.probe
+                .map(|probe| probe.sequence(self.dialect, self.coordinate_system))
+                .unwrap_or_default(),
+        );
+        tokens.append(&mut self.work_coordinates());
+        tokens.extend(
+            self.depth
+                .map(|depth| Depth::move_to(depth.travel_z, None))
+                .unwrap_or_default(),
+        );
+        if self.inline_power && self.dry_run {
+            tokens.push(Self::dry_run_comment());
+        } else if self.inline_power {
+            tokens.append(&mut self.sequence(&self.tool_on_action));
+        }
+        tokens
+    }
+
+    /// Output user-defined teardown gcode, preceded by turning the tool off if power is set inline
+    /// and followed by parking
     pub fn program_end(&self) -> Vec<Token<'input>> {
-        self.program_end_sequence
+        let mut tokens = if self.inline_power {
+            self.sequence(&self.tool_off_action)
+        } else {
+            vec![]
+        };
+        tokens.append(&mut self.sequence(&self.program_end_sequence));
+        tokens.append(&mut self.park());
+        tokens
+    }
+
+    /// Settings that Grbl should have for this machine to behave as expected, as a `$32` laser
+    /// mode setting line, along with any problems with the tool sequences for that laser mode.
+    ///
+    /// <https://github.com/gnea/grbl/wiki/Grbl-v1.1-Laser-Mode>
+    pub fn grbl_laser_mode(&self, enabled: bool) -> (Vec<Token<'input>>, Vec<&'static str>) {
+        let setting = vec![Token::Field(Field {
+            letters: Cow::Borrowed("$32="),
+            value: Value::Integer(enabled as usize),
+        })];
+
+        let tool_on = self.sequence(&self.tool_on_action);
+        let has_power = tool_on
             .iter()
-            .flat_map(|s| s.iter_fields())
-            .map(Token::from)
-            .collect()
+            .chain(self.sequence(&self.program_begin_sequence).iter())
+            .any(|token| matches!(token, Token::Field(Field { letters, .. }) if letters == "S"));
+        let is_spindle_on = has_command(&tool_on, "M", 3) || has_command(&tool_on, "M", 4);
+
+        let mut warnings = vec![];
+        if enabled {
+            if is_spindle_on && !has_power {
+                warnings.push(
+                    "the laser only fires in Grbl laser mode with a power (S) greater than 0, but \
+                     neither the tool on nor begin sequence sets one",
+                );
+            }
+        } else {
+            if has_command(&tool_on, "M", 4) {
+                warnings.push(
+                    "without laser mode, Grbl treats M4 as counterclockwise spindle rotation \
+                     rather than dynamic laser power",
+                );
+            }
+            if is_spindle_on {
+                warnings.push(
+                    "without laser mode, Grbl stops moving whenever the tool turns on or off, \
+                     which can leave burn marks at the ends of cuts",
+                );
+            }
+        }
+        (setting, warnings)
+    }
+
+    /// Problems with combinations of settings that are known to confuse controllers, each with a
+    /// link to where the controller documents the behavior
+    pub fn configuration_warnings(&self) -> Vec<&'static str> {
+        let tool_on = self.sequence(&self.tool_on_action);
+        let is_fan_laser = has_command(&tool_on, "M", 106);
+        let mut warnings = vec![];
+        if is_fan_laser
+            && self.dialect != Some(Dialect::Marlin)
+            && (self.tool_on_dwell.is_some() || self.tool_off_dwell.is_some())
+        {
+            warnings.push(
+                "the tool on sequence turns a laser on with M106 like Marlin, which waits for \
+                 dwells (G4 P) in milliseconds rather than seconds, so set --dialect marlin: \
+                 https://marlinfw.org/docs/gcode/G004.html",
+            );
+        }
+        if self.dialect == Some(Dialect::Marlin) && self.inline_power {
+            warnings.push(
+                "Marlin only sets laser power on moves when built with LASER_POWER_INLINE, and \
+                 never for a laser on a fan output (M106): \
+                 https://marlinfw.org/docs/configuration/laser_spindle.html",
+            );
+        }
+        let power = self
+            .variables
+            .iter()
+            .find(|(name, _)| *name == "power")
+            .and_then(|(_, power)| power.parse::<f64>().ok());
+        if self.dialect == Some(Dialect::Smoothie) && power.is_some_and(|power| power > 1.) {
+            warnings.push(
+                "Smoothieware sets laser power (S) as a fraction from 0 to 1, so a power over 1 is \
+                 full power: http://smoothieware.org/laser-cutter-guide",
+            );
+        }
+        if is_fan_laser && self.depth.is_some() {
+            warnings.push(
+                "the tool on sequence turns a laser on with M106, but the tool is also plunged on \
+                 the Z axis, which lasers usually keep at their focal height",
+            );
+        }
+        warnings
     }
 
     /// Output absolute distance field if mode was relative or unknown.
     pub fn absolute(&mut self) -> Vec<Token<'input>> {
-        if self.distance_mode == Some(Distance::Relative) || self.distance_mode == None {
+        if self.distance_mode == Some(Distance::Relative) || self.distance_mode.is_none() {
             self.distance_mode = Some(Distance::Absolute);
             command!(AbsoluteDistanceMode {}).into_token_vec()
         } else {
@@ -105,7 +949,7 @@ impl<'input> Machine<'input> {
 
     /// Output relative distance field if mode was absolute or unknown.
     pub fn relative(&mut self) -> Vec<Token<'input>> {
-        if self.distance_mode == Some(Distance::Absolute) || self.distance_mode == None {
+        if self.distance_mode == Some(Distance::Absolute) || self.distance_mode.is_none() {
             self.distance_mode = Some(Distance::Relative);
             command!(RelativeDistanceMode {}).into_token_vec()
         } else {
@@ -113,3 +957,480 @@ impl<'input> Machine<'input> {
         }
     }
 }
+
+/// Whether a command like M106 is in the tokens
+fn has_command(tokens: &[Token], letters: &str, number: usize) -> bool {
+    tokens.iter().any(|token| match token {
+        Token::Field(Field { letters: l, value }) => {
+            l == letters && value.as_f64() == Some(number as f64)
+        }
+        _ => false,
+    })
+}
+
+/// Builds a [Machine] from user-defined GCode, checking it before any conversion happens.
+#[derive(Debug, Default, Clone)]
+pub struct MachineBuilder<'input> {
+    tool_on_sequence: Option<&'input str>,
+    tool_off_sequence: Option<&'input str>,
+    begin_sequence: Option<&'input str>,
+    end_sequence: Option<&'input str>,
+    tool_on_dwell: Option<f64>,
+    tool_off_dwell: Option<f64>,
+    dialect: Option<Dialect>,
+    inline_power: bool,
+    power: Option<f64>,
+    coordinate_system: Option<CoordinateSystem>,
+    work_position: Option<[f64; 2]>,
+    parking: Option<Parking>,
+    depth: Option<Depth>,
+    dry_run: bool,
+    power_curve: Option<PowerCurve>,
+    tool_change_sequence: Option<&'input str>,
+    tool_offsets: Vec<ToolOffset>,
+    pause_sequence: Option<&'input str>,
+    homing: Option<Homing>,
+    probe: Option<Probe>,
+}
+
+impl<'input> MachineBuilder<'input> {
+    /// GCode that turns the tool on
+    pub fn tool_on(mut self, gcode: impl Into<Option<&'input str>>) -> Self {
+        self.tool_on_sequence = gcode.into();
+        self
+    }
+
+    /// GCode that turns the tool off
+    pub fn tool_off(mut self, gcode: impl Into<Option<&'input str>>) -> Self {
+        self.tool_off_sequence = gcode.into();
+        self
+    }
+
+    /// GCode run at the start of the program
+    pub fn begin(mut self, gcode: impl Into<Option<&'input str>>) -> Self {
+        self.begin_sequence = gcode.into();
+        self
+    }
+
+    /// GCode run at the end of the program
+    pub fn end(mut self, gcode: impl Into<Option<&'input str>>) -> Self {
+        self.end_sequence = gcode.into();
+        self
+    }
+
+    /// Seconds to wait after turning the tool on
+    pub fn tool_on_dwell(mut self, seconds: impl Into<Option<f64>>) -> Self {
+        self.tool_on_dwell = seconds.into();
+        self
+    }
+
+    /// Seconds to wait before turning the tool off
+    pub fn tool_off_dwell(mut self, seconds: impl Into<Option<f64>>) -> Self {
+        self.tool_off_dwell = seconds.into();
+        self
+    }
+
+    /// Controller family to adjust commands for, which also provides tool on and off sequences
+    /// if they aren't set
+    pub fn dialect(mut self, dialect: impl Into<Option<Dialect>>) -> Self {
+        self.dialect = dialect.into();
+        self
+    }
+
+    /// Set tool power inline with each move instead of turning the tool on and off around every
+    /// cut. The tool is turned on with dynamic laser power (`M4 S0`) and off (`M5`) unless tool on
+    /// and off sequences are set.
+    pub fn inline_power(mut self, inline_power: bool) -> Self {
+        self.inline_power = inline_power;
+        self
+    }
+
+    /// Tool power substituted for `{power}` in sequences
+    pub fn power(mut self, power: impl Into<Option<f64>>) -> Self {
+        self.power = power.into();
+        self
+    }
+
+    /// Work coordinate system to select at the start of the program
+    pub fn coordinate_system(
+        mut self,
+        coordinate_system: impl Into<Option<CoordinateSystem>>,
+    ) -> Self {
+        self.coordinate_system = coordinate_system.into();
+        self
+    }
+
+    /// Position to declare the machine to be at when the program starts, with G92
+    pub fn work_position(mut self, position: impl Into<Option<[f64; 2]>>) -> Self {
+        self.work_position = position.into();
+        self
+    }
+
+    /// Where to move the machine at the end of the program
+    pub fn parking(mut self, parking: impl Into<Option<Parking>>) -> Self {
+        self.parking = parking.into();
+        self
+    }
+
+    /// Heights to travel and cut at, for machines with a Z axis. Plunging is part of turning the
+    /// tool on and retracting is part of turning it off.
+    pub fn depth(mut self, depth: impl Into<Option<Depth>>) -> Self {
+        self.depth = depth.into();
+        self
+    }
+
+    /// Replace every tool on sequence with a comment, including plunging to the cut height, to
+    /// air-run a program and check where it goes without cutting
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Map and clamp the S value of every command once the program is generated, including the
+    /// power of elements, ramps and inline power
+    pub fn power_curve(mut self, power_curve: impl Into<Option<PowerCurve>>) -> Self {
+        self.power_curve = power_curve.into();
+        self
+    }
+
+    /// GCode that swaps in the tool numbered `{tool}`, for tool changes between operations.
+    /// Defaults to `T{tool} M6`.
+    pub fn tool_change(mut self, gcode: impl Into<Option<&'input str>>) -> Self {
+        self.tool_change_sequence = gcode.into();
+        self
+    }
+
+    /// GCode that pauses the program until the operator resumes it, like `M1` for an optional
+    /// stop. Defaults to `M0`.
+    pub fn pause(mut self, gcode: impl Into<Option<&'input str>>) -> Self {
+        self.pause_sequence = gcode.into();
+        self
+    }
+
+    /// Home the machine at the start of the program, before the begin sequence
+    pub fn homing(mut self, homing: impl Into<Option<Homing>>) -> Self {
+        self.homing = homing.into();
+        self
+    }
+
+    /// Probe for the top of the work at the start of the program, after the begin sequence.
+    /// This needs a [MachineBuilder::depth] to lift the tool off the touch plate afterwards.
+    pub fn probe(mut self, probe: impl Into<Option<Probe>>) -> Self {
+        self.probe = probe.into();
+        self
+    }
+
+    /// Offsets of tools from the one the machine is set up with, applied after changing to them
+    pub fn tool_offsets(mut self, offsets: Vec<ToolOffset>) -> Self {
+        self.tool_offsets = offsets;
+        self
+    }
+
+    /// Parse the sequences, reporting every problem found rather than just the first.
+    ///
+    /// Sequences with variables are checked with placeholder values, since the values are only
+    /// known as the program is generated.
+    pub fn build(self) -> Result<Machine<'input>, Vec<MachineError<'input>>> {
+        let mut errors = vec![];
+        let power = self.power;
+        let mut parse = |name: &'static str, gcode: Option<&'input str>| {
+            let gcode = gcode?;
+            if !gcode.contains('{') {
+                return match snippet_parser(gcode) {
+                    Ok(snippet) => Some(Sequence::Parsed(snippet)),
+                    Err(error) => {
+                        errors.push(MachineError::InvalidSequence {
+                            name,
+                            gcode: Cow::Borrowed(gcode),
+                            error,
+                        });
+                        None
+                    }
+                };
+            }
+
+            let placeholder = |variable: &str| match variable {
+                "power" => power.map(|power| power.to_string()),
+                _ if VARIABLES.contains(&variable) => Some("0".to_string()),
+                _ => None,
+            };
+            match substitute(gcode, placeholder) {
+                Ok(substituted) => match snippet_parser(&substituted) {
+                    Ok(_) => Some(Sequence::Template(gcode)),
+                    Err(error) => {
+                        errors.push(MachineError::InvalidSequence {
+                            name,
+                            gcode: Cow::Owned(substituted),
+                            error,
+                        });
+                        None
+                    }
+                },
+                Err(variable) => {
+                    errors.push(MachineError::UnknownVariable { name, variable });
+                    None
+                }
+            }
+        };
+        let (default_tool_on, default_tool_off) = match self.dialect {
+            _ if self.inline_power => (Some("M4 S0"), Some("M5")),
+            Some(dialect) => {
+                let (on, off) = dialect.default_tool_sequences();
+                (Some(on), Some(off))
+            }
+            None => (None, None),
+        };
+        let mut machine = Machine::new(None, None, None, None);
+        machine.tool_on_action = parse(
+            "tool_on_sequence",
+            self.tool_on_sequence.or(default_tool_on),
+        );
+        machine.tool_off_action = parse(
+            "tool_off_sequence",
+            self.tool_off_sequence.or(default_tool_off),
+        );
+        machine.program_begin_sequence = parse("begin_sequence", self.begin_sequence);
+        machine.program_end_sequence = parse("end_sequence", self.end_sequence);
+        machine.tool_change_action = parse("tool_change_sequence", self.tool_change_sequence);
+        machine.pause_action = parse("pause_sequence", self.pause_sequence);
+        if let Some(power) = self.power {
+            machine.set_variable("power", power.to_string());
+        }
+
+        for (name, seconds) in [
+            ("tool_on_dwell", self.tool_on_dwell),
+            ("tool_off_dwell", self.tool_off_dwell),
+        ] {
+            if let Some(seconds) = seconds.filter(|seconds| seconds.is_nan() || *seconds < 0.) {
+                errors.push(MachineError::InvalidDwell { name, seconds });
+            }
+        }
+        machine.tool_on_dwell = self.tool_on_dwell;
+        machine.tool_off_dwell = self.tool_off_dwell;
+        machine.dialect = self.dialect;
+        machine.inline_power = self.inline_power;
+        machine.coordinate_system = self.coordinate_system;
+        machine.work_position = self.work_position;
+        if self.dialect == Some(Dialect::Marlin) && self.parking == Some(Parking::G30) {
+            // G30 probes the bed instead
+            errors.push(MachineError::UnsupportedParking {
+                parking: Parking::G30,
+                dialect: Dialect::Marlin,
+            });
+        }
+        machine.parking = self.parking;
+        if let Some(dialect @ (Dialect::Marlin | Dialect::LinuxCnc)) = self.dialect {
+            if self.homing == Some(Homing::Grbl) {
+                errors.push(MachineError::UnsupportedHoming {
+                    homing: Homing::Grbl,
+                    dialect,
+                });
+            }
+        }
+        machine.homing = self.homing;
+        if let Some(problem) = self.probe.and_then(|probe| probe.problem(self.depth)) {
+            errors.push(MachineError::InvalidProbe(problem));
+        }
+        machine.probe = self.probe;
+        if let Some(depth) = self.depth.filter(|depth| depth.cut_z >= depth.travel_z) {
+            errors.push(MachineError::InvalidDepth(depth));
+        }
+        machine.depth = self.depth;
+        machine.dry_run = self.dry_run;
+        if let Some(problem) = self.power_curve.as_ref().and_then(PowerCurve::problem) {
+            errors.push(MachineError::InvalidPowerCurve(problem));
+        }
+        machine.power_curve = self.power_curve;
+        machine.tool_offsets = self.tool_offsets;
+
+        if errors.is_empty() {
+            Ok(machine)
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+/// A problem with the configuration given to a [MachineBuilder]
+#[derive(Debug)]
+pub enum MachineError<'input> {
+    /// A user-defined sequence is not valid GCode
+    InvalidSequence {
+        name: &'static str,
+        gcode: Cow<'input, str>,
+        error: ParseError,
+    },
+    /// A user-defined sequence references a variable that doesn't exist or has no value
+    UnknownVariable {
+        name: &'static str,
+        variable: String,
+    },
+    /// A dwell is negative or not a number
+    InvalidDwell { name: &'static str, seconds: f64 },
+    /// The tool would not travel above where it cuts
+    InvalidDepth(Depth),
+    /// The dialect interprets the parking command as something else
+    UnsupportedParking { parking: Parking, dialect: Dialect },
+    /// The power curve doesn't map power to a single value
+    InvalidPowerCurve(&'static str),
+    /// The dialect doesn't know the homing command
+    UnsupportedHoming { homing: Homing, dialect: Dialect },
+    /// The probe can't find the work or get off the touch plate
+    InvalidProbe(&'static str),
+}
+
+impl fmt::Display for MachineError<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidSequence { name, error, .. } => {
+                write!(f, "could not parse {}: {}", name, error)
+            }
+            Self::UnknownVariable { name, variable } => write!(
+                f,
+                "{} references {{{}}}, but only {} can be used, and power only if it is set",
+                name,
+                variable,
+                VARIABLES
+                    .iter()
+                    .map(|variable| format!("{{{}}}", variable))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            Self::InvalidDwell { name, seconds } => write!(
+                f,
+                "{} must be a non-negative number of seconds, got {}",
+                name, seconds
+            ),
+            Self::InvalidDepth(depth) => write!(
+                f,
+                "the travel height ({}) must be above the cut height ({})",
+                depth.travel_z, depth.cut_z
+            ),
+            Self::UnsupportedParking { parking, dialect } => write!(
+                f,
+                "{:?} doesn't park the machine on {:?} controllers",
+                parking, dialect
+            ),
+            Self::InvalidPowerCurve(problem) => write!(f, "invalid power curve: {}", problem),
+            Self::UnsupportedHoming { homing, dialect } => write!(
+                f,
+                "{} doesn't home the machine on {:?} controllers",
+                match homing {
+                    Homing::Grbl => "$H",
+                    Homing::G28 => "G28",
+                },
+                dialect
+            ),
+            Self::InvalidProbe(problem) => write!(f, "invalid probe: {}", problem),
+        }
+    }
+}
+
+impl std::error::Error for MachineError<'_> {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::writer::tokens_into_gcode_bytes;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn sequences_substitute_power_and_tool() {
+        let mut machine = MachineBuilder::default()
+            .tool_on("M3 S{power}")
+            .power(1000.)
+            .tool_change("T{tool} M6 G43 H{tool}")
+            .build()
+            .unwrap();
+        let mut actual = vec![];
+        tokens_into_gcode_bytes(&machine.tool_on(), &mut actual).unwrap();
+        machine.set_power(Some(250.));
+        machine.tool_off();
+        tokens_into_gcode_bytes(&machine.tool_on(), &mut actual).unwrap();
+        tokens_into_gcode_bytes(&machine.tool_change(2), &mut actual).unwrap();
+
+        assert_eq!(
+            String::from_utf8(actual).unwrap(),
+            "M3 S1000\nM3 S250\n;Tool change to T2\nT2\nM6\nG43 H2\n"
+        );
+    }
+
+    #[test]
+    fn sequences_with_unknown_variables_are_refused() {
+        let errors = MachineBuilder::default()
+            .tool_on("M3 S{speed}")
+            .build()
+            .unwrap_err();
+        assert!(
+            matches!(
+                errors.as_slice(),
+                [MachineError::UnknownVariable { name: "tool_on_sequence", variable }]
+                    if variable == "speed"
+            ),
+            "{:?}",
+            errors
+        );
+        // Power has no value to substitute unless it is set
+        assert!(matches!(
+            MachineBuilder::default()
+                .tool_on("M3 S{power}")
+                .build()
+                .unwrap_err()
+                .as_slice(),
+            [MachineError::UnknownVariable { .. }]
+        ));
+    }
+
+    #[test]
+    fn grbl_laser_mode_is_set_and_checked_against_the_tool_sequences() {
+        let laser_mode = |tool_on, enabled| {
+            let machine = MachineBuilder::default().tool_on(tool_on).build().unwrap();
+            let (setting, warnings) = machine.grbl_laser_mode(enabled);
+            let mut actual = vec![];
+            tokens_into_gcode_bytes(&setting, &mut actual).unwrap();
+            (String::from_utf8(actual).unwrap(), warnings.len())
+        };
+
+        assert_eq!(laser_mode("M4 S1000", true), ("$32=1\n".to_string(), 0));
+        // The laser won't fire without a power
+        assert_eq!(laser_mode("M4", true), ("$32=1\n".to_string(), 1));
+        // M4 is a spindle direction, and the machine stops at each toggle
+        assert_eq!(laser_mode("M4 S1000", false), ("$32=0\n".to_string(), 2));
+        assert_eq!(laser_mode("M3 S1000", false), ("$32=0\n".to_string(), 1));
+    }
+
+    #[test]
+    fn homing_and_probing_start_the_program() {
+        let machine = MachineBuilder::default()
+            .begin("M3")
+            .homing(Homing::Grbl)
+            .probe(Probe {
+                distance: 20.,
+                plate_thickness: 1.5,
+                feedrate: 100.,
+            })
+            .depth(Depth {
+                travel_z: 5.,
+                cut_z: -1.,
+                plunge_feedrate: 100.,
+                retract_feedrate: 200.,
+            })
+            .build()
+            .unwrap();
+        let mut actual = vec![];
+        tokens_into_gcode_bytes(&machine.program_begin(), &mut actual).unwrap();
+
+        assert_eq!(
+            String::from_utf8(actual).unwrap(),
+            "$H\nM3\nG91\nG38.2 Z-20 F100\nG90\nG10 L20 P0 Z1.5\nG0 Z5\n"
+        );
+        assert!(MachineBuilder::default()
+            .probe(Probe {
+                distance: 20.,
+                plate_thickness: 0.,
+                feedrate: 100.,
+            })
+            .build()
+            .is_err());
+    }
+}
@@ -2,46 +2,186 @@
 extern crate log;
 
 use std::env;
+use std::ffi::OsString;
 use std::fs::File;
-use std::io::{self, Read};
-use std::path::PathBuf;
-
-use g_code::parse::{ast::Snippet, snippet_parser, ParseError};
-use structopt::StructOpt;
-
-/// Converts an SVG to GCode in an internal representation
-mod converter;
-/// Emulates the state of an arbitrary machine that can run GCode
-mod machine;
-/// Operations that are easier to implement after GCode is generated, or would
-/// over-complicate SVG conversion
-mod postprocess;
-/// Provides an interface for drawing lines in GCode
-/// This concept is referred to as [Turtle graphics](https://en.wikipedia.org/wiki/Turtle_graphics).
-mod turtle;
-
-use converter::ProgramOptions;
-use machine::Machine;
-use turtle::Turtle;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use structopt::{
+    clap::{ArgGroup, Shell},
+    StructOpt,
+};
+
+use svg2gcode::converter::{
+    CommentLevel, DegenerateHandling, DimensionlessPolicy, MaskHandling, PauseGrouping,
+    ProgramOptions,
+};
+use svg2gcode::machine::{
+    CoordinateSystem, Depth, Dialect, Homing, MachineBuilder, MachineError, Parking, PowerCurve,
+    Probe, ToolOffset,
+};
+use svg2gcode::raster::{RasterMode, RasterOptions};
+#[cfg(feature = "tracing")]
+use svg2gcode::trace;
+use svg2gcode::turtle::Turtle;
+use svg2gcode::writer::{tokens_into_gcode_bytes, GCodeWriter};
+use svg2gcode::{
+    analysis, converter, gallery, postprocess, preview, profile, raster, sender, simulator,
+    test_pattern, thumbnail,
+};
 
 #[derive(Debug, StructOpt)]
 #[structopt(name = "svg2gcode", author, about)]
+// Options that rewrite the program once it is generated, which --stream can't be used with
+#[structopt(group = ArgGroup::with_name("postprocessing").multiple(true))]
 struct Opt {
     /// Curve interpolation tolerance
-    #[structopt(long, default_value = "0.002")]
+    #[structopt(long, default_value = "0.002", parse(try_from_str = parse_number))]
     tolerance: f64,
+    /// Merge consecutive moves shorter than this many millimeters, i.e. for traced SVGs with
+    /// thousands of tiny segments
+    #[structopt(long, group = "postprocessing", parse(try_from_str = parse_number))]
+    min_segment_length: Option<f64>,
+    /// Don't cut again where a path retraces an earlier cut within this many millimeters, i.e.
+    /// the edges shared by adjacent shapes of vectorized SVGs, which would be cut twice. Use
+    /// --remove-redundant to also drop the tool toggles of paths that are skipped entirely.
+    #[structopt(long, group = "postprocessing", parse(try_from_str = parse_number))]
+    remove_overlaps: Option<f64>,
+    /// Merge runs of moves that stay within this many millimeters of a straight line, so that
+    /// flattened curves don't starve the controller's planner with tiny segments
+    #[structopt(long, group = "postprocessing", parse(try_from_str = parse_number))]
+    merge_collinear: Option<f64>,
+    /// Elliptical arc interpolation tolerance, defaults to the curve interpolation tolerance.
+    /// Loosening it emits fewer segments for arcs without affecting other curves.
+    #[structopt(long, parse(try_from_str = parse_positive_number))]
+    arc_tolerance: Option<f64>,
     /// Machine feed rate in mm/min
-    #[structopt(long, default_value = "300")]
+    #[structopt(long, default_value = "300", parse(try_from_str = parse_number))]
     feedrate: f64,
+    /// Slow down around features smaller than this many millimeters, like small circles and
+    /// tight curves, where lasers overburn and plotters distort details at full speed
+    #[structopt(long, group = "postprocessing", parse(try_from_str = parse_number))]
+    small_feature_size: Option<f64>,
+    /// Fraction of the feed rate that the smallest features are cut at, for --small-feature-size.
+    /// Larger features are cut faster, up to the full feed rate at the size.
+    #[structopt(long, default_value = "0.25", parse(try_from_str = parse_number))]
+    small_feature_factor: f64,
     /// Dots per inch (DPI) for pixels, points, picas, etc.
-    #[structopt(long, default_value = "96")]
+    #[structopt(long, default_value = "96", parse(try_from_str = parse_number))]
     dpi: f64,
+    /// Override the width and height of the SVG (i.e. 210mm,297mm).
+    /// When the SVG is sized in pixels, this also determines the DPI.
+    #[structopt(long, parse(try_from_str = parse_dimensions))]
+    dimensions: Option<[svgtypes::Length; 2]>,
+    /// How to read the user units of an SVG without a physical size, one missing a width and
+    /// height or with a width and height without units: px (pixels at --dpi, as browsers do), mm,
+    /// or error to refuse it instead of guessing. Without a width and height, the viewBox is its
+    /// size.
+    #[structopt(long, default_value = "px")]
+    dimensionless: DimensionlessPolicy,
+    /// What to do with masked elements, since masks can't be cut: ignore (draw them whole) or
+    /// skip (leave them out)
+    #[structopt(long, default_value = "ignore")]
+    masks: MaskHandling,
+    /// What to do with degenerate path segments, like lines with no length and arcs with a zero
+    /// radius: skip them, warn about them as they are skipped, or error
+    #[structopt(long, default_value = "skip")]
+    degenerate: DegenerateHandling,
+    /// Write the program out as each path is converted instead of all at once, to convert very
+    /// large SVGs with little memory. The program keeps the coordinates of the SVG, and options
+    /// that rewrite the program once it is generated, like placing the origin, can't be used.
+    #[structopt(
+        long,
+        // Options with a default value count as given for conflicts with a group, and the
+        // conflicts of one option in a group apply to all of them, so those are left out of it
+        conflicts_with_all = &[
+            "postprocessing", "origin", "origin-mode", "axes", "lead-in", "lead-out", "scale",
+            "center", "power-table",
+        ]
+    )]
+    stream: bool,
+    /// Show how many paths have been converted while converting
+    #[structopt(long)]
+    progress: bool,
+    /// Emit `;PROGRESS n/m` comments between paths, so simple senders can show job progress
+    #[structopt(long)]
+    progress_comments: bool,
+    /// Insert `M73 P<percent> R<minutes>` each time the estimated progress advances by this
+    /// many percent, so Marlin machines show job progress on their displays. The estimate uses
+    /// the feedrates of the program and --travel-feedrate for rapids.
+    #[structopt(long, group = "postprocessing", parse(try_from_str = parse_number))]
+    m73_progress: Option<f64>,
+    /// Emit machine-readable comments for senders that show job information: `;BOUNDS: x0 y0 x1
+    /// y1` of the cuts at the start, `;LAYER: name` before the paths of each top-level element,
+    /// and `;PASS: 2/3` before each pass of an --operation
+    #[structopt(long, group = "postprocessing")]
+    metadata_comments: bool,
+    /// How much of the SVG to describe in comments: none, paths (the element each path is cut
+    /// for), segments (also each command of a path), or debug (also the transform, feedrate and
+    /// power of each path). Some controllers choke on comments, and they bloat large programs.
+    #[structopt(long, default_value = "paths")]
+    comments: CommentLevel,
+    /// Cut along the outline of stroked paths instead of their center line, following their
+    /// stroke width, line caps and line joins
+    #[structopt(long)]
+    outline_strokes: bool,
+    /// Engrave PNG images, embedded or linked, line by line: dither (dots fully on or off) or
+    /// power (S follows the darkness of each dot, for lasers in dynamic power mode). Images are
+    /// skipped without this.
+    #[structopt(long)]
+    raster: Option<RasterMode>,
+    /// Distance between the scan lines of --raster in millimeters, which is also the size of
+    /// the dots along them
+    #[structopt(long, default_value = "0.1", parse(try_from_str = parse_positive_number))]
+    scan_gap: f64,
+    /// Power (S) for black in --raster power, which scales down to zero for white
+    #[structopt(long, default_value = "1000", parse(try_from_str = parse_number))]
+    raster_max_power: f64,
+    /// Pixels at least this dark, from 0 for white to 1 for black, are inside the shapes traced
+    /// from a PNG input. Its pixels are sized by --dpi.
+    #[cfg(feature = "tracing")]
+    #[structopt(long, default_value = "0.5", parse(try_from_str = parse_number))]
+    trace_threshold: f64,
+    /// How far in pixels traced outlines may stray from the edges of the pixels, which
+    /// straightens the staircases along slanted and curved edges
+    #[cfg(feature = "tracing")]
+    #[structopt(long, default_value = "1", parse(try_from_str = parse_number))]
+    trace_tolerance: f64,
+    /// Leave out traced shapes and holes with an area of fewer pixels than this, i.e. specks
+    #[cfg(feature = "tracing")]
+    #[structopt(long, default_value = "2", parse(try_from_str = parse_number))]
+    trace_min_area: f64,
+    /// Adjust commands for a controller: grbl, marlin, smoothie, or linuxcnc. This also turns
+    /// the tool on and off in the usual way for the controller unless --on and --off are given.
+    #[structopt(long)]
+    dialect: Option<Dialect>,
+    /// Use the options of a machine profile (i.e. laser, plotter, drag-knife) from the --profiles
+    /// file. Options given on the command line override the profile's.
+    #[structopt(long)]
+    profile: Option<String>,
+    /// File of machine profiles, each a `[name]` line followed by options with one per line like
+    /// `--dialect grbl`, defaults to svg2gcode/profiles in the user's config directory
+    #[structopt(long, requires = "profile")]
+    profiles: Option<PathBuf>,
     #[structopt(alias = "tool_on_sequence", long = "on")]
-    /// Tool on GCode sequence
+    /// Tool on GCode sequence. This and the other sequences can reference {feedrate},
+    /// {layer_name} (the id of the top-level element being cut), and {power} (from
+    /// --inline-power or --ramp-power), i.e. `M3 S{power}`.
     tool_on_sequence: Option<String>,
     #[structopt(alias = "tool_off_sequence", long = "off")]
     /// Tool off GCode sequence
     tool_off_sequence: Option<String>,
+    /// Seconds to dwell after turning the tool on, i.e. to let a pen or drag knife settle
+    #[structopt(long = "on-dwell", parse(try_from_str = parse_number))]
+    tool_on_dwell: Option<f64>,
+    /// Seconds to dwell before turning the tool off
+    #[structopt(long = "off-dwell", parse(try_from_str = parse_number))]
+    tool_off_dwell: Option<f64>,
+    /// Start the program by setting Grbl laser mode (`$32`) on or off, and warn about tool
+    /// sequences that behave differently than expected in that mode
+    #[structopt(long, group = "postprocessing", parse(try_from_str))]
+    grbl_laser_mode: Option<bool>,
     /// Optional GCode begin sequence (i.e. change to a cutter tool)
     #[structopt(alias = "begin_sequence", long = "begin")]
     begin_sequence: Option<String>,
@@ -53,10 +193,391 @@ struct Opt {
     /// Output file path (overwrites old files), else writes to stdout
     #[structopt(short, long)]
     out: Option<PathBuf>,
-    /// Set where the bottom left corner of the SVG will be placed. Also affects begin/end and
-    /// on/off sequences.
-    #[structopt(long, default_value = "0,0")]
-    origin: String,
+    /// Also write each top-level path or group of the SVG to its own program in this directory,
+    /// named after its id (i.e. layer1.gcode), so parts of a drawing can be run selectively
+    #[structopt(long, group = "postprocessing")]
+    split_dir: Option<PathBuf>,
+    /// Split programs written to files, the --out file and the programs in --split-dir, into
+    /// numbered parts of at most this many lines (i.e. out-1.gcode, out-2.gcode) for controllers
+    /// with small file limits. Parts are split between paths and each can be run on its own.
+    #[structopt(long, group = "postprocessing")]
+    max_lines: Option<usize>,
+    /// Scale the toolpath uniformly by this factor, as if the SVG were resized
+    #[structopt(long, conflicts_with = "fit", parse(try_from_str = parse_number))]
+    scale: Option<f64>,
+    /// Scale the toolpath uniformly to fit in a width and height in millimeters (i.e. 300x200),
+    /// keeping the bottom left corner of its bounding box in place
+    #[structopt(long, group = "postprocessing", parse(try_from_str = parse_size))]
+    fit: Option<[f64; 2]>,
+    /// Rotate the toolpath counterclockwise by this many degrees, i.e. 90 to run landscape
+    /// artwork on a portrait bed
+    #[structopt(
+        long,
+        group = "postprocessing",
+        allow_hyphen_values = true,
+        parse(try_from_str = parse_number)
+    )]
+    rotate: Option<f64>,
+    /// Point to rotate about (i.e. 100,50), instead of the center of the toolpath's bounding box
+    #[structopt(
+        long,
+        requires = "rotate",
+        allow_hyphen_values = true,
+        parse(try_from_str = parse_point)
+    )]
+    rotate_about: Option<[f64; 2]>,
+    /// Mirror the toolpath horizontally, for machines whose X axis increases to the left
+    #[structopt(long, group = "postprocessing")]
+    mirror_x: bool,
+    /// Mirror the toolpath vertically, for machines whose Y axis increases downward
+    #[structopt(long, group = "postprocessing")]
+    mirror_y: bool,
+    /// Machine axes that the X and Y of the drawing move, i.e. YX to swap them or XZ for the Y
+    /// of the drawing to move Z on a foam cutter. Each is one of XYZABCUVW.
+    #[structopt(long, default_value = "XY", parse(try_from_str = parse_axes))]
+    axes: [&'static str; 2],
+    /// Round cut positions to multiples of the machine's step resolution in millimeters (i.e.
+    /// 0.0125), dropping moves smaller than a step
+    #[structopt(long, group = "postprocessing", parse(try_from_str = parse_positive_number))]
+    resolution: Option<f64>,
+    /// Comment each rapid move with the element cut after it, to debug the order of a program.
+    /// `{element}` is replaced by the element's name, i.e. "-> {element}" becomes "-> path#star".
+    #[structopt(long, group = "postprocessing", allow_hyphen_values = true)]
+    travel_comment: Option<String>,
+    /// Output the program in inches (G20), for machines that only work well with them. Other
+    /// settings are still given in millimeters.
+    #[structopt(long, group = "postprocessing")]
+    inches: bool,
+    /// Round coordinates to this many decimal places, i.e. 3 for millimeters or 4 for inches, to shrink files
+    /// and keep lines short for controllers that limit their length. Paths are also rounded as
+    /// they are generated, so that the program is identical across builds and platforms.
+    #[structopt(long, group = "postprocessing")]
+    precision: Option<u32>,
+    /// Set where the point chosen by --origin-mode will be placed, by default the bottom left
+    /// corner of the SVG. If the SVG has an element with the id "svg2gcode-origin" or a
+    /// data-svg2gcode-origin attribute, i.e. a circle registered to a jig, that element is placed
+    /// here instead and left out of the program. Also affects the tool on and off sequences, but
+    /// not the begin and end sequences.
+    #[structopt(
+        long,
+        default_value = "0,0",
+        allow_hyphen_values = true,
+        parse(try_from_str = parse_point)
+    )]
+    origin: [f64; 2],
+    /// Which point of the program is placed at the origin: viewbox-origin, bounding-box-min,
+    /// center, or custom-marker to use the center of the element with the id "origin" (or
+    /// custom-marker=ID for another id). Give two comma-separated modes to choose them per axis
+    /// (i.e. center,bounding-box-min).
+    #[structopt(
+        long,
+        default_value = "bounding-box-min",
+        parse(try_from_str = parse_origin_modes)
+    )]
+    origin_mode: [postprocess::OriginMode; 2],
+    /// Place the center of the toolpath's bounding box here (i.e. 150,100), for material placed in
+    /// the middle of the bed. Shorthand for --origin X,Y --origin-mode center.
+    #[structopt(
+        long,
+        allow_hyphen_values = true,
+        conflicts_with_all = &["origin", "origin-mode"],
+        parse(try_from_str = parse_point)
+    )]
+    center: Option<[f64; 2]>,
+    /// Embed a PNG thumbnail of the toolpath with the given size in comments at the top of the
+    /// program (i.e. 220x124), as PrusaSlicer does for front-ends that show job previews.
+    #[structopt(long, group = "postprocessing", parse(try_from_str = parse_size))]
+    thumbnail: Option<[u32; 2]>,
+    /// Distance in millimeters over which tool power ramps up at the start of a cut and down at
+    /// its end, to avoid burn-in dots from a laser
+    #[structopt(
+        long,
+        group = "postprocessing",
+        requires = "ramp-power",
+        parse(try_from_str = parse_positive_number)
+    )]
+    ramp_distance: Option<f64>,
+    /// Full tool power (S value) reached after ramping
+    #[structopt(long, requires = "ramp-distance", parse(try_from_str = parse_number))]
+    ramp_power: Option<f64>,
+    /// Tool power or spindle speed, set with an S word every time the tool is turned on
+    #[structopt(long, parse(try_from_str = parse_number))]
+    power: Option<f64>,
+    /// Use a different --power for paths stroked in some colors, i.e. `red=1000,#00f=500`
+    #[structopt(long, require_delimiter = true, parse(try_from_str = parse_color_power))]
+    color_power: Vec<(svgtypes::Color, f64)>,
+    /// Do an operation with its own settings on the elements it selects, like
+    /// `cut,color=red,feedrate=100,power=1000,passes=2`. Operations are done in the order they are
+    /// given, followed by unselected elements. Elements are selected with `color=COLOR`,
+    /// `layer=ID` (a top-level group's id or Inkscape layer name) or `attribute=NAME[=VALUE]`, and
+    /// can have a feedrate, power, number of passes and a tool number to change to, i.e.
+    /// `tool=2`.
+    #[structopt(long = "operation", number_of_values = 1)]
+    operations: Vec<converter::Operation>,
+    /// Pause the program whenever the paths move on to another layer (top-level element) or
+    /// stroke color, i.e. so that the operator can swap pens or material: layers or colors
+    #[structopt(long)]
+    pause_between: Option<PauseGrouping>,
+    /// GCode that pauses the program for the operator, with --pause-between and at elements with
+    /// a data-pause attribute, i.e. `M1` for an optional stop. Defaults to `M0`.
+    #[structopt(long)]
+    pause_sequence: Option<String>,
+    /// Tool to change to for elements not cut by an --operation with its own tool
+    #[structopt(long)]
+    tool: Option<u32>,
+    /// GCode that changes to the tool numbered {tool}, before operations with another tool than
+    /// the current one
+    #[structopt(long, default_value = "T{tool} M6")]
+    tool_change_sequence: String,
+    /// Shift the moves of a tool to make up for where it sits relative to the tool the machine is
+    /// set up with, like `2=10,-5` or `2=10,-5,1.5` to also shift --travel-z and --cut-z. Can be
+    /// given once per tool.
+    #[structopt(long = "tool-offset", number_of_values = 1)]
+    tool_offsets: Vec<ToolOffset>,
+    /// Set tool power inline with every cut move to this S value, turning the tool on once at the
+    /// start of the program instead of around every path. With Grbl, this uses dynamic laser
+    /// power (`M4`), which turns the laser off during travel moves.
+    #[structopt(long, group = "postprocessing", parse(try_from_str = parse_number))]
+    inline_power: Option<f64>,
+    /// Scale the --inline-power of top-level elements (layers) by id, in percent, i.e.
+    /// `layer1=50,layer2=80`
+    #[structopt(
+        long,
+        requires = "inline-power",
+        require_delimiter = true,
+        parse(try_from_str = parse_layer_power)
+    )]
+    layer_power: Vec<(String, f64)>,
+    /// S value of full tool power, which --power-gamma and --power-table are relative to. Defaults
+    /// to 255 for Marlin, 1 for Smoothieware, and 1000 otherwise.
+    #[structopt(long, parse(try_from_str = parse_number))]
+    full_power: Option<f64>,
+    /// Raise the tool power of every command to this exponent, as a fraction of --full-power, to
+    /// compensate for a laser that doesn't respond linearly to power
+    #[structopt(long, group = "postprocessing", parse(try_from_str = parse_number))]
+    power_gamma: Option<f64>,
+    /// Map tool power through a curve of points in percent of --full-power, interpolating between
+    /// them, i.e. `0=0,50=30,100=100`
+    #[structopt(
+        long,
+        conflicts_with = "power-gamma",
+        require_delimiter = true,
+        parse(try_from_str = parse_power_table_point)
+    )]
+    power_table: Vec<(f64, f64)>,
+    /// Raise the S value of every command that turns the tool on to at least this
+    #[structopt(long, group = "postprocessing", parse(try_from_str = parse_number))]
+    min_power: Option<f64>,
+    /// Limit the S value of every command to this
+    #[structopt(long, group = "postprocessing", parse(try_from_str = parse_number))]
+    max_power: Option<f64>,
+    /// Cut the program a second time with a second head, offset by "X,Y" in millimeters. The
+    /// heads are selected with T0 and T1.
+    #[structopt(
+        long,
+        group = "postprocessing",
+        allow_hyphen_values = true,
+        parse(try_from_str = parse_point)
+    )]
+    second_head: Option<[f64; 2]>,
+    /// Mirror the toolpath of the second head in X, for dual carriages that move in opposite
+    /// directions
+    #[structopt(long, requires = "second-head")]
+    second_head_mirror: bool,
+    /// Work coordinate system to select at the start of the program, one of G54 to G59
+    #[structopt(long)]
+    coordinate_system: Option<CoordinateSystem>,
+    /// Declare the machine to be at "X,Y" in millimeters when the program starts, with G92. This
+    /// is relative to where --origin places the drawing, and is cleared at the end of the program.
+    #[structopt(long, allow_hyphen_values = true, parse(try_from_str = parse_point))]
+    work_position: Option<[f64; 2]>,
+    /// Height in millimeters to travel at between cuts, for machines that cut by lowering a tool
+    /// on the Z axis. The tool is lowered to --cut-z when turning it on and raised when turning it
+    /// off.
+    #[structopt(
+        long,
+        requires = "cut-z",
+        allow_hyphen_values = true,
+        parse(try_from_str = parse_number)
+    )]
+    travel_z: Option<f64>,
+    /// Height in millimeters to cut at
+    #[structopt(
+        long,
+        requires = "travel-z",
+        allow_hyphen_values = true,
+        parse(try_from_str = parse_number)
+    )]
+    cut_z: Option<f64>,
+    /// Feedrate in mm/min to plunge down to --cut-z at, defaults to --feedrate
+    #[structopt(long, requires = "cut-z", parse(try_from_str = parse_number))]
+    plunge_feedrate: Option<f64>,
+    /// Feedrate in mm/min to retract up to --travel-z at, defaults to --feedrate
+    #[structopt(long, requires = "travel-z", parse(try_from_str = parse_number))]
+    retract_feedrate: Option<f64>,
+    /// Home the machine at the start of the program, before the begin sequence: grbl for Grbl's
+    /// `$H` homing cycle, or g28
+    #[structopt(long)]
+    home: Option<Homing>,
+    /// Probe down for a touch plate on the work by up to this many mm at the start of the program,
+    /// after the begin sequence, and set Z there to --probe-plate-thickness
+    #[structopt(
+        long,
+        requires_all = &["travel-z", "cut-z"],
+        parse(try_from_str = parse_number)
+    )]
+    probe_distance: Option<f64>,
+    /// Thickness of the touch plate in mm
+    #[structopt(long, default_value = "0", parse(try_from_str = parse_number))]
+    probe_plate_thickness: f64,
+    /// Feedrate in mm/min to probe at
+    #[structopt(long, default_value = "100", parse(try_from_str = parse_number))]
+    probe_feedrate: f64,
+    /// Keep the tool on (or down, with --travel-z) and cut straight to the next path when it
+    /// starts within this many millimeters, instead of turning the tool off and on again. Zero
+    /// joins paths where one ends exactly where the next begins.
+    #[structopt(
+        long,
+        group = "postprocessing",
+        alias = "join-distance",
+        parse(try_from_str = parse_number)
+    )]
+    stay_down_distance: Option<f64>,
+    /// Output all of the motion with the tool left off, replacing each tool on sequence with a
+    /// comment, to air-run a job and check its positioning
+    #[structopt(long)]
+    dry_run_motion: bool,
+    /// Where to move the machine at the end of the program: g28 or g30 for the positions stored
+    /// on the controller with G28.1 or G30.1, or machine coordinates like "0,300"
+    #[structopt(long)]
+    park: Option<Parking>,
+    /// Leave out modes and feedrates that are already set, moves that go nowhere, and tool
+    /// toggles with nothing cut between them, for smaller programs
+    #[structopt(long, group = "postprocessing")]
+    remove_redundant: bool,
+    /// Warn about travel moves that pass over parts which were already cut free and could snag
+    /// the tool
+    #[structopt(long, group = "postprocessing")]
+    check_travel: bool,
+    /// Make closed paths go around in one direction: cw, ccw, climb (outer contours clockwise
+    /// and holes counterclockwise, for a clockwise spindle) or conventional. Drag knives and
+    /// milling cutters care about direction, and that of SVG paths is arbitrary.
+    #[structopt(long, group = "postprocessing")]
+    direction: Option<postprocess::Direction>,
+    /// Reverse every path in the top-level group with this id, after --direction
+    #[structopt(long = "reverse-layer", group = "postprocessing", number_of_values = 1)]
+    reversed_layers: Vec<String>,
+    /// Width of material removed by the tool (kerf) in millimeters. Closed paths are offset by
+    /// half of it towards their waste side, so that cut out parts come out at their true size.
+    #[structopt(long, group = "postprocessing", parse(try_from_str = parse_number))]
+    kerf: Option<f64>,
+    /// Number of uncut tabs to leave in each closed path, so cut out parts stay in the stock
+    #[structopt(long, group = "postprocessing", requires = "tab-width")]
+    tabs: Option<usize>,
+    /// Length of each tab in millimeters
+    #[structopt(long, requires = "tabs", parse(try_from_str = parse_number))]
+    tab_width: Option<f64>,
+    /// Closed paths shorter than this many millimeters don't get tabs
+    #[structopt(long, default_value = "0", parse(try_from_str = parse_number))]
+    tab_min_length: f64,
+    /// Report the length of cuts and travel, the time the job takes, and optionally its cost
+    #[structopt(long, group = "postprocessing")]
+    estimate: bool,
+    /// Speed of travel moves in mm/min for --estimate and --samples, defaults to the feed rate
+    #[structopt(long, parse(try_from_str = parse_number))]
+    travel_feedrate: Option<f64>,
+    /// Acceleration of the machine in mm/s² for --estimate (i.e. Grbl's $120), to account for
+    /// speeding up and slowing down at corners. Without it, moves are assumed to be at full speed.
+    #[structopt(long, parse(try_from_str = parse_positive_number))]
+    acceleration: Option<f64>,
+    /// How far in millimeters the machine may deviate from a corner while keeping speed through
+    /// it (i.e. Grbl's $11), for --acceleration
+    #[structopt(long, default_value = "0.01", parse(try_from_str = parse_number))]
+    junction_deviation: f64,
+    /// Cost of running the machine per hour for --estimate
+    #[structopt(long, parse(try_from_str = parse_number))]
+    hourly_rate: Option<f64>,
+    /// Cost of material per square meter for --estimate, applied to the bounding box of the cuts
+    #[structopt(long, parse(try_from_str = parse_number))]
+    material_cost: Option<f64>,
+    /// Write the toolpath resampled at --sample-interval to this file as CSV, with the time in
+    /// seconds at the feed rate (--travel-feedrate for travel), the X and Y position, and whether
+    /// the tool is cutting. This is for driving devices that aren't controlled with GCode, like
+    /// galvanometer scanners.
+    #[structopt(long, group = "postprocessing", requires = "sample-interval")]
+    samples: Option<PathBuf>,
+    /// Distance between samples along the toolpath in millimeters
+    #[structopt(long, requires = "samples", parse(try_from_str = parse_positive_number))]
+    sample_interval: Option<f64>,
+    /// Write an SVG preview of the toolpath to this file, with cuts, their directions, travel and
+    /// the bounding box of the cuts, to check a program before running it
+    #[structopt(long, group = "postprocessing")]
+    preview: Option<PathBuf>,
+    /// Width and height in millimeters of the machine's bed from the origin (i.e. 300x200), which
+    /// is outlined in the --preview and checked with --verify
+    #[structopt(long, parse(try_from_str = parse_size))]
+    bed: Option<[f64; 2]>,
+    /// Check the program by simulating it, failing if it moves rapidly with the tool on, below
+    /// --min-z, or outside of the --bed
+    #[structopt(long, group = "postprocessing")]
+    verify: bool,
+    /// Lowest Z that the tool may move to, for --verify
+    #[structopt(long, requires = "verify", allow_hyphen_values = true, parse(try_from_str = parse_number))]
+    min_z: Option<f64>,
+    /// Length of a lead-in move into closed paths in millimeters, so pierce marks land on the
+    /// waste side instead of the finished edge
+    #[structopt(long, default_value = "0", parse(try_from_str = parse_number))]
+    lead_in: f64,
+    /// Length of a lead-out move out of closed paths in millimeters
+    #[structopt(long, default_value = "0", parse(try_from_str = parse_number))]
+    lead_out: f64,
+    /// Make lead-ins and lead-outs quarter circle arcs, with their length as the radius
+    #[structopt(long)]
+    lead_arc: bool,
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// Print a shell completion script (i.e. `svg2gcode completions bash > /etc/bash_completion.d/svg2gcode`)
+    Completions {
+        #[structopt(possible_values = &Shell::variants())]
+        shell: Shell,
+    },
+    /// Print a man page (i.e. `svg2gcode man | man -l -`)
+    Man,
+    /// Convert a test pattern instead of an SVG file, to check that the machine's axis
+    /// directions and origin match the other options: a border around the area, a quarter circle
+    /// around the origin, and arrows labeled X and Y pointing in the positive direction of each axis.
+    TestPattern {
+        /// Width of the pattern in millimeters
+        #[structopt(default_value = "100", parse(try_from_str = parse_number))]
+        width: f64,
+        /// Height of the pattern in millimeters
+        #[structopt(default_value = "100", parse(try_from_str = parse_number))]
+        height: f64,
+    },
+    /// Stream the program to a Grbl or Marlin controller on a serial port, waiting for each line
+    /// to be acknowledged, instead of writing it out. The port's baud rate must already be set
+    /// (i.e. `stty -F /dev/ttyUSB0 115200 raw`).
+    Send {
+        /// Serial port of the controller, like /dev/ttyUSB0
+        port: PathBuf,
+        /// Number and checksum each line, as Marlin expects
+        #[structopt(long)]
+        checksums: bool,
+    },
+    /// Convert every SVG in a directory with several settings and write an HTML page of toolpath
+    /// previews and statistics, to check changes to the conversion before releasing them
+    Gallery {
+        /// Directory of SVGs to convert
+        #[structopt(default_value = "examples")]
+        examples: PathBuf,
+        /// Directory to write the page and previews to
+        #[structopt(long, default_value = "gallery")]
+        out: PathBuf,
+    },
 }
 
 fn main() -> io::Result<()> {
@@ -65,197 +586,1447 @@ fn main() -> io::Result<()> {
     }
     env_logger::init();
 
-    let opt = Opt::from_args();
+    // Options on the command line override those of the profile, so it is merged in before
+    // they are parsed for good
+    let args = env::args_os().collect::<Vec<_>>();
+    let opt = Opt::from_iter(&args);
+    let opt = match &opt.profile {
+        Some(name) => Opt::from_iter(profile::merge(read_profile(&opt, name)?, args)),
+        None => opt,
+    };
+    run(opt)
+}
 
-    let input = match opt.file {
-        Some(filename) => {
-            let mut f = File::open(filename)?;
-            let len = f.metadata()?.len();
-            let mut input = String::with_capacity(len as usize + 1);
-            f.read_to_string(&mut input)?;
-            input
-        }
+/// Options of the machine profile named by --profile, from the --profiles file
+fn read_profile(opt: &Opt, name: &str) -> io::Result<Vec<OsString>> {
+    let path = match opt.profiles.clone().or_else(default_profiles_path) {
+        Some(path) => path,
         None => {
-            info!("Reading from standard input");
-            let mut input = String::new();
-            io::stdin().read_to_string(&mut input)?;
-            input
+            error!("--profile needs a --profiles file, there is no config directory");
+            std::process::exit(1)
+        }
+    };
+    let profiles = std::fs::read_to_string(&path).map_err(|err| {
+        io::Error::new(
+            err.kind(),
+            format!("could not read profiles from {}: {}", path.display(), err),
+        )
+    })?;
+    match profile::profile_args(&profiles, name) {
+        Ok(args) => Ok(args),
+        Err(err) => {
+            error!("{}", err);
+            std::process::exit(1)
+        }
+    }
+}
+
+/// Do what the command line options ask for
+fn run(opt: Opt) -> io::Result<()> {
+    let test_pattern = match &opt.command {
+        Some(Command::Completions { shell }) => {
+            Opt::clap().gen_completions_to(env!("CARGO_PKG_NAME"), *shell, &mut io::stdout());
+            return Ok(());
+        }
+        Some(Command::Man) => return write_man_page(io::stdout()),
+        Some(Command::Gallery { examples, out }) => return gallery::write_gallery(examples, out),
+        Some(Command::TestPattern { width, height }) => {
+            Some(test_pattern::test_pattern_svg(*width, *height))
+        }
+        Some(Command::Send { .. }) | None => None,
+    };
+
+    let base_dir = opt
+        .file
+        .as_deref()
+        .and_then(Path::parent)
+        .map(Path::to_path_buf);
+    let input = match (test_pattern, &opt.file) {
+        (Some(test_pattern), _) => test_pattern,
+        (None, file) => {
+            let mut bytes = vec![];
+            match file {
+                Some(filename) => {
+                    File::open(filename)?.read_to_end(&mut bytes)?;
+                }
+                None => {
+                    info!("Reading from standard input");
+                    io::stdin().read_to_end(&mut bytes)?;
+                }
+            }
+            if raster::is_png(&bytes) {
+                trace_png(&bytes, &opt)?
+            } else {
+                String::from_utf8(bytes)
+                    .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?
+            }
         }
     };
 
     let options = ProgramOptions {
         tolerance: opt.tolerance,
+        arc_tolerance: opt.arc_tolerance,
         feedrate: opt.feedrate,
         dpi: opt.dpi,
+        dimensions: opt.dimensions,
+        dimensionless: opt.dimensionless,
+        masks: opt.masks,
+        degenerate: opt.degenerate,
+        progress_comments: opt.progress_comments,
+        metadata_comments: opt.metadata_comments,
+        outline_strokes: opt.outline_strokes,
+        power: opt.power,
+        precision: opt.precision,
+        comments: opt.comments,
+        raster: opt.raster.map(|mode| RasterOptions {
+            scan_gap: opt.scan_gap,
+            mode,
+            max_power: opt.raster_max_power,
+            base_dir,
+        }),
+        color_power: opt.color_power.clone(),
+        operations: opt.operations.clone(),
+        tool: opt.tool,
+        pause_between: opt.pause_between,
     };
 
-    let snippets = [
-        opt.tool_on_sequence.as_ref().map(parse_snippet).transpose(),
-        opt.tool_off_sequence
-            .as_ref()
-            .map(parse_snippet)
-            .transpose(),
-        opt.begin_sequence.as_ref().map(parse_snippet).transpose(),
-        opt.end_sequence.as_ref().map(parse_snippet).transpose(),
-    ];
-
-    let machine = if let [Ok(tool_on_action), Ok(tool_off_action), Ok(program_begin_sequence), Ok(program_end_sequence)] =
-        snippets
-    {
-        Machine {
-            tool_on_action,
-            tool_off_action,
-            program_begin_sequence,
-            program_end_sequence,
-            tool_state: None,
-            distance_mode: None,
-        }
+    let plunge_feedrate = opt.plunge_feedrate.unwrap_or(opt.feedrate);
+    let retract_feedrate = opt.retract_feedrate.unwrap_or(opt.feedrate);
+    let has_power_curve = opt.power_gamma.is_some()
+        || !opt.power_table.is_empty()
+        || opt.min_power.is_some()
+        || opt.max_power.is_some();
+    let power_curve = if has_power_curve {
+        Some(PowerCurve {
+            full_power: opt
+                .full_power
+                .unwrap_or(opt.dialect.map_or(1000., Dialect::full_power)),
+            gamma: opt.power_gamma.unwrap_or(1.),
+            table: opt.power_table.clone(),
+            min_power: opt.min_power,
+            max_power: opt.max_power,
+        })
     } else {
-        use codespan_reporting::term::{
-            emit,
-            termcolor::{ColorChoice, StandardStream},
-        };
-        let mut writer = StandardStream::stderr(ColorChoice::Auto);
-        let config = codespan_reporting::term::Config::default();
-
-        for (i, (filename, gcode)) in [
-            ("tool_on_sequence", &opt.tool_on_sequence),
-            ("tool_off_sequence", &opt.tool_off_sequence),
-            ("begin_sequence", &opt.begin_sequence),
-            ("end_sequence", &opt.end_sequence),
-        ]
-        .iter()
-        .enumerate()
-        {
-            if let Err(err) = &snippets[i] {
-                emit(
-                    &mut writer,
-                    &config,
-                    &codespan_reporting::files::SimpleFile::new(filename, gcode.as_ref().unwrap()),
-                    &g_code::parse::into_diagnostic(&err),
-                )
-                .unwrap();
+        None
+    };
+    let machine = match MachineBuilder::default()
+        .tool_on(opt.tool_on_sequence.as_deref())
+        .tool_off(opt.tool_off_sequence.as_deref())
+        .begin(opt.begin_sequence.as_deref())
+        .end(opt.end_sequence.as_deref())
+        .tool_on_dwell(opt.tool_on_dwell)
+        .tool_off_dwell(opt.tool_off_dwell)
+        .dialect(opt.dialect)
+        .inline_power(opt.inline_power.is_some())
+        .power(opt.power.or(opt.inline_power).or(opt.ramp_power))
+        .coordinate_system(opt.coordinate_system)
+        .parking(opt.park)
+        .dry_run(opt.dry_run_motion)
+        .power_curve(power_curve)
+        .tool_change(opt.tool_change_sequence.as_str())
+        .tool_offsets(opt.tool_offsets.clone())
+        .pause(opt.pause_sequence.as_deref())
+        .homing(opt.home)
+        .probe(opt.probe_distance.map(|distance| Probe {
+            distance,
+            plate_thickness: opt.probe_plate_thickness,
+            feedrate: opt.probe_feedrate,
+        }))
+        .depth(opt.travel_z.zip(opt.cut_z).map(|(travel_z, cut_z)| Depth {
+            travel_z,
+            cut_z,
+            plunge_feedrate,
+            retract_feedrate,
+        }))
+        .work_position(opt.work_position)
+        .build()
+    {
+        Ok(machine) => machine,
+        Err(errors) => {
+            use codespan_reporting::term::{
+                emit,
+                termcolor::{ColorChoice, StandardStream},
+            };
+            let mut writer = StandardStream::stderr(ColorChoice::Auto);
+            let config = codespan_reporting::term::Config::default();
+
+            for err in errors {
+                match err {
+                    MachineError::InvalidSequence { name, gcode, error } => emit(
+                        &mut writer,
+                        &config,
+                        &codespan_reporting::files::SimpleFile::new(name, gcode.as_ref()),
+                        &g_code::parse::into_diagnostic(&error),
+                    )
+                    .unwrap(),
+                    other => error!("{}", other),
+                }
             }
+            std::process::exit(1)
         }
-        std::process::exit(1)
     };
 
     let document = roxmltree::Document::parse(&input).expect("Invalid or unsupported SVG file");
 
+    for warning in machine.configuration_warnings() {
+        warn!("{}", warning);
+    }
     let mut turtle = Turtle::new(machine);
-    let mut program = converter::svg2program(&document, options, &mut turtle);
+    let mut report = |converted: usize, total: usize| {
+        eprint!("\rConverted {}/{} paths", converted, total);
+        if converted == total {
+            eprintln!();
+        }
+    };
+    let progress: Option<&mut dyn FnMut(usize, usize)> = if opt.progress {
+        Some(&mut report)
+    } else {
+        None
+    };
+    if opt.stream {
+        if let Some(Command::Send { .. }) = &opt.command {
+            error!("--stream writes the program to --out or stdout, it can't be sent");
+            std::process::exit(1)
+        }
+        let w: Box<dyn Write> = match &opt.out {
+            Some(out_path) => Box::new(io::BufWriter::new(File::create(out_path)?)),
+            None => Box::new(io::BufWriter::new(io::stdout())),
+        };
+        let mut writer = GCodeWriter::new(w);
+        let comments = opt.comments;
+        let mut tool = None;
+        if let Err(err) = converter::svg2program_streaming(
+            &document,
+            options,
+            &mut turtle,
+            progress,
+            None,
+            &mut |mut chunk| {
+                postprocess::offset_tools(&mut chunk, &opt.tool_offsets, &mut tool);
+                if comments == CommentLevel::None {
+                    postprocess::remove_comments(&mut chunk);
+                }
+                writer.write(&chunk)
+            },
+        ) {
+            error!("{}", err);
+            std::process::exit(1)
+        }
+        return writer.finish();
+    }
 
-    let origin = opt
-        .origin
-        .split(',')
-        .map(|point| point.parse().expect("could not parse coordinate"))
-        .collect::<Vec<f64>>();
-    postprocess::set_origin(&mut program, lyon_geom::point(origin[0], origin[1]));
+    // The setup and teardown are kept apart from the paths, so that passes which move the paths
+    // leave the machine's work coordinates and parking alone
+    let (origin_marker, [mut setup, mut program, mut teardown]) =
+        match converter::origin_marker(&document, &options).and_then(|marker| {
+            convert_parts(&document, options, &mut turtle, progress).map(|parts| (marker, parts))
+        }) {
+            Ok(converted) => converted,
+            Err(err) => {
+                error!("{}", err);
+                std::process::exit(1)
+            }
+        };
+
+    // Scale before anything that works in tool distances (kerf, leads, tabs, ...) so those stay true to size
+    if let Some(factor) = opt.scale {
+        program = postprocess::transform_program(
+            program,
+            &euclid::default::Transform2D::scale(factor, factor),
+        );
+    }
+
+    if let Some([width, height]) = opt.fit {
+        program = postprocess::scale_to_fit(program, width, height);
+    }
+
+    if let Some(min_length) = opt.min_segment_length {
+        program = postprocess::merge_short_segments(program, min_length);
+    }
+
+    if let Some(tolerance) = opt.merge_collinear {
+        program = postprocess::merge_collinear_segments(program, tolerance);
+    }
+
+    if let Some(tolerance) = opt.remove_overlaps {
+        program = postprocess::remove_overlaps(
+            program,
+            &turtle.machine.tool_on_sequence(),
+            &turtle.machine.tool_off_sequence(),
+            tolerance,
+        );
+    }
 
-    if let Some(out_path) = opt.out {
-        tokens_into_gcode_bytes(&program, File::create(out_path)?)
+    if let Some(size) = opt.small_feature_size {
+        program = postprocess::slow_small_features(program, size, opt.small_feature_factor);
+    }
+
+    if let (Some(distance), Some(power)) = (opt.ramp_distance, opt.ramp_power) {
+        program = postprocess::ramp_power(program, distance, power);
+    }
+
+    if opt.direction.is_some() || !opt.reversed_layers.is_empty() {
+        // Mirroring the program along one axis later on reverses the direction of every cut
+        let direction = if opt.mirror_x != opt.mirror_y {
+            opt.direction.map(postprocess::Direction::mirrored)
+        } else {
+            opt.direction
+        };
+        program = postprocess::set_direction(program, direction, &opt.reversed_layers);
+    }
+
+    if let Some(kerf) = opt.kerf {
+        program = postprocess::offset_closed_cuts(program, kerf / 2.);
+    }
+
+    if opt.lead_in > 0. || opt.lead_out > 0. {
+        program = postprocess::add_leads(
+            program,
+            postprocess::LeadOptions {
+                lead_in: opt.lead_in,
+                lead_out: opt.lead_out,
+                arc: opt.lead_arc,
+            },
+        );
+    }
+
+    if let (Some(count), Some(width)) = (opt.tabs, opt.tab_width) {
+        program = postprocess::insert_tabs(
+            program,
+            postprocess::TabOptions {
+                width,
+                count,
+                min_length: opt.tab_min_length,
+            },
+            &turtle.machine.tool_on_sequence(),
+            &turtle.machine.tool_off_sequence(),
+        );
+    }
+
+    if let Some(max_power) = opt.inline_power {
+        program = postprocess::inline_power(
+            program,
+            &postprocess::PowerOptions {
+                max_power,
+                layer_power: opt.layer_power.clone(),
+            },
+        );
+    }
+
+    if let Some(curve) = turtle.machine.power_curve() {
+        postprocess::map_power(&mut program, curve);
+    }
+
+    if let Some(degrees) = opt.rotate {
+        let about = opt.rotate_about.map(|[x, y]| lyon_geom::point(x, y));
+        program = postprocess::rotate(program, degrees, about);
+    }
+
+    if opt.mirror_x || opt.mirror_y {
+        program = postprocess::mirror(program, opt.mirror_x, opt.mirror_y);
+    }
+
+    // Centering is a shorthand for placing the center at the origin
+    let (origin, origin_modes) = match &opt.center {
+        Some(center) => (
+            *center,
+            [
+                postprocess::OriginMode::Center,
+                postprocess::OriginMode::Center,
+            ],
+        ),
+        None => (opt.origin, opt.origin_mode.clone()),
+    };
+    let origin = lyon_geom::point(origin[0], origin[1]);
+    if let (Some(marker), None) = (origin_marker, &opt.center) {
+        info!("Using the origin marker in the SVG instead of --origin-mode");
+        postprocess::set_origin(
+            &mut program,
+            origin - marker.to_vector(),
+            &[
+                postprocess::OriginMode::ViewBox,
+                postprocess::OriginMode::ViewBox,
+            ],
+        );
     } else {
-        tokens_into_gcode_bytes(&program, std::io::stdout())
+        postprocess::set_origin(&mut program, origin, &origin_modes);
     }
-}
+    postprocess::offset_tools(&mut program, &opt.tool_offsets, &mut None);
 
-/// Convenience function for calling the g-code crate's PEG parser with user-defined g-code.
-fn parse_snippet(gcode: &'_ String) -> Result<Snippet<'_>, ParseError> {
-    snippet_parser(gcode)
-}
+    if let Some([x, y]) = opt.second_head {
+        program = postprocess::add_second_head(
+            &program,
+            postprocess::SecondHeadOptions {
+                offset: lyon_geom::vector(x, y),
+                mirror: opt.second_head_mirror,
+            },
+            &turtle.machine.tool_off_sequence(),
+        );
+    }
 
-/// Write GCode tokens to a byte sink in a nicely formatted manner
-fn tokens_into_gcode_bytes<W: std::io::Write>(
-    program: &[g_code::emit::Token<'_>],
-    mut w: W,
-) -> io::Result<()> {
-    use g_code::emit::Token::*;
-    let mut preceded_by_newline = true;
-    for token in program {
-        match token {
-            Field(f) => {
-                if !preceded_by_newline {
-                    if matches!(f.letters.as_ref(), "G" | "M") {
-                        writeln!(w)?;
+    if let Some(resolution) = opt.resolution {
+        program = postprocess::snap_to_resolution(program, resolution);
+    }
+
+    if let Some(distance) = opt.stay_down_distance {
+        program = postprocess::stay_down(
+            program,
+            &turtle.machine.tool_on_sequence(),
+            &turtle.machine.tool_off_sequence(),
+            distance,
+            opt.feedrate,
+        );
+    }
+
+    if opt.remove_redundant {
+        program = postprocess::remove_empty_toggles(
+            program,
+            &turtle.machine.tool_on_sequence(),
+            &turtle.machine.tool_off_sequence(),
+        );
+        program = postprocess::remove_redundant(program);
+    }
+
+    if opt.check_travel {
+        for snag in analysis::find_snags(&analysis::motions(&program)) {
+            warn!("{}", snag);
+        }
+    }
+
+    if opt.estimate {
+        let motions = analysis::motions(&program);
+        let statistics = analysis::statistics(&motions);
+        let travel_feedrate = opt.travel_feedrate.unwrap_or(opt.feedrate);
+        let duration = match opt.acceleration {
+            Some(acceleration) => analysis::estimate_duration(
+                &motions,
+                opt.feedrate,
+                travel_feedrate,
+                analysis::Acceleration {
+                    acceleration,
+                    junction_deviation: opt.junction_deviation,
+                },
+            ),
+            None => statistics.duration(opt.feedrate, travel_feedrate),
+        };
+        let area_in_square_meters = statistics.area() / 1e6;
+        info!(
+            "Cutting {:.1}mm and traveling {:.1}mm takes about {}:{:02}:{:02}, over {:.4}m²",
+            statistics.cut_length,
+            statistics.travel_length,
+            (duration / 3600.) as u64,
+            (duration / 60.) as u64 % 60,
+            duration as u64 % 60,
+            area_in_square_meters
+        );
+        let machine_cost = opt.hourly_rate.map(|rate| rate * duration / 3600.);
+        let material_cost = opt.material_cost.map(|cost| cost * area_in_square_meters);
+        if machine_cost.is_some() || material_cost.is_some() {
+            info!(
+                "Estimated cost is {:.2} for the machine and {:.2} for material, {:.2} in total",
+                machine_cost.unwrap_or(0.),
+                material_cost.unwrap_or(0.),
+                machine_cost.unwrap_or(0.) + material_cost.unwrap_or(0.)
+            );
+        }
+    }
+
+    if let (Some(path), Some(interval)) = (opt.samples, opt.sample_interval) {
+        let mut w = io::BufWriter::new(File::create(path)?);
+        writeln!(w, "time,x,y,cutting")?;
+        let samples = analysis::resample(
+            &analysis::motions(&program),
+            interval,
+            opt.feedrate,
+            opt.travel_feedrate.unwrap_or(opt.feedrate),
+        );
+        for sample in samples {
+            writeln!(
+                w,
+                "{},{},{},{}",
+                sample.time, sample.position.x, sample.position.y, sample.is_cutting as u8
+            )?;
+        }
+    }
+
+    let bed = opt.bed;
+    if let Some(path) = &opt.preview {
+        std::fs::write(path, preview::preview_svg(&program, bed))?;
+    }
+
+    if opt.verify {
+        let limits = simulator::Limits {
+            min_z: opt.min_z,
+            bounds: bed.map(|[width, height]| {
+                euclid::default::Box2D::new(
+                    lyon_geom::point(0., 0.),
+                    lyon_geom::point(width, height),
+                )
+            }),
+        };
+        let violations = simulator::verify(
+            &[setup.as_slice(), &program, &teardown].concat(),
+            &turtle.machine.tool_on_sequence(),
+            &turtle.machine.tool_off_sequence(),
+            &limits,
+        );
+        const SHOWN_VIOLATIONS: usize = 10;
+        for violation in violations.iter().take(SHOWN_VIOLATIONS) {
+            error!("{}", violation);
+        }
+        if violations.len() > SHOWN_VIOLATIONS {
+            error!("and {} more problems", violations.len() - SHOWN_VIOLATIONS);
+        }
+        if !violations.is_empty() {
+            std::process::exit(1);
+        }
+    }
+
+    if let Some(laser_mode) = opt.grbl_laser_mode {
+        let (setting, warnings) = turtle.machine.grbl_laser_mode(laser_mode);
+        for warning in warnings {
+            warn!("{}", warning);
+        }
+        setup.splice(0..0, setting);
+    }
+
+    if let Some([width, height]) = opt.thumbnail {
+        setup.splice(0..0, thumbnail::thumbnail_comments(&program, width, height));
+    }
+
+    if opt.inches {
+        for part in [&mut setup, &mut program, &mut teardown] {
+            postprocess::convert_to_inches(part);
+        }
+    }
+
+    if let Some(precision) = opt.precision {
+        for part in [&mut setup, &mut program, &mut teardown] {
+            postprocess::round_coordinates(part, precision);
+        }
+    }
+
+    if opt.metadata_comments {
+        setup.splice(0..0, postprocess::bounds_comment(&program));
+    }
+
+    if let Some(step) = opt.m73_progress {
+        program = postprocess::insert_progress(
+            program,
+            step,
+            opt.travel_feedrate.unwrap_or(opt.feedrate),
+        );
+    }
+
+    if opt.axes != ["X", "Y"] {
+        for part in [&mut setup, &mut program, &mut teardown] {
+            postprocess::remap_axes(part, opt.axes);
+        }
+    }
+
+    if let Some(template) = &opt.travel_comment {
+        program = postprocess::annotate_travel(program, template);
+    }
+
+    if let Some(split_dir) = &opt.split_dir {
+        std::fs::create_dir_all(split_dir)?;
+        for (name, part) in postprocess::split_by_top_level_element(&program) {
+            let file_name = name
+                .chars()
+                .map(|c| {
+                    if c.is_alphanumeric() || "-_.".contains(c) {
+                        c
                     } else {
-                        write!(w, " ")?;
+                        '_'
                     }
+                })
+                .collect::<String>();
+            let mut parts = [setup.clone(), part, teardown.clone()];
+            if opt.comments == CommentLevel::None {
+                for part in &mut parts {
+                    postprocess::remove_comments(part);
                 }
-                write!(w, "{}", f)?;
-                preceded_by_newline = false;
-            }
-            Comment {
-                is_inline: true,
-                inner,
-            } => {
-                write!(w, "({})", inner)?;
-                preceded_by_newline = false;
             }
-            Comment {
-                is_inline: false,
-                inner,
-            } => {
-                writeln!(w, ";{}", inner)?;
-                preceded_by_newline = true;
-            }
-            _ => {}
+            write_program_file(
+                &parts,
+                &split_dir.join(format!("{}.gcode", file_name)),
+                opt.max_lines,
+            )?;
         }
     }
-    // Ensure presence of trailing newline
-    if !preceded_by_newline {
-        writeln!(w)?;
+
+    if opt.comments == CommentLevel::None {
+        for part in [&mut setup, &mut program, &mut teardown] {
+            postprocess::remove_comments(part);
+        }
+    }
+
+    if opt.max_lines.is_some() && opt.out.is_none() && opt.split_dir.is_none() {
+        warn!("--max-lines only splits programs written to files with --out or --split-dir");
+    }
+    if let Some(Command::Send { port, checksums }) = &opt.command {
+        let mut gcode = vec![];
+        tokens_into_gcode_bytes(&[setup, program, teardown].concat(), &mut gcode)?;
+        sender::send(&String::from_utf8_lossy(&gcode), port, *checksums)
+    } else if let Some(out_path) = opt.out {
+        write_program_file(&[setup, program, teardown], &out_path, opt.max_lines)
+    } else {
+        tokens_into_gcode_bytes(&[setup, program, teardown].concat(), std::io::stdout())
+    }
+}
+
+/// Convert an SVG into the setup, the paths and the teardown of a program
+fn convert_parts<'input>(
+    document: &roxmltree::Document,
+    options: ProgramOptions,
+    turtle: &mut Turtle<'input>,
+    progress: Option<&mut dyn FnMut(usize, usize)>,
+) -> Result<[Vec<g_code::emit::Token<'input>>; 3], converter::ConversionError> {
+    let mut chunks = vec![];
+    converter::svg2program_streaming(document, options, turtle, progress, None, &mut |chunk| {
+        chunks.push(chunk);
+        Ok(())
+    })?;
+    // The setup and teardown come in chunks of their own
+    let teardown = chunks.pop().unwrap_or_default();
+    let setup = if chunks.is_empty() {
+        vec![]
+    } else {
+        chunks.remove(0)
+    };
+    Ok([setup, chunks.concat(), teardown])
+}
+
+/// Write a program, given as its setup, paths and teardown, to a file, or to numbered files in
+/// its place (i.e. out-1.gcode and out-2.gcode for out.gcode) if it is over `max_lines`
+fn write_program_file(
+    [setup, paths, teardown]: &[Vec<g_code::emit::Token<'_>>; 3],
+    path: &Path,
+    max_lines: Option<usize>,
+) -> io::Result<()> {
+    let parts = match max_lines {
+        Some(max_lines) => postprocess::split_by_lines(setup, paths, teardown, max_lines),
+        None => vec![[setup.as_slice(), paths, teardown].concat()],
+    };
+    if parts.len() == 1 {
+        return tokens_into_gcode_bytes(&parts[0], File::create(path)?);
     }
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let extension = path
+        .extension()
+        .map(|extension| format!(".{}", extension.to_string_lossy()))
+        .unwrap_or_default();
+    for (i, part) in parts.iter().enumerate() {
+        let part_path = path.with_file_name(format!("{}-{}{}", stem, i + 1, extension));
+        tokens_into_gcode_bytes(part, File::create(&part_path)?)?;
+    }
+    info!("Split {} into {} parts", path.display(), parts.len());
     Ok(())
 }
 
+/// Trace a PNG input into an SVG of the outlines of its dark areas
+#[cfg(feature = "tracing")]
+fn trace_png(bytes: &[u8], opt: &Opt) -> io::Result<String> {
+    let bitmap = raster::decode_png(bytes)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))?;
+    info!("Tracing a {}x{} pixel image", bitmap.width, bitmap.height);
+    Ok(trace::trace_svg(
+        &bitmap,
+        &trace::TraceOptions {
+            threshold: opt.trace_threshold,
+            tolerance: opt.trace_tolerance,
+            min_area: opt.trace_min_area,
+            dpi: opt.dpi,
+        },
+    ))
+}
+
+#[cfg(not(feature = "tracing"))]
+fn trace_png(_bytes: &[u8], _opt: &Opt) -> io::Result<String> {
+    Err(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "the input is a PNG, which can only be traced by svg2gcode built with --features tracing",
+    ))
+}
+
+/// The profiles file in the user's config directory, `$XDG_CONFIG_HOME` or `~/.config`
+fn default_profiles_path() -> Option<PathBuf> {
+    env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .map(|config| config.join("svg2gcode").join("profiles"))
+}
+
+/// Parse a number like `0.1`, also accepting the `0,1` of locales with decimal commas, which
+/// would otherwise fail with a confusing error
+fn parse_number(s: &str) -> Result<f64, String> {
+    if let Ok(number) = s.parse() {
+        return Ok(number);
+    }
+    match s.split_once(',') {
+        // 1,000 could be a thousand or one
+        Some((_, fraction))
+            if fraction.len() == 3 && fraction.chars().all(|c| c.is_ascii_digit()) =>
+        {
+            Err(format!(
+                "{} is ambiguous, write it with a decimal point like {} or without a separator",
+                s,
+                s.replace(',', ".")
+            ))
+        }
+        Some(_) if !s.contains('.') && s.matches(',').count() == 1 => s
+            .replace(',', ".")
+            .parse()
+            .map_err(|_| format!("{} is not a number", s)),
+        _ => Err(format!("{} is not a number, like 0.1", s)),
+    }
+}
+
+/// Parse a width and height like `210mm,297mm`
+fn parse_dimensions(s: &str) -> Result<[svgtypes::Length; 2], String> {
+    let dimensions = s
+        .split(',')
+        .map(|dimension| {
+            svgtypes::Length::from_str(dimension.trim())
+                .map_err(|_| format!("{} is not a length, like 210mm", dimension))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    match dimensions.as_slice() {
+        [width, height] => Ok([*width, *height]),
+        _ => Err(format!("{} is not a width and height, like 210mm,297mm", s)),
+    }
+}
+
+/// Parse the two machine axes that X and Y are moved on, like `YX`
+fn parse_axes(s: &str) -> Result<[&'static str; 2], String> {
+    let axes = s
+        .chars()
+        .map(|axis| {
+            postprocess::AXES
+                .iter()
+                .find(|candidate| candidate.starts_with(axis.to_ascii_uppercase()))
+                .copied()
+        })
+        .collect::<Option<Vec<_>>>();
+    match axes.as_deref() {
+        Some([x, y]) if x != y => Ok([x, y]),
+        _ => Err(format!(
+            "{} is not two different letters of {}",
+            s,
+            postprocess::AXES.concat()
+        )),
+    }
+}
+
+/// Parse the origin mode of both axes like `center`, or of each axis like
+/// `center,bounding-box-min`
+fn parse_origin_modes(s: &str) -> Result<[postprocess::OriginMode; 2], String> {
+    let modes = s
+        .split(',')
+        .map(|mode| mode.trim().parse())
+        .collect::<Result<Vec<postprocess::OriginMode>, _>>()?;
+    match modes.as_slice() {
+        [mode] => Ok([mode.clone(), mode.clone()]),
+        [x, y] => Ok([x.clone(), y.clone()]),
+        _ => Err(format!("{} is not one origin mode or one for each axis", s)),
+    }
+}
+
+/// Parse the power of a color like `red=1000`
+fn parse_color_power(s: &str) -> Result<(svgtypes::Color, f64), String> {
+    let (color, power) = s
+        .split_once('=')
+        .ok_or_else(|| format!("{} is not a color and power, like red=1000", s))?;
+    let color = color
+        .trim()
+        .parse()
+        .map_err(|_| format!("{} is not a color, like red or #00f", color))?;
+    Ok((color, parse_number(power.trim())?))
+}
+
+/// Parse a point of a power curve like `50=30`, in percent of full power, as fractions
+fn parse_power_table_point(s: &str) -> Result<(f64, f64), String> {
+    let (from, to) = s
+        .split_once('=')
+        .ok_or_else(|| format!("{} is not a point of a power curve, like 50=30", s))?;
+    Ok((
+        parse_number(from.trim())? / 100.,
+        parse_number(to.trim())? / 100.,
+    ))
+}
+
+/// Parse the power of a layer like `layer1=50`, in percent, as a fraction
+fn parse_layer_power(s: &str) -> Result<(String, f64), String> {
+    match s.split_once('=') {
+        Some((id, percent)) if !id.trim().is_empty() => {
+            Ok((id.trim().to_string(), parse_number(percent.trim())? / 100.))
+        }
+        _ => Err(format!("{} is not a layer and percent, like layer1=50", s)),
+    }
+}
+
+/// Parse a number above zero, like a distance that something is divided by
+fn parse_positive_number(s: &str) -> Result<f64, String> {
+    match parse_number(s)? {
+        number if number > 0. => Ok(number),
+        _ => Err(format!("{} must be above zero", s)),
+    }
+}
+
+/// Parse a point like `10,-5`
+fn parse_point(s: &str) -> Result<[f64; 2], String> {
+    match s.split_once(',') {
+        Some((x, y)) => match (x.trim().parse(), y.trim().parse()) {
+            (Ok(x), Ok(y)) => Ok([x, y]),
+            _ => Err(format!("{} is not a point, like 10,-5", s)),
+        },
+        None => Err(format!("{} is not a point, like 10,-5", s)),
+    }
+}
+
+/// Parse a size like `300x200`, with a width and height above zero
+fn parse_size<T>(s: &str) -> Result<[T; 2], String>
+where
+    T: FromStr + Default + PartialOrd,
+{
+    match s.split_once('x') {
+        Some((width, height)) => match (width.trim().parse(), height.trim().parse()) {
+            (Ok(width), Ok(height)) if width > T::default() && height > T::default() => {
+                Ok([width, height])
+            }
+            (Ok(_), Ok(_)) => Err(format!("{} must have a width and height above zero", s)),
+            _ => Err(format!("{} is not a size, like 300x200", s)),
+        },
+        None => Err(format!("{} is not a size, like 300x200", s)),
+    }
+}
+
+/// Write a roff man page containing the long help for the CLI
+fn write_man_page<W: std::io::Write>(mut w: W) -> io::Result<()> {
+    let mut help = vec![];
+    Opt::clap()
+        .write_long_help(&mut help)
+        .map_err(|err| io::Error::other(err.to_string()))?;
+    let help = String::from_utf8_lossy(&help);
+    // Skip the name, version, author and about lines that are already in the header
+    let usage = help
+        .lines()
+        .skip_while(|line| !line.starts_with("USAGE:"))
+        .map(|line| {
+            // Escape roff control characters
+            let line = line.replace('\\', "\\e");
+            if line.starts_with('.') || line.starts_with('\'') {
+                format!("\\&{}", line)
+            } else {
+                line
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let name = env!("CARGO_PKG_NAME");
+    writeln!(
+        w,
+        ".TH {} 1 \"\" \"{} {}\"",
+        name.to_uppercase(),
+        name,
+        env!("CARGO_PKG_VERSION")
+    )?;
+    writeln!(w, ".SH NAME")?;
+    writeln!(w, "{} \\- {}", name, env!("CARGO_PKG_DESCRIPTION"))?;
+    writeln!(w, ".SH DESCRIPTION")?;
+    writeln!(w, ".nf")?;
+    writeln!(w, "{}", usage)?;
+    writeln!(w, ".fi")?;
+    writeln!(w, ".SH AUTHORS")?;
+    writeln!(w, "{}", env!("CARGO_PKG_AUTHORS"))
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::turtle::Turtle;
     use pretty_assertions::assert_eq;
 
-    fn get_actual(input: &str) -> String {
-        let options = ProgramOptions::default();
-        let machine = Machine {
-            tool_state: None,
-            distance_mode: None,
-            tool_on_action: None,
-            tool_off_action: None,
-            program_begin_sequence: None,
-            program_end_sequence: None,
-        };
-        let document = roxmltree::Document::parse(input).unwrap();
+    /// Run the command line on tests/square.svg with more arguments, returning the program written
+    fn run_square(name: &str, args: &[&str]) -> String {
+        let out = env::temp_dir().join(format!("svg2gcode-{}-{}.gcode", name, std::process::id()));
+        let mut all_args = vec![
+            "svg2gcode",
+            concat!(env!("CARGO_MANIFEST_DIR"), "/tests/square.svg"),
+            "--out",
+            out.to_str().unwrap(),
+        ];
+        all_args.extend_from_slice(args);
+        run(Opt::from_iter_safe(&all_args).unwrap()).unwrap();
+        let program = std::fs::read_to_string(&out).unwrap();
+        std::fs::remove_file(&out).unwrap();
+        program
+    }
 
-        let mut turtle = Turtle::new(machine);
-        let mut program = converter::svg2program(&document, options, &mut turtle);
-        postprocess::set_origin(&mut program, lyon_geom::point(0., 0.));
+    /// Run the command line on tests/split.svg with a tool and --max-lines, returning the parts
+    /// written in order, or the whole program if it wasn't split
+    fn run_split(name: &str, max_lines: &str) -> Vec<String> {
+        let out = env::temp_dir().join(format!("svg2gcode-{}-{}.gcode", name, std::process::id()));
+        run(Opt::from_iter_safe(&[
+            "svg2gcode",
+            concat!(env!("CARGO_MANIFEST_DIR"), "/tests/split.svg"),
+            "--out",
+            out.to_str().unwrap(),
+            "--begin",
+            "G28",
+            "--on",
+            "M3",
+            "--off",
+            "M5",
+            "--end",
+            "M30",
+            "--max-lines",
+            max_lines,
+        ])
+        .unwrap())
+        .unwrap();
+        if let Ok(program) = std::fs::read_to_string(&out) {
+            std::fs::remove_file(&out).unwrap();
+            return vec![program];
+        }
+        let mut parts = vec![];
+        for i in 1.. {
+            let part = out.with_file_name(format!(
+                "svg2gcode-{}-{}-{}.gcode",
+                name,
+                std::process::id(),
+                i
+            ));
+            match std::fs::read_to_string(&part) {
+                Ok(program) => parts.push(program),
+                Err(_) => break,
+            }
+            std::fs::remove_file(&part).unwrap();
+        }
+        parts
+    }
 
+    #[test]
+    fn second_head_selects_each_head_on_its_own_line() {
+        let document = roxmltree::Document::parse(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="10mm" height="10mm" viewBox="0 0 10 10">
+                <path d="M1 1 L2 2"/>
+            </svg>"#,
+        )
+        .unwrap();
+        let machine = MachineBuilder::default()
+            .tool_on(Some("M3"))
+            .tool_off(Some("M5"))
+            .build()
+            .unwrap();
+        let mut turtle = Turtle::new(machine);
+        let [setup, program, teardown] =
+            convert_parts(&document, ProgramOptions::default(), &mut turtle, None).unwrap();
+        let program = postprocess::add_second_head(
+            &program,
+            postprocess::SecondHeadOptions {
+                offset: lyon_geom::vector(100., 0.),
+                mirror: false,
+            },
+            &turtle.machine.tool_off_sequence(),
+        );
         let mut actual = vec![];
-        assert!(tokens_into_gcode_bytes(&program, &mut actual).is_ok());
-        String::from_utf8(actual).unwrap()
+        tokens_into_gcode_bytes(&[setup, program, teardown].concat(), &mut actual).unwrap();
+        let actual = String::from_utf8(actual).unwrap();
+
+        assert!(actual.contains("G90\nT0;svg > path\nM5\nG0 X1 Y9\n"));
+        assert!(actual.contains("G1 X2 Y8 F300\nM5\nT1;svg > path\nM5\nG0 X101 Y9\n"));
     }
 
     #[test]
-    fn square_produces_expected_gcode() {
-        let square = include_str!("../tests/square.svg");
-        let actual = get_actual(square);
+    fn second_head_offset_needs_x_and_y() {
+        assert!(Opt::from_iter_safe(&["svg2gcode", "--second-head", "5"]).is_err());
+        let opt = Opt::from_iter_safe(&["svg2gcode", "--second-head", "-5,2.5"]).unwrap();
+        assert_eq!(opt.second_head, Some([-5., 2.5]));
+    }
 
-        assert_eq!(actual, include_str!("../tests/square.gcode"))
+    #[test]
+    fn work_coordinates_are_set_up_after_other_setup() {
+        let program = run_square(
+            "work-coordinates",
+            &[
+                "--thumbnail",
+                "16x16",
+                "--grbl-laser-mode",
+                "true",
+                "--coordinate-system",
+                "G55",
+                "--work-position",
+                "5,5",
+                "--park",
+                "0,300",
+                "--origin",
+                "10,10",
+            ],
+        );
+        let lines = program.lines().collect::<Vec<_>>();
+        let position = |line: &str| lines.iter().position(|l| l.starts_with(line)).unwrap();
+
+        assert!(lines[0].starts_with("; thumbnail begin"));
+        assert!(position("G90") < position("G55"));
+        assert!(position("G55") < position("G92 X5 Y5"));
+        assert!(lines.contains(&"G0 X11 Y19"));
+        assert_eq!(&lines[lines.len() - 3..], ["G53 G0 X0 Y300", "G92.1", "M2"]);
     }
 
     #[test]
-    fn square_transformed_produces_expected_gcode() {
-        let square_transformed = include_str!("../tests/square_transformed.svg");
-        let actual = get_actual(square_transformed);
+    fn work_coordinates_are_set_up_after_homing_and_probing() {
+        let program = run_square(
+            "homing-work-coordinates",
+            &[
+                "--home",
+                "grbl",
+                "--travel-z",
+                "5",
+                "--cut-z",
+                "-1",
+                "--probe-distance",
+                "20",
+                "--coordinate-system",
+                "G55",
+                "--work-position",
+                "5,5",
+            ],
+        );
+        let lines = program.lines().collect::<Vec<_>>();
+        let position = |line: &str| lines.iter().position(|l| l.starts_with(line)).unwrap();
 
-        assert_eq!(actual, include_str!("../tests/square_transformed.gcode"))
+        // Homing resets G92, so it has to come first, and the probed Z goes into G55 (P2)
+        assert!(position("$H") < position("G38.2 Z-20"));
+        assert!(position("G38.2 Z-20") < position("G10 L20 P2 Z0"));
+        assert!(position("G10 L20 P2 Z0") < position("G55"));
+        assert!(position("G55") < position("G92 X5 Y5"));
     }
 
     #[test]
-    fn square_viewport_produces_expected_gcode() {
-        let square_transformed = include_str!("../tests/square_viewport.svg");
-        let actual = get_actual(square_transformed);
+    fn plunges_and_retracts_default_to_the_feedrate() {
+        let program = run_square(
+            "depth",
+            &["--travel-z", "5", "--cut-z", "-1", "--feedrate", "500"],
+        );
+        assert!(program.contains("G0 X1 Y9\nG1 Z-1 F500\nG1 X9 Y9 F500"));
+        assert!(program.contains("\nG1 Z5 F500\nG0 X8 Y2.5\n"));
+        assert!(!program.contains("G0 Z-1"));
+
+        let program = run_square(
+            "depth-feedrates",
+            &[
+                "--travel-z",
+                "5",
+                "--cut-z",
+                "-1",
+                "--plunge-feedrate",
+                "100",
+                "--retract-feedrate",
+                "200",
+            ],
+        );
+        assert!(program.contains("G1 Z-1 F100"));
+        assert!(program.contains("G1 Z5 F200"));
+    }
+
+    #[test]
+    fn command_line_overrides_profile() {
+        let profiles = env::temp_dir().join(format!("svg2gcode-profiles-{}", std::process::id()));
+        std::fs::write(&profiles, "[laser]\n--dialect grbl\n--feedrate 1200\n").unwrap();
+        let command_line = [
+            "svg2gcode",
+            "--feedrate=300",
+            "--profile",
+            "laser",
+            "--profiles",
+            profiles.to_str().unwrap(),
+            "in.svg",
+        ]
+        .iter()
+        .map(Into::into)
+        .collect::<Vec<OsString>>();
+        let opt = Opt::from_iter_safe(&command_line).unwrap();
+        let args = read_profile(&opt, "laser").unwrap();
+        std::fs::remove_file(&profiles).unwrap();
+        let opt = Opt::from_iter_safe(profile::merge(args, command_line)).unwrap();
+        assert_eq!(opt.dialect, Some(Dialect::Grbl));
+        assert_eq!(opt.feedrate, 300.);
+        assert_eq!(opt.file, Some(PathBuf::from("in.svg")));
+    }
+
+    #[test]
+    fn sizes_need_a_positive_width_and_height() {
+        let thumbnail = |size: &str| {
+            Opt::from_iter_safe(&["svg2gcode", "--thumbnail", size]).map(|opt| opt.thumbnail)
+        };
+        assert_eq!(thumbnail("220x124").unwrap(), Some([220, 124]));
+        assert!(thumbnail("220").is_err());
+        assert!(thumbnail("0x0").is_err());
+        assert!(thumbnail("16x-1").is_err());
+        let fit =
+            |size: &str| Opt::from_iter_safe(&["svg2gcode", "--fit", size]).map(|opt| opt.fit);
+        assert_eq!(fit("300x200.5").unwrap(), Some([300., 200.5]));
+        assert!(fit("100").is_err());
+        assert!(fit("100x0").is_err());
+    }
+
+    #[test]
+    fn tool_offsets_shift_the_placed_program() {
+        let args = [
+            "--operation",
+            "cut,color=black,tool=2",
+            "--tool-offset",
+            "2=10,-5",
+        ];
+        let program = run_square("tool-offset", &args);
+        assert!(program.contains("T2\nM6"), "{}", program);
+        assert!(program.contains("G0 X11 Y4\nG1 X19 Y4 F300"), "{}", program);
+
+        let streamed = run_square("tool-offset-stream", &[&args[..], &["--stream"]].concat());
+        assert!(
+            streamed.contains("G0 X11 Y4\nG1 X19 Y4 F300"),
+            "{}",
+            streamed
+        );
+    }
+
+    #[test]
+    fn dry_run_makes_the_same_moves_with_the_tool_off() {
+        let args = ["--on", "M3", "--off", "M5", "--power", "1000"];
+        let program = run_square("tool-on", &args);
+        let dry_run = run_square("dry-run", &[&args[..], &["--dry-run-motion"]].concat());
+        let moves = |program: &str| {
+            program
+                .lines()
+                .filter(|line| line.starts_with("G0") || line.starts_with("G1"))
+                .map(|line| line.split(';').next().unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(moves(&dry_run), moves(&program));
+        assert!(program.contains("M3 S1000"));
+        assert!(!dry_run.contains("M3"), "{}", dry_run);
+        assert_eq!(
+            dry_run.matches(";Dry run, tool stays off").count(),
+            program.matches("M3 S1000").count()
+        );
+    }
 
-        assert_eq!(actual, include_str!("../tests/square_viewport.gcode"))
+    #[test]
+    fn kerf_is_not_scaled() {
+        // The 8mm square doubles to 16mm, then grows by the 1mm kerf, not by twice that
+        let program = run_square("kerf-scale", &["--kerf", "1", "--scale", "2"]);
+        let first_path = program
+            .split(";svg#svg8 > g#layer1 > path#path832")
+            .next()
+            .unwrap();
+        assert!(first_path.contains("G0 X1.5 Y18.5\n"), "{}", program);
+        assert!(first_path.contains("G1 X18.5 Y1.5 F300\n"), "{}", program);
+    }
+
+    #[test]
+    fn streaming_refuses_postprocessing() {
+        assert!(Opt::from_iter_safe(&["svg2gcode", "--stream"]).is_ok());
+        assert!(Opt::from_iter_safe(&["svg2gcode", "--stream", "--comments", "none"]).is_ok());
+        for args in [
+            &["--origin", "10,10"][..],
+            &["--scale", "2"],
+            &["--fit", "100x100"],
+            &["--lead-in", "1"],
+            &["--kerf", "0.2"],
+            &["--rotate", "-90"],
+            &["--inches"],
+            &["--max-lines", "100"],
+        ] {
+            let mut all_args = vec!["svg2gcode", "--stream"];
+            all_args.extend_from_slice(args);
+            assert!(Opt::from_iter_safe(&all_args).is_err(), "{:?}", args);
+        }
+    }
+
+    #[test]
+    fn split_dir_writes_a_program_per_top_level_element() {
+        let dir = env::temp_dir().join(format!("svg2gcode-split-dir-{}", std::process::id()));
+        let out = dir.with_extension("gcode");
+        run(Opt::from_iter_safe(&[
+            "svg2gcode",
+            concat!(env!("CARGO_MANIFEST_DIR"), "/tests/split.svg"),
+            "--out",
+            out.to_str().unwrap(),
+            "--begin",
+            "G28",
+            "--on",
+            "M3",
+            "--off",
+            "M5",
+            "--end",
+            "M30",
+            "--split-dir",
+            dir.to_str().unwrap(),
+        ])
+        .unwrap())
+        .unwrap();
+        let mut names = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        names.sort();
+        let second = std::fs::read_to_string(dir.join("second.gcode")).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        std::fs::remove_file(&out).unwrap();
+
+        assert_eq!(names, ["first.gcode", "second.gcode", "third.gcode"]);
+        // Each has the setup and teardown of the whole program around its own paths
+        assert_eq!(
+            second,
+            "G21\nG90\nG28;svg > path#second\nM5\nG0 X0 Y10\nM3\nG1 X30 Y10 F300\nM5\nM30\nM2\n"
+        );
+    }
+
+    #[test]
+    fn max_lines_splits_into_expected_gcode() {
+        // The setup and teardown are 3 lines each and each path is 4, so two paths fit in 14
+        let parts = run_split("max-lines-fit", "14");
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0], include_str!("../tests/split-1.gcode"));
+        assert_eq!(parts[1], include_str!("../tests/split-2.gcode"));
+    }
+
+    #[test]
+    fn max_lines_gives_long_paths_parts_of_their_own() {
+        let parts = run_split("max-lines-over", "9");
+        assert_eq!(parts.len(), 3);
+        for (part, name) in parts.iter().zip(["first", "second", "third"]) {
+            let lines = part.lines().collect::<Vec<_>>();
+            assert_eq!(lines.len(), 10);
+            assert_eq!(
+                &lines[..3],
+                ["G21", "G90", &format!("G28;svg > path#{}", name)]
+            );
+            assert_eq!(&lines[7..], ["M5", "M30", "M2"]);
+        }
+    }
+
+    #[test]
+    fn max_lines_leaves_short_programs_whole() {
+        let parts = run_split("max-lines-whole", "18");
+        assert_eq!(parts.len(), 1);
+        assert_eq!(parts[0].lines().count(), 18);
+        assert_eq!(
+            parts[0]
+                .lines()
+                .filter(|line| line.starts_with("G28"))
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn bed_needs_a_width_and_height() {
+        let bed =
+            |size: &str| Opt::from_iter_safe(&["svg2gcode", "--bed", size]).map(|opt| opt.bed);
+        assert_eq!(bed("300x200").unwrap(), Some([300., 200.]));
+        assert!(bed("300").is_err());
+        assert!(bed("300x-1").is_err());
+    }
+
+    #[test]
+    fn ramp_distance_must_be_positive() {
+        let ramp_distance = |distance: &str| {
+            Opt::from_iter_safe(&[
+                "svg2gcode",
+                "--ramp-power",
+                "1000",
+                "--ramp-distance",
+                distance,
+            ])
+        };
+        assert!(ramp_distance("0,5").is_ok());
+        assert!(ramp_distance("0").is_err());
+        assert!(ramp_distance("-1").is_err());
+    }
+
+    #[test]
+    fn sample_interval_must_be_positive() {
+        let interval = |interval: &str| {
+            Opt::from_iter_safe(&[
+                "svg2gcode",
+                "--samples",
+                "samples.csv",
+                "--sample-interval",
+                interval,
+            ])
+        };
+        assert!(interval("0.1").is_ok());
+        assert!(interval("0").is_err());
+    }
+
+    #[test]
+    fn arc_tolerance_must_be_positive() {
+        let arc_tolerance = |tolerance: &str| {
+            Opt::from_iter_safe(&["svg2gcode", "--arc-tolerance", tolerance])
+                .map(|opt| opt.arc_tolerance)
+        };
+        assert_eq!(arc_tolerance("0.5").unwrap(), Some(0.5));
+        assert!(arc_tolerance("0").is_err());
+        assert!(arc_tolerance("-0.1").is_err());
+    }
+
+    #[test]
+    fn resolution_must_be_positive() {
+        let resolution =
+            |resolution: &str| Opt::from_iter_safe(&["svg2gcode", "--resolution", resolution]);
+        assert!(resolution("0.0125").is_ok());
+        assert!(resolution("0").is_err());
+    }
+
+    #[test]
+    fn acceleration_must_be_positive() {
+        let acceleration = |acceleration: &str| {
+            Opt::from_iter_safe(&["svg2gcode", "--acceleration", acceleration])
+                .map(|opt| opt.acceleration)
+        };
+        assert_eq!(acceleration("500").unwrap(), Some(500.));
+        assert!(acceleration("0").is_err());
+    }
+
+    #[test]
+    fn scan_gap_must_be_positive() {
+        let scan_gap = |scan_gap: &str| {
+            Opt::from_iter_safe(&["svg2gcode", "--raster", "dither", "--scan-gap", scan_gap])
+                .map(|opt| opt.scan_gap)
+        };
+        assert_eq!(scan_gap("0.5").unwrap(), 0.5);
+        assert!(scan_gap("0").is_err());
+        assert!(scan_gap("-1").is_err());
+    }
+
+    #[test]
+    fn dimensions_need_a_width_and_height() {
+        let dimensions = |dimensions: &str| {
+            Opt::from_iter_safe(&["svg2gcode", "--dimensions", dimensions])
+                .map(|opt| opt.dimensions)
+        };
+        assert_eq!(
+            dimensions("210mm,297mm").unwrap(),
+            Some([
+                svgtypes::Length::new(210., svgtypes::LengthUnit::Mm),
+                svgtypes::Length::new(297., svgtypes::LengthUnit::Mm)
+            ])
+        );
+        assert!(dimensions("210mm").is_err());
+        assert!(dimensions("210mm,297mm,1mm").is_err());
+        assert!(dimensions("210mm,tall").is_err());
+    }
+
+    #[test]
+    fn origin_modes_are_one_or_one_per_axis() {
+        use postprocess::OriginMode::*;
+        let origin_mode = |mode: &str| {
+            Opt::from_iter_safe(&["svg2gcode", "--origin-mode", mode]).map(|opt| opt.origin_mode)
+        };
+        assert_eq!(origin_mode("center").unwrap(), [Center, Center]);
+        assert_eq!(
+            origin_mode("viewbox-origin,custom-marker=mark").unwrap(),
+            [ViewBox, Marker("mark".to_string())]
+        );
+        assert!(origin_mode("bogus").is_err());
+        assert!(origin_mode("center,bogus").is_err());
+        assert!(origin_mode("center,center,center").is_err());
+    }
+
+    #[test]
+    fn color_powers_need_a_color_and_power() {
+        let color_power = |color_power: &str| {
+            Opt::from_iter_safe(&["svg2gcode", "--color-power", color_power])
+                .map(|opt| opt.color_power)
+        };
+        assert_eq!(
+            color_power("red=1000,#00f=500").unwrap(),
+            [
+                (svgtypes::Color::new(255, 0, 0), 1000.),
+                (svgtypes::Color::new(0, 0, 255), 500.)
+            ]
+        );
+        assert!(color_power("red").is_err());
+        assert!(color_power("red=bright").is_err());
+        assert!(color_power("reddish=1000").is_err());
+        let opt = Opt::from_iter_safe(&["svg2gcode", "--color-power", "red=1000", "in.svg"]);
+        assert_eq!(opt.unwrap().file, Some(PathBuf::from("in.svg")));
+    }
+
+    #[test]
+    fn power_table_points_map_from_and_to_percents() {
+        let power_table = |power_table: &str| {
+            Opt::from_iter_safe(&["svg2gcode", "--power-table", power_table])
+                .map(|opt| opt.power_table)
+        };
+        assert_eq!(
+            power_table("0=0,50=30,100=100").unwrap(),
+            [(0., 0.), (0.5, 0.3), (1., 1.)]
+        );
+        assert!(power_table("5").is_err());
+        assert!(power_table("50=dim").is_err());
+    }
+
+    #[test]
+    fn layer_power_scales_the_inline_power() {
+        let layer_power = |layer_power: &str| {
+            Opt::from_iter_safe(&[
+                "svg2gcode",
+                "--inline-power",
+                "1000",
+                "--layer-power",
+                layer_power,
+            ])
+            .map(|opt| opt.layer_power)
+        };
+        assert_eq!(
+            layer_power("layer1=50,layer2=80").unwrap(),
+            [("layer1".to_string(), 0.5), ("layer2".to_string(), 0.8)]
+        );
+        assert!(layer_power("foo").is_err());
+        assert!(layer_power("=50").is_err());
+        assert!(layer_power("layer1=half").is_err());
+
+        let program = run_square(
+            "layer-power",
+            &["--inline-power", "1000", "--layer-power", "layer1=50"],
+        );
+        assert!(program.contains("G1 X9 Y9 F300 S500\n"), "{}", program);
+        assert!(!program.contains("S1000"));
+    }
+
+    #[test]
+    fn points_need_an_x_and_y() {
+        let origin = |origin: &str| {
+            Opt::from_iter_safe(&["svg2gcode", "--origin", origin]).map(|opt| opt.origin)
+        };
+        assert_eq!(origin("10,-5").unwrap(), [10., -5.]);
+        assert_eq!(origin("-1.5, 2").unwrap(), [-1.5, 2.]);
+        assert!(origin("5").is_err());
+        assert!(origin("5,").is_err());
+        assert!(origin("1,2,3").is_err());
+        let center = |center: &str| {
+            Opt::from_iter_safe(&["svg2gcode", "--center", center]).map(|opt| opt.center)
+        };
+        assert_eq!(center("150,100").unwrap(), Some([150., 100.]));
+        assert!(center("5").is_err());
+    }
+
+    #[test]
+    fn axes_are_two_different_machine_axes() {
+        let axes =
+            |axes: &str| Opt::from_iter_safe(&["svg2gcode", "--axes", axes]).map(|opt| opt.axes);
+        assert_eq!(axes("yx").unwrap(), ["Y", "X"]);
+        assert_eq!(axes("XZ").unwrap(), ["X", "Z"]);
+        assert!(axes("XX").is_err());
+        assert!(axes("X").is_err());
+        assert!(axes("XYZ").is_err());
+        assert!(axes("XQ").is_err());
     }
 }
@@ -1,14 +1,107 @@
-use euclid::default::Box2D;
-use g_code::emit::{
-    Field, Token, Value, ABSOLUTE_DISTANCE_MODE_FIELD, RELATIVE_DISTANCE_MODE_FIELD,
+use euclid::default::{Box2D, Transform2D};
+use g_code::{
+    command,
+    emit::{
+        Field, Token, Value, ABSOLUTE_DISTANCE_MODE_FIELD, LINEAR_INTERPOLATION_FIELD,
+        RAPID_POSITIONING_FIELD, RELATIVE_DISTANCE_MODE_FIELD,
+    },
 };
-use lyon_geom::{point, vector, Point};
+use lyon_geom::{point, vector, LineSegment, Point, Vector};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use crate::analysis::{motions, statistics, Motion};
+use crate::machine::{PowerCurve, ToolOffset};
 
 type F64Point = Point<f64>;
 
-/// Moves all the commands so that they are beyond a specified position
-pub fn set_origin(tokens: &mut [Token<'_>], origin: F64Point) {
-    let offset = -get_bounding_box(tokens.iter()).min.to_vector() + origin.to_vector();
+/// Number of power steps used to ramp up or down
+const RAMP_STEPS: usize = 8;
+
+/// Number of linear moves approximating a quarter circle lead
+const LEAD_ARC_SEGMENTS: usize = 8;
+
+/// Size in millimeters of the cells that cuts are grouped by to find overlaps between them
+const OVERLAP_CELL_SIZE: f64 = 5.;
+
+/// Smallest turn in degrees between moves that is a corner, which tabs are kept off of.
+/// Flattened curves turn by much less than this.
+const TAB_CORNER_ANGLE: f64 = 30.;
+
+/// Maximum ratio of the miter length to the offset distance at the corners of offset cuts
+const MITER_LIMIT: f64 = 4.;
+
+/// Which point of a program is moved to the origin, chosen separately for each axis
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OriginMode {
+    /// The bottom left corner of the SVG, leaving the program where it is
+    ViewBox,
+    /// The minimum of the bounding box of the program, which includes the bottom left corner of
+    /// the SVG
+    BoundingBoxMin,
+    /// The center of the bounding box of the cuts
+    Center,
+    /// The center of the cuts made for the element with this id, i.e. a registration mark
+    Marker(String),
+}
+
+impl FromStr for OriginMode {
+    type Err = String;
+
+    /// Parses `viewbox-origin`, `bounding-box-min`, `center`, or `custom-marker`, which looks
+    /// for an element with the id `origin` unless another is given like `custom-marker=mark`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "viewbox-origin" => Ok(Self::ViewBox),
+            "bounding-box-min" => Ok(Self::BoundingBoxMin),
+            "center" => Ok(Self::Center),
+            "custom-marker" => Ok(Self::Marker("origin".to_string())),
+            other => match other.strip_prefix("custom-marker=") {
+                Some(id) if !id.is_empty() => Ok(Self::Marker(id.to_string())),
+                _ => Err(format!(
+                    "unknown origin mode {}, expected one of viewbox-origin, bounding-box-min, center or custom-marker",
+                    other
+                )),
+            },
+        }
+    }
+}
+
+/// Moves all the commands so that the point chosen by the X and Y modes is at a specified position
+pub fn set_origin(tokens: &mut [Token<'_>], origin: F64Point, modes: &[OriginMode; 2]) {
+    let motions = motions(tokens);
+    let reference_point = |mode: &OriginMode| match mode {
+        OriginMode::ViewBox => point(0., 0.),
+        OriginMode::BoundingBoxMin => get_bounding_box(tokens.iter()).min,
+        OriginMode::Center => cut_bounding_box(&motions, |_| true)
+            .map(|bounds| bounds.center())
+            .unwrap_or_else(|| point(0., 0.)),
+        OriginMode::Marker(id) => {
+            let suffix = format!("#{}", id);
+            cut_bounding_box(&motions, |source| {
+                source
+                    .split(" > ")
+                    .any(|name| name.trim().ends_with(&suffix))
+            })
+            .map(|bounds| bounds.center())
+            .unwrap_or_else(|| {
+                warn!(
+                    "No element with the id {} was cut, so it can't be used as the origin",
+                    id
+                );
+                point(0., 0.)
+            })
+        }
+    };
+    let x_reference = reference_point(&modes[0]);
+    let y_reference = if modes[1] == modes[0] {
+        x_reference
+    } else {
+        reference_point(&modes[1])
+    };
+    let reference = point(x_reference.x, y_reference.y);
+    let offset = origin - reference;
 
     let mut is_relative = false;
     let mut current_position = point(0f64, 0f64);
@@ -45,6 +138,410 @@ pub fn set_origin(tokens: &mut [Token<'_>], origin: F64Point) {
     }
 }
 
+/// Shift the moves made with each tool (selected with T) by its XY offset, once the program is
+/// placed so that the offsets aren't undone.
+///
+/// The tool selected before the tokens is updated to the one selected after them, so a program
+/// can be offset a chunk at a time.
+pub fn offset_tools(tokens: &mut [Token<'_>], offsets: &[ToolOffset], tool: &mut Option<u32>) {
+    let offset_of = |tool: Option<u32>| {
+        offsets
+            .iter()
+            .find(|offset| Some(offset.tool) == tool)
+            .map_or(vector(0., 0.), |offset| vector(offset.x, offset.y))
+    };
+    let mut offset = offset_of(*tool);
+    let mut is_relative = false;
+    let mut should_skip = false;
+    for token in tokens {
+        match token {
+            abs if *abs == Token::Field(ABSOLUTE_DISTANCE_MODE_FIELD) => is_relative = false,
+            rel if *rel == Token::Field(RELATIVE_DISTANCE_MODE_FIELD) => is_relative = true,
+            Token::Field(Field { letters, value }) if *letters == "T" => {
+                *tool = value.as_f64().map(|number| number as u32);
+                offset = offset_of(*tool);
+            }
+            // Don't shift M codes
+            Token::Field(Field { letters, .. }) if *letters == "M" => should_skip = true,
+            Token::Field(Field { letters, .. }) if *letters == "G" => should_skip = false,
+            // Relative moves are already relative to the shifted position
+            Token::Field(Field { letters, value })
+                if *letters == "X" && !should_skip && !is_relative =>
+            {
+                if let Some(x) = value.as_f64() {
+                    *value = Value::Float(x + offset.x);
+                }
+            }
+            Token::Field(Field { letters, value })
+                if *letters == "Y" && !should_skip && !is_relative =>
+            {
+                if let Some(y) = value.as_f64() {
+                    *value = Value::Float(y + offset.y);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Apply a transform to the position of every move, i.e. to scale or rotate the toolpath
+pub fn transform_program<'input>(
+    tokens: Vec<Token<'input>>,
+    transform: &Transform2D<f64>,
+) -> Vec<Token<'input>> {
+    let mut transformed: Vec<Token<'input>> = Vec::with_capacity(tokens.len());
+    let mut is_relative = false;
+    let mut current_position = point(0f64, 0f64);
+    let mut command: Option<AxisFields> = None;
+
+    for token in tokens {
+        match &token {
+            Token::Field(Field { letters, .. }) if *letters == "G" || *letters == "M" => {
+                if let Some(command) = command.take() {
+                    command.transform(
+                        &mut transformed,
+                        is_relative,
+                        &mut current_position,
+                        transform,
+                    );
+                }
+                match &token {
+                    abs if *abs == Token::Field(ABSOLUTE_DISTANCE_MODE_FIELD) => {
+                        is_relative = false
+                    }
+                    rel if *rel == Token::Field(RELATIVE_DISTANCE_MODE_FIELD) => is_relative = true,
+                    _ => {}
+                }
+                // Don't edit M codes, which may use X and Y for other purposes
+                if *letters == "G" {
+                    command = Some(AxisFields::default());
+                }
+            }
+            Token::Field(Field { letters, .. }) if *letters == "X" => {
+                if let Some(command) = &mut command {
+                    command.x = Some(transformed.len());
+                }
+            }
+            Token::Field(Field { letters, .. }) if *letters == "Y" => {
+                if let Some(command) = &mut command {
+                    command.y = Some(transformed.len());
+                }
+            }
+            Token::Comment { .. } => {
+                if let Some(command) = command.take() {
+                    command.transform(
+                        &mut transformed,
+                        is_relative,
+                        &mut current_position,
+                        transform,
+                    );
+                }
+            }
+            _ => {}
+        }
+        transformed.push(token);
+    }
+    if let Some(command) = command.take() {
+        command.transform(
+            &mut transformed,
+            is_relative,
+            &mut current_position,
+            transform,
+        );
+    }
+
+    transformed
+}
+
+/// Indices of the X and Y fields of a G command
+#[derive(Default)]
+struct AxisFields {
+    x: Option<usize>,
+    y: Option<usize>,
+}
+
+impl AxisFields {
+    fn transform(
+        self,
+        tokens: &mut Vec<Token<'_>>,
+        is_relative: bool,
+        current_position: &mut F64Point,
+        transform: &Transform2D<f64>,
+    ) {
+        let value = |i: Option<usize>| match i.map(|i| &tokens[i]) {
+            Some(Token::Field(Field { value, .. })) => value.as_f64(),
+            _ => None,
+        };
+        let (x, y) = (value(self.x), value(self.y));
+        if x.is_none() && y.is_none() {
+            return;
+        }
+        let new_position = if is_relative {
+            let delta = vector(x.unwrap_or(0.), y.unwrap_or(0.));
+            *current_position += delta;
+            transform.transform_vector(delta).to_point()
+        } else {
+            *current_position = point(
+                x.unwrap_or(current_position.x),
+                y.unwrap_or(current_position.y),
+            );
+            transform.transform_point(*current_position)
+        };
+
+        // Both axes are needed once they are mixed together
+        let field = |letters: &'static str, value: f64| {
+            Token::Field(Field {
+                letters: Cow::Borrowed(letters),
+                value: Value::Float(value),
+            })
+        };
+        match (self.x, self.y) {
+            (Some(x), Some(y)) => {
+                tokens[x] = field("X", new_position.x);
+                tokens[y] = field("Y", new_position.y);
+            }
+            (Some(x), None) => {
+                tokens[x] = field("X", new_position.x);
+                tokens.insert(x + 1, field("Y", new_position.y));
+            }
+            (None, Some(y)) => {
+                tokens[y] = field("Y", new_position.y);
+                tokens.insert(y, field("X", new_position.x));
+            }
+            (None, None) => unreachable!(),
+        }
+    }
+}
+
+/// Scale the toolpath uniformly so that its cuts fit in a width and height in millimeters,
+/// keeping the bottom left corner of the cuts in place
+pub fn scale_to_fit(tokens: Vec<Token<'_>>, width: f64, height: f64) -> Vec<Token<'_>> {
+    let bounds = match cut_bounding_box(&motions(&tokens), |_| true) {
+        Some(bounds) => bounds,
+        None => return tokens,
+    };
+    let size = bounds.size();
+    let factor = (width / size.width).min(height / size.height);
+    if !factor.is_finite() {
+        return tokens;
+    }
+    let corner = bounds.min.to_vector();
+    transform_program(
+        tokens,
+        &Transform2D::translation(-corner.x, -corner.y)
+            .then_scale(factor, factor)
+            .then_translate(corner),
+    )
+}
+
+/// Rotate the toolpath counterclockwise in degrees, about a point or else the center of the
+/// bounding box of its cuts
+pub fn rotate(tokens: Vec<Token<'_>>, degrees: f64, about: Option<F64Point>) -> Vec<Token<'_>> {
+    let about = match about
+        .or_else(|| cut_bounding_box(&motions(&tokens), |_| true).map(|bounds| bounds.center()))
+    {
+        Some(about) => about.to_vector(),
+        None => return tokens,
+    };
+    transform_program(
+        tokens,
+        &Transform2D::translation(-about.x, -about.y)
+            .then_rotate(euclid::Angle::degrees(degrees))
+            .then_translate(about),
+    )
+}
+
+/// Mirror the toolpath in place, about the center of the bounding box of its cuts, negating X
+/// and/or Y. This is for machines whose axes point the opposite way of the usual ones.
+pub fn mirror(tokens: Vec<Token<'_>>, mirror_x: bool, mirror_y: bool) -> Vec<Token<'_>> {
+    let center = match cut_bounding_box(&motions(&tokens), |_| true) {
+        Some(bounds) => bounds.center().to_vector(),
+        None => return tokens,
+    };
+    let sign = |mirror: bool| if mirror { -1. } else { 1. };
+    transform_program(
+        tokens,
+        &Transform2D::translation(-center.x, -center.y)
+            .then_scale(sign(mirror_x), sign(mirror_y))
+            .then_translate(center),
+    )
+}
+
+/// Axis letters that the X and Y of a drawing can be mapped to
+pub const AXES: [&str; 9] = ["X", "Y", "Z", "A", "B", "C", "U", "V", "W"];
+
+/// Rename the X and Y fields of moves to other axes, i.e. to swap them or to drive Z with the Y
+/// of a drawing. This should be done last, since other passes expect X and Y.
+pub fn remap_axes(tokens: &mut [Token<'_>], axes: [&'static str; 2]) {
+    let mut should_skip = false;
+    for token in tokens {
+        if let Token::Field(Field { letters, .. }) = token {
+            match letters.as_ref() {
+                // M codes may use X and Y for other purposes
+                "M" => should_skip = true,
+                "G" => should_skip = false,
+                "X" if !should_skip => *letters = Cow::Borrowed(axes[0]),
+                "Y" if !should_skip => *letters = Cow::Borrowed(axes[1]),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Convert a program to inches, for machines that only work well with G20. Coordinates,
+/// feedrates and path blending tolerances are converted, while dwell times and tool power are
+/// left alone. This should be done after passes that expect millimeters.
+pub fn convert_to_inches(tokens: &mut [Token<'_>]) {
+    const MILLIMETERS_PER_INCH: f64 = 25.4;
+    let mut should_skip = false;
+    let mut is_path_blending = false;
+    for token in tokens {
+        if let Token::Field(Field { letters, value }) = token {
+            match letters.as_ref() {
+                "M" => should_skip = true,
+                "G" => {
+                    should_skip = false;
+                    is_path_blending = value.as_f64() == Some(64.);
+                    if value.as_f64() == Some(21.) {
+                        *value = Value::Integer(20);
+                    }
+                }
+                "X" | "Y" | "Z" | "I" | "J" | "K" | "R" | "F" if !should_skip => {
+                    if let Some(f) = value.as_f64() {
+                        *value = Value::Float(f / MILLIMETERS_PER_INCH);
+                    }
+                }
+                "P" if is_path_blending => {
+                    if let Some(f) = value.as_f64() {
+                        *value = Value::Float(f / MILLIMETERS_PER_INCH);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Round the coordinates of moves to a number of decimal places, i.e. 3 for millimeters, so that
+/// lines stay short for controllers with limited line lengths
+pub fn round_coordinates(tokens: &mut [Token<'_>], decimals: u32) {
+    let precision = 10f64.powi(decimals as i32);
+    let mut should_skip = false;
+    for token in tokens {
+        if let Token::Field(Field { letters, value }) = token {
+            match letters.as_ref() {
+                "M" => should_skip = true,
+                "G" => should_skip = false,
+                "X" | "Y" | "Z" | "I" | "J" | "K" | "R" if !should_skip => {
+                    if let Value::Float(f) = value {
+                        // Adding zero turns -0 into 0
+                        *f = (*f * precision).round() / precision + 0.;
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Insert `M73 P<percent> R<minutes remaining>` each time the estimated progress of a program
+/// advances by `step` percent, so that Marlin machines show it on their displays. Progress is by
+/// the time spent moving at the feedrates of the program, with rapids at `travel_feedrate`,
+/// ignoring acceleration and dwells.
+pub fn insert_progress(tokens: Vec<Token<'_>>, step: f64, travel_feedrate: f64) -> Vec<Token<'_>> {
+    let states = crate::simulator::simulate(&tokens, &[], &[]);
+    let mut previous = [None; 3];
+    let times = states
+        .iter()
+        .map(|(command, state)| {
+            let from = std::mem::replace(&mut previous, state.position);
+            let feedrate = match command.first() {
+                Some(rapid) if *rapid == Token::Field(RAPID_POSITIONING_FIELD) => travel_feedrate,
+                Some(linear) if *linear == Token::Field(LINEAR_INTERPOLATION_FIELD) => {
+                    state.feedrate.unwrap_or(travel_feedrate)
+                }
+                _ => return 0.,
+            };
+            let distance = from
+                .iter()
+                .zip(state.position)
+                .map(|(from, to)| match (from, to) {
+                    (Some(from), Some(to)) => (to - from).powi(2),
+                    _ => 0.,
+                })
+                .sum::<f64>()
+                .sqrt();
+            distance / feedrate * 60.
+        })
+        .collect::<Vec<_>>();
+    let total = times.iter().sum::<f64>();
+    if total <= 0. {
+        return tokens;
+    }
+
+    let m73 = |percent: usize, remaining: f64| {
+        vec![
+            ("M", Value::Integer(73)),
+            ("P", Value::Integer(percent)),
+            ("R", Value::Integer((remaining / 60.).ceil() as usize)),
+        ]
+        .into_iter()
+        .map(|(letters, value)| {
+            Token::Field(Field {
+                letters: Cow::Borrowed(letters),
+                value,
+            })
+        })
+    };
+    let mut updated = m73(0, total).collect::<Vec<_>>();
+    let mut elapsed = 0.;
+    let mut reported = 0.;
+    for ((command, _), time) in states.into_iter().zip(times) {
+        updated.extend(command);
+        if time <= 0. {
+            continue;
+        }
+        elapsed += time;
+        let percent = (elapsed / total * 100.).floor();
+        if percent >= reported + step || (percent >= 100. && reported < 100.) {
+            reported = percent.min(100.);
+            updated.extend(m73(reported as usize, total - elapsed));
+        }
+    }
+    updated
+}
+
+/// A machine-readable comment with the bounding box of the cuts, like `;BOUNDS: 0 0 100 50`, for
+/// senders that show the extent of a job before running it
+pub fn bounds_comment(tokens: &[Token<'_>]) -> Option<Token<'static>> {
+    statistics(&motions(tokens))
+        .bounds
+        .map(|bounds| Token::Comment {
+            is_inline: false,
+            inner: Cow::Owned(format!(
+                "BOUNDS: {} {} {} {}",
+                bounds.min.x, bounds.min.y, bounds.max.x, bounds.max.y
+            )),
+        })
+}
+
+/// Remove every comment, for controllers that choke on them. This is done last, since other
+/// operations find the elements of cuts from their comments.
+pub fn remove_comments(tokens: &mut Vec<Token<'_>>) {
+    tokens.retain(|token| !matches!(token, Token::Comment { .. }));
+}
+
+/// Bounding box of the cuts made while drawing the elements accepted by the filter
+fn cut_bounding_box(motions: &[Motion], filter: impl Fn(&str) -> bool) -> Option<Box2D<f64>> {
+    let mut points = motions
+        .iter()
+        .filter(|motion| motion.is_cutting && motion.source.as_deref().is_some_and(&filter))
+        .flat_map(|motion| [motion.segment.from, motion.segment.to])
+        .peekable();
+    points.peek()?;
+    Some(Box2D::from_points(points))
+}
+
 fn get_bounding_box<'a, I: Iterator<Item = &'a Token<'a>>>(tokens: I) -> Box2D<f64> {
     let (mut minimum, mut maximum) = (point(0f64, 0f64), point(0f64, 0f64));
     let mut is_relative = false;
@@ -84,3 +581,2200 @@ fn get_bounding_box<'a, I: Iterator<Item = &'a Token<'a>>>(tokens: I) -> Box2D<f
     }
     Box2D::new(minimum, maximum)
 }
+
+/// Ramp tool power (S) up over a distance at the start of each cut and back down at its end, to
+/// avoid burn-in dots where a laser dwells while the machine accelerates.
+///
+/// Moves in the ramp regions are split into [RAMP_STEPS] pieces of increasing or decreasing
+/// power, the rest are cut at full power.
+pub fn ramp_power(tokens: Vec<Token<'_>>, distance: f64, power: f64) -> Vec<Token<'_>> {
+    map_cuts(tokens, |_, from, mut moves| {
+        // Replaced by the ramp
+        moves
+            .iter_mut()
+            .for_each(|m| m.args.retain(|arg| arg.letters != "S"));
+        let total_length = cut_length(from, &moves);
+
+        let breakpoints = (1..=RAMP_STEPS)
+            .map(|i| distance * i as f64 / RAMP_STEPS as f64)
+            .flat_map(|step| vec![step, total_length - step])
+            .collect::<Vec<_>>();
+
+        split_cut(from, &moves, breakpoints)
+            .iter()
+            .flat_map(|piece| {
+                let midpoint = (piece.start + piece.end) / 2.;
+                let factor = (midpoint / distance)
+                    .min((total_length - midpoint) / distance)
+                    .min(1.);
+                let mut tokens = linear_interpolation_tokens(piece.to, piece.args);
+                tokens.push(Token::Field(Field {
+                    letters: Cow::Borrowed("S"),
+                    value: Value::Float(power * factor),
+                }));
+                tokens
+            })
+            .collect()
+    })
+}
+
+/// Options for [inline_power]
+#[derive(Debug, Clone)]
+pub struct PowerOptions {
+    /// Tool power (S value) of cuts
+    pub max_power: f64,
+    /// Fraction of the power used for the cuts in a top-level element, by its id
+    pub layer_power: Vec<(String, f64)>,
+}
+
+/// Set the tool power (S) on every cut move, for machines where the tool stays on for the whole
+/// program and the power follows the moves, like a laser in Grbl's dynamic power mode (`M4`).
+///
+/// Moves that already have a power, i.e. from [ramp_power], keep it scaled by their layer's power.
+pub fn inline_power<'input>(
+    tokens: Vec<Token<'input>>,
+    options: &PowerOptions,
+) -> Vec<Token<'input>> {
+    map_cuts(tokens, |preceding, _, moves| {
+        let layer = preceding.iter().rev().find_map(top_level_name);
+        let factor = options
+            .layer_power
+            .iter()
+            .find(|(id, _)| {
+                layer
+                    .as_ref()
+                    .and_then(|layer| layer.split_once('#'))
+                    .is_some_and(|(_, layer_id)| layer_id == id)
+            })
+            .map(|(_, factor)| *factor)
+            .unwrap_or(1.);
+
+        moves
+            .into_iter()
+            .flat_map(|mut m| {
+                let mut has_power = false;
+                for arg in m.args.iter_mut().filter(|arg| arg.letters == "S") {
+                    has_power = true;
+                    if let Some(power) = arg.value.as_f64() {
+                        arg.value = Value::Float(power * factor);
+                    }
+                }
+                if !has_power {
+                    m.args.push(Field {
+                        letters: Cow::Borrowed("S"),
+                        value: Value::Float(options.max_power * factor),
+                    });
+                }
+                linear_interpolation_tokens(m.to, &m.args)
+            })
+            .collect()
+    })
+}
+
+/// Map every S value of a program through a power curve, i.e. to compensate for the nonlinear
+/// response of a laser
+pub fn map_power(tokens: &mut [Token<'_>], curve: &PowerCurve) {
+    for token in tokens.iter_mut() {
+        if let Token::Field(Field { letters, value }) = token {
+            if *letters == "S" {
+                if let Some(power) = value.as_f64() {
+                    *value = Value::Float(curve.map(power));
+                }
+            }
+        }
+    }
+}
+
+/// Options for [insert_tabs]
+#[derive(Debug, Clone, Copy)]
+pub struct TabOptions {
+    /// Length of each tab along the cut, in millimeters
+    pub width: f64,
+    /// Number of tabs, evenly spaced along the cut
+    pub count: usize,
+    /// Closed cuts shorter than this are left alone, in millimeters
+    pub min_length: f64,
+}
+
+/// Leave small uncut gaps (tabs) in closed cuts, so that cut out parts stay attached to the
+/// stock. The tool is turned off and travels over each tab. Tabs are spaced evenly, except that
+/// they are moved off of corners.
+pub fn insert_tabs<'input>(
+    tokens: Vec<Token<'input>>,
+    options: TabOptions,
+    tool_on: &[Token<'input>],
+    tool_off: &[Token<'input>],
+) -> Vec<Token<'input>> {
+    map_cuts(tokens, |_, from, moves| {
+        let total_length = cut_length(from, &moves);
+        let is_closed = moves
+            .last()
+            .map(|last| (last.to - from).length() < f64::EPSILON.sqrt())
+            .unwrap_or(false);
+        if !is_closed || options.count == 0 || total_length < options.min_length {
+            return moves
+                .iter()
+                .flat_map(|m| linear_interpolation_tokens(m.to, &m.args))
+                .collect();
+        }
+
+        // Tabs on corners are weak and leave nubs that are hard to clean up, so they are moved
+        // along the cut onto the nearest straight stretch
+        let corners = corner_distances(from, &moves, total_length);
+        let spacing = total_length / options.count as f64;
+        let tabs = (0..options.count)
+            .map(|i| {
+                let center = avoid_corners(
+                    spacing * (i as f64 + 0.5),
+                    options.width,
+                    &corners,
+                    total_length,
+                );
+                (center - options.width / 2.)..(center + options.width / 2.)
+            })
+            .collect::<Vec<_>>();
+
+        let pieces = split_cut(
+            from,
+            &moves,
+            tabs.iter()
+                .flat_map(|tab| vec![tab.start, tab.end])
+                .collect(),
+        );
+        let mut tokens = vec![];
+        let mut pieces = pieces.iter().peekable();
+        while let Some(piece) = pieces.next() {
+            let midpoint = (piece.start + piece.end) / 2.;
+            if !tabs.iter().any(|tab| tab.contains(&midpoint)) {
+                tokens.append(&mut linear_interpolation_tokens(piece.to, piece.args));
+                continue;
+            }
+
+            // Travel to the end of the tab
+            let mut tab_end = piece.to;
+            while let Some(next) = pieces.peek() {
+                if !tabs
+                    .iter()
+                    .any(|tab| tab.contains(&((next.start + next.end) / 2.)))
+                {
+                    break;
+                }
+                tab_end = next.to;
+                pieces.next();
+            }
+            tokens.extend(tool_off.iter().cloned());
+            tokens.append(
+                &mut command!(RapidPositioning {
+                    X: tab_end.x,
+                    Y: tab_end.y,
+                })
+                .into_token_vec(),
+            );
+            tokens.extend(tool_on.iter().cloned());
+        }
+        tokens
+    })
+}
+
+/// Distances along a closed cut where it turns by more than [TAB_CORNER_ANGLE], including its
+/// start and end if it turns there
+fn corner_distances(from: F64Point, moves: &[CutMove<'_>], total_length: f64) -> Vec<f64> {
+    let mut directions = vec![];
+    let mut position = from;
+    let mut traveled = 0.;
+    for m in moves {
+        let step = m.to - position;
+        if step.length() > f64::EPSILON {
+            directions.push((traveled, step));
+        }
+        traveled += step.length();
+        position = m.to;
+    }
+
+    let is_corner = |before: Vector<f64>, after: Vector<f64>| {
+        before.angle_to(after).radians.abs() > TAB_CORNER_ANGLE.to_radians()
+    };
+    let mut corners = directions
+        .windows(2)
+        .filter(|pair| is_corner(pair[0].1, pair[1].1))
+        .map(|pair| pair[1].0)
+        .collect::<Vec<_>>();
+    if let (Some(first), Some(last)) = (directions.first(), directions.last()) {
+        if is_corner(last.1, first.1) {
+            corners.extend_from_slice(&[0., total_length]);
+        }
+    }
+    corners
+}
+
+/// Move the center of a tab to the nearest distance along a cut where the tab doesn't cover a
+/// corner, or leave it if there is nowhere
+fn avoid_corners(center: f64, width: f64, corners: &[f64], total_length: f64) -> f64 {
+    let fits = |center: f64| {
+        center - width / 2. >= 0.
+            && center + width / 2. <= total_length
+            && corners
+                .iter()
+                .all(|corner| (corner - center).abs() > width / 2.)
+    };
+    if fits(center) {
+        return center;
+    }
+    corners
+        .iter()
+        .flat_map(|corner| vec![corner - width, corner + width])
+        .filter(|candidate| fits(*candidate))
+        .min_by(|a, b| {
+            (a - center)
+                .abs()
+                .partial_cmp(&(b - center).abs())
+                .unwrap_or(std::cmp::Ordering::Equal)
+        })
+        .unwrap_or(center)
+}
+
+/// Merge runs of moves shorter than a minimum length into single moves.
+///
+/// Autotracing tools can produce thousands of tiny segments which flood a controller's planner.
+/// Points are dropped until the distance from the last kept point reaches the minimum length,
+/// so the deviation from the original cut stays below it. The end of each cut is always kept.
+pub fn merge_short_segments(tokens: Vec<Token<'_>>, min_length: f64) -> Vec<Token<'_>> {
+    map_cuts(tokens, |_, from, moves| {
+        let mut tokens = vec![];
+        let mut last_kept = from;
+        let count = moves.len();
+        for (i, m) in moves.iter().enumerate() {
+            if (m.to - last_kept).length() >= min_length || i + 1 == count {
+                tokens.append(&mut linear_interpolation_tokens(m.to, &m.args));
+                last_kept = m.to;
+            }
+        }
+        tokens
+    })
+}
+
+/// Travel over cuts that retrace cuts made earlier in the program within a tolerance in
+/// millimeters, instead of cutting them twice. Vectorized SVGs often have the edges shared by
+/// adjacent shapes in both shapes, which would be burnt or cut twice.
+///
+/// Moves that partly overlap an earlier cut are split where it starts and ends, so that only the
+/// overlapping part is skipped. Skipped parts are traveled over with the tool off, see
+/// [filter_cut_moves].
+pub fn remove_overlaps<'input>(
+    tokens: Vec<Token<'input>>,
+    tool_on: &[Token<'input>],
+    tool_off: &[Token<'input>],
+    tolerance: f64,
+) -> Vec<Token<'input>> {
+    let mut cut = SegmentGrid::default();
+    let tokens = map_cuts(tokens, |_, from, moves| {
+        let mut tokens = vec![];
+        let mut position = from;
+        for m in &moves {
+            let segment = LineSegment {
+                from: position,
+                to: m.to,
+            };
+            let length = segment.length();
+            let mut splits = vec![];
+            for other in cut.near(&segment, tolerance) {
+                if is_collinear(&segment, other, tolerance) {
+                    for end in [other.from, other.to].iter() {
+                        let t = (*end - segment.from).dot(segment.to_vector()) / (length * length);
+                        if t * length > tolerance && (1. - t) * length > tolerance {
+                            splits.push(t);
+                        }
+                    }
+                }
+            }
+            splits.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+            for t in splits {
+                tokens.append(&mut linear_interpolation_tokens(segment.sample(t), &m.args));
+            }
+            tokens.append(&mut linear_interpolation_tokens(m.to, &m.args));
+            cut.insert(segment);
+            position = m.to;
+        }
+        tokens
+    });
+
+    // Every piece is now either entirely over earlier cuts or entirely not
+    let mut cut = SegmentGrid::default();
+    filter_cut_moves(tokens, tool_on, tool_off, |m| {
+        let piece = LineSegment {
+            from: m.from,
+            to: m.to,
+        };
+        let middle = piece.sample(0.5);
+        let is_overlap = cut.near(&piece, tolerance).any(|other| {
+            is_collinear(other, &piece, tolerance)
+                && distance_to_segment(other, middle) <= tolerance
+        });
+        cut.insert(piece);
+        if is_overlap {
+            CutMoveAction::Skip
+        } else {
+            CutMoveAction::Keep
+        }
+    })
+}
+
+/// Whether both ends of `other` are within a tolerance of the line through `segment`
+fn is_collinear(segment: &LineSegment<f64>, other: &LineSegment<f64>, tolerance: f64) -> bool {
+    let line = segment.to_line();
+    segment.length() > tolerance
+        && line.distance_to_point(&other.from) <= tolerance
+        && line.distance_to_point(&other.to) <= tolerance
+}
+
+fn distance_to_segment(segment: &LineSegment<f64>, p: F64Point) -> f64 {
+    let vector = segment.to_vector();
+    let t = ((p - segment.from).dot(vector) / vector.square_length()).clamp(0., 1.);
+    (segment.sample(t) - p).length()
+}
+
+/// Segments grouped by the cells of a grid that their bounding boxes overlap, for finding the
+/// segments near another without comparing it against all of them
+#[derive(Default)]
+struct SegmentGrid {
+    segments: Vec<LineSegment<f64>>,
+    cells: HashMap<(i64, i64), Vec<usize>>,
+}
+
+impl SegmentGrid {
+    fn cells(segment: &LineSegment<f64>, margin: f64) -> impl Iterator<Item = (i64, i64)> {
+        let bounds = Box2D::new(segment.from.min(segment.to), segment.from.max(segment.to))
+            .inflate(margin, margin);
+        let cell = |value: f64| (value / OVERLAP_CELL_SIZE).floor() as i64;
+        let (x, y) = (
+            cell(bounds.min.x)..=cell(bounds.max.x),
+            cell(bounds.min.y)..=cell(bounds.max.y),
+        );
+        x.flat_map(move |x| y.clone().map(move |y| (x, y)))
+    }
+
+    fn insert(&mut self, segment: LineSegment<f64>) {
+        if segment.from == segment.to {
+            return;
+        }
+        for cell in Self::cells(&segment, 0.) {
+            self.cells
+                .entry(cell)
+                .or_default()
+                .push(self.segments.len());
+        }
+        self.segments.push(segment);
+    }
+
+    /// Segments whose cells are within a margin of a segment, each once
+    fn near<'a>(
+        &'a self,
+        segment: &LineSegment<f64>,
+        margin: f64,
+    ) -> impl Iterator<Item = &'a LineSegment<f64>> + 'a {
+        let mut indices = Self::cells(segment, margin)
+            .filter_map(|cell| self.cells.get(&cell))
+            .flatten()
+            .copied()
+            .collect::<Vec<_>>();
+        indices.sort_unstable();
+        indices.dedup();
+        indices.into_iter().map(move |i| &self.segments[i])
+    }
+}
+
+/// Slow down cuts around small features, where lasers overburn and plotters distort at full
+/// speed. The feedrate of each move is scaled by how tightly the cut curves there, from `factor`
+/// for a point down to not at all for curves as wide as `size` millimeters, like a circle of that
+/// diameter. Small shapes are tight curves all the way around.
+pub fn slow_small_features(tokens: Vec<Token<'_>>, size: f64, factor: f64) -> Vec<Token<'_>> {
+    map_cuts(tokens, |_, from, moves| {
+        let mut points = std::iter::once(from)
+            .chain(moves.iter().map(|m| m.to))
+            .collect::<Vec<_>>();
+        points.dedup();
+        let is_closed = closed_polygon(from, &moves).is_some();
+        // Radius of the circle through a vertex and its neighbours
+        let radius = |i: usize| {
+            let (previous, next) = if is_closed && points.len() > 3 {
+                let n = points.len() - 1;
+                (points[(i + n - 1) % n], points[(i + 1) % n])
+            } else if i > 0 && i + 1 < points.len() {
+                (points[i - 1], points[i + 1])
+            } else {
+                return f64::INFINITY;
+            };
+            let (a, b, c) = (points[i] - previous, next - points[i], next - previous);
+            let twice_area = a.cross(b).abs();
+            if twice_area <= f64::EPSILON {
+                f64::INFINITY
+            } else {
+                a.length() * b.length() * c.length() / (2. * twice_area)
+            }
+        };
+
+        let mut vertex = 0;
+        let mut position = from;
+        moves
+            .into_iter()
+            .flat_map(|mut m| {
+                if m.to != position {
+                    let tightest = radius(vertex).min(radius(vertex + 1));
+                    let scale = factor + (1. - factor) * (2. * tightest / size).min(1.);
+                    for arg in &mut m.args {
+                        if arg.letters == "F" {
+                            if let Some(feedrate) = arg.value.as_f64() {
+                                arg.value = Value::Float(feedrate * scale);
+                            }
+                        }
+                    }
+                    vertex += 1;
+                    position = m.to;
+                }
+                linear_interpolation_tokens(m.to, &m.args)
+            })
+            .collect()
+    })
+}
+
+/// Merge runs of moves that stay within a tolerance in millimeters of a straight line into single
+/// moves. Flattened curves and traced SVGs can have thousands of tiny, nearly collinear segments
+/// that starve a controller's planner, which can only look ahead a fixed number of moves.
+///
+/// Every dropped point is within the tolerance of the move that replaces it, and moves with
+/// different fields, like feedrates, are never merged.
+pub fn merge_collinear_segments(tokens: Vec<Token<'_>>, tolerance: f64) -> Vec<Token<'_>> {
+    map_cuts(tokens, |_, from, moves| {
+        let mut tokens = vec![];
+        let mut start = from;
+        let mut dropped: Vec<F64Point> = vec![];
+        let mut previous: Option<&CutMove> = None;
+        for m in &moves {
+            if let Some(previous) = previous {
+                let merged = LineSegment {
+                    from: start,
+                    to: m.to,
+                };
+                let can_merge = previous.args == m.args
+                    && merged.length() > 0.
+                    && dropped
+                        .iter()
+                        .chain(std::iter::once(&previous.to))
+                        .all(|p| distance_to_segment(&merged, *p) <= tolerance);
+                if can_merge {
+                    dropped.push(previous.to);
+                } else {
+                    tokens.append(&mut linear_interpolation_tokens(
+                        previous.to,
+                        &previous.args,
+                    ));
+                    start = previous.to;
+                    dropped.clear();
+                }
+            }
+            previous = Some(m);
+        }
+        if let Some(last) = previous {
+            tokens.append(&mut linear_interpolation_tokens(last.to, &last.args));
+        }
+        tokens
+    })
+}
+
+/// Round the positions of cuts to multiples of a machine's step resolution in millimeters, i.e.
+/// 0.0125, dropping moves that become empty.
+///
+/// Controllers can round sub-step moves inconsistently, so that their errors add up over long
+/// cuts made of tiny segments.
+pub fn snap_to_resolution(tokens: Vec<Token<'_>>, resolution: f64) -> Vec<Token<'_>> {
+    // Enough decimal places to represent multiples of the resolution, without float noise
+    let decimals = (0..10)
+        .find(|decimals| {
+            let scaled = resolution * 10f64.powi(*decimals);
+            (scaled - scaled.round()).abs() < 1e-9
+        })
+        .unwrap_or(10);
+    let precision = 10f64.powi(decimals);
+    let snap = |p: F64Point| {
+        let snap_axis =
+            |v: f64| ((v / resolution).round() * resolution * precision).round() / precision;
+        point(snap_axis(p.x), snap_axis(p.y))
+    };
+
+    map_cuts(tokens, |preceding, from, moves| {
+        let from = snap(from);
+        redirect_last_rapid(preceding, from);
+        let mut tokens = vec![];
+        let mut last_kept = from;
+        for m in &moves {
+            let to = snap(m.to);
+            if to != last_kept {
+                tokens.append(&mut linear_interpolation_tokens(to, &m.args));
+                last_kept = to;
+            }
+        }
+        tokens
+    })
+}
+
+/// Options for [add_leads]
+#[derive(Debug, Clone, Copy)]
+pub struct LeadOptions {
+    /// Length of the lead-in in millimeters, or its radius for arcs
+    pub lead_in: f64,
+    /// Length of the lead-out in millimeters, or its radius for arcs
+    pub lead_out: f64,
+    /// Use quarter circle arcs tangent to the cut instead of lines, where the cut doesn't start
+    /// at a corner
+    pub arc: bool,
+}
+
+/// Enter and exit closed cuts through extra lead moves on the waste side of the cut, so the marks
+/// left by piercing and stopping don't land on the finished edge.
+///
+/// The waste side is outside of outer contours and inside of holes, which are told apart by how
+/// many other closed cuts they are nested in. The rapid move to each closed cut is redirected to
+/// the start of its lead-in.
+pub fn add_leads(tokens: Vec<Token<'_>>, options: LeadOptions) -> Vec<Token<'_>> {
+    let (tokens, is_hole) = find_holes(tokens);
+
+    let mut cut_index = 0;
+    map_cuts(tokens, |preceding, from, moves| {
+        let is_hole = is_hole[cut_index];
+        cut_index += 1;
+        let mut tokens = vec![];
+        let (is_hole, polygon) = match (is_hole, closed_polygon(from, &moves)) {
+            (Some(is_hole), Some(polygon)) => (is_hole, polygon),
+            _ => {
+                return moves
+                    .iter()
+                    .flat_map(|m| linear_interpolation_tokens(m.to, &m.args))
+                    .collect()
+            }
+        };
+
+        let waste_is_right = waste_is_right(&polygon, is_hole);
+        let waste_normal = |direction| waste_normal(direction, waste_is_right);
+
+        let first_direction = moves
+            .iter()
+            .scan(from, |previous, m| {
+                let direction = m.to - *previous;
+                *previous = m.to;
+                Some(direction)
+            })
+            .find(|direction| direction.length() > 0.)
+            .map(|direction| direction.normalize());
+        let last_direction = moves
+            .iter()
+            .rev()
+            .zip(moves.iter().rev().skip(1).map(|m| m.to).chain(Some(from)))
+            .map(|(m, previous)| m.to - previous)
+            .find(|direction| direction.length() > 0.)
+            .map(|direction| direction.normalize());
+        let (first_direction, last_direction) = match (first_direction, last_direction) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return vec![],
+        };
+        let args = &moves[0].args;
+
+        // Cuts that start at a corner have an edge on either side of the start, and the leads
+        // must stay on the waste side of both. Tangent arcs reach behind the start, which only
+        // works where the cut is smooth, so elsewhere leads follow the bisector of the corner.
+        let first_normal = waste_normal(first_direction);
+        let last_normal = waste_normal(last_direction);
+        let bisector = (first_normal + last_normal)
+            .try_normalize()
+            .unwrap_or(first_normal);
+        let is_on_waste_side = |points: &[F64Point]| {
+            points.iter().all(|p| {
+                let offset = *p - from;
+                offset.dot(first_normal) > -f64::EPSILON.sqrt()
+                    && offset.dot(last_normal) > -f64::EPSILON.sqrt()
+            })
+        };
+
+        if options.lead_in > 0. {
+            let radius = options.lead_in;
+            let center = from + first_normal * radius;
+            let arc = (0..=LEAD_ARC_SEGMENTS)
+                .map(|i| {
+                    let angle = std::f64::consts::FRAC_PI_2 * i as f64 / LEAD_ARC_SEGMENTS as f64;
+                    center
+                        - first_direction * radius * angle.cos()
+                        - first_normal * radius * angle.sin()
+                })
+                .collect::<Vec<_>>();
+            let lead_in = if options.arc && is_on_waste_side(&arc) {
+                arc
+            } else {
+                vec![from + bisector * radius, from]
+            };
+            redirect_last_rapid(preceding, lead_in[0]);
+            for point in lead_in.iter().skip(1) {
+                tokens.append(&mut linear_interpolation_tokens(*point, args));
+            }
+        }
+
+        tokens.extend(
+            moves
+                .iter()
+                .flat_map(|m| linear_interpolation_tokens(m.to, &m.args)),
+        );
+
+        if options.lead_out > 0. {
+            let radius = options.lead_out;
+            let center = from + last_normal * radius;
+            let arc = (1..=LEAD_ARC_SEGMENTS)
+                .map(|i| {
+                    let angle = std::f64::consts::FRAC_PI_2 * i as f64 / LEAD_ARC_SEGMENTS as f64;
+                    center - last_normal * radius * angle.cos()
+                        + last_direction * radius * angle.sin()
+                })
+                .collect::<Vec<_>>();
+            let lead_out = if options.arc && is_on_waste_side(&arc) {
+                arc
+            } else {
+                vec![from + bisector * radius]
+            };
+            for point in lead_out {
+                tokens.append(&mut linear_interpolation_tokens(point, args));
+            }
+        }
+
+        tokens
+    })
+}
+
+/// Direction that closed cuts go around in, seen from above the machine
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Clockwise,
+    Counterclockwise,
+    /// Climb milling with a clockwise spindle (`M3`): outer contours clockwise and holes
+    /// counterclockwise
+    Climb,
+    /// Conventional milling with a clockwise spindle: outer contours counterclockwise and holes
+    /// clockwise
+    Conventional,
+}
+
+impl Direction {
+    /// The direction that becomes this one once the program is mirrored along one axis
+    pub fn mirrored(self) -> Self {
+        match self {
+            Self::Clockwise => Self::Counterclockwise,
+            Self::Counterclockwise => Self::Clockwise,
+            Self::Climb => Self::Conventional,
+            Self::Conventional => Self::Climb,
+        }
+    }
+}
+
+impl FromStr for Direction {
+    type Err = String;
+
+    /// Parses `cw`, `ccw`, `climb` or `conventional`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cw" => Ok(Self::Clockwise),
+            "ccw" => Ok(Self::Counterclockwise),
+            "climb" => Ok(Self::Climb),
+            "conventional" => Ok(Self::Conventional),
+            other => Err(format!(
+                "unknown direction {}, expected one of cw, ccw, climb or conventional",
+                other
+            )),
+        }
+    }
+}
+
+/// Make closed cuts go around in a direction, since the direction of SVG paths is arbitrary but
+/// matters to drag knives and milling cutters. Then reverse every cut, open or closed, of the
+/// top-level elements of the SVG named in `reversed` by their id.
+///
+/// Moves that only change the height of the tool at the start and end of a cut, like plunges,
+/// stay where they are.
+pub fn set_direction<'input>(
+    tokens: Vec<Token<'input>>,
+    direction: Option<Direction>,
+    reversed: &[String],
+) -> Vec<Token<'input>> {
+    let (tokens, is_hole) = find_holes(tokens);
+
+    let mut cut_index = 0;
+    map_cuts(tokens, |preceding, from, moves| {
+        let is_hole = is_hole[cut_index];
+        cut_index += 1;
+        let is_counterclockwise = closed_polygon(from, &moves).map(|polygon| {
+            polygon
+                .iter()
+                .zip(polygon.iter().cycle().skip(1))
+                .map(|(a, b)| a.x * b.y - b.x * a.y)
+                .sum::<f64>()
+                > 0.
+        });
+        let mut reverse = match (direction, is_hole, is_counterclockwise) {
+            (Some(direction), Some(is_hole), Some(is_counterclockwise)) => {
+                let should_be_counterclockwise = match direction {
+                    Direction::Clockwise => false,
+                    Direction::Counterclockwise => true,
+                    Direction::Climb => is_hole,
+                    Direction::Conventional => !is_hole,
+                };
+                is_counterclockwise != should_be_counterclockwise
+            }
+            _ => false,
+        };
+        if !reversed.is_empty() {
+            let layer = preceding.iter().rev().find_map(top_level_name);
+            let id = layer
+                .as_deref()
+                .map(|layer| layer.split_once('#').map_or(layer, |(_, id)| id));
+            if id.is_some_and(|id| reversed.iter().any(|reversed| reversed == id)) {
+                reverse = !reverse;
+            }
+        }
+        if reverse {
+            reverse_cut(preceding, from, moves)
+        } else {
+            moves
+                .iter()
+                .flat_map(|m| linear_interpolation_tokens(m.to, &m.args))
+                .collect()
+        }
+    })
+}
+
+/// Cut the moves of a cut in reverse, starting from where it ended
+fn reverse_cut<'input>(
+    preceding: &mut [Token<'input>],
+    from: F64Point,
+    moves: Vec<CutMove<'input>>,
+) -> Vec<Token<'input>> {
+    let points = std::iter::once(from)
+        .chain(moves.iter().map(|m| m.to))
+        .collect::<Vec<_>>();
+    // Moves that stay in place, at the start and end of the cut
+    let start = moves
+        .iter()
+        .zip(&points)
+        .take_while(|(m, from)| m.to == **from)
+        .count();
+    let end = moves.len()
+        - moves
+            .iter()
+            .zip(&points)
+            .skip(start)
+            .rev()
+            .take_while(|(m, from)| m.to == **from)
+            .count();
+    if start == end {
+        return moves
+            .iter()
+            .flat_map(|m| linear_interpolation_tokens(m.to, &m.args))
+            .collect();
+    }
+    redirect_last_rapid(preceding, points[end]);
+
+    // Each move goes back to where it started, so it keeps its other fields
+    let mut tokens = vec![];
+    for m in &moves[..start] {
+        tokens.extend(linear_interpolation_tokens(points[end], &m.args));
+    }
+    for i in (start..end).rev() {
+        tokens.extend(linear_interpolation_tokens(points[i], &moves[i].args));
+    }
+    for m in &moves[end..] {
+        tokens.extend(linear_interpolation_tokens(from, &m.args));
+    }
+    tokens
+}
+
+/// Offset closed cuts by a distance towards their waste side, to compensate for the width of
+/// material removed by the tool (kerf). Without this, cut out parts are undersized by the width
+/// of the tool.
+///
+/// Outer contours are grown and holes are shrunk, see [add_leads]. Vertices are joined with
+/// miters, limited to [MITER_LIMIT] times the distance at sharp corners. Offsets larger than the
+/// features of a shape will produce self-intersecting cuts.
+pub fn offset_closed_cuts(tokens: Vec<Token<'_>>, distance: f64) -> Vec<Token<'_>> {
+    let (tokens, is_hole) = find_holes(tokens);
+
+    let mut cut_index = 0;
+    map_cuts(tokens, |preceding, from, moves| {
+        let is_hole = is_hole[cut_index];
+        cut_index += 1;
+        let (is_hole, mut polygon) = match (is_hole, closed_polygon(from, &moves)) {
+            (Some(is_hole), Some(polygon)) => (is_hole, polygon),
+            _ => {
+                return moves
+                    .iter()
+                    .flat_map(|m| linear_interpolation_tokens(m.to, &m.args))
+                    .collect()
+            }
+        };
+        polygon.dedup();
+        if polygon.len() < 3 {
+            return moves
+                .iter()
+                .flat_map(|m| linear_interpolation_tokens(m.to, &m.args))
+                .collect();
+        }
+
+        let waste_is_right = waste_is_right(&polygon, is_hole);
+        let offset = |i: usize| {
+            let previous = polygon[(i + polygon.len() - 1) % polygon.len()];
+            let next = polygon[(i + 1) % polygon.len()];
+            let incoming = waste_normal((polygon[i] - previous).normalize(), waste_is_right);
+            let outgoing = waste_normal((next - polygon[i]).normalize(), waste_is_right);
+            let miter = (incoming + outgoing).normalize();
+            let cos_half_angle = miter.dot(outgoing).max(1. / MITER_LIMIT);
+            if cos_half_angle.is_nan() {
+                // A reversal, the edges are parallel
+                polygon[i] + outgoing * distance
+            } else {
+                polygon[i] + miter * (distance / cos_half_angle)
+            }
+        };
+        let start = offset(0);
+        redirect_last_rapid(preceding, start);
+
+        // The original moves are mapped onto offset vertices, so their other fields are kept
+        let mut vertex = 0;
+        let mut previous = from;
+        moves
+            .iter()
+            .flat_map(|m| {
+                if m.to != previous {
+                    vertex += 1;
+                    previous = m.to;
+                }
+                linear_interpolation_tokens(offset(vertex % polygon.len()), &m.args)
+            })
+            .collect()
+    })
+}
+
+/// Split the paths of a program into the paths of each top-level element of the SVG, named after
+/// its id or its tag name and position if it has none.
+///
+/// The setup and teardown of the program aren't part of its paths, and are left for the caller to
+/// add to each part. Positions are unchanged, so the parts stay registered to each other.
+pub fn split_by_top_level_element<'input>(
+    paths: &[Token<'input>],
+) -> Vec<(String, Vec<Token<'input>>)> {
+    let mut parts: Vec<(String, Vec<Token<'input>>)> = vec![];
+    for token in paths {
+        match top_level_name(token) {
+            Some(name) if parts.last().map(|(last, _)| last) != Some(&name) => {
+                parts.push((name, vec![]))
+            }
+            _ => {}
+        }
+        if let Some((_, part)) = parts.last_mut() {
+            part.push(token.clone());
+        }
+    }
+
+    parts
+        .into_iter()
+        .enumerate()
+        .map(|(i, (name, part))| {
+            let name = match name.split_once('#') {
+                Some((_, id)) => id.to_string(),
+                None => format!("{}-{}", name, i + 1),
+            };
+            (name, part)
+        })
+        .collect()
+}
+
+/// Split a program into programs of at most `max_lines` lines each, for controllers that can
+/// only hold small files. Each part has the setup and the teardown of the program, and the body
+/// between them is split between paths. A path that is too long on its own gets a part to itself,
+/// which is longer than `max_lines`.
+pub fn split_by_lines<'input>(
+    setup: &[Token<'input>],
+    body: &[Token<'input>],
+    teardown: &[Token<'input>],
+    max_lines: usize,
+) -> Vec<Vec<Token<'input>>> {
+    let overhead = line_count(setup) + line_count(teardown);
+
+    let mut paths: Vec<&[Token<'input>]> = vec![];
+    let mut start = 0;
+    for (i, token) in body.iter().enumerate() {
+        if i > start && top_level_name(token).is_some() {
+            paths.push(&body[start..i]);
+            start = i;
+        }
+    }
+    paths.push(&body[start..]);
+
+    let mut parts: Vec<(usize, Vec<Token<'input>>)> = vec![];
+    for path in paths {
+        let lines = line_count(path);
+        match parts.last_mut() {
+            Some((part_lines, part)) if *part_lines + lines + overhead <= max_lines => {
+                *part_lines += lines;
+                part.extend_from_slice(path);
+            }
+            _ => {
+                if lines + overhead > max_lines {
+                    warn!(
+                        "A path of {} lines is split into a part of its own, which is over {} lines",
+                        lines, max_lines
+                    );
+                }
+                let mut part = setup.to_vec();
+                part.extend_from_slice(path);
+                parts.push((lines, part));
+            }
+        }
+    }
+    parts
+        .into_iter()
+        .map(|(_, mut part)| {
+            part.extend_from_slice(teardown);
+            part
+        })
+        .collect()
+}
+
+/// Number of lines the tokens are written on, which each start with a G or M command
+fn line_count(tokens: &[Token]) -> usize {
+    tokens
+        .iter()
+        .filter(|token| {
+            matches!(token, Token::Field(Field { letters, .. }) if letters == "G" || letters == "M")
+        })
+        .count()
+}
+
+/// Whether each cut in the program is a hole, or [None] if it isn't closed.
+/// A closed cut nested in an odd number of other closed cuts is a hole.
+fn find_holes(tokens: Vec<Token<'_>>) -> (Vec<Token<'_>>, Vec<Option<bool>>) {
+    let mut closed_cuts = vec![];
+    let tokens = map_cuts(tokens, |_, from, moves| {
+        closed_cuts.push(closed_polygon(from, &moves));
+        moves
+            .iter()
+            .flat_map(|m| linear_interpolation_tokens(m.to, &m.args))
+            .collect()
+    });
+    let is_hole = closed_cuts
+        .iter()
+        .map(|polygon| {
+            polygon.as_ref().map(|polygon| {
+                let nesting = closed_cuts
+                    .iter()
+                    .flatten()
+                    .filter(|other| !std::ptr::eq(*other, polygon))
+                    .filter(|other| polygon_contains(other, polygon[0]))
+                    .count();
+                nesting % 2 == 1
+            })
+        })
+        .collect();
+    (tokens, is_hole)
+}
+
+/// Whether the waste side of a closed cut is to the right of its direction of travel.
+/// The waste is outside of outer contours and inside of holes.
+fn waste_is_right(polygon: &[F64Point], is_hole: bool) -> bool {
+    let area = polygon
+        .iter()
+        .zip(polygon.iter().cycle().skip(1))
+        .map(|(a, b)| a.x * b.y - b.x * a.y)
+        .sum::<f64>();
+    let is_counterclockwise = area > 0.;
+    is_counterclockwise != is_hole
+}
+
+/// Unit normal of a unit direction pointing towards the waste side
+fn waste_normal(direction: Vector<f64>, waste_is_right: bool) -> Vector<f64> {
+    if waste_is_right {
+        vector(direction.y, -direction.x)
+    } else {
+        vector(-direction.y, direction.x)
+    }
+}
+
+/// Change the destination of the last rapid positioning move
+pub(crate) fn redirect_last_rapid(tokens: &mut [Token<'_>], to: F64Point) {
+    if let Some(rapid) = tokens
+        .iter()
+        .rposition(|token| *token == Token::Field(RAPID_POSITIONING_FIELD))
+    {
+        for token in tokens[rapid + 1..].iter_mut() {
+            match token {
+                Token::Field(Field { letters, value }) if *letters == "X" => {
+                    *value = Value::Float(to.x)
+                }
+                Token::Field(Field { letters, value }) if *letters == "Y" => {
+                    *value = Value::Float(to.y)
+                }
+                Token::Field(Field { letters, .. }) if *letters == "G" || *letters == "M" => break,
+                _ => {}
+            }
+        }
+    }
+}
+
+/// The vertices of a cut, if it ends where it started
+pub(crate) fn closed_polygon(from: F64Point, moves: &[CutMove<'_>]) -> Option<Vec<F64Point>> {
+    let last = moves.last()?;
+    if (last.to - from).length() < f64::EPSILON.sqrt() {
+        Some(
+            std::iter::once(from)
+                .chain(moves.iter().map(|m| m.to))
+                .take(moves.len())
+                .collect(),
+        )
+    } else {
+        None
+    }
+}
+
+/// Even-odd rule point in polygon test
+fn polygon_contains(polygon: &[F64Point], p: F64Point) -> bool {
+    let mut inside = false;
+    for (from, to) in polygon.iter().zip(polygon.iter().cycle().skip(1)) {
+        if (from.y > p.y) != (to.y > p.y)
+            && p.x < (to.x - from.x) * (p.y - from.y) / (to.y - from.y) + from.x
+        {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+/// Remove commands and words that don't change anything, to shrink programs for controllers with
+/// little memory: repeated distance modes (G90/G91) and units (G20/G21), feedrates that are
+/// already set, and moves to where the machine already is.
+pub fn remove_redundant(tokens: Vec<Token<'_>>) -> Vec<Token<'_>> {
+    let mut distance_mode = None;
+    let mut units = None;
+    let mut feedrate = None;
+    let mut position: [Option<f64>; 2] = [None, None];
+
+    let mut kept = Vec::with_capacity(tokens.len());
+    for mut command in commands(tokens) {
+        let code = match command.first() {
+            Some(Token::Field(Field { letters, value })) if *letters == "G" => value.as_f64(),
+            _ => None,
+        };
+        let is_repeated_mode = match code {
+            Some(mode) if mode == 90. || mode == 91. => distance_mode.replace(mode) == Some(mode),
+            Some(mode) if mode == 20. || mode == 21. => units.replace(mode) == Some(mode),
+            _ => false,
+        };
+        if is_repeated_mode {
+            continue;
+        }
+
+        command.retain(|token| match token {
+            Token::Field(Field { letters, value }) if *letters == "F" => {
+                let value = value.as_f64();
+                value.is_none() || feedrate.replace(value) != Some(value)
+            }
+            _ => true,
+        });
+
+        if code == Some(0.) || code == Some(1.) {
+            let is_relative = distance_mode == Some(91.);
+            let mut is_zero_length = true;
+            for token in command.iter().skip(1) {
+                match token {
+                    Token::Field(Field { letters, value }) if *letters == "X" || *letters == "Y" => {
+                        let axis = (*letters == "Y") as usize;
+                        let value = value.as_f64();
+                        let to = match (is_relative, value, position[axis]) {
+                            (true, Some(offset), Some(from)) => Some(from + offset),
+                            (true, ..) => None,
+                            (false, value, _) => value,
+                        };
+                        if !(is_relative && value == Some(0.)) && to != position[axis] {
+                            is_zero_length = false;
+                        }
+                        position[axis] = to;
+                    }
+                    _ => is_zero_length = false,
+                }
+            }
+            if is_zero_length {
+                continue;
+            }
+        } else if command.iter().skip(1).any(|token| {
+            matches!(token, Token::Field(Field { letters, .. }) if *letters == "X" || *letters == "Y")
+        }) {
+            // Other commands may move the machine anywhere, i.e. homing
+            if matches!(command.first(), Some(Token::Field(Field { letters, .. })) if *letters == "G")
+            {
+                position = [None, None];
+            }
+        }
+        kept.append(&mut command);
+    }
+    kept
+}
+
+/// Remove tool toggles with no motion between them, like turning the tool on and right back off
+/// for a path that is only a point, or off and right back on between paths that join.
+///
+/// Comments between the toggles are kept, and moves that go nowhere are removed with them. Tool
+/// sequences are matched regardless of their power (S), which changes between paths.
+pub fn remove_empty_toggles<'input>(
+    tokens: Vec<Token<'input>>,
+    tool_on: &[Token<'input>],
+    tool_off: &[Token<'input>],
+) -> Vec<Token<'input>> {
+    let tool_on = commands(tool_on.to_vec());
+    let tool_off = commands(tool_off.to_vec());
+    if tool_on.is_empty() || tool_off.is_empty() {
+        return tokens;
+    }
+
+    let commands = commands(tokens);
+    let mut kept = Vec::with_capacity(commands.len());
+    let mut is_relative = false;
+    let mut position: [Option<f64>; 2] = [None, None];
+    // Comments, and moves to where the machine already is
+    let is_idle = |command: &[Token], position: [Option<f64>; 2], is_relative: bool| match command {
+        [Token::Comment { .. }] => true,
+        [Token::Field(Field { letters, value }), args @ ..]
+            if *letters == "G"
+                && matches!(value.as_f64(), Some(code) if code == 0. || code == 1.) =>
+        {
+            !is_relative
+                && args.iter().all(|arg| match arg {
+                    Token::Field(Field { letters, value }) => match letters.as_ref() {
+                        "X" => value.as_f64().is_some() && value.as_f64() == position[0],
+                        "Y" => value.as_f64().is_some() && value.as_f64() == position[1],
+                        "F" | "S" => true,
+                        _ => false,
+                    },
+                    _ => false,
+                })
+        }
+        _ => false,
+    };
+
+    let mut i = 0;
+    'commands: while i < commands.len() {
+        for (first, second) in [(&tool_on, &tool_off), (&tool_off, &tool_on)] {
+            if !is_sequence_at(&commands, i, first) {
+                continue;
+            }
+            let start = i + first.len();
+            let mut end = start;
+            while end < commands.len() && is_idle(&commands[end], position, is_relative) {
+                end += 1;
+            }
+            if is_sequence_at(&commands, end, second) {
+                kept.extend(
+                    commands[start..end]
+                        .iter()
+                        .filter(|command| matches!(command.as_slice(), [Token::Comment { .. }]))
+                        .cloned(),
+                );
+                i = end + second.len();
+                continue 'commands;
+            }
+        }
+
+        track_position(&commands[i], &mut position, &mut is_relative);
+        kept.push(commands[i].clone());
+        i += 1;
+    }
+    kept.into_iter().flatten().collect()
+}
+
+/// Keep the tool on between cuts that are within a distance, instead of turning it off and on
+/// again (i.e. retracting and plunging), by cutting straight to the next one at a feedrate. Paths
+/// that join, where one ends exactly where the next begins, become a single cut at a distance of
+/// zero.
+pub fn stay_down<'input>(
+    tokens: Vec<Token<'input>>,
+    tool_on: &[Token<'input>],
+    tool_off: &[Token<'input>],
+    max_distance: f64,
+    feedrate: f64,
+) -> Vec<Token<'input>> {
+    let tool_on = commands(tool_on.to_vec());
+    let tool_off = commands(tool_off.to_vec());
+    if tool_on.is_empty() || tool_off.is_empty() {
+        return tokens;
+    }
+
+    let commands = commands(tokens);
+    let mut kept = Vec::with_capacity(commands.len());
+    let mut is_relative = false;
+    let mut position: [Option<f64>; 2] = [None, None];
+    let mut i = 0;
+    while i < commands.len() {
+        if !is_relative && is_sequence_at(&commands, i, &tool_off) {
+            // Path comments may be between the tool off, the travel and the tool on
+            let is_comment =
+                |command: &Vec<Token>| matches!(command.as_slice(), [Token::Comment { .. }]);
+            let travel = i
+                + tool_off.len()
+                + commands[i + tool_off.len()..]
+                    .iter()
+                    .take_while(|command| is_comment(command))
+                    .count();
+            let tool_on_index = travel
+                + 1
+                + commands
+                    .get(travel + 1..)
+                    .unwrap_or_default()
+                    .iter()
+                    .take_while(|command| is_comment(command))
+                    .count();
+            let to = match commands.get(travel).map(Vec::as_slice) {
+                Some(
+                    [rapid, Token::Field(Field {
+                        letters: x,
+                        value: x_value,
+                    }), Token::Field(Field {
+                        letters: y,
+                        value: y_value,
+                    })],
+                ) if *rapid == Token::Field(RAPID_POSITIONING_FIELD) && *x == "X" && *y == "Y" => {
+                    x_value.as_f64().zip(y_value.as_f64())
+                }
+                _ => None,
+            };
+            let from = position[0].zip(position[1]);
+            if let (Some(from), Some(to)) = (from, to) {
+                if (point(to.0, to.1) - point(from.0, from.1)).length() <= max_distance
+                    && is_sequence_at(&commands, tool_on_index, &tool_on)
+                {
+                    kept.extend(commands[i + tool_off.len()..travel].iter().cloned());
+                    // Paths that join need no move at all
+                    if from != to {
+                        kept.push(
+                            command!(LinearInterpolation {
+                                X: to.0,
+                                Y: to.1,
+                                F: feedrate,
+                            })
+                            .into_token_vec(),
+                        );
+                    }
+                    kept.extend(commands[travel + 1..tool_on_index].iter().cloned());
+                    position = [Some(to.0), Some(to.1)];
+                    i = tool_on_index + tool_on.len();
+                    continue;
+                }
+            }
+        }
+
+        track_position(&commands[i], &mut position, &mut is_relative);
+        kept.push(commands[i].clone());
+        i += 1;
+    }
+    kept.into_iter().flatten().collect()
+}
+
+/// Whether the commands starting at an index are a tool sequence, regardless of their power (S),
+/// which changes between paths
+pub(crate) fn is_sequence_at(commands: &[Vec<Token>], i: usize, sequence: &[Vec<Token>]) -> bool {
+    let is_same_command = |a: &[Token], b: &[Token]| {
+        a.len() == b.len()
+            && a.iter().zip(b).all(|pair| match pair {
+                (
+                    Token::Field(Field { letters, .. }),
+                    Token::Field(Field {
+                        letters: other_letters,
+                        ..
+                    }),
+                ) if *letters == "S" => *other_letters == "S",
+                (a, b) => a == b,
+            })
+    };
+    commands.len() >= i + sequence.len()
+        && commands[i..]
+            .iter()
+            .zip(sequence)
+            .all(|(command, expected)| is_same_command(command, expected))
+}
+
+/// Follow the X and Y position of the machine through a command, which becomes unknown after
+/// relative moves
+fn track_position(command: &[Token], position: &mut [Option<f64>; 2], is_relative: &mut bool) {
+    match command.first() {
+        Some(abs) if *abs == Token::Field(ABSOLUTE_DISTANCE_MODE_FIELD) => *is_relative = false,
+        Some(rel) if *rel == Token::Field(RELATIVE_DISTANCE_MODE_FIELD) => *is_relative = true,
+        Some(Token::Field(Field { letters, .. })) if *letters == "G" => {
+            for arg in &command[1..] {
+                if let Token::Field(Field { letters, value }) = arg {
+                    let axis = match letters.as_ref() {
+                        "X" => 0,
+                        "Y" => 1,
+                        _ => continue,
+                    };
+                    position[axis] = if *is_relative { None } else { value.as_f64() };
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Comment each rapid move with the element cut after it, to make the order of a program easier
+/// to follow in a sender. `{element}` in the template is replaced by the element's name, i.e.
+/// `-> {element}` becomes `-> path#star`.
+pub fn annotate_travel<'input>(tokens: Vec<Token<'input>>, template: &str) -> Vec<Token<'input>> {
+    // Find the element of each cut from the path comments before it, then the next cut after each
+    // rapid move
+    let mut element = None;
+    let mut elements = vec![None; tokens.len()];
+    for (i, token) in tokens.iter().enumerate() {
+        match token {
+            Token::Comment {
+                is_inline: false,
+                inner,
+            } if inner.starts_with("svg") => {
+                element = inner
+                    .rsplit(" > ")
+                    .next()
+                    .map(|name| name.trim().to_string());
+            }
+            linear if *linear == Token::Field(LINEAR_INTERPOLATION_FIELD) => {
+                elements[i] = element.clone()
+            }
+            _ => {}
+        }
+    }
+    let mut upcoming = None;
+    for (i, token) in tokens.iter().enumerate().rev() {
+        if *token == Token::Field(LINEAR_INTERPOLATION_FIELD) {
+            upcoming = elements[i].take();
+        } else if *token == Token::Field(RAPID_POSITIONING_FIELD) {
+            elements[i] = upcoming.clone();
+        }
+    }
+
+    let mut annotated = Vec::with_capacity(tokens.len());
+    let mut pending = None;
+    for (token, element) in tokens.into_iter().zip(elements) {
+        let ends_command = match &token {
+            Token::Field(Field { letters, .. }) => *letters == "G" || *letters == "M",
+            _ => true,
+        };
+        if ends_command {
+            annotated.extend(pending.take());
+        }
+        if token == Token::Field(RAPID_POSITIONING_FIELD) {
+            pending = element.map(|element| Token::Comment {
+                is_inline: true,
+                inner: Cow::Owned(template.replace("{element}", &element)),
+            });
+        }
+        annotated.push(token);
+    }
+    annotated.extend(pending);
+    annotated
+}
+
+/// A cut move, as given to the filter of [filter_cut_moves]
+#[derive(Debug, Clone)]
+pub struct CutMoveContext<'a> {
+    pub from: F64Point,
+    pub to: F64Point,
+    /// The most recent full-line comment, which names the SVG element being drawn
+    pub source: Option<&'a str>,
+}
+
+/// What [filter_cut_moves] does with a cut move
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CutMoveAction {
+    Keep,
+    /// Travel over the move with the tool off
+    Skip,
+    /// Cut to another point instead
+    MoveTo(F64Point),
+}
+
+/// Let downstream code veto or modify each cut move with its own predicate, i.e. to skip anything
+/// outside of a mask that changes at runtime.
+///
+/// Skipped moves are traveled over with the tool off, like the gaps of [dash](crate::stroke::dash).
+pub fn filter_cut_moves<'input>(
+    tokens: Vec<Token<'input>>,
+    tool_on: &[Token<'input>],
+    tool_off: &[Token<'input>],
+    mut filter: impl FnMut(&CutMoveContext) -> CutMoveAction,
+) -> Vec<Token<'input>> {
+    map_cuts(tokens, |preceding, from, moves| {
+        let source = preceding.iter().rev().find_map(|token| match token {
+            Token::Comment {
+                is_inline: false,
+                inner,
+            } => Some(inner.to_string()),
+            _ => None,
+        });
+
+        let mut tokens = vec![];
+        let mut is_first_move = true;
+        let mut is_in_gap = false;
+        let mut position = from;
+        for m in &moves {
+            let to = match filter(&CutMoveContext {
+                from: position,
+                to: m.to,
+                source: source.as_deref(),
+            }) {
+                CutMoveAction::Keep => m.to,
+                CutMoveAction::MoveTo(to) => to,
+                CutMoveAction::Skip => {
+                    if !is_in_gap && !is_first_move {
+                        tokens.extend(tool_off.iter().cloned());
+                    }
+                    is_in_gap = true;
+                    position = m.to;
+                    continue;
+                }
+            };
+            if is_in_gap {
+                if is_first_move {
+                    redirect_last_rapid(preceding, position);
+                } else {
+                    tokens.append(
+                        &mut command!(RapidPositioning {
+                            X: position.x,
+                            Y: position.y,
+                        })
+                        .into_token_vec(),
+                    );
+                    tokens.extend(tool_on.iter().cloned());
+                }
+                is_in_gap = false;
+            }
+            tokens.append(&mut linear_interpolation_tokens(to, &m.args));
+            is_first_move = false;
+            position = to;
+        }
+        if is_first_move {
+            // Every move was skipped, the tool was turned on for nothing
+            tokens.extend(tool_off.iter().cloned());
+        }
+        tokens
+    })
+}
+
+/// Group tokens by command, which is a G or M field followed by its arguments, or a comment
+pub(crate) fn commands(tokens: Vec<Token<'_>>) -> Vec<Vec<Token<'_>>> {
+    let mut commands: Vec<Vec<Token>> = vec![];
+    let mut in_command = false;
+    for token in tokens {
+        match &token {
+            Token::Field(Field { letters, .. }) if letters == "G" || letters == "M" => {
+                in_command = true;
+                commands.push(vec![token]);
+            }
+            Token::Field(_) if in_command => commands.last_mut().unwrap().push(token),
+            _ => {
+                in_command = matches!(token, Token::Field(_));
+                commands.push(vec![token]);
+            }
+        }
+    }
+    commands
+}
+
+/// Options for [add_second_head]
+#[derive(Debug, Clone, Copy)]
+pub struct SecondHeadOptions {
+    /// Offset of the second head's toolpath from the first's, in millimeters
+    pub offset: Vector<f64>,
+    /// Mirror the second head's toolpath in X, as needed by dual carriages that move in opposite
+    /// directions
+    pub mirror: bool,
+}
+
+/// Repeat a program for the second head of a machine with two of them, i.e. a dual carriage.
+///
+/// The first head is selected with `T0` and cuts the program as is, then the second head is
+/// selected with `T1` and cuts it again, offset and optionally mirrored about the center of the
+/// cuts. This is given the paths of the program, without its setup and teardown.
+pub fn add_second_head<'input>(
+    body: &[Token<'input>],
+    options: SecondHeadOptions,
+    tool_off: &[Token<'input>],
+) -> Vec<Token<'input>> {
+    let select_tool = |number: usize| {
+        Token::Field(Field {
+            letters: Cow::Borrowed("T"),
+            value: Value::Integer(number),
+        })
+    };
+
+    let mut second = body.to_vec();
+    if options.mirror {
+        second = mirror(second, true, false);
+    }
+    second = transform_program(
+        second,
+        &Transform2D::translation(options.offset.x, options.offset.y),
+    );
+
+    let mut program = vec![select_tool(0)];
+    program.extend_from_slice(body);
+    program.extend_from_slice(tool_off);
+    program.push(select_tool(1));
+    program.append(&mut second);
+    program
+}
+
+/// Name of the top-level element of the SVG that a path comment belongs to, like `g#layer1`
+fn top_level_name(token: &Token) -> Option<String> {
+    match token {
+        // Path comments start with the root element, unlike other comments (i.e. progress)
+        Token::Comment {
+            is_inline: false,
+            inner,
+        } if inner.starts_with("svg") => Some(
+            inner
+                .split(" > ")
+                .nth(1)
+                .unwrap_or_else(|| inner.as_ref())
+                .trim()
+                .to_string(),
+        ),
+        _ => None,
+    }
+}
+
+/// A linear interpolation move in a cut
+pub(crate) struct CutMove<'input> {
+    pub to: F64Point,
+    /// Fields other than X and Y, i.e. the feedrate
+    pub args: Vec<Field<'input>>,
+}
+
+/// Part of a [CutMove], after splitting it at distances along its cut
+pub(crate) struct Piece<'a, 'input> {
+    pub to: F64Point,
+    /// Distance along the cut where the piece starts
+    pub start: f64,
+    /// Distance along the cut where the piece ends
+    pub end: f64,
+    pub args: &'a [Field<'input>],
+}
+
+/// Rewrite each cut in a program, leaving everything else untouched.
+///
+/// A cut is a run of consecutive linear interpolation moves in absolute distance mode.
+/// The rewrite is given the already rewritten tokens preceding the cut, which it may modify, the
+/// position the cut starts from and its moves.
+pub(crate) fn map_cuts<'input, F>(tokens: Vec<Token<'input>>, mut rewrite: F) -> Vec<Token<'input>>
+where
+    F: FnMut(&mut [Token<'input>], F64Point, Vec<CutMove<'input>>) -> Vec<Token<'input>>,
+{
+    let mut rewritten = Vec::with_capacity(tokens.len());
+    let mut is_relative = false;
+    let mut should_skip = false;
+    let mut current_position = point(0f64, 0f64);
+    let mut cut_start = current_position;
+    let mut cut = vec![];
+
+    let mut tokens = tokens.into_iter().peekable();
+    while let Some(token) = tokens.next() {
+        if !is_relative && token == Token::Field(LINEAR_INTERPOLATION_FIELD) {
+            if cut.is_empty() {
+                cut_start = current_position;
+            }
+            let mut to = current_position;
+            let mut args = vec![];
+            while let Some(Token::Field(Field { letters, .. })) = tokens.peek() {
+                if *letters == "G" || *letters == "M" {
+                    break;
+                }
+                if let Some(Token::Field(field)) = tokens.next() {
+                    match (field.letters.as_ref(), field.value.as_f64()) {
+                        ("X", Some(x)) => to.x = x,
+                        ("Y", Some(y)) => to.y = y,
+                        _ => args.push(field),
+                    }
+                }
+            }
+            current_position = to;
+            cut.push(CutMove { to, args });
+            continue;
+        }
+
+        if !cut.is_empty() {
+            let mut cut_tokens = rewrite(&mut rewritten, cut_start, std::mem::take(&mut cut));
+            rewritten.append(&mut cut_tokens);
+        }
+
+        match &token {
+            abs if *abs == Token::Field(ABSOLUTE_DISTANCE_MODE_FIELD) => is_relative = false,
+            rel if *rel == Token::Field(RELATIVE_DISTANCE_MODE_FIELD) => is_relative = true,
+            Token::Field(Field { letters, .. }) if *letters == "M" => should_skip = true,
+            Token::Field(Field { letters, .. }) if *letters == "G" => should_skip = false,
+            Token::Field(Field { letters, value }) if *letters == "X" && !should_skip => {
+                if let Some(x) = value.as_f64() {
+                    current_position.x = if is_relative {
+                        current_position.x + x
+                    } else {
+                        x
+                    };
+                }
+            }
+            Token::Field(Field { letters, value }) if *letters == "Y" && !should_skip => {
+                if let Some(y) = value.as_f64() {
+                    current_position.y = if is_relative {
+                        current_position.y + y
+                    } else {
+                        y
+                    };
+                }
+            }
+            _ => {}
+        }
+        rewritten.push(token);
+    }
+    if !cut.is_empty() {
+        let mut cut_tokens = rewrite(&mut rewritten, cut_start, cut);
+        rewritten.append(&mut cut_tokens);
+    }
+
+    rewritten
+}
+
+pub(crate) fn cut_length(from: F64Point, moves: &[CutMove<'_>]) -> f64 {
+    moves
+        .iter()
+        .scan(from, |from, m| {
+            let length = (m.to - *from).length();
+            *from = m.to;
+            Some(length)
+        })
+        .sum()
+}
+
+/// Split the moves of a cut at the given distances along it.
+/// Distances outside of the cut are ignored.
+pub(crate) fn split_cut<'a, 'input>(
+    mut from: F64Point,
+    moves: &'a [CutMove<'input>],
+    mut breakpoints: Vec<f64>,
+) -> Vec<Piece<'a, 'input>> {
+    breakpoints.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+    let mut pieces = vec![];
+    let mut traveled = 0.;
+    for m in moves {
+        let segment = LineSegment { from, to: m.to };
+        let length = segment.length();
+        let mut piece_start = traveled;
+        let piece_ends = breakpoints
+            .iter()
+            .copied()
+            .filter(|breakpoint| *breakpoint > traveled && *breakpoint < traveled + length)
+            .chain(std::iter::once(traveled + length));
+        for piece_end in piece_ends {
+            pieces.push(Piece {
+                to: if piece_end < traveled + length {
+                    segment.sample((piece_end - traveled) / length)
+                } else {
+                    m.to
+                },
+                start: piece_start,
+                end: piece_end,
+                args: &m.args,
+            });
+            piece_start = piece_end;
+        }
+        traveled += length;
+        from = m.to;
+    }
+    pieces
+}
+
+pub(crate) fn linear_interpolation_tokens<'input>(
+    to: F64Point,
+    args: &[Field<'input>],
+) -> Vec<Token<'input>> {
+    let mut tokens = command! {LinearInterpolation { X: to.x, Y: to.y, }}.into_token_vec();
+    tokens.extend(args.iter().cloned().map(Token::Field));
+    tokens
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::analysis;
+    use crate::converter::{self, ProgramOptions};
+    use crate::machine::{Dialect, Machine, MachineBuilder};
+    use crate::turtle::Turtle;
+    use crate::writer::tokens_into_gcode_bytes;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn empty_paths_produce_no_tool_toggles() {
+        let machine = MachineBuilder::default()
+            .dialect(Dialect::Grbl)
+            .build()
+            .unwrap();
+        let document =
+            roxmltree::Document::parse(include_str!("../tests/empty_paths.svg")).unwrap();
+        let mut turtle = Turtle::new(machine);
+        let program =
+            converter::svg2program(&document, ProgramOptions::default(), &mut turtle).unwrap();
+        let program = remove_empty_toggles(
+            program,
+            &turtle.machine.tool_on_sequence(),
+            &turtle.machine.tool_off_sequence(),
+        );
+        let program = remove_redundant(program);
+
+        let mut actual = vec![];
+        assert!(tokens_into_gcode_bytes(&program, &mut actual).is_ok());
+        assert_eq!(
+            String::from_utf8(actual).unwrap(),
+            include_str!("../tests/empty_paths.gcode")
+        )
+    }
+
+    #[test]
+    fn filter_cut_moves_travels_over_skipped_moves() {
+        let document = roxmltree::Document::parse(include_str!("../tests/square.svg")).unwrap();
+        let mut turtle = Turtle::new(Machine::new(None, None, None, None));
+        let program =
+            converter::svg2program(&document, ProgramOptions::default(), &mut turtle).unwrap();
+        let program = filter_cut_moves(program, &[], &[], |m| {
+            if m.to.x > 5. {
+                CutMoveAction::Skip
+            } else {
+                CutMoveAction::Keep
+            }
+        });
+
+        let motions = analysis::motions(&program);
+        assert!(motions
+            .iter()
+            .any(|motion| !motion.is_cutting && motion.segment.to.x > 5.));
+        assert!(motions
+            .iter()
+            .filter(|motion| motion.is_cutting)
+            .all(|motion| motion.segment.to.x <= 5.));
+    }
+
+    #[test]
+    fn power_ramps_up_and_down_at_the_ends_of_cuts() {
+        let document = roxmltree::Document::parse(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="20mm" height="20mm" viewBox="0 0 20 20"><path d="M0,10 H20"/></svg>"#,
+        )
+        .unwrap();
+        let mut turtle = Turtle::new(Machine::new(None, None, None, None));
+        let program =
+            converter::svg2program(&document, ProgramOptions::default(), &mut turtle).unwrap();
+        let program = ramp_power(program, 4., 1000.);
+        let mut actual = vec![];
+        tokens_into_gcode_bytes(&program, &mut actual).unwrap();
+        let powers = String::from_utf8(actual)
+            .unwrap()
+            .lines()
+            .filter_map(|line| {
+                line.split_once(" S")
+                    .map(|(_, power)| power.parse().unwrap())
+            })
+            .collect::<Vec<f64>>();
+
+        // Eight steps up over 4mm, the 12mm between them at full power, and eight steps down
+        assert_eq!(powers.len(), 17);
+        assert_eq!(powers[0], 62.5);
+        assert!(powers[..9].windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(powers[8], 1000.);
+        assert!(powers[8..].windows(2).all(|pair| pair[0] > pair[1]));
+        assert_eq!(powers[16], 62.5);
+        assert!((analysis::statistics(&analysis::motions(&program)).cut_length - 20.).abs() < 1e-9);
+    }
+
+    /// Convert an SVG drawn in millimeters with a Grbl machine, returning the program and the
+    /// machine's tool on and off sequences
+    fn convert_mm(
+        svg: &str,
+        options: ProgramOptions,
+    ) -> (
+        Vec<g_code::emit::Token<'static>>,
+        Vec<g_code::emit::Token<'static>>,
+        Vec<g_code::emit::Token<'static>>,
+    ) {
+        let document = roxmltree::Document::parse(svg).unwrap();
+        let machine = MachineBuilder::default()
+            .dialect(Dialect::Grbl)
+            .build()
+            .unwrap();
+        let mut turtle = Turtle::new(machine);
+        let program = converter::svg2program(&document, options, &mut turtle).unwrap();
+        (
+            program,
+            turtle.machine.tool_on_sequence(),
+            turtle.machine.tool_off_sequence(),
+        )
+    }
+
+    #[test]
+    fn tabs_leave_gaps_away_from_corners() {
+        let square = r#"<svg xmlns="http://www.w3.org/2000/svg" width="10mm" height="10mm" viewBox="0 0 10 10"><path d="M0,0 H10 V10 H0 Z"/></svg>"#;
+        let tabbed = |count| {
+            let (program, tool_on, tool_off) = convert_mm(square, ProgramOptions::default());
+            analysis::motions(&insert_tabs(
+                program,
+                TabOptions {
+                    width: 2.,
+                    count,
+                    min_length: 0.,
+                },
+                &tool_on,
+                &tool_off,
+            ))
+        };
+
+        for count in [1, 3, 4] {
+            let motions = tabbed(count);
+            // The first travel is to the start of the square
+            let gaps = motions
+                .iter()
+                .skip(1)
+                .filter(|motion| !motion.is_cutting)
+                .map(|motion| motion.segment.length())
+                .collect::<Vec<_>>();
+            assert_eq!(gaps.len(), count);
+            assert!(gaps.iter().all(|gap| (gap - 2.).abs() < 1e-9), "{:?}", gaps);
+            let cut_length = analysis::statistics(&motions).cut_length;
+            assert!((cut_length - (40. - 2. * count as f64)).abs() < 1e-9);
+
+            // Evenly spaced, the second of three tabs would be on the far corner
+            for corner in [(0., 0.), (10., 0.), (10., 10.), (0., 10.)] {
+                let corner = lyon_geom::point(corner.0, corner.1);
+                assert!(
+                    motions
+                        .iter()
+                        .filter(|motion| motion.is_cutting)
+                        .any(|motion| motion.segment.to == corner),
+                    "{} tabs cover {:?}",
+                    count,
+                    corner
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn leads_stay_on_the_waste_side() {
+        // A part with a hole, both starting at a corner
+        let part = r#"<svg xmlns="http://www.w3.org/2000/svg" width="100mm" height="100mm" viewBox="0 0 100 100"><path d="M0,0 H100 V100 H0 Z M40,40 H60 V60 H40 Z"/></svg>"#;
+        let is_in_part = |p: lyon_geom::Point<f64>| {
+            let inside = |min: f64, max: f64| p.x > min && p.x < max && p.y > min && p.y < max;
+            inside(1e-9, 100. - 1e-9) && !inside(40. - 1e-9, 60. + 1e-9)
+        };
+        let is_on_edge = |p: lyon_geom::Point<f64>| {
+            let on = |min: f64, max: f64| {
+                let between = |v: f64| v > min - 1e-9 && v < max + 1e-9;
+                let at = |v: f64| (v - min).abs() < 1e-9 || (v - max).abs() < 1e-9;
+                (at(p.x) && between(p.y)) || (at(p.y) && between(p.x))
+            };
+            on(0., 100.) || on(40., 60.)
+        };
+
+        for arc in [false, true] {
+            let (program, _, _) = convert_mm(part, ProgramOptions::default());
+            let program = add_leads(
+                program,
+                LeadOptions {
+                    lead_in: 3.,
+                    lead_out: 3.,
+                    arc,
+                },
+            );
+            let motions = analysis::motions(&program);
+            let lead_starts = motions
+                .iter()
+                .filter(|motion| !motion.is_cutting)
+                .map(|motion| motion.segment.to)
+                .collect::<Vec<_>>();
+            assert_eq!(lead_starts.len(), 2);
+            for start in lead_starts {
+                assert!(
+                    !is_in_part(start) && !is_on_edge(start),
+                    "arc {}: lead starts at {:?}",
+                    arc,
+                    start
+                );
+            }
+            for motion in motions.iter().filter(|motion| motion.is_cutting) {
+                for t in [0.25, 0.5, 0.75, 1.] {
+                    let p = motion.segment.sample(t);
+                    assert!(!is_in_part(p), "arc {}: cuts through {:?}", arc, p);
+                }
+            }
+            // The part is cut out whole
+            let cut_length = analysis::statistics(&motions).cut_length;
+            assert!(cut_length > 480., "{}", cut_length);
+        }
+    }
+
+    /// Group the cutting motions of a program into the cuts between travel moves
+    fn cuts(motions: &[analysis::Motion]) -> Vec<Vec<lyon_geom::LineSegment<f64>>> {
+        let mut cuts: Vec<Vec<_>> = vec![];
+        let mut is_cutting = false;
+        for motion in motions {
+            if motion.is_cutting {
+                if !is_cutting {
+                    cuts.push(vec![]);
+                }
+                cuts.last_mut().unwrap().push(motion.segment);
+            }
+            is_cutting = motion.is_cutting;
+        }
+        cuts
+    }
+
+    #[test]
+    fn directions_wind_holes_opposite_to_outer_contours() {
+        // Both contours are drawn the same way around
+        let part = r#"<svg xmlns="http://www.w3.org/2000/svg" width="100mm" height="100mm" viewBox="0 0 100 100"><g id="part"><path d="M0,0 H100 V100 H0 Z M40,40 H60 V60 H40 Z"/></g></svg>"#;
+        let is_counterclockwise = |direction, reversed: &[String]| {
+            let (program, _, _) = convert_mm(part, ProgramOptions::default());
+            let program = set_direction(program, direction, reversed);
+            cuts(&analysis::motions(&program))
+                .iter()
+                .map(|cut| {
+                    cut.iter()
+                        .map(|segment| {
+                            segment.from.x * segment.to.y - segment.to.x * segment.from.y
+                        })
+                        .sum::<f64>()
+                        > 0.
+                })
+                .collect::<Vec<_>>()
+        };
+        use Direction::*;
+
+        // The outer contour comes first, then the hole
+        assert_eq!(is_counterclockwise(Some(Climb), &[]), [false, true]);
+        assert_eq!(is_counterclockwise(Some(Conventional), &[]), [true, false]);
+        assert_eq!(is_counterclockwise(Some(Clockwise), &[]), [false, false]);
+        assert_eq!(
+            is_counterclockwise(Some(Counterclockwise), &[]),
+            [true, true]
+        );
+        assert_eq!(
+            is_counterclockwise(Some(Climb), &["part".to_string()]),
+            [true, false]
+        );
+    }
+
+    #[test]
+    fn kerf_grows_parts_and_shrinks_holes() {
+        let part = r#"<svg xmlns="http://www.w3.org/2000/svg" width="100mm" height="100mm" viewBox="0 0 100 100"><path d="M0,0 H100 V100 H0 Z M40,40 H60 V60 H40 Z"/></svg>"#;
+        let (program, _, _) = convert_mm(part, ProgramOptions::default());
+        let program = offset_closed_cuts(program, 0.5);
+        let cuts = cuts(&analysis::motions(&program));
+
+        assert_eq!(cuts.len(), 2);
+        let bounds = cuts
+            .iter()
+            .map(|cut| {
+                let bounds = euclid::default::Box2D::from_points(
+                    cut.iter()
+                        .flat_map(|segment| vec![segment.from, segment.to]),
+                );
+                [bounds.min.x, bounds.min.y, bounds.max.x, bounds.max.y]
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            bounds,
+            [[-0.5, -0.5, 100.5, 100.5], [40.5, 40.5, 59.5, 59.5]]
+        );
+        for cut in &cuts {
+            assert_eq!(cut.first().unwrap().from, cut.last().unwrap().to);
+        }
+    }
+
+    #[test]
+    fn coordinates_round_to_the_precision() {
+        let line = r#"<svg xmlns="http://www.w3.org/2000/svg" width="10mm" height="10mm" viewBox="0 0 10 10"><path d="M1.23456,5.6789 L-0.001,2"/></svg>"#;
+        let (mut program, _, _) = convert_mm(line, ProgramOptions::default());
+        round_coordinates(&mut program, 2);
+        let mut actual = vec![];
+        tokens_into_gcode_bytes(&program, &mut actual).unwrap();
+        let actual = String::from_utf8(actual).unwrap();
+
+        assert!(
+            actual.contains("G0 X1.23 Y4.32\nM3\nG1 X0 Y8 F300\n"),
+            "{}",
+            actual
+        );
+    }
+
+    #[test]
+    fn m73_progress_follows_the_time_spent_cutting() {
+        // A minute of cutting at 300 mm/min, in two halves
+        let line = r#"<svg xmlns="http://www.w3.org/2000/svg" width="300mm" height="10mm" viewBox="0 0 300 10"><path d="M0,0 L150,0 L300,0"/></svg>"#;
+        let (program, _, _) = convert_mm(line, ProgramOptions::default());
+        let program = insert_progress(program, 25., 3000.);
+        let mut actual = vec![];
+        tokens_into_gcode_bytes(&program, &mut actual).unwrap();
+        let actual = String::from_utf8(actual).unwrap();
+
+        let updates = actual
+            .lines()
+            .filter(|line| line.starts_with("M73"))
+            .collect::<Vec<_>>();
+        assert_eq!(updates, ["M73 P0 R1", "M73 P50 R1", "M73 P100 R0"]);
+        // Each update follows the move that reached it
+        let after_halfway = actual.split_once("G1 X150").unwrap().1;
+        assert!(
+            after_halfway.lines().nth(1) == Some("M73 P50 R1"),
+            "{}",
+            actual
+        );
+    }
+
+    #[test]
+    fn close_cuts_are_joined_without_lifting_the_tool() {
+        let lines = r#"<svg xmlns="http://www.w3.org/2000/svg" width="10mm" height="10mm" viewBox="0 0 10 10">
+            <path d="M0,0 L4,0"/><path d="M4.5,0 L8,0"/><path d="M8,0 L8,8"/><path d="M0,8 L4,8"/>
+        </svg>"#;
+        let (program, tool_on, tool_off) = convert_mm(lines, ProgramOptions::default());
+        let mut program = stay_down(program, &tool_on, &tool_off, 1., 100.);
+        remove_comments(&mut program);
+        let mut actual = vec![];
+        tokens_into_gcode_bytes(&program, &mut actual).unwrap();
+        let actual = String::from_utf8(actual).unwrap();
+
+        // The gap is cut at the feedrate, the joint needs no move, and the far path is traveled to
+        assert_eq!(actual.matches("M3").count(), 2, "{}", actual);
+        assert!(
+            actual.contains("G1 X4 Y10 F300\nG1 X4.5 Y10 F100\n"),
+            "{}",
+            actual
+        );
+        assert!(
+            actual.contains("G1 X8 Y10 F300\nG1 X8 Y2 F300\n"),
+            "{}",
+            actual
+        );
+    }
+
+    #[test]
+    fn small_features_are_cut_slower() {
+        let shapes = r#"<svg xmlns="http://www.w3.org/2000/svg" width="20mm" height="20mm" viewBox="0 0 20 20">
+            <path d="M0,0 H1 V1 H0 Z"/><path d="M5,5 H15 V15 H5 Z"/><path d="M0,19 H20"/>
+        </svg>"#;
+        let (program, _, _) = convert_mm(shapes, ProgramOptions::default());
+        let mut program = slow_small_features(program, 4., 0.25);
+        remove_comments(&mut program);
+        let mut actual = vec![];
+        tokens_into_gcode_bytes(&program, &mut actual).unwrap();
+        let actual = String::from_utf8(actual).unwrap();
+        let feedrates = actual
+            .lines()
+            .filter_map(|line| line.split_once(" F"))
+            .map(|(_, feedrate)| (feedrate.parse::<f64>().unwrap() * 10.).round() / 10.)
+            .collect::<Vec<_>>();
+
+        // The corners of the small square curve with a radius of half its diagonal, the large
+        // square's corners are wider than the feature size, and a straight line has no curve
+        let small = (0.25 + 0.75 * 2f64.sqrt() / 4.) * 300.;
+        let small = (small * 10.).round() / 10.;
+        let mut expected = vec![small; 4];
+        expected.extend([300.; 5]);
+        assert_eq!(feedrates, expected);
+    }
+
+    #[test]
+    fn travel_is_commented_with_the_element_cut_next() {
+        let lines = r#"<svg xmlns="http://www.w3.org/2000/svg" width="10mm" height="10mm" viewBox="0 0 10 10">
+            <path id="first" d="M0,0 L4,0"/><path id="second" d="M0,5 L4,5"/>
+        </svg>"#;
+        let (program, _, _) = convert_mm(lines, ProgramOptions::default());
+        let program = annotate_travel(program, "-> {element}");
+        let mut actual = vec![];
+        tokens_into_gcode_bytes(&program, &mut actual).unwrap();
+        let actual = String::from_utf8(actual).unwrap();
+
+        let travel = actual
+            .lines()
+            .filter(|line| line.starts_with("G0"))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            travel,
+            ["G0 X0 Y10(-> path#first)", "G0 X0 Y5(-> path#second)"]
+        );
+    }
+
+    #[test]
+    fn rotation_is_counterclockwise_about_a_point_or_the_center() {
+        let line = r#"<svg xmlns="http://www.w3.org/2000/svg" width="10mm" height="10mm" viewBox="0 0 10 10"><path d="M0,0 L4,0"/></svg>"#;
+        let rotated = |about| {
+            let (program, _, _) = convert_mm(line, ProgramOptions::default());
+            let mut program = rotate(program, 90., about);
+            round_coordinates(&mut program, 6);
+            remove_comments(&mut program);
+            let mut actual = vec![];
+            tokens_into_gcode_bytes(&program, &mut actual).unwrap();
+            String::from_utf8(actual).unwrap()
+        };
+
+        let about_origin = rotated(Some(point(0., 0.)));
+        assert!(
+            about_origin.contains("G0 X-10 Y0\nM3\nG1 X-10 Y4 F300\n"),
+            "{}",
+            about_origin
+        );
+        let about_center = rotated(None);
+        assert!(
+            about_center.contains("G0 X2 Y8\nM3\nG1 X2 Y12 F300\n"),
+            "{}",
+            about_center
+        );
+    }
+
+    #[test]
+    fn cuts_snap_to_the_step_resolution() {
+        let line = r#"<svg xmlns="http://www.w3.org/2000/svg" width="10mm" height="10mm" viewBox="0 0 10 10"><path d="M1.01,5 L9.004,5 L9.005,5 L9.005,4"/></svg>"#;
+        let (program, _, _) = convert_mm(line, ProgramOptions::default());
+        let program = snap_to_resolution(program, 0.0125);
+        let mut actual = vec![];
+        tokens_into_gcode_bytes(&program, &mut actual).unwrap();
+        let actual = String::from_utf8(actual).unwrap();
+
+        // The move to 9.005 is smaller than a step
+        assert!(
+            actual.contains("G0 X1.0125 Y5\nM3\nG1 X9 Y5 F300\nG1 X9 Y6 F300\n"),
+            "{}",
+            actual
+        );
+    }
+
+    #[test]
+    fn overlapping_cuts_are_made_once() {
+        let document =
+            roxmltree::Document::parse(include_str!("../tests/shared_edges.svg")).unwrap();
+        let machine = MachineBuilder::default()
+            .dialect(Dialect::Grbl)
+            .build()
+            .unwrap();
+        let mut turtle = Turtle::new(machine);
+        let program =
+            converter::svg2program(&document, ProgramOptions::default(), &mut turtle).unwrap();
+        let program = remove_overlaps(
+            program,
+            &turtle.machine.tool_on_sequence(),
+            &turtle.machine.tool_off_sequence(),
+            0.01,
+        );
+        let cut_length = analysis::statistics(&analysis::motions(&program)).cut_length;
+        // Two squares sharing an edge, and a line along that edge
+        assert!((cut_length - 70.).abs() < 1e-9, "{}", cut_length);
+    }
+}
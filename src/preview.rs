@@ -0,0 +1,104 @@
+use std::fmt::Write;
+
+use euclid::default::Box2D;
+use g_code::emit::Token;
+use lyon_geom::{point, vector, LineSegment};
+
+use crate::analysis::{motions, statistics};
+
+/// Cuts shorter than this fraction of the preview's size don't get an arrow, which would hide them
+const MIN_ARROW_LENGTH: f64 = 0.02;
+
+/// Render the motions of a program as an SVG to check it before running it: cuts are solid lines
+/// with arrows showing their direction, travel is dashed, and the bounding box of the cuts and
+/// the bed (from the origin to `bed`, in millimeters) are outlined.
+pub fn preview_svg(tokens: &[Token<'_>], bed: Option<[f64; 2]>) -> String {
+    let motions = motions(tokens);
+    let cut_bounds = statistics(&motions).bounds;
+
+    let mut view = motions
+        .iter()
+        .map(|motion| {
+            let LineSegment { from, to } = motion.segment;
+            Box2D::new(from.min(to), from.max(to))
+        })
+        .chain(bed.map(|[width, height]| Box2D::new(point(0., 0.), point(width, height))))
+        .reduce(|a, b| Box2D::new(a.min.min(b.min), a.max.max(b.max)))
+        .unwrap_or_else(|| Box2D::new(point(0., 0.), point(1., 1.)));
+    let size = view.width().max(view.height()).max(1.);
+    view = view.inflate(size * 0.02, size * 0.02);
+    let stroke_width = size / 500.;
+
+    let mut svg = String::new();
+    // The Y axis of machines points up, so the drawing is flipped about the middle of the view
+    let _ = writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="{} {} {} {}" width="{}mm" height="{}mm">"#,
+        view.min.x,
+        -view.max.y,
+        view.width(),
+        view.height(),
+        view.width(),
+        view.height()
+    );
+    let _ = writeln!(
+        svg,
+        r#"<g transform="scale(1,-1)" fill="none" stroke-width="{}" stroke-linecap="round">"#,
+        stroke_width
+    );
+    if let Some([width, height]) = bed {
+        let _ = writeln!(
+            svg,
+            r##"<rect id="bed" width="{}" height="{}" stroke="#888"/>"##,
+            width, height
+        );
+    }
+    if let Some(bounds) = cut_bounds {
+        let _ = writeln!(
+            svg,
+            r##"<rect id="bounds" x="{}" y="{}" width="{}" height="{}" stroke="#0a0" stroke-dasharray="{} {}"/>"##,
+            bounds.min.x,
+            bounds.min.y,
+            bounds.width(),
+            bounds.height(),
+            stroke_width * 4.,
+            stroke_width * 4.
+        );
+    }
+
+    let mut travel = String::new();
+    let mut cuts = String::new();
+    let mut arrows = String::new();
+    for motion in &motions {
+        let LineSegment { from, to } = motion.segment;
+        if !motion.is_cutting {
+            let _ = write!(travel, "M{} {}L{} {}", from.x, from.y, to.x, to.y);
+            continue;
+        }
+        let _ = write!(cuts, "M{} {}L{} {}", from.x, from.y, to.x, to.y);
+
+        let length = motion.segment.length();
+        if length >= size * MIN_ARROW_LENGTH {
+            let middle = motion.segment.sample(0.5);
+            let direction = (to - from) / length * stroke_width * 6.;
+            let normal = vector(-direction.y, direction.x) / 2.;
+            let (left, right) = (middle - direction + normal, middle - direction - normal);
+            let _ = write!(
+                arrows,
+                "M{} {}L{} {}L{} {}",
+                left.x, left.y, middle.x, middle.y, right.x, right.y
+            );
+        }
+    }
+    let _ = writeln!(
+        svg,
+        r##"<path id="travel" d="{}" stroke="#c00" stroke-dasharray="{} {}"/>"##,
+        travel,
+        stroke_width * 2.,
+        stroke_width * 2.
+    );
+    let _ = writeln!(svg, r##"<path id="cuts" d="{}" stroke="#000"/>"##, cuts);
+    let _ = writeln!(svg, r##"<path id="arrows" d="{}" stroke="#00c"/>"##, arrows);
+    svg.push_str("</g>\n</svg>\n");
+    svg
+}
@@ -0,0 +1,141 @@
+use std::ffi::OsString;
+use std::fmt;
+
+/// A problem finding a profile in a profiles file
+#[derive(Debug)]
+pub enum ProfileError {
+    /// No section of the file has the profile's name
+    UnknownProfile {
+        name: String,
+        available: Vec<String>,
+    },
+    /// A line is neither a section, an option, a comment, nor blank
+    InvalidLine { number: usize, line: String },
+}
+
+impl fmt::Display for ProfileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownProfile { name, available } => write!(
+                f,
+                "there is no profile named {}, the profiles are {}",
+                name,
+                available.join(", ")
+            ),
+            Self::InvalidLine { number, line } => write!(
+                f,
+                "line {} of the profiles is not a [profile] or an --option: {}",
+                number, line
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ProfileError {}
+
+/// The options of a named machine profile in a file that holds several, each in a section of
+/// options with one per line:
+///
+/// ```text
+/// # Comments start with a hash
+/// [laser]
+/// --dialect grbl
+/// --inline-power 1000
+///
+/// [plotter]
+/// --on M3 S90
+/// --off M5
+/// ```
+///
+/// The rest of an option's line is its value, so it isn't quoted.
+pub fn profile_args(profiles: &str, name: &str) -> Result<Vec<OsString>, ProfileError> {
+    let mut available = vec![];
+    let mut args = None;
+    for (i, line) in profiles.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(section) = line
+            .strip_prefix('[')
+            .and_then(|line| line.strip_suffix(']'))
+        {
+            let section = section.trim();
+            available.push(section.to_string());
+            if args.is_some() {
+                break;
+            }
+            if section == name {
+                args = Some(vec![]);
+            }
+        } else if line.starts_with("--") && !available.is_empty() {
+            if let Some(args) = &mut args {
+                match line.split_once(char::is_whitespace) {
+                    Some((option, value)) => {
+                        args.push(option.into());
+                        args.push(value.trim().into());
+                    }
+                    None => args.push(line.into()),
+                }
+            }
+        } else {
+            return Err(ProfileError::InvalidLine {
+                number: i + 1,
+                line: line.to_string(),
+            });
+        }
+    }
+    args.ok_or_else(|| ProfileError::UnknownProfile {
+        name: name.to_string(),
+        available,
+    })
+}
+
+/// Insert the options of a profile into command line arguments, leaving out the ones that the
+/// command line sets itself so that it can override the profile
+pub fn merge(profile: Vec<OsString>, args: Vec<OsString>) -> Vec<OsString> {
+    let option_name = |arg: &OsString| {
+        arg.to_str()
+            .filter(|arg| arg.starts_with("--"))
+            .map(|arg| arg.split('=').next().unwrap_or(arg).to_string())
+    };
+    let overridden = args.iter().filter_map(option_name).collect::<Vec<_>>();
+
+    let mut kept = vec![];
+    let mut profile = profile.into_iter().peekable();
+    while let Some(option) = profile.next() {
+        let mut group = vec![option];
+        while let Some(value) = profile.next_if(|arg| option_name(arg).is_none()) {
+            group.push(value);
+        }
+        if !option_name(&group[0]).is_some_and(|name| overridden.contains(&name)) {
+            kept.extend(group);
+        }
+    }
+
+    let mut args = args.into_iter();
+    args.next().into_iter().chain(kept).chain(args).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn profiles_are_found_by_name() {
+        let profiles = "# Machines in the shop\n[laser]\n--dialect grbl\n--feedrate 1200\n\n[plotter]\n--on M3 S90\n--off M5\n";
+        assert_eq!(
+            profile_args(profiles, "laser").unwrap(),
+            ["--dialect", "grbl", "--feedrate", "1200"]
+        );
+        assert_eq!(
+            profile_args(profiles, "plotter").unwrap(),
+            ["--on", "M3 S90", "--off", "M5"]
+        );
+        assert!(matches!(
+            profile_args(profiles, "drag-knife"),
+            Err(ProfileError::UnknownProfile { .. })
+        ));
+    }
+}
@@ -0,0 +1,762 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use euclid::default::Box2D;
+use g_code::emit::{Field, Token, Value};
+
+use crate::turtle::Turtle;
+
+/// How the shades of an image are engraved
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RasterMode {
+    /// Dots with the tool fully on or off, spread out by Floyd-Steinberg dithering to look gray
+    Dither,
+    /// The tool's power (S) follows the darkness of the image, for lasers in dynamic power mode
+    /// (`M4`)
+    Power,
+}
+
+impl FromStr for RasterMode {
+    type Err = String;
+
+    /// Parses `dither` or `power`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dither" => Ok(Self::Dither),
+            "power" => Ok(Self::Power),
+            other => Err(format!(
+                "unknown raster mode {}, expected dither or power",
+                other
+            )),
+        }
+    }
+}
+
+/// Settings for engraving images
+#[derive(Debug, Clone)]
+pub struct RasterOptions {
+    /// Distance between scan lines in millimeters, which is also the size of the dots along them
+    pub scan_gap: f64,
+    pub mode: RasterMode,
+    /// Power (S) for black in [RasterMode::Power], which scales down to zero for white
+    pub max_power: f64,
+    /// Directory that images linked by a relative path are read from, else the working directory
+    pub base_dir: Option<PathBuf>,
+}
+
+/// A problem reading an image
+#[derive(Debug)]
+pub enum RasterError {
+    /// Only PNG images can be decoded
+    UnsupportedFormat,
+    /// The image is a PNG, but it is malformed or uses a feature that isn't supported
+    InvalidPng(&'static str),
+    /// A `data:` URL that isn't base64 encoded
+    InvalidDataUrl,
+    Io(io::Error),
+}
+
+impl fmt::Display for RasterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnsupportedFormat => write!(f, "only PNG images are supported"),
+            Self::InvalidPng(problem) => write!(f, "could not decode the PNG: {}", problem),
+            Self::InvalidDataUrl => write!(f, "the data URL is not base64 encoded"),
+            Self::Io(err) => write!(f, "could not read the image: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for RasterError {}
+
+/// An image as the darkness of each pixel, from 0 for white to 1 for black, row by row from the
+/// top. Transparent pixels are on a white background.
+#[derive(Debug, Clone)]
+pub struct Bitmap {
+    pub width: usize,
+    pub height: usize,
+    pub darkness: Vec<f64>,
+}
+
+impl Bitmap {
+    /// Average darkness over part of the image, given in fractions of its width and height
+    fn average(&self, u: [f64; 2], v: [f64; 2]) -> f64 {
+        let range = |fractions: [f64; 2], size: usize| {
+            let start = ((fractions[0] * size as f64).floor() as usize).min(size - 1);
+            let end = ((fractions[1] * size as f64).ceil() as usize).clamp(start + 1, size);
+            start..end
+        };
+        let (columns, rows) = (range(u, self.width), range(v, self.height));
+        let count = columns.len() * rows.len();
+        rows.flat_map(|row| {
+            columns
+                .clone()
+                .map(move |column| self.darkness[row * self.width + column])
+        })
+        .sum::<f64>()
+            / count as f64
+    }
+}
+
+/// Read the image an `href` points to, a `data:` URL or a path
+pub fn load(href: &str, base_dir: Option<&Path>) -> Result<Bitmap, RasterError> {
+    let bytes = match href.strip_prefix("data:") {
+        Some(url) => {
+            let (header, data) = url.split_once(',').ok_or(RasterError::InvalidDataUrl)?;
+            if !header.ends_with(";base64") {
+                return Err(RasterError::InvalidDataUrl);
+            }
+            decode_base64(data).ok_or(RasterError::InvalidDataUrl)?
+        }
+        None => {
+            let path = Path::new(href.strip_prefix("file://").unwrap_or(href));
+            let path = match base_dir {
+                Some(base_dir) if path.is_relative() => base_dir.join(path),
+                _ => path.to_path_buf(),
+            };
+            fs::read(path).map_err(RasterError::Io)?
+        }
+    };
+    decode_png(&bytes)
+}
+
+/// Engrave an image over a rectangle, in the current transform of the turtle, as scan lines along
+/// its X axis that alternate in direction. `clip` is the part of the rectangle that is engraved.
+pub fn engrave<'input>(
+    bitmap: &Bitmap,
+    rect: Box2D<f64>,
+    clip: Box2D<f64>,
+    turtle: &mut Turtle<'input>,
+    options: &RasterOptions,
+    feedrate: f64,
+) -> Vec<Token<'input>> {
+    let mut tokens = vec![];
+    if bitmap.width == 0 || bitmap.height == 0 || rect.is_empty() || clip.is_empty() {
+        return tokens;
+    }
+    let gap = options.scan_gap / turtle.current_scale();
+    let columns = (clip.width() / gap).ceil().max(1.) as usize;
+    let rows = (clip.height() / gap).ceil().max(1.) as usize;
+    let (cell_width, cell_height) = (clip.width() / columns as f64, clip.height() / rows as f64);
+    let fraction = |x: f64, y: f64| {
+        (
+            (x - rect.min.x) / rect.width(),
+            (y - rect.min.y) / rect.height(),
+        )
+    };
+    let mut cells = (0..rows)
+        .flat_map(|row| (0..columns).map(move |column| (row, column)))
+        .map(|(row, column)| {
+            let x = clip.min.x + column as f64 * cell_width;
+            let y = clip.min.y + row as f64 * cell_height;
+            let (u0, v0) = fraction(x, y);
+            let (u1, v1) = fraction(x + cell_width, y + cell_height);
+            bitmap.average([u0, u1], [v0, v1])
+        })
+        .collect::<Vec<_>>();
+
+    let mut position = None;
+    for row in 0..rows {
+        let is_reversed = row % 2 == 1;
+        let order = (0..columns)
+            .map(|i| if is_reversed { columns - 1 - i } else { i })
+            .collect::<Vec<_>>();
+        // The level of each cell: on or off when dithering, else a percent of power
+        let mut levels = Vec::with_capacity(columns);
+        for (i, &column) in order.iter().enumerate() {
+            let darkness = cells[row * columns + column];
+            let level = match options.mode {
+                RasterMode::Dither => {
+                    let level = if darkness >= 0.5 { 100 } else { 0 };
+                    let error = darkness - level as f64 / 100.;
+                    let mut spread = |column: Option<usize>, row: usize, weight: f64| {
+                        if let Some(column) = column.filter(|column| *column < columns) {
+                            if row < rows {
+                                cells[row * columns + column] += error * weight;
+                            }
+                        }
+                    };
+                    let (ahead, behind) = if is_reversed {
+                        (column.checked_sub(1), Some(column + 1))
+                    } else {
+                        (Some(column + 1), column.checked_sub(1))
+                    };
+                    if i + 1 < columns {
+                        spread(ahead, row, 7. / 16.);
+                        spread(ahead, row + 1, 1. / 16.);
+                    }
+                    spread(behind, row + 1, 3. / 16.);
+                    spread(Some(column), row + 1, 5. / 16.);
+                    level
+                }
+                RasterMode::Power => (darkness.clamp(0., 1.) * 100.).round() as u32,
+            };
+            levels.push(level);
+        }
+
+        let y = clip.min.y + (row as f64 + 0.5) * cell_height;
+        // The edge of a cell that the scan line enters it through, or leaves it through
+        let edge = |i: usize, is_leaving: bool| {
+            let offset = if is_reversed != is_leaving { 1 } else { 0 };
+            clip.min.x + (order[i] + offset) as f64 * cell_width
+        };
+        let mut start = 0;
+        while start < columns {
+            let level = levels[start];
+            let end = start + levels[start..].iter().take_while(|l| **l == level).count();
+            if level > 0 {
+                let (from, to) = (edge(start, false), edge(end - 1, true));
+                if position != Some((from, y)) {
+                    tokens.extend(turtle.move_to(true, from, y));
+                }
+                tokens.extend(turtle.line(true, to, y, None, feedrate));
+                if options.mode == RasterMode::Power {
+                    tokens.push(Token::Field(Field {
+                        letters: Cow::Borrowed("S"),
+                        value: Value::Float(options.max_power * level as f64 / 100.),
+                    }));
+                }
+                position = Some((to, y));
+            }
+            start = end;
+        }
+    }
+    tokens
+}
+
+fn decode_base64(encoded: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(encoded.len() / 4 * 3);
+    let (mut buffer, mut bits) = (0u32, 0);
+    for c in encoded.bytes() {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            b'=' => break,
+            c if c.is_ascii_whitespace() => continue,
+            _ => return None,
+        };
+        buffer = buffer << 6 | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+        }
+    }
+    Some(bytes)
+}
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+
+/// The most pixels an image may have, far beyond what is worth engraving, so that a header
+/// can't ask for more memory than there is
+const MAX_PIXELS: usize = 1 << 26;
+
+/// Whether the bytes of a file are a PNG
+pub fn is_png(bytes: &[u8]) -> bool {
+    bytes.starts_with(&PNG_SIGNATURE)
+}
+
+/// Decode a PNG, which is what editors embed images as. Interlaced images aren't supported.
+pub fn decode_png(bytes: &[u8]) -> Result<Bitmap, RasterError> {
+    if !is_png(bytes) {
+        return Err(RasterError::UnsupportedFormat);
+    }
+    let invalid = RasterError::InvalidPng;
+
+    let mut header = None;
+    let mut palette: &[u8] = &[];
+    let mut transparency: &[u8] = &[];
+    let mut compressed = vec![];
+    let mut rest = &bytes[PNG_SIGNATURE.len()..];
+    while rest.len() >= 12 {
+        let length = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+        let kind = &rest[4..8];
+        let data = rest
+            .get(8..8 + length)
+            .ok_or(invalid("a chunk is cut short"))?;
+        match kind {
+            b"IHDR" if length >= 13 => header = Some(data),
+            b"PLTE" => palette = data,
+            b"tRNS" => transparency = data,
+            b"IDAT" => compressed.extend_from_slice(data),
+            b"IEND" => break,
+            _ => {}
+        }
+        rest = &rest[(12 + length).min(rest.len())..];
+    }
+    let header = header.ok_or(invalid("there is no header"))?;
+    let width = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+    let height = u32::from_be_bytes([header[4], header[5], header[6], header[7]]) as usize;
+    let (depth, color_type, interlace) = (header[8] as usize, header[9], header[12]);
+    if interlace != 0 {
+        return Err(invalid("interlaced images are not supported"));
+    }
+    let channels = match color_type {
+        0 | 3 => 1,
+        4 => 2,
+        2 => 3,
+        6 => 4,
+        _ => return Err(invalid("the color type is unknown")),
+    };
+    if !matches!(depth, 1 | 2 | 4 | 8 | 16) || (color_type != 0 && color_type != 3 && depth < 8) {
+        return Err(invalid("the bit depth is not allowed for the color type"));
+    }
+    if width == 0 || height == 0 {
+        return Err(invalid("the image is empty"));
+    }
+    if width
+        .checked_mul(height)
+        .is_none_or(|pixels| pixels > MAX_PIXELS)
+    {
+        return Err(invalid("the image is too large"));
+    }
+    let stride = (width * channels * depth).div_ceil(8);
+    let pixel_bytes = (channels * depth).div_ceil(8);
+    let raw_length = (stride + 1)
+        .checked_mul(height)
+        .ok_or(invalid("the image is too large"))?;
+
+    let raw = inflate(
+        compressed
+            .get(2..)
+            .ok_or(invalid("the image data is empty"))?,
+        raw_length,
+    )
+    .map_err(invalid)?;
+    if raw.len() < raw_length {
+        return Err(invalid("the image data is cut short"));
+    }
+    let mut previous = vec![0u8; stride];
+    let mut darkness = Vec::with_capacity(width * height);
+    for row in raw.chunks(stride + 1).take(height) {
+        let (filter, row) = (row[0], &row[1..]);
+        let mut current = row.to_vec();
+        for i in 0..stride {
+            let left = if i >= pixel_bytes {
+                current[i - pixel_bytes]
+            } else {
+                0
+            };
+            let up = previous[i];
+            let up_left = if i >= pixel_bytes {
+                previous[i - pixel_bytes]
+            } else {
+                0
+            };
+            let prediction = match filter {
+                0 => 0,
+                1 => left,
+                2 => up,
+                3 => ((left as u16 + up as u16) / 2) as u8,
+                4 => {
+                    let estimate = left as i16 + up as i16 - up_left as i16;
+                    let (a, b, c) = (
+                        (estimate - left as i16).abs(),
+                        (estimate - up as i16).abs(),
+                        (estimate - up_left as i16).abs(),
+                    );
+                    if a <= b && a <= c {
+                        left
+                    } else if b <= c {
+                        up
+                    } else {
+                        up_left
+                    }
+                }
+                _ => return Err(invalid("a row has an unknown filter")),
+            };
+            current[i] = current[i].wrapping_add(prediction);
+        }
+
+        let max = ((1u32 << depth) - 1) as f64;
+        let sample = |index: usize| -> u32 {
+            match depth {
+                8 => current[index] as u32,
+                16 => u16::from_be_bytes([current[index * 2], current[index * 2 + 1]]) as u32,
+                _ => {
+                    let bit = index * depth;
+                    (current[bit / 8] as u32 >> (8 - depth - bit % 8)) & ((1 << depth) - 1)
+                }
+            }
+        };
+        for x in 0..width {
+            let samples = (0..channels)
+                .map(|channel| sample(x * channels + channel))
+                .collect::<Vec<_>>();
+            let (luminance, alpha) = match color_type {
+                0 => (samples[0] as f64 / max, 1.),
+                4 => (samples[0] as f64 / max, samples[1] as f64 / max),
+                3 => {
+                    let index = samples[0] as usize;
+                    let color = palette
+                        .get(index * 3..index * 3 + 3)
+                        .ok_or(invalid("a pixel is not in the palette"))?;
+                    let alpha = transparency.get(index).map_or(1., |a| *a as f64 / 255.);
+                    (luminance(color.iter().map(|c| *c as f64 / 255.)), alpha)
+                }
+                _ => (
+                    luminance(samples[..3].iter().map(|c| *c as f64 / max)),
+                    if channels == 4 {
+                        samples[3] as f64 / max
+                    } else {
+                        1.
+                    },
+                ),
+            };
+            darkness.push(1. - (luminance * alpha + 1. - alpha));
+        }
+        previous = current;
+    }
+    Ok(Bitmap {
+        width,
+        height,
+        darkness,
+    })
+}
+
+fn luminance(mut rgb: impl Iterator<Item = f64>) -> f64 {
+    let (r, g, b) = (
+        rgb.next().unwrap_or(0.),
+        rgb.next().unwrap_or(0.),
+        rgb.next().unwrap_or(0.),
+    );
+    0.299 * r + 0.587 * g + 0.114 * b
+}
+
+/// Reads the bits of a deflate stream, starting from the least significant bit of each byte
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    bit: usize,
+}
+
+impl BitReader<'_> {
+    fn bits(&mut self, count: usize) -> Result<usize, &'static str> {
+        let mut value = 0;
+        for i in 0..count {
+            let byte = self
+                .bytes
+                .get(self.bit / 8)
+                .ok_or("the image data is cut short")?;
+            value |= ((*byte as usize >> (self.bit % 8)) & 1) << i;
+            self.bit += 1;
+        }
+        Ok(value)
+    }
+}
+
+/// A canonical Huffman code, as the number of codes of each length and the symbols in order
+struct Huffman {
+    counts: [usize; 16],
+    symbols: Vec<usize>,
+}
+
+impl Huffman {
+    fn new(lengths: &[usize]) -> Self {
+        let mut counts = [0; 16];
+        for length in lengths {
+            counts[*length] += 1;
+        }
+        counts[0] = 0;
+        let mut symbols = (0..lengths.len())
+            .filter(|symbol| lengths[*symbol] != 0)
+            .collect::<Vec<_>>();
+        symbols.sort_by_key(|symbol| lengths[*symbol]);
+        Self { counts, symbols }
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> Result<usize, &'static str> {
+        let (mut code, mut first, mut index) = (0, 0, 0);
+        for count in &self.counts[1..] {
+            code |= reader.bits(1)?;
+            if code < first + count {
+                return Ok(self.symbols[index + code - first]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err("the image data has an invalid code")
+    }
+}
+
+/// Decompress a deflate stream ([RFC 1951](https://www.rfc-editor.org/rfc/rfc1951)), stopping
+/// once there are at least `limit` bytes
+fn inflate(compressed: &[u8], limit: usize) -> Result<Vec<u8>, &'static str> {
+    const LENGTH_BASE: [usize; 29] = [
+        3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115,
+        131, 163, 195, 227, 258,
+    ];
+    const LENGTH_EXTRA: [usize; 29] = [
+        0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+    ];
+    const DISTANCE_BASE: [usize; 30] = [
+        1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+        2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+    ];
+    const DISTANCE_EXTRA: [usize; 30] = [
+        0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12,
+        13, 13,
+    ];
+    const CODE_LENGTH_ORDER: [usize; 19] = [
+        16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+    ];
+
+    let mut reader = BitReader {
+        bytes: compressed,
+        bit: 0,
+    };
+    let mut output = vec![];
+    loop {
+        let is_last = reader.bits(1)? == 1;
+        let (literals, distances) = match reader.bits(2)? {
+            // Stored
+            0 => {
+                let start = reader.bit.div_ceil(8);
+                let header = compressed
+                    .get(start..start + 4)
+                    .ok_or("the image data is cut short")?;
+                let length = u16::from_le_bytes([header[0], header[1]]) as usize;
+                let data = compressed
+                    .get(start + 4..start + 4 + length)
+                    .ok_or("the image data is cut short")?;
+                output.extend_from_slice(data);
+                reader.bit = (start + 4 + length) * 8;
+                if is_last || output.len() >= limit {
+                    return Ok(output);
+                }
+                continue;
+            }
+            // Fixed Huffman codes
+            1 => {
+                let mut lengths = [8; 288];
+                lengths[144..256].iter_mut().for_each(|length| *length = 9);
+                lengths[256..280].iter_mut().for_each(|length| *length = 7);
+                (Huffman::new(&lengths), Huffman::new(&[5; 30]))
+            }
+            // Dynamic Huffman codes
+            2 => {
+                let literal_count = reader.bits(5)? + 257;
+                let distance_count = reader.bits(5)? + 1;
+                let code_length_count = reader.bits(4)? + 4;
+                let mut code_lengths = [0; 19];
+                for symbol in &CODE_LENGTH_ORDER[..code_length_count] {
+                    code_lengths[*symbol] = reader.bits(3)?;
+                }
+                let code_lengths = Huffman::new(&code_lengths);
+                let mut lengths = Vec::with_capacity(literal_count + distance_count);
+                while lengths.len() < literal_count + distance_count {
+                    let (length, repeat) = match code_lengths.decode(&mut reader)? {
+                        length @ 0..=15 => (length, 1),
+                        16 => (
+                            *lengths.last().ok_or("the image data has an invalid code")?,
+                            3 + reader.bits(2)?,
+                        ),
+                        17 => (0, 3 + reader.bits(3)?),
+                        _ => (0, 11 + reader.bits(7)?),
+                    };
+                    lengths.extend(std::iter::repeat_n(length, repeat));
+                }
+                if lengths.len() > literal_count + distance_count {
+                    return Err("the image data has an invalid code");
+                }
+                (
+                    Huffman::new(&lengths[..literal_count]),
+                    Huffman::new(&lengths[literal_count..]),
+                )
+            }
+            _ => return Err("the image data has an invalid block"),
+        };
+
+        loop {
+            let symbol = literals.decode(&mut reader)?;
+            match symbol {
+                0..=255 => output.push(symbol as u8),
+                256 => break,
+                _ => {
+                    let i = symbol - 257;
+                    if i >= LENGTH_BASE.len() {
+                        return Err("the image data has an invalid length");
+                    }
+                    let length = LENGTH_BASE[i] + reader.bits(LENGTH_EXTRA[i])?;
+                    let i = distances.decode(&mut reader)?;
+                    if i >= DISTANCE_BASE.len() {
+                        return Err("the image data has an invalid distance");
+                    }
+                    let distance = DISTANCE_BASE[i] + reader.bits(DISTANCE_EXTRA[i])?;
+                    if distance > output.len() {
+                        return Err("the image data has an invalid distance");
+                    }
+                    for _ in 0..length {
+                        output.push(output[output.len() - distance]);
+                    }
+                }
+            }
+            if output.len() >= limit {
+                return Ok(output);
+            }
+        }
+        if is_last {
+            return Ok(output);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    /// A PNG of one IDAT chunk, with its scan lines in a stored deflate block. The checksums
+    /// are left as zeros, since they aren't checked.
+    fn png(
+        width: u32,
+        height: u32,
+        depth: u8,
+        color_type: u8,
+        chunks: &[(&[u8; 4], &[u8])],
+        raw: &[u8],
+    ) -> Vec<u8> {
+        let mut header = vec![];
+        header.extend_from_slice(&width.to_be_bytes());
+        header.extend_from_slice(&height.to_be_bytes());
+        header.extend_from_slice(&[depth, color_type, 0, 0, 0]);
+        let mut zlib = vec![0x78, 0x01, 1];
+        zlib.extend_from_slice(&(raw.len() as u16).to_le_bytes());
+        zlib.extend_from_slice(&(!(raw.len() as u16)).to_le_bytes());
+        zlib.extend_from_slice(raw);
+        zlib.extend_from_slice(&[0; 4]);
+
+        let mut png = PNG_SIGNATURE.to_vec();
+        let mut all_chunks = vec![(b"IHDR", header.as_slice())];
+        all_chunks.extend_from_slice(chunks);
+        all_chunks.extend_from_slice(&[(b"IDAT", zlib.as_slice()), (b"IEND", &[])]);
+        for (kind, data) in all_chunks {
+            png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            png.extend_from_slice(kind);
+            png.extend_from_slice(data);
+            png.extend_from_slice(&[0; 4]);
+        }
+        png
+    }
+
+    fn brightness(bitmap: &Bitmap) -> Vec<u8> {
+        bitmap
+            .darkness
+            .iter()
+            .map(|darkness| ((1. - darkness) * 255.).round() as u8)
+            .collect()
+    }
+
+    #[test]
+    fn inflate_decodes_each_kind_of_block() {
+        // Stored, then fixed Huffman codes
+        let mut compressed = vec![0, 3, 0, !3, !0, b'x', b'y', b'z'];
+        compressed.extend_from_slice(&[0x4B, 0x4C, 0x4A, 0x4E, 0x84, 0x21, 0x00]);
+        assert_eq!(
+            inflate(&compressed, usize::MAX).unwrap(),
+            b"xyzabcabcabcabc"
+        );
+
+        // Dynamic Huffman codes, as zlib writes them
+        let dynamic = [
+            0x25, 0xCA, 0xCB, 0x0D, 0x80, 0x30, 0x0C, 0x03, 0xD0, 0x55, 0x3C, 0x00, 0x4B, 0xB9,
+            0x10, 0x21, 0xA4, 0x92, 0x56, 0x24, 0x3D, 0xA4, 0xD3, 0x13, 0xCA, 0xCD, 0x9F, 0x47,
+            0x54, 0xCE, 0xC0, 0xD1, 0x4E, 0x28, 0xBB, 0xE1, 0x52, 0x10, 0x36, 0x54, 0x03, 0xD6,
+            0x9B, 0x6F, 0xD9, 0x16, 0xD8, 0xE9, 0x3F, 0x68, 0x1F, 0xB8, 0xB9, 0x9E, 0x32, 0x2C,
+            0x50, 0x44, 0x32, 0xCC, 0x29, 0x86, 0x12, 0x39, 0x3A, 0x6B, 0x85, 0x3F, 0x22, 0x2F,
+        ];
+        assert_eq!(
+            String::from_utf8(inflate(&dynamic, usize::MAX).unwrap()).unwrap(),
+            "a lazy dog naps in a sunny spot, a lazy cat naps on a mat, a busy bee buzzes by a tall tree"
+        );
+
+        for length in 0..dynamic.len() {
+            assert!(
+                inflate(&dynamic[..length], usize::MAX).is_err(),
+                "{}",
+                length
+            );
+        }
+        // Stops early instead of expanding without bound
+        assert_eq!(inflate(&dynamic, 10).unwrap().len(), 10);
+    }
+
+    #[test]
+    fn png_filters_predict_from_neighbouring_bytes() {
+        let filtered: [[u8; 8]; 4] = [
+            [1, 10, 190, 86, 1, 40, 10, 205],
+            [2, 10, 200, 30, 2, 30, 106, 225],
+            [3, 10, 195, 186, 3, 35, 186, 215],
+            [4, 10, 190, 86, 4, 30, 106, 225],
+        ];
+        for raw in filtered {
+            let bitmap = decode_png(&png(3, 2, 8, 0, &[], &raw)).unwrap();
+            assert_eq!((bitmap.width, bitmap.height), (3, 2));
+            assert_eq!(
+                brightness(&bitmap),
+                [10, 200, 30, 40, 50, 255],
+                "filter {}",
+                raw[0]
+            );
+        }
+        assert!(matches!(
+            decode_png(&png(3, 1, 8, 0, &[], &[5, 0, 0, 0])),
+            Err(RasterError::InvalidPng(_))
+        ));
+    }
+
+    #[test]
+    fn palette_images_look_up_colors_and_transparency() {
+        let palette = [0, 0, 0, 128, 128, 128, 255, 0, 0];
+        // 2 bits per pixel: black, transparent gray, red, transparent gray
+        let image = png(
+            4,
+            1,
+            2,
+            3,
+            &[(b"PLTE", &palette), (b"tRNS", &[255, 0])],
+            &[0, 0b00_01_10_01],
+        );
+        let bitmap = decode_png(&image).unwrap();
+        let expected = [1., 0., 1. - 0.299, 0.];
+        for (darkness, expected) in bitmap.darkness.iter().zip(expected) {
+            assert!((darkness - expected).abs() < 1e-9, "{:?}", bitmap.darkness);
+        }
+
+        let outside = png(4, 1, 2, 3, &[(b"PLTE", &palette)], &[0, 0b11_00_00_00]);
+        assert!(matches!(
+            decode_png(&outside),
+            Err(RasterError::InvalidPng("a pixel is not in the palette"))
+        ));
+    }
+
+    #[test]
+    fn truncated_and_oversized_pngs_are_errors() {
+        let image = png(3, 2, 8, 0, &[], &[0, 1, 2, 3, 0, 4, 5, 6]);
+        assert!(decode_png(&image).is_ok());
+        // Everything up to the checksum of the IDAT chunk is needed
+        for length in 0..image.len() - 16 {
+            assert!(decode_png(&image[..length]).is_err(), "{}", length);
+        }
+
+        assert!(matches!(
+            decode_png(&png(100_000, 100_000, 8, 0, &[], &[])),
+            Err(RasterError::InvalidPng("the image is too large"))
+        ));
+        assert!(matches!(
+            decode_png(&png(u32::MAX, u32::MAX, 16, 6, &[], &[])),
+            Err(RasterError::InvalidPng("the image is too large"))
+        ));
+        assert!(matches!(
+            decode_png(&png(0, 1, 8, 0, &[], &[])),
+            Err(RasterError::InvalidPng("the image is empty"))
+        ));
+    }
+}
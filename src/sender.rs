@@ -0,0 +1,107 @@
+use std::fs::OpenOptions;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+
+/// What a controller answered to a line
+enum Response {
+    Ok,
+    /// The line must be sent again, starting from this line number
+    Resend(usize),
+    Error(String),
+}
+
+/// Stream a program to a controller, waiting for it to acknowledge each line before sending the
+/// next, which is the simple send-response flow control understood by Grbl and Marlin.
+///
+/// The port is opened as a file, so its baud rate must already be set, i.e. with
+/// `stty -F /dev/ttyUSB0 115200 raw`. With checksums, lines are numbered and checksummed as
+/// Marlin expects, and lines the controller asks for again are resent.
+pub fn send(program: &str, port: &Path, checksums: bool) -> io::Result<()> {
+    let mut writer = OpenOptions::new().read(true).write(true).open(port)?;
+    let mut reader = BufReader::new(writer.try_clone()?);
+
+    // Comments take up space in the controller's buffer for nothing
+    let lines = program
+        .lines()
+        .map(|line| line.split(';').next().unwrap_or_default().trim())
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>();
+
+    if checksums {
+        // Restart line numbering
+        writeln!(writer, "{}", numbered(0, "M110 N0"))?;
+        if let Response::Error(message) = response(&mut reader)? {
+            return Err(io::Error::other(format!(
+                "controller could not reset line numbers: {}",
+                message
+            )));
+        }
+    }
+
+    let mut i = 0;
+    while i < lines.len() {
+        if checksums {
+            writeln!(writer, "{}", numbered(i + 1, lines[i]))?;
+        } else {
+            writeln!(writer, "{}", lines[i])?;
+        }
+        writer.flush()?;
+        match response(&mut reader)? {
+            Response::Ok => i += 1,
+            Response::Resend(number) if checksums && (1..=i + 1).contains(&number) => {
+                warn!("Resending from line {}", number);
+                i = number - 1;
+            }
+            Response::Resend(number) => {
+                return Err(io::Error::other(format!(
+                    "controller asked to resend line {}, which wasn't sent",
+                    number
+                )))
+            }
+            Response::Error(message) => {
+                return Err(io::Error::other(format!(
+                    "controller rejected line {} ({}): {}",
+                    i + 1,
+                    lines[i],
+                    message
+                )))
+            }
+        }
+    }
+    info!("Sent {} lines", lines.len());
+    Ok(())
+}
+
+/// Number a line and append its checksum, the XOR of its bytes
+fn numbered(number: usize, line: &str) -> String {
+    let line = format!("N{} {}", number, line);
+    let checksum = line.bytes().fold(0u8, |checksum, byte| checksum ^ byte);
+    format!("{}*{}", line, checksum)
+}
+
+/// Read until the controller acknowledges or rejects a line, logging anything else it reports
+fn response(reader: &mut impl BufRead) -> io::Result<Response> {
+    let mut resend = None;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let line = line.trim();
+        let lowercase = line.to_ascii_lowercase();
+        if lowercase.starts_with("ok") {
+            // Marlin asks to resend a line and then acknowledges it
+            return Ok(resend.map_or(Response::Ok, Response::Resend));
+        } else if lowercase.starts_with("error") || lowercase.starts_with("alarm") {
+            return Ok(Response::Error(line.to_string()));
+        } else if let Some(number) = lowercase
+            .strip_prefix("resend:")
+            .or_else(|| lowercase.strip_prefix("rs"))
+        {
+            resend = number.trim().trim_start_matches('n').parse().ok();
+        } else if !line.is_empty() {
+            info!("{}", line);
+        }
+    }
+}
@@ -0,0 +1,176 @@
+use g_code::emit::Token;
+use svgtypes::PathSegment;
+
+use crate::converter::{paths2program, ConversionError, ProgramOptions};
+use crate::turtle::Turtle;
+
+/// A basic shape, in millimeters with the Y axis pointing up
+#[derive(Debug, Clone, PartialEq)]
+pub enum Shape {
+    /// A rectangle from its bottom left corner, with corners rounded by rx and ry if they are
+    /// more than zero
+    Rect {
+        x: f64,
+        y: f64,
+        width: f64,
+        height: f64,
+        rx: f64,
+        ry: f64,
+    },
+    Circle {
+        cx: f64,
+        cy: f64,
+        r: f64,
+    },
+    Ellipse {
+        cx: f64,
+        cy: f64,
+        rx: f64,
+        ry: f64,
+    },
+    Line {
+        x1: f64,
+        y1: f64,
+        x2: f64,
+        y2: f64,
+    },
+    /// Open lines through the points
+    Polyline(Vec<(f64, f64)>),
+    /// Lines through the points that return to the first one
+    Polygon(Vec<(f64, f64)>),
+}
+
+impl Shape {
+    /// The path equivalent to the shape, as defined for the basic shapes of SVG:
+    /// [SVG 2 §10](https://www.w3.org/TR/SVG/shapes.html)
+    pub fn to_path(&self) -> Vec<PathSegment> {
+        use PathSegment::*;
+        let move_to = |x, y| MoveTo { abs: true, x, y };
+        let line_to = |x, y| LineTo { abs: true, x, y };
+        let arc_to = |rx, ry, x, y| EllipticalArc {
+            abs: true,
+            rx,
+            ry,
+            x_axis_rotation: 0.,
+            large_arc: false,
+            sweep: true,
+            x,
+            y,
+        };
+        match self {
+            Self::Rect {
+                x,
+                y,
+                width,
+                height,
+                rx,
+                ry,
+            } => {
+                // Radii are limited to half of each side
+                let rx = rx.clamp(0., width / 2.);
+                let ry = ry.clamp(0., height / 2.);
+                let (right, top) = (x + width, y + height);
+                let mut path = vec![move_to(x + rx, *y), line_to(right - rx, *y)];
+                let corner = |path: &mut Vec<PathSegment>, x, y| {
+                    if rx > 0. && ry > 0. {
+                        path.push(arc_to(rx, ry, x, y));
+                    }
+                };
+                corner(&mut path, right, y + ry);
+                path.push(line_to(right, top - ry));
+                corner(&mut path, right - rx, top);
+                path.push(line_to(x + rx, top));
+                corner(&mut path, *x, top - ry);
+                path.push(line_to(*x, y + ry));
+                corner(&mut path, x + rx, *y);
+                path.push(ClosePath { abs: true });
+                path
+            }
+            Self::Circle { cx, cy, r } => Self::Ellipse {
+                cx: *cx,
+                cy: *cy,
+                rx: *r,
+                ry: *r,
+            }
+            .to_path(),
+            Self::Ellipse { cx, cy, rx, ry } => vec![
+                move_to(cx + rx, *cy),
+                arc_to(*rx, *ry, *cx, cy + ry),
+                arc_to(*rx, *ry, cx - rx, *cy),
+                arc_to(*rx, *ry, *cx, cy - ry),
+                arc_to(*rx, *ry, cx + rx, *cy),
+                ClosePath { abs: true },
+            ],
+            Self::Line { x1, y1, x2, y2 } => vec![move_to(*x1, *y1), line_to(*x2, *y2)],
+            Self::Polyline(points) | Self::Polygon(points) => {
+                let mut path = points
+                    .iter()
+                    .enumerate()
+                    .map(|(i, (x, y))| match i {
+                        0 => move_to(*x, *y),
+                        _ => line_to(*x, *y),
+                    })
+                    .collect::<Vec<_>>();
+                if matches!(self, Self::Polygon(_)) && !path.is_empty() {
+                    path.push(ClosePath { abs: true });
+                }
+                path
+            }
+        }
+    }
+
+    /// Name of the shape, like the tag name of its SVG element
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Rect { .. } => "rect",
+            Self::Circle { .. } => "circle",
+            Self::Ellipse { .. } => "ellipse",
+            Self::Line { .. } => "line",
+            Self::Polyline(_) => "polyline",
+            Self::Polygon(_) => "polygon",
+        }
+    }
+
+    /// Convert the shape into a whole program, without composing an SVG for it
+    pub fn to_program<'input>(
+        &self,
+        options: &ProgramOptions,
+        turtle: &mut Turtle<'input>,
+    ) -> Result<Vec<Token<'input>>, ConversionError> {
+        paths2program(
+            std::iter::once((self.name().to_string(), self.to_path())),
+            options,
+            turtle,
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::machine::Machine;
+    use crate::writer::tokens_into_gcode_bytes;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn shapes_produce_expected_gcode() {
+        let mut turtle = Turtle::new(Machine::new(None, None, None, None));
+        let program = Shape::Rect {
+            x: 0.,
+            y: 0.,
+            width: 10.,
+            height: 5.,
+            rx: 0.,
+            ry: 0.,
+        }
+        .to_program(&ProgramOptions::default(), &mut turtle)
+        .unwrap();
+
+        let mut actual = vec![];
+        assert!(tokens_into_gcode_bytes(&program, &mut actual).is_ok());
+        assert_eq!(
+            String::from_utf8(actual).unwrap(),
+            "G21\nG90;rect\nG0 X0 Y0\nG1 X10 Y0 F300\nG1 X10 Y5 F300\nG1 X0 Y5 F300\nG1 X0 Y0 F300\nM2\n"
+        );
+    }
+}
@@ -0,0 +1,237 @@
+use std::fmt;
+
+use euclid::default::Box2D;
+use g_code::emit::{Field, Token};
+
+use crate::machine::Tool;
+use crate::postprocess::{commands, is_sequence_at};
+
+/// What the machine knows after running a command of a program. Positions are unknown until a
+/// move sets them, and the tool state until it is turned on or off.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct MachineState {
+    /// X, Y and Z in work coordinates
+    pub position: [Option<f64>; 3],
+    pub tool: Option<Tool>,
+    /// Feedrate in units per minute of the most recent move that set one
+    pub feedrate: Option<f64>,
+    pub is_relative: bool,
+}
+
+/// Run a program command by command, returning the state of the machine after each one along with
+/// the command. The tool is turned on and off by the given sequences.
+pub fn simulate<'input>(
+    tokens: &[Token<'input>],
+    tool_on: &[Token<'input>],
+    tool_off: &[Token<'input>],
+) -> Vec<(Vec<Token<'input>>, MachineState)> {
+    let program = commands(tokens.to_vec());
+    let tool_on = commands(tool_on.to_vec());
+    let tool_off = commands(tool_off.to_vec());
+    let mut state = MachineState::default();
+    let mut states = Vec::with_capacity(program.len());
+    let mut in_machine_coordinates = false;
+    for (i, command) in program.iter().enumerate() {
+        if !tool_on.is_empty() && is_sequence_at(&program, i, &tool_on) {
+            state.tool = Some(Tool::On);
+        } else if !tool_off.is_empty() && is_sequence_at(&program, i, &tool_off) {
+            state.tool = Some(Tool::Off);
+        }
+        step(&mut state, command);
+        // G53 applies to the move that follows it, whose position isn't in work coordinates
+        if std::mem::take(&mut in_machine_coordinates) {
+            state.position = [None; 3];
+        }
+        in_machine_coordinates = matches!(
+            command.first(),
+            Some(Token::Field(Field { letters, value })) if *letters == "G" && value.as_f64() == Some(53.)
+        );
+        states.push((command.clone(), state));
+    }
+    states
+}
+
+fn step(state: &mut MachineState, command: &[Token]) {
+    let code = match command.first() {
+        Some(Token::Field(Field { letters, value })) if *letters == "G" => value.as_f64(),
+        _ => return,
+    };
+    let args = command[1..].iter().filter_map(|token| match token {
+        Token::Field(Field { letters, value }) => value.as_f64().map(|value| (letters, value)),
+        _ => None,
+    });
+    match code {
+        Some(code) if code == 0. || code == 1. => {
+            for (letters, value) in args {
+                let axis = match letters.as_ref() {
+                    "X" => 0,
+                    "Y" => 1,
+                    "Z" => 2,
+                    "F" => {
+                        state.feedrate = Some(value);
+                        continue;
+                    }
+                    _ => continue,
+                };
+                state.position[axis] = if state.is_relative {
+                    state.position[axis].map(|position| position + value)
+                } else {
+                    Some(value)
+                };
+            }
+        }
+        Some(90.) => state.is_relative = false,
+        Some(91.) => state.is_relative = true,
+        // Declares the current position without moving
+        Some(92.) => {
+            for (letters, value) in args {
+                match letters.as_ref() {
+                    "X" => state.position[0] = Some(value),
+                    "Y" => state.position[1] = Some(value),
+                    "Z" => state.position[2] = Some(value),
+                    _ => {}
+                }
+            }
+        }
+        // Moves in machine coordinates or to stored positions, which aren't known here
+        Some(code) if code == 28. || code == 30. || code == 53. => {
+            state.position = [None; 3];
+        }
+        _ => {}
+    }
+}
+
+/// Limits that a program must stay within
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Limits {
+    /// Lowest Z that the tool can move to
+    pub min_z: Option<f64>,
+    /// Area that the tool can move in
+    pub bounds: Option<Box2D<f64>>,
+}
+
+/// A command of a program that breaks an invariant
+#[derive(Debug, Clone, PartialEq)]
+pub enum Violation {
+    /// A rapid move with the tool on, which drags it across the work
+    RapidWithToolOn {
+        command: usize,
+        source: Option<String>,
+    },
+    /// A move below [Limits::min_z]
+    BelowMinZ { command: usize, z: f64 },
+    /// A move outside of [Limits::bounds]
+    OutOfBounds { command: usize, x: f64, y: f64 },
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::RapidWithToolOn { command, source } => write!(
+                f,
+                "command {} moves rapidly with the tool on, in {}",
+                command,
+                source.as_deref().unwrap_or("an unnamed path").trim()
+            ),
+            Self::BelowMinZ { command, z } => {
+                write!(f, "command {} moves below the lowest Z, to {}", command, z)
+            }
+            Self::OutOfBounds { command, x, y } => write!(
+                f,
+                "command {} moves outside of the bed, to ({}, {})",
+                command, x, y
+            ),
+        }
+    }
+}
+
+/// Check that a program never moves rapidly with the tool on and stays within limits.
+///
+/// Rapids are allowed with the tool on if the tool is never turned off, as in dynamic laser power
+/// (`M4`) where the controller turns the laser off during rapids.
+pub fn verify(
+    tokens: &[Token<'_>],
+    tool_on: &[Token<'_>],
+    tool_off: &[Token<'_>],
+    limits: &Limits,
+) -> Vec<Violation> {
+    let mut violations = vec![];
+    let mut source = None;
+    let states = simulate(tokens, tool_on, tool_off);
+    let checks_tool = !tool_off.is_empty();
+    for (i, (command, state)) in states.iter().enumerate() {
+        let code = match command.first() {
+            Some(Token::Comment {
+                is_inline: false,
+                inner,
+            }) => {
+                source = Some(inner.to_string());
+                continue;
+            }
+            Some(Token::Field(Field { letters, value })) if *letters == "G" => value.as_f64(),
+            _ => continue,
+        };
+        if code != Some(0.) && code != Some(1.) {
+            continue;
+        }
+        if checks_tool && code == Some(0.) && state.tool == Some(Tool::On) {
+            violations.push(Violation::RapidWithToolOn {
+                command: i,
+                source: source.clone(),
+            });
+        }
+        if let (Some(min_z), Some(z)) = (limits.min_z, state.position[2]) {
+            if z < min_z {
+                violations.push(Violation::BelowMinZ { command: i, z });
+            }
+        }
+        if let (Some(bounds), [Some(x), Some(y), _]) = (limits.bounds, state.position) {
+            if x < bounds.min.x || x > bounds.max.x || y < bounds.min.y || y > bounds.max.y {
+                violations.push(Violation::OutOfBounds { command: i, x, y });
+            }
+        }
+    }
+    violations
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::converter::{self, ProgramOptions};
+    use crate::machine::{Dialect, MachineBuilder};
+    use crate::turtle::Turtle;
+
+    #[test]
+    fn verify_finds_rapids_with_the_tool_on() {
+        let document = roxmltree::Document::parse(include_str!("../tests/square.svg")).unwrap();
+        let machine = MachineBuilder::default()
+            .dialect(Dialect::Grbl)
+            .build()
+            .unwrap();
+        let mut turtle = Turtle::new(machine);
+        let mut program =
+            converter::svg2program(&document, ProgramOptions::default(), &mut turtle).unwrap();
+        let (tool_on, tool_off) = (
+            turtle.machine.tool_on_sequence(),
+            turtle.machine.tool_off_sequence(),
+        );
+        let limits = Limits::default();
+        assert!(verify(&program, &tool_on, &tool_off, &limits).is_empty());
+
+        // Turning the tool off between the paths is the second tool off, after the one at the start
+        let tool_off_index = program
+            .iter()
+            .enumerate()
+            .filter(|(_, token)| **token == tool_off[0])
+            .map(|(i, _)| i)
+            .nth(1)
+            .unwrap();
+        program.remove(tool_off_index);
+        let violations = verify(&program, &tool_on, &tool_off, &limits);
+        assert!(matches!(
+            violations.as_slice(),
+            [Violation::RapidWithToolOn { source: Some(source), .. }]
+                if source.contains("path832")
+        ));
+    }
+}
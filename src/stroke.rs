@@ -0,0 +1,303 @@
+use std::borrow::Cow;
+
+use g_code::{command, emit::Token};
+use lyon_geom::{vector, Point, Vector};
+
+use crate::postprocess::{
+    closed_polygon, cut_length, linear_interpolation_tokens, map_cuts, redirect_last_rapid,
+    split_cut,
+};
+
+type F64Point = Point<f64>;
+
+/// Shape at the ends of an open stroke: [SVG 2 §13.5.3](https://www.w3.org/TR/SVG/painting.html#LineCaps)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineCap {
+    Butt,
+    Round,
+    Square,
+}
+
+/// Shape at the corners of a stroke: [SVG 2 §13.5.4](https://www.w3.org/TR/SVG/painting.html#LineJoin)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineJoin {
+    Miter,
+    Round,
+    Bevel,
+}
+
+/// The geometry of a stroke, in millimeters
+#[derive(Debug, Clone, Copy)]
+pub struct Stroke {
+    pub width: f64,
+    pub line_cap: LineCap,
+    pub line_join: LineJoin,
+    /// Miters longer than this many stroke widths become bevels
+    pub miter_limit: f64,
+}
+
+/// Replace each cut with the outline of its stroke.
+///
+/// Open cuts become a single closed outline. Closed cuts have no caps, so both of their sides
+/// are cut, traveling between them with the tool off.
+pub fn outline_strokes<'input>(
+    tokens: Vec<Token<'input>>,
+    stroke: &Stroke,
+    tolerance: f64,
+    tool_on: &[Token<'input>],
+    tool_off: &[Token<'input>],
+) -> Vec<Token<'input>> {
+    map_cuts(tokens, |preceding, from, moves| {
+        let args = moves[0].args.clone();
+        let (mut points, is_closed) = match closed_polygon(from, &moves) {
+            Some(polygon) => (polygon, true),
+            None => (
+                std::iter::once(from)
+                    .chain(moves.iter().map(|m| m.to))
+                    .collect(),
+                false,
+            ),
+        };
+        points.dedup();
+        if is_closed && points.len() > 1 && points.first() == points.last() {
+            points.pop();
+        }
+        if points.len() < 2 {
+            return moves
+                .iter()
+                .flat_map(|m| linear_interpolation_tokens(m.to, &m.args))
+                .collect();
+        }
+
+        let loops = outline(&points, is_closed, stroke, tolerance);
+        let mut tokens = vec![];
+        for (i, outline) in loops.iter().enumerate() {
+            if i == 0 {
+                redirect_last_rapid(preceding, outline[0]);
+            } else {
+                tokens.extend(tool_off.iter().cloned());
+                tokens.append(
+                    &mut command!(RapidPositioning {
+                        X: outline[0].x,
+                        Y: outline[0].y,
+                    })
+                    .into_token_vec(),
+                );
+                tokens.extend(tool_on.iter().cloned());
+            }
+            for point in outline.iter().skip(1).chain(std::iter::once(&outline[0])) {
+                tokens.append(&mut linear_interpolation_tokens(*point, &args));
+            }
+        }
+        tokens
+    })
+}
+
+/// Split each cut into the dashes of a dash pattern, traveling over the gaps between them with the
+/// tool off: [SVG 2 §13.5.5](https://www.w3.org/TR/SVG/painting.html#StrokeDashing)
+///
+/// The pattern alternates between dash and gap lengths, starting `offset` into it, and restarts at
+/// every cut like it does for every subpath.
+pub fn dash<'input>(
+    tokens: Vec<Token<'input>>,
+    pattern: &[f64],
+    offset: f64,
+    tool_on: &[Token<'input>],
+    tool_off: &[Token<'input>],
+) -> Vec<Token<'input>> {
+    // An odd number of lengths is repeated to make an even one
+    let pattern = if pattern.len() % 2 == 1 {
+        pattern.repeat(2)
+    } else {
+        pattern.to_vec()
+    };
+    let period: f64 = pattern.iter().sum();
+    let is_dash = |distance: f64| {
+        let mut into_pattern = (distance + offset).rem_euclid(period);
+        for (i, length) in pattern.iter().enumerate() {
+            if into_pattern < *length {
+                return i % 2 == 0;
+            }
+            into_pattern -= length;
+        }
+        false
+    };
+
+    map_cuts(tokens, |preceding, from, moves| {
+        let total_length = cut_length(from, &moves);
+        let mut breakpoints = vec![];
+        let mut distance = -offset.rem_euclid(period);
+        for length in pattern.iter().cycle() {
+            distance += length;
+            if distance >= total_length {
+                break;
+            }
+            breakpoints.push(distance);
+        }
+
+        let mut tokens = vec![];
+        let mut is_first_dash = true;
+        let mut is_in_gap = false;
+        let mut position = from;
+        for piece in split_cut(from, &moves, breakpoints) {
+            if !is_dash((piece.start + piece.end) / 2.) {
+                if !is_in_gap && !is_first_dash {
+                    tokens.extend(tool_off.iter().cloned());
+                }
+                is_in_gap = true;
+            } else if is_in_gap {
+                if is_first_dash {
+                    redirect_last_rapid(preceding, position);
+                } else {
+                    tokens.append(
+                        &mut command!(RapidPositioning {
+                            X: position.x,
+                            Y: position.y,
+                        })
+                        .into_token_vec(),
+                    );
+                    tokens.extend(tool_on.iter().cloned());
+                }
+                is_in_gap = false;
+            }
+            if !is_in_gap {
+                tokens.append(&mut linear_interpolation_tokens(piece.to, piece.args));
+                is_first_dash = false;
+            }
+            position = piece.to;
+        }
+        if is_first_dash {
+            // Nothing but a gap, the tool was turned on for nothing
+            tokens.extend(tool_off.iter().cloned());
+        }
+        tokens
+    })
+}
+
+/// Closed outlines of the stroke of a polyline.
+/// Closed polylines should not repeat their first point at the end.
+fn outline(
+    points: &[F64Point],
+    is_closed: bool,
+    stroke: &Stroke,
+    tolerance: f64,
+) -> Vec<Vec<F64Point>> {
+    let reversed = points.iter().rev().copied().collect::<Vec<_>>();
+    if is_closed {
+        vec![
+            side(points, true, stroke, tolerance),
+            side(&reversed, true, stroke, tolerance),
+        ]
+    } else {
+        let mut outline = side(points, false, stroke, tolerance);
+        let end_direction = (points[points.len() - 1] - points[points.len() - 2]).normalize();
+        outline.append(&mut cap(
+            points[points.len() - 1],
+            end_direction,
+            stroke,
+            tolerance,
+        ));
+        outline.append(&mut side(&reversed, false, stroke, tolerance));
+        let start_direction = (points[0] - points[1]).normalize();
+        outline.append(&mut cap(points[0], start_direction, stroke, tolerance));
+        vec![outline]
+    }
+}
+
+/// Left side of a polyline stroke, with joins at the corners
+fn side(points: &[F64Point], is_closed: bool, stroke: &Stroke, tolerance: f64) -> Vec<F64Point> {
+    let half_width = stroke.width / 2.;
+    let count = points.len();
+    let direction = |i: usize| (points[(i + 1) % count] - points[i]).normalize();
+
+    let mut side = vec![];
+    if !is_closed {
+        side.push(points[0] + left_normal(direction(0)) * half_width);
+    }
+    let corners = if is_closed { 0..count } else { 1..count - 1 };
+    for i in corners {
+        let incoming = direction((i + count - 1) % count);
+        let outgoing = direction(i);
+        side.append(&mut join(points[i], incoming, outgoing, stroke, tolerance));
+    }
+    if !is_closed {
+        side.push(points[count - 1] + left_normal(direction(count - 2)) * half_width);
+    }
+    side
+}
+
+/// Points on the left side of a stroke at a corner between two unit directions
+fn join(
+    corner: F64Point,
+    incoming: Vector<f64>,
+    outgoing: Vector<f64>,
+    stroke: &Stroke,
+    tolerance: f64,
+) -> Vec<F64Point> {
+    let half_width = stroke.width / 2.;
+    let incoming_normal = left_normal(incoming);
+    let outgoing_normal = left_normal(outgoing);
+    let miter = (incoming_normal + outgoing_normal).normalize();
+    let cos_half_angle = miter.dot(outgoing_normal);
+    let miter_point = corner + miter * (half_width / cos_half_angle);
+
+    // Turning left puts the left side on the inside of the corner, where the offsets overlap
+    let is_inside = incoming.cross(outgoing) > 0.;
+    if is_inside && cos_half_angle > 1. / stroke.miter_limit {
+        return vec![miter_point];
+    }
+
+    let bevel = vec![
+        corner + incoming_normal * half_width,
+        corner + outgoing_normal * half_width,
+    ];
+    if is_inside {
+        return bevel;
+    }
+    match stroke.line_join {
+        LineJoin::Miter if 1. / cos_half_angle <= stroke.miter_limit => vec![miter_point],
+        LineJoin::Miter | LineJoin::Bevel => bevel,
+        LineJoin::Round => {
+            let sweep = incoming_normal.angle_to(outgoing_normal).radians;
+            arc(corner, incoming_normal * half_width, sweep, tolerance)
+        }
+    }
+}
+
+/// Points going around the end of an open stroke, from its left side to its right side
+fn cap(end: F64Point, direction: Vector<f64>, stroke: &Stroke, tolerance: f64) -> Vec<F64Point> {
+    let half_width = stroke.width / 2.;
+    let normal = left_normal(direction);
+    match stroke.line_cap {
+        LineCap::Butt => vec![],
+        LineCap::Square => vec![
+            end + (normal + direction) * half_width,
+            end + (direction - normal) * half_width,
+        ],
+        LineCap::Round => {
+            let mut arc = arc(end, normal * half_width, -std::f64::consts::PI, tolerance);
+            // The ends of the arc are already on the sides
+            arc.pop();
+            arc.remove(0);
+            arc
+        }
+    }
+}
+
+/// Flatten a circular arc around a center, starting at an offset from it
+fn arc(center: F64Point, start: Vector<f64>, sweep: f64, tolerance: f64) -> Vec<F64Point> {
+    let radius = start.length();
+    let max_step = 2. * (1. - tolerance.min(radius) / radius).acos();
+    let steps = ((sweep.abs() / max_step).ceil() as usize).max(1);
+    (0..=steps)
+        .map(|i| {
+            let angle = sweep * i as f64 / steps as f64;
+            let (sin, cos) = angle.sin_cos();
+            center + vector(start.x * cos - start.y * sin, start.x * sin + start.y * cos)
+        })
+        .collect()
+}
+
+fn left_normal(direction: Vector<f64>) -> Vector<f64> {
+    vector(-direction.y, direction.x)
+}
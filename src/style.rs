@@ -0,0 +1,265 @@
+use roxmltree::{Document, Node};
+
+/// Properties that elements take from their parent when they don't specify them
+const INHERITED_PROPERTIES: [&str; 10] = [
+    "fill",
+    "stroke",
+    "stroke-dasharray",
+    "stroke-dashoffset",
+    "stroke-width",
+    "stroke-linecap",
+    "stroke-linejoin",
+    "stroke-miterlimit",
+    "visibility",
+    "color",
+];
+
+/// The style sheets of an SVG, from its `<style>` elements.
+///
+/// Only the selectors found in exported drawings are supported: type, class and id selectors and
+/// combinations of them, like `path.cls-1`. Rules with other selectors are ignored.
+#[derive(Debug, Default)]
+pub struct StyleSheet {
+    rules: Vec<Rule>,
+}
+
+#[derive(Debug)]
+struct Rule {
+    selector: Selector,
+    declarations: Vec<(String, String)>,
+}
+
+#[derive(Debug)]
+struct Selector {
+    tag: Option<String>,
+    id: Option<String>,
+    classes: Vec<String>,
+}
+
+impl Selector {
+    fn parse(selector: &str) -> Option<Self> {
+        let mut parsed = Self {
+            tag: None,
+            id: None,
+            classes: vec![],
+        };
+        let is_name_char = |c: char| c.is_alphanumeric() || c == '-' || c == '_';
+        let mut rest = selector.trim();
+        if rest.is_empty() {
+            return None;
+        }
+        while !rest.is_empty() {
+            let (prefix, body) = match rest.chars().next() {
+                Some(c @ ('#' | '.')) => (Some(c), &rest[1..]),
+                Some('*') if parsed.tag.is_none() => {
+                    rest = &rest[1..];
+                    continue;
+                }
+                _ => (None, rest),
+            };
+            let end = body.find(|c| !is_name_char(c)).unwrap_or(body.len());
+            if end == 0 {
+                // Combinators, pseudo-classes, attribute selectors, etc.
+                return None;
+            }
+            let name = body[..end].to_string();
+            match prefix {
+                Some('#') => parsed.id = Some(name),
+                Some(_) => parsed.classes.push(name),
+                None if parsed.tag.is_none() => parsed.tag = Some(name),
+                None => return None,
+            }
+            rest = &body[end..];
+        }
+        Some(parsed)
+    }
+
+    fn matches(&self, node: &Node) -> bool {
+        self.tag
+            .as_ref()
+            .is_none_or(|tag| node.tag_name().name() == tag)
+            && self
+                .id
+                .as_ref()
+                .is_none_or(|id| node.attribute("id") == Some(id))
+            && self.classes.iter().all(|class| {
+                node.attribute("class")
+                    .is_some_and(|classes| classes.split_whitespace().any(|c| c == class))
+            })
+    }
+
+    /// How much precedence a rule with this selector has, as counts of ids, classes and types
+    fn specificity(&self) -> (usize, usize, usize) {
+        (
+            self.id.is_some() as usize,
+            self.classes.len(),
+            self.tag.is_some() as usize,
+        )
+    }
+}
+
+impl StyleSheet {
+    /// Collect the rules of every `<style>` element in a document
+    pub fn new(doc: &Document) -> Self {
+        let mut sheet = Self::default();
+        for style in doc
+            .descendants()
+            .filter(|node| node.tag_name().name() == "style")
+        {
+            let text = style
+                .children()
+                .filter_map(|child| child.text())
+                .collect::<String>();
+            sheet.parse(&without_comments(&text));
+        }
+        sheet
+    }
+
+    fn parse(&mut self, mut css: &str) {
+        loop {
+            css = css.trim_start();
+            if css.is_empty() {
+                return;
+            }
+            if css.starts_with('@') {
+                // At-rules like @media or @font-face don't affect what is cut
+                let block = css.find('{');
+                let statement = css.find(';');
+                css = match (block, statement) {
+                    (Some(block), Some(statement)) if statement < block => &css[statement + 1..],
+                    (Some(block), _) => &css[block + matching_brace(&css[block..])..],
+                    (None, Some(statement)) => &css[statement + 1..],
+                    (None, None) => return,
+                };
+                continue;
+            }
+
+            let (prelude, rest) = match css.split_once('{') {
+                Some(split) => split,
+                None => return,
+            };
+            let (block, rest) = rest.split_once('}').unwrap_or((rest, ""));
+            css = rest;
+
+            let declarations = declarations(block);
+            for selector in prelude.split(',').filter_map(Selector::parse) {
+                self.rules.push(Rule {
+                    selector,
+                    declarations: declarations.clone(),
+                });
+            }
+        }
+    }
+
+    /// The value of a property of an element, from its style attribute, the style sheets, or its
+    /// presentation attribute, in that order of precedence. Inherited properties are looked up on
+    /// the element's ancestors if it doesn't have them.
+    pub fn property<'a>(&'a self, node: &Node<'a, '_>, name: &str) -> Option<&'a str> {
+        let is_inherited = INHERITED_PROPERTIES.contains(&name);
+        for node in node.ancestors().filter(|node| node.is_element()) {
+            match self.specified(&node, name) {
+                Some("inherit") => continue,
+                Some(value) => return Some(value),
+                None if is_inherited => continue,
+                None => return None,
+            }
+        }
+        None
+    }
+
+    fn specified<'a>(&'a self, node: &Node<'a, '_>, name: &str) -> Option<&'a str> {
+        let inline = node.attribute("style").and_then(|style| {
+            style
+                .split(';')
+                .filter_map(|declaration| declaration.split_once(':'))
+                .rfind(|(property, _)| property.trim().eq_ignore_ascii_case(name))
+                .map(|(_, value)| value.trim())
+        });
+        let rule = || {
+            // Later rules win over earlier ones with the same specificity
+            self.rules
+                .iter()
+                .filter(|rule| rule.selector.matches(node))
+                .filter_map(|rule| {
+                    rule.declarations
+                        .iter()
+                        .rev()
+                        .find(|(property, _)| property == name)
+                        .map(|(_, value)| (rule.selector.specificity(), value.as_str()))
+                })
+                .fold(
+                    None,
+                    |best: Option<((usize, usize, usize), &str)>, found| match best {
+                        Some(best) if best.0 > found.0 => Some(best),
+                        _ => Some(found),
+                    },
+                )
+                .map(|(_, value)| value)
+        };
+        inline
+            .map(|value| value.trim_end_matches("!important").trim())
+            .or_else(rule)
+            .or_else(|| node.attribute(name))
+    }
+
+    /// Whether an element and everything in it is not drawn
+    pub fn is_hidden(&self, node: &Node) -> bool {
+        self.property(node, "display") == Some("none")
+    }
+
+    /// Whether an element is invisible, although its children may still be visible
+    pub fn is_invisible(&self, node: &Node) -> bool {
+        matches!(
+            self.property(node, "visibility"),
+            Some("hidden" | "collapse")
+        )
+    }
+}
+
+fn declarations(block: &str) -> Vec<(String, String)> {
+    block
+        .split(';')
+        .filter_map(|declaration| declaration.split_once(':'))
+        .map(|(property, value)| {
+            (
+                property.trim().to_ascii_lowercase(),
+                value
+                    .trim()
+                    .trim_end_matches("!important")
+                    .trim()
+                    .to_string(),
+            )
+        })
+        .collect()
+}
+
+fn without_comments(css: &str) -> String {
+    let mut stripped = String::with_capacity(css.len());
+    let mut rest = css;
+    while let Some(start) = rest.find("/*") {
+        stripped += &rest[..start];
+        rest = rest[start + 2..]
+            .find("*/")
+            .map_or("", |end| &rest[start + 2 + end + 2..]);
+    }
+    stripped += rest;
+    stripped
+}
+
+/// Length of a block starting with an opening brace, including its closing brace
+fn matching_brace(block: &str) -> usize {
+    let mut depth = 0;
+    for (i, c) in block.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return i + 1;
+                }
+            }
+            _ => {}
+        }
+    }
+    block.len()
+}
@@ -0,0 +1,82 @@
+/// An SVG for checking that a machine moves the way its user expects before running real jobs.
+///
+/// The pattern is `width` by `height` millimeters:
+/// * a border around the whole area, to check that it fits on the machine
+/// * a quarter circle around the origin in the bottom left corner
+/// * an arrow pointing along +X with an X at its tip, and one pointing along +Y with a Y at its tip
+///
+/// If the arrows don't point the way the machine's axes do, or the circle isn't where the
+/// origin was expected, the machine or the --origin needs adjusting.
+pub fn test_pattern_svg(width: f64, height: f64) -> String {
+    let size = width.min(height) / 10.;
+    // Machine coordinates have Y going up, SVG coordinates have it going down
+    let point = |x: f64, y: f64| format!("{},{}", x, height - y);
+
+    let border = format!(
+        "M {} L {} L {} L {} Z",
+        point(0., 0.),
+        point(width, 0.),
+        point(width, height),
+        point(0., height)
+    );
+    let origin = format!(
+        "M {} A {r},{r} 0 0 0 {}",
+        point(size / 2., 0.),
+        point(0., size / 2.),
+        r = size / 2.
+    );
+
+    let x_tip = width / 2.;
+    let x_axis = format!(
+        "M {} L {} M {} L {} L {}",
+        point(size, size),
+        point(x_tip, size),
+        point(x_tip - size / 2., size * 1.5),
+        point(x_tip, size),
+        point(x_tip - size / 2., size / 2.)
+    );
+    let x_label = format!(
+        "M {} L {} M {} L {}",
+        point(x_tip + size / 2., size / 2.),
+        point(x_tip + size * 1.5, size * 1.5),
+        point(x_tip + size / 2., size * 1.5),
+        point(x_tip + size * 1.5, size / 2.)
+    );
+
+    let y_tip = height / 2.;
+    let y_axis = format!(
+        "M {} L {} M {} L {} L {}",
+        point(size, size),
+        point(size, y_tip),
+        point(size / 2., y_tip - size / 2.),
+        point(size, y_tip),
+        point(size * 1.5, y_tip - size / 2.)
+    );
+    let y_label = format!(
+        "M {} L {} L {} M {} L {}",
+        point(size / 2., y_tip + size * 1.5),
+        point(size, y_tip + size),
+        point(size * 1.5, y_tip + size * 1.5),
+        point(size, y_tip + size),
+        point(size, y_tip + size / 2.)
+    );
+
+    let paths = [
+        ("border", border),
+        ("origin", origin),
+        ("x-axis", x_axis),
+        ("x-label", x_label),
+        ("y-axis", y_axis),
+        ("y-label", y_label),
+    ]
+    .iter()
+    .map(|(id, d)| format!(r#"<path id="{}" d="{}"/>"#, id, d))
+    .collect::<String>();
+
+    format!(
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}mm" height="{h}mm" viewBox="0 0 {w} {h}">{}</svg>"#,
+        paths,
+        w = width,
+        h = height
+    )
+}
@@ -0,0 +1,260 @@
+use std::borrow::Cow;
+
+use euclid::default::Box2D;
+use g_code::emit::Token;
+use lyon_geom::{point, LineSegment, Point};
+
+use crate::analysis::motions;
+
+type F64Point = Point<f64>;
+
+/// Width in characters of each base64 comment line, as PrusaSlicer writes them
+const BASE64_LINE_LENGTH: usize = 78;
+
+/// Render the cutting moves of a program into a PNG and return it as comments in the
+/// PrusaSlicer thumbnail format:
+///
+/// ```text
+/// ; thumbnail begin 16x16 1234
+/// ; iVBORw0KGgo...
+/// ; thumbnail end
+/// ```
+pub fn thumbnail_comments(tokens: &[Token<'_>], width: u32, height: u32) -> Vec<Token<'static>> {
+    // Rapid positioning is travel, so it is left out of the thumbnail
+    let cuts = motions(tokens)
+        .drain(..)
+        .filter(|motion| motion.is_cutting)
+        .map(|motion| motion.segment)
+        .collect::<Vec<_>>();
+    let png = encode_png(&rasterize(&cuts, width, height), width, height);
+    let encoded = base64(&png);
+
+    let mut comments = vec![format!(
+        " thumbnail begin {}x{} {}",
+        width,
+        height,
+        encoded.len()
+    )];
+    comments.extend(
+        encoded
+            .as_bytes()
+            .chunks(BASE64_LINE_LENGTH)
+            .map(|line| format!(" {}", String::from_utf8_lossy(line))),
+    );
+    comments.push(" thumbnail end".to_string());
+
+    comments
+        .drain(..)
+        .map(|inner| Token::Comment {
+            is_inline: false,
+            inner: Cow::Owned(inner),
+        })
+        .collect()
+}
+
+/// Draw segments as dark lines on a white 8-bit grayscale canvas, scaled to fit with a 1px margin
+fn rasterize(segments: &[LineSegment<f64>], width: u32, height: u32) -> Vec<u8> {
+    let mut pixels = vec![u8::MAX; (width * height) as usize];
+    if segments.is_empty() || width < 3 || height < 3 {
+        return pixels;
+    }
+
+    let bounding_box = Box2D::from_points(segments.iter().flat_map(|s| vec![s.from, s.to]));
+    let size = bounding_box.size();
+    let scale = ((width - 3) as f64 / size.width.max(f64::EPSILON))
+        .min((height - 3) as f64 / size.height.max(f64::EPSILON));
+    // Center the drawing on the canvas
+    let offset = point(
+        (width as f64 - 1. - size.width * scale) / 2.,
+        (height as f64 - 1. - size.height * scale) / 2.,
+    );
+    let to_pixel = |p: F64Point| {
+        let x = (p.x - bounding_box.min.x) * scale + offset.x;
+        // Image rows go downward, g-code Y goes upward
+        let y = (height - 1) as f64 - ((p.y - bounding_box.min.y) * scale + offset.y);
+        (x.round() as i64, y.round() as i64)
+    };
+
+    for segment in segments {
+        let (mut x0, mut y0) = to_pixel(segment.from);
+        let (x1, y1) = to_pixel(segment.to);
+        // Bresenham's line algorithm
+        let dx = (x1 - x0).abs();
+        let dy = -(y1 - y0).abs();
+        let sx = if x0 < x1 { 1 } else { -1 };
+        let sy = if y0 < y1 { 1 } else { -1 };
+        let mut err = dx + dy;
+        loop {
+            if (0..width as i64).contains(&x0) && (0..height as i64).contains(&y0) {
+                pixels[(y0 * width as i64 + x0) as usize] = 0;
+            }
+            if x0 == x1 && y0 == y1 {
+                break;
+            }
+            let e2 = 2 * err;
+            if e2 >= dy {
+                err += dy;
+                x0 += sx;
+            }
+            if e2 <= dx {
+                err += dx;
+                y0 += sy;
+            }
+        }
+    }
+
+    pixels
+}
+
+/// Minimal PNG encoder for 8-bit grayscale images using uncompressed deflate blocks.
+/// Thumbnails are tiny, so compression isn't worth a dependency.
+fn encode_png(pixels: &[u8], width: u32, height: u32) -> Vec<u8> {
+    let mut raw = Vec::with_capacity(pixels.len() + height as usize);
+    for row in pixels.chunks(width as usize) {
+        // Filter type 0 (none)
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+
+    // zlib stream with stored deflate blocks
+    let mut zlib = vec![0x78, 0x01];
+    let mut blocks = raw.chunks(u16::MAX as usize).peekable();
+    if blocks.peek().is_none() {
+        zlib.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        zlib.push(blocks.peek().is_none() as u8);
+        let len = block.len() as u16;
+        zlib.extend_from_slice(&len.to_le_bytes());
+        zlib.extend_from_slice(&(!len).to_le_bytes());
+        zlib.extend_from_slice(block);
+    }
+    zlib.extend_from_slice(&adler32(&raw).to_be_bytes());
+
+    let mut ihdr = vec![];
+    ihdr.extend_from_slice(&width.to_be_bytes());
+    ihdr.extend_from_slice(&height.to_be_bytes());
+    // Bit depth 8, grayscale, deflate, adaptive filtering, no interlace
+    ihdr.extend_from_slice(&[8, 0, 0, 0, 0]);
+
+    let mut png = vec![0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+    for (chunk_type, data) in [(b"IHDR", ihdr), (b"IDAT", zlib), (b"IEND", vec![])].iter() {
+        png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        let start = png.len();
+        png.extend_from_slice(*chunk_type);
+        png.extend_from_slice(data);
+        let crc = crc32(&png[start..]);
+        png.extend_from_slice(&crc.to_be_bytes());
+    }
+    png
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = u32::MAX;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                0xEDB8_8320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn adler32(bytes: &[u8]) -> u32 {
+    const MOD_ADLER: u32 = 65521;
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in bytes {
+        a = (a + *byte as u32) % MOD_ADLER;
+        b = (b + a) % MOD_ADLER;
+    }
+    (b << 16) | a
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |acc, (i, b)| acc | (*b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(n >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn checksums_match_known_values() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(b"IEND"), 0xAE42_6082);
+        assert_eq!(adler32(b""), 1);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+    }
+
+    #[test]
+    fn base64_pads_partial_chunks() {
+        let cases = [
+            ("", ""),
+            ("f", "Zg=="),
+            ("fo", "Zm8="),
+            ("foo", "Zm9v"),
+            ("foob", "Zm9vYg=="),
+            ("fooba", "Zm9vYmE="),
+            ("foobar", "Zm9vYmFy"),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(base64(input.as_bytes()), expected);
+        }
+        assert_eq!(base64(&[0xFB, 0xFF]), "+/8=");
+    }
+
+    #[test]
+    fn encoded_png_decodes_to_the_same_pixels() {
+        let pixels = [0, 255, 128, 255, 0, 64];
+        let png = encode_png(&pixels, 3, 2);
+        assert_eq!(
+            &png[..16],
+            [
+                0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n', 0, 0, 0, 13, b'I', b'H', b'D',
+                b'R'
+            ]
+        );
+        assert_eq!(
+            &png[png.len() - 12..],
+            [0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xAE, 0x42, 0x60, 0x82]
+        );
+
+        let bitmap = crate::raster::decode_png(&png).unwrap();
+        assert_eq!((bitmap.width, bitmap.height), (3, 2));
+        let decoded = bitmap
+            .darkness
+            .iter()
+            .map(|darkness| ((1. - darkness) * 255.).round() as u8)
+            .collect::<Vec<_>>();
+        assert_eq!(decoded, pixels);
+    }
+
+    #[test]
+    fn large_pngs_are_split_into_stored_blocks() {
+        let pixels = vec![0; 300 * 300];
+        let bitmap = crate::raster::decode_png(&encode_png(&pixels, 300, 300)).unwrap();
+        assert_eq!((bitmap.width, bitmap.height), (300, 300));
+        assert!(bitmap.darkness.iter().all(|darkness| *darkness == 1.));
+    }
+}
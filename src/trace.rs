@@ -0,0 +1,181 @@
+use std::collections::HashMap;
+use std::fmt::Write as _;
+
+use crate::raster::Bitmap;
+
+/// Settings for tracing a bitmap
+#[derive(Debug, Clone)]
+pub struct TraceOptions {
+    /// Pixels at least this dark, from 0 for white to 1 for black, are inside the traced shapes
+    pub threshold: f64,
+    /// How far in pixels the outlines may stray from the edges of the pixels, so that the
+    /// staircases of slanted and curved edges become straight lines
+    pub tolerance: f64,
+    /// Shapes and holes smaller than this many pixels are left out, i.e. specks of noise
+    pub min_area: f64,
+    /// Pixels per inch, which sizes the traced SVG
+    pub dpi: f64,
+}
+
+type Vertex = (i64, i64);
+
+/// Trace the outlines of the dark areas of a bitmap into an SVG with one path, whose holes wind
+/// opposite to the shapes around them
+pub fn trace_svg(bitmap: &Bitmap, options: &TraceOptions) -> String {
+    let mut d = String::new();
+    for outline in outlines(bitmap, options.threshold) {
+        if area(&outline).abs() < options.min_area {
+            continue;
+        }
+        let outline = simplify(&outline, options.tolerance);
+        for (i, (x, y)) in outline.iter().enumerate() {
+            let _ = write!(d, "{}{},{} ", if i == 0 { 'M' } else { 'L' }, x, y);
+        }
+        d.push_str("Z ");
+    }
+    let to_mm = 25.4 / options.dpi;
+    format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}mm\" height=\"{}mm\" viewBox=\"0 0 {} {}\">\n<path id=\"trace\" fill=\"black\" d=\"{}\"/>\n</svg>\n",
+        bitmap.width as f64 * to_mm,
+        bitmap.height as f64 * to_mm,
+        bitmap.width,
+        bitmap.height,
+        d.trim_end()
+    )
+}
+
+/// The closed outlines along the edges between dark and light pixels, as their corners. Shapes go
+/// clockwise on the screen and holes counterclockwise. Pixels that only touch diagonally are kept
+/// apart.
+fn outlines(bitmap: &Bitmap, threshold: f64) -> Vec<Vec<Vertex>> {
+    let is_dark = |x: i64, y: i64| {
+        x >= 0
+            && y >= 0
+            && (x as usize) < bitmap.width
+            && (y as usize) < bitmap.height
+            && bitmap.darkness[y as usize * bitmap.width + x as usize] >= threshold
+    };
+
+    // Edges with the dark pixel on their right, by where they start
+    let mut edges: HashMap<Vertex, Vec<Vertex>> = HashMap::new();
+    let mut starts = vec![];
+    for y in 0..bitmap.height as i64 {
+        for x in 0..bitmap.width as i64 {
+            if !is_dark(x, y) {
+                continue;
+            }
+            let sides = [
+                ((x, y - 1), (x, y), (x + 1, y)),
+                ((x + 1, y), (x + 1, y), (x + 1, y + 1)),
+                ((x, y + 1), (x + 1, y + 1), (x, y + 1)),
+                ((x - 1, y), (x, y + 1), (x, y)),
+            ];
+            for (neighbor, from, to) in sides {
+                if !is_dark(neighbor.0, neighbor.1) {
+                    edges.entry(from).or_default().push(to);
+                    starts.push(from);
+                }
+            }
+        }
+    }
+
+    let mut outlines = vec![];
+    for start in starts {
+        let mut outline = vec![];
+        let mut from = start;
+        let mut direction: Option<Vertex> = None;
+        while let Some(next) = edges.get_mut(&from).and_then(|next| {
+            // Where two shapes touch at a corner, turn toward the dark pixel to stay in its shape
+            let i = match (direction, next.len()) {
+                (Some((dx, dy)), 2) => next
+                    .iter()
+                    .position(|to: &Vertex| (to.0 - from.0, to.1 - from.1) == (-dy, dx))
+                    .unwrap_or(0),
+                _ => 0,
+            };
+            (!next.is_empty()).then(|| next.swap_remove(i))
+        }) {
+            let turn = (next.0 - from.0, next.1 - from.1);
+            if direction != Some(turn) {
+                outline.push(from);
+            }
+            direction = Some(turn);
+            from = next;
+        }
+        if outline.len() >= 3 {
+            // The start is a corner only if the outline turns there
+            if from == start && outline.len() > 1 {
+                let second = outline[1];
+                let last = outline[outline.len() - 1];
+                let is_straight = (second.0 - start.0) * (start.1 - last.1)
+                    == (second.1 - start.1) * (start.0 - last.0);
+                if is_straight {
+                    outline.remove(0);
+                }
+            }
+            outlines.push(outline);
+        }
+    }
+    outlines
+}
+
+/// The signed area of a polygon, positive for clockwise on the screen
+fn area(outline: &[Vertex]) -> f64 {
+    let n = outline.len();
+    (0..n)
+        .map(|i| {
+            let (a, b) = (outline[i], outline[(i + 1) % n]);
+            (a.0 * b.1 - b.0 * a.1) as f64
+        })
+        .sum::<f64>()
+        / 2.
+}
+
+/// Drop corners of a closed outline that are within the tolerance of a line between the corners
+/// around them (Ramer-Douglas-Peucker)
+fn simplify(outline: &[Vertex], tolerance: f64) -> Vec<(f64, f64)> {
+    let points = outline
+        .iter()
+        .map(|(x, y)| (*x as f64, *y as f64))
+        .collect::<Vec<_>>();
+    if tolerance <= 0. || points.len() < 4 {
+        return points;
+    }
+    // Split the outline at its start and the corner farthest from it, which are both kept
+    let distance = |a: (f64, f64), b: (f64, f64)| (a.0 - b.0).hypot(a.1 - b.1);
+    let farthest = (1..points.len())
+        .max_by(|i, j| distance(points[0], points[*i]).total_cmp(&distance(points[0], points[*j])))
+        .unwrap_or(1);
+    let mut keep = vec![false; points.len()];
+    keep[0] = true;
+    keep[farthest] = true;
+    let mut closed = points.clone();
+    closed.push(points[0]);
+    let mut stack = vec![(0, farthest), (farthest, points.len())];
+    while let Some((start, end)) = stack.pop() {
+        let (a, b) = (closed[start], closed[end]);
+        let length = distance(a, b);
+        let deviation = |p: (f64, f64)| {
+            if length == 0. {
+                distance(a, p)
+            } else {
+                ((b.0 - a.0) * (a.1 - p.1) - (a.0 - p.0) * (b.1 - a.1)).abs() / length
+            }
+        };
+        if let Some((i, max)) = (start + 1..end)
+            .map(|i| (i, deviation(closed[i])))
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+        {
+            if max > tolerance {
+                keep[i] = true;
+                stack.push((start, i));
+                stack.push((i, end));
+            }
+        }
+    }
+    points
+        .into_iter()
+        .zip(keep)
+        .filter_map(|(point, keep)| keep.then_some(point))
+        .collect()
+}
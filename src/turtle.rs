@@ -113,7 +113,7 @@ impl<'input> Turtle<'input> {
         // which could result in a G91 G1 X0 Y0
         if (self.current_position - self.initial_position)
             .abs()
-            .lower_than(vector(std::f64::EPSILON, std::f64::EPSILON))
+            .lower_than(vector(f64::EPSILON, f64::EPSILON))
             .all()
         {
             return vec![];
@@ -216,6 +216,7 @@ impl<'input> Turtle<'input> {
 
     /// Draw a cubic curve from the current point to (x, y) with specified control points (x1, y1) and (x2, y2)
     /// https://www.w3.org/TR/SVG/paths.html#PathDataCubicBezierCommands
+    #[allow(clippy::too_many_arguments)]
     pub fn cubic_bezier<Z, F>(
         &mut self,
         abs: bool,
@@ -260,6 +261,7 @@ impl<'input> Turtle<'input> {
 
     /// Draw a shorthand/smooth cubic bezier segment, where the first control point was already given
     /// https://www.w3.org/TR/SVG/paths.html#PathDataCubicBezierCommands
+    #[allow(clippy::too_many_arguments)]
     pub fn smooth_cubic_bezier<Z, F>(
         &mut self,
         abs: bool,
@@ -330,6 +332,7 @@ impl<'input> Turtle<'input> {
 
     /// Draw a quadratic bezier segment
     /// https://www.w3.org/TR/SVG/paths.html#PathDataQuadraticBezierCommands
+    #[allow(clippy::too_many_arguments)]
     pub fn quadratic_bezier<Z, F>(
         &mut self,
         abs: bool,
@@ -364,6 +367,7 @@ impl<'input> Turtle<'input> {
 
     /// Draw an elliptical arc curve
     /// https://www.w3.org/TR/SVG/paths.html#PathDataEllipticalArcCommands
+    #[allow(clippy::too_many_arguments)]
     pub fn elliptical<Z, F>(
         &mut self,
         abs: bool,
@@ -444,6 +448,25 @@ impl<'input> Turtle<'input> {
         self.current_transform = Transform2D::identity();
     }
 
+    /// Transform from user units to machine coordinates that paths are drawn in
+    pub fn current_transform(&self) -> Transform2D<f64> {
+        self.current_transform
+    }
+
+    /// The current position in the user units of the current transform, where relative
+    /// commands start from
+    pub fn local_position(&self) -> F64Point {
+        self.current_transform
+            .inverse()
+            .unwrap()
+            .transform_point(self.current_position)
+    }
+
+    /// Average factor by which the current transform scales lengths
+    pub fn current_scale(&self) -> f64 {
+        self.current_transform.determinant().abs().sqrt()
+    }
+
     /// Reset the position of the turtle to the origin in the current transform stack
     /// Used for starting a new path
     pub fn reset(&mut self) {
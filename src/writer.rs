@@ -0,0 +1,81 @@
+use std::io;
+
+/// Write GCode tokens to a byte sink in a nicely formatted manner
+pub fn tokens_into_gcode_bytes<W: std::io::Write>(
+    program: &[g_code::emit::Token<'_>],
+    w: W,
+) -> io::Result<()> {
+    let mut writer = GCodeWriter::new(w);
+    writer.write(program)?;
+    writer.finish()
+}
+
+/// Formats GCode tokens written in any number of chunks the same as if they were written at once
+pub struct GCodeWriter<W> {
+    w: W,
+    preceded_by_newline: bool,
+    /// G53 only applies to the move on the same line
+    preceded_by_g53: bool,
+}
+
+impl<W: std::io::Write> GCodeWriter<W> {
+    pub fn new(w: W) -> Self {
+        Self {
+            w,
+            preceded_by_newline: true,
+            preceded_by_g53: false,
+        }
+    }
+
+    pub fn write(&mut self, tokens: &[g_code::emit::Token<'_>]) -> io::Result<()> {
+        use g_code::emit::Token::*;
+        for token in tokens {
+            match token {
+                Field(f) => {
+                    if !self.preceded_by_newline {
+                        let starts_line = matches!(f.letters.as_ref(), "G" | "M" | "T")
+                            || f.letters.starts_with('$');
+                        if starts_line && !self.preceded_by_g53 {
+                            writeln!(self.w)?;
+                        } else {
+                            write!(self.w, " ")?;
+                        }
+                    }
+                    match &f.value {
+                        // Grbl system commands like $H have no value
+                        g_code::emit::Value::String(value) if value.is_empty() => {
+                            write!(self.w, "{}", f.letters)?
+                        }
+                        _ => write!(self.w, "{}", f)?,
+                    }
+                    self.preceded_by_newline = false;
+                    self.preceded_by_g53 = f.letters == "G" && f.value.as_f64() == Some(53.);
+                }
+                Comment {
+                    is_inline: true,
+                    inner,
+                } => {
+                    write!(self.w, "({})", inner)?;
+                    self.preceded_by_newline = false;
+                }
+                Comment {
+                    is_inline: false,
+                    inner,
+                } => {
+                    writeln!(self.w, ";{}", inner)?;
+                    self.preceded_by_newline = true;
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        // Ensure presence of trailing newline
+        if !self.preceded_by_newline {
+            writeln!(self.w)?;
+        }
+        self.w.flush()
+    }
+}
@@ -0,0 +1,41 @@
+G21
+G90;svg > path#even
+G0 X10 Y90
+G1 X14 Y90 F300
+G0 X16 Y90
+G1 X20 Y90 F300
+G0 X22 Y90
+G1 X26 Y90 F300
+G0 X28 Y90
+G1 X30 Y90 F300;svg > path#odd
+G0 X10 Y80
+G1 X13 Y80 F300
+G0 X16 Y80
+G1 X19 Y80 F300
+G0 X22 Y80
+G1 X25 Y80 F300
+G0 X28 Y80
+G1 X30 Y80 F300;svg > path#offset
+G0 X11 Y70
+G1 X15 Y70 F300
+G0 X17 Y70
+G1 X21 Y70 F300
+G0 X23 Y70
+G1 X27 Y70 F300
+G0 X29 Y70
+G1 X30 Y70 F300;svg > path#corner
+G0 X40 Y90
+G1 X46 Y90 F300
+G0 X50 Y90
+G1 X50 Y84 F300;svg > path#closed
+G0 X40 Y70
+G1 X45 Y70 F300
+G0 X50 Y70
+G1 X50 Y65 F300
+G0 X50 Y60
+G1 X45 Y60 F300
+G0 X40 Y60
+G1 X40 Y65 F300;svg > path#solid
+G0 X10 Y50
+G1 X30 Y50 F300
+M2
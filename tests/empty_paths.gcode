@@ -0,0 +1,15 @@
+G21
+G90;svg > path#empty
+;svg > path#move
+M5
+G0 X1 Y9;svg > path#point
+G0 X2 Y8;svg > path#closed-point
+G0 X3 Y7;svg > path#line
+G0 X1 Y1
+M3
+G1 X9 Y1 F300;svg > path#repeated-moves
+M5
+G0 X4 Y6
+G0 X5 Y5
+G0 X6 Y4
+M2
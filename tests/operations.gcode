@@ -0,0 +1,17 @@
+G21
+G90;Operation score, pass 1 of 1
+;svg > g#art > path#scored
+G0 X10 Y80
+G1 X30 Y80 F1000;Operation mark, pass 1 of 1
+;svg > g#art > path#marked
+G0 X10 Y70
+G1 X30 Y70 F500;Operation cut, pass 1 of 2
+;svg > g#cuts > path#outline
+G0 X10 Y90
+G1 X30 Y90 F100;Operation cut, pass 2 of 2
+;svg > g#cuts > path#outline
+G0 X10 Y90
+G1 X30 Y90 F100;svg > g#art > path#unselected
+G0 X10 Y60
+G1 X30 Y60 F300
+M2
@@ -0,0 +1,14 @@
+G21
+G90
+G28;svg > path#first
+M5
+G0 X0 Y20
+M3
+G1 X30 Y20 F300;svg > path#second
+M5
+G0 X0 Y10
+M3
+G1 X30 Y10 F300
+M5
+M30
+M2
@@ -0,0 +1,10 @@
+G21
+G90
+G28;svg > path#third
+M5
+G0 X0 Y0
+M3
+G1 X30 Y0 F300
+M5
+M30
+M2
@@ -0,0 +1,10 @@
+G21
+G90;svg > svg#outer > svg#inner > path#square
+G0 X2 Y7
+G1 X6 Y7 F300
+G1 X6 Y3 F300
+G1 X2 Y3 F300
+G1 X2 Y7 F300;svg > svg#offset > path#line
+G0 X6 Y4
+G1 X8 Y2 F300
+M2
@@ -0,0 +1,42 @@
+G21
+G90;svg > path#butt
+G0 X10 Y91
+G1 X30 Y91 F300
+G1 X30 Y89 F300
+G1 X10 Y89 F300
+G1 X10 Y91 F300;svg > path#square
+G0 X10 Y81
+G1 X30 Y81 F300
+G1 X31 Y81 F300
+G1 X31 Y79 F300
+G1 X30 Y79 F300
+G1 X10 Y79 F300
+G1 X9 Y79 F300
+G1 X9 Y81 F300
+G1 X10 Y81 F300;svg > path#miter
+G0 X40 Y91
+G1 X61 Y91 F300
+G1 X61 Y70 F300
+G1 X59 Y70 F300
+G1 X59 Y89 F300
+G1 X40 Y89 F300
+G1 X40 Y91 F300;svg > path#bevel
+G0 X70 Y91
+G1 X90 Y91 F300
+G1 X91 Y90 F300
+G1 X91 Y70 F300
+G1 X89 Y70 F300
+G1 X89 Y89 F300
+G1 X70 Y89 F300
+G1 X70 Y91 F300;svg > path#closed
+G0 X9 Y61
+G1 X31 Y61 F300
+G1 X31 Y39 F300
+G1 X9 Y39 F300
+G1 X9 Y61 F300
+G0 X11 Y41
+G1 X29 Y41 F300
+G1 X29 Y59 F300
+G1 X11 Y59 F300
+G1 X11 Y41 F300
+M2
@@ -0,0 +1,7 @@
+G21
+G90;svg > g#ghost2 > path
+G0 X4 Y96
+G1 X5 Y95 F300;svg > path
+G0 X8 Y92
+G1 X9 Y91 F300
+M2
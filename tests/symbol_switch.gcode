@@ -0,0 +1,11 @@
+G21
+G90;svg > use > symbol#s > path
+G0 X20 Y70
+G1 X40 Y70 F300;svg > use > symbol#s > path
+G0 X50 Y50
+G1 X60 Y50 F300;svg > use > path#d
+G0 X5 Y95
+G1 X6 Y94 F300;svg > switch > path
+G0 X70 Y30
+G1 X80 Y20 F300
+M2